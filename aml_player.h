@@ -1,5 +1,169 @@
 typedef void* video_player_ptr;
 
+typedef struct {
+	double vpts_secs;
+	double apts_secs;
+	// vpts - apts, >0 si la vidéo est en avance
+	float av_diff_ms;
+	double pcr_secs;
+	// nombre de fois où |av_diff_ms| > 500
+	unsigned int vpts_discontinuity;
+} AmlTimingInfo;
+
+typedef struct {
+	unsigned int width;
+	unsigned int height;
+	unsigned int frame_rate;
+	// en bits par seconde, 0 si le pilote ne le donne pas
+	unsigned int bit_rate;
+	// nombre d'images que le décodeur n'a pas pu décoder
+	unsigned int error_count;
+	// 0 si le pilote ne le donne pas
+	unsigned long long frame_count;
+} AmlVpuState;
+
+typedef struct {
+	// AMPLAYER_CODEC_*, AMPLAYER_CODEC_AUTO pour un
+	// autre codec forcé par force_video_format
+	unsigned int codec_id;
+	// general_profile_idc en HEVC : 1 Main, 2 Main 10,
+	// 3 Main Still Picture, 4 Range Extensions
+	unsigned int profile;
+	// general_level_idc en HEVC : 30 fois le niveau
+	// (153 pour 5.1)
+	unsigned int level;
+	// en bits par échantillon de luminance
+	unsigned int bit_depth;
+	// AMPLAYER_CHROMA_*
+	unsigned int chroma_format;
+} AmlCodecInfo;
+
+typedef struct {
+	// paquets envoyés au décodeur par le démultiplexeur
+	unsigned long long packets_demuxed;
+	// paquets entièrement écrits dans le décodeur
+	unsigned long long packets_written;
+	unsigned long long bytes_written;
+	// paquets en attente entre le démultiplexeur et le
+	// décodeur
+	unsigned long long queue_depth;
+	unsigned long long device_reopens;
+	// écritures interrompues et recommencées
+	unsigned long long write_retries;
+	// nombre d'AMPLAYER_EVENT_STALLED
+	unsigned long long stalls;
+	// événements perdus car la file était pleine
+	unsigned long long events_dropped;
+	// depuis la création du lecteur
+	unsigned long long uptime_ms;
+	// AMPLAYER_STATE_*
+	int state;
+	// durée de vidéo dans le buffer du timeshift,
+	// 0 hors d'un direct mis en pause
+	unsigned long long timeshift_span_ms;
+	// temps passé par le démultiplexeur et le décodeur à
+	// attendre, le reste de uptime_ms ils travaillaient
+	unsigned long long libav_idle_ms;
+	unsigned long long amcodec_idle_ms;
+	// erreurs et avertissements journalisés depuis la
+	// création du lecteur, par n'importe quel lecteur
+	// (voir aml_video_player_get_log_counts)
+	unsigned long long errors;
+	unsigned long long warnings;
+} AmlPlayerStats;
+
+typedef struct {
+	// avformat_open_input
+	unsigned int open_ms;
+	// avformat_find_stream_info
+	unsigned int stream_info_ms;
+	// du début du benchmark au premier paquet vidéo
+	unsigned int first_packet_ms;
+	// paquets de tous les flux
+	float packets_per_sec;
+	float bytes_per_sec;
+	// écriture dans le VPU, 0 hors aarch64 ou si le
+	// VPU n'a pas pu être ouvert
+	float write_bytes_per_sec;
+} AmlBenchmarkResult;
+
+typedef struct {
+	// de la fin du chargement à la fin de la vidéo ou
+	// du benchmark
+	unsigned int elapsed_ms;
+	// paquets vidéo écrits dans le VPU (dans le VPU
+	// factice hors aarch64)
+	float packets_per_sec;
+	float bytes_per_sec;
+	// temps passé par chaque thread à attendre, le reste
+	// de elapsed_ms il travaillait
+	unsigned int libav_idle_ms;
+	unsigned int amcodec_idle_ms;
+} AmlPipelineBenchmarkResult;
+
+typedef struct {
+	// AMPLAYER_EVENT_*
+	int kind;
+	// code d'erreur de FATAL_ERROR et PRELOAD_FAILED,
+	// nombre de boucles de LOOP_WRAPPED, index du flux
+	// de STREAM_CHANGED
+	int code;
+	// nombre d'événements perdus depuis le précédent
+	// (la file est pleine si personne ne les lit)
+	unsigned int dropped;
+	// message de FATAL_ERROR, URL de PRELOAD_FAILED,
+	// texte de SUBTITLE
+	char message[256];
+	// position du sous-titre dans la vidéo en ms,
+	// end_ms vaut -1 s'il dure jusqu'au suivant.
+	// Pour SEEK_COMPLETED, start_ms est la position
+	// effective après le déplacement
+	long long start_ms;
+	long long end_ms;
+} AmlEvent;
+
+// 0 donne la valeur par défaut pour chaque champ
+typedef struct {
+	// nombre de vérifications d'affilée sans que le
+	// buffer du VPU ne bouge après la fin du fichier
+	// avant de considérer la vidéo terminée (3)
+	unsigned int finishing_threshold;
+	// nombre maximum de paquets en attente entre le
+	// démultiplexeur et le décodeur, au moins 4
+	// (illimité)
+	unsigned int packet_buffer_bound;
+	// taille de la fenêtre à sa création (800x600)
+	unsigned short window_width;
+	unsigned short window_height;
+	// délai après lequel une lecture réseau bloquée
+	// échoue, ramené entre 1 et 120 secondes (aucun)
+	unsigned int network_timeout_ms;
+	// fichier dans lequel un direct (UDP, HLS) mis en
+	// pause continue d'être enregistré, pour reprendre
+	// là où il a été mis en pause (NULL : désactivé)
+	const char* timeshift_path;
+	// taille maximale de ce fichier, au moins 16 (512)
+	unsigned int timeshift_size_mb;
+	// ce qui reste à l'écran quand une vidéo s'arrête,
+	// à sa fin, au chargement d'une autre ou à la
+	// destruction du lecteur : AMPLAYER_BLACKOUT_*
+	// (AMPLAYER_BLACKOUT_CLEAR)
+	unsigned int blackout_mode;
+} AmlPlayerConfig;
+
+// la dernière image est effacée
+#define AMPLAYER_BLACKOUT_CLEAR			0
+// effacée, et la couche vidéo est désactivée jusqu'à
+// la vidéo suivante : certains firmwares laissent
+// sinon la dernière image affichée
+#define AMPLAYER_BLACKOUT_DISABLE		1
+// la dernière image reste affichée jusqu'à la vidéo
+// suivante, même après la destruction du lecteur
+#define AMPLAYER_BLACKOUT_KEEP_LAST_FRAME	2
+
+#define AMPLAYER_ERROR_NOTHING_DISPLAYED	4
+#define AMPLAYER_ERROR_UNSUPPORTED 		3
+#define AMPLAYER_ERROR_INVALID_PLAYLIST 	2
 #define AMPLAYER_ERROR_INVALID_COMMAND 		1
 #define AMPLAYER_ERROR_NONE 			0
 #define AMPLAYER_ERROR_UNKNOWN 			-1
@@ -10,24 +174,334 @@ typedef void* video_player_ptr;
 #define AMPLAYER_ERROR_NO_HEVC_STREAM		-6
 #define AMPLAYER_ERROR_X11_DL_OPEN		-7
 #define AMPLAYER_ERROR_X11_INTERNAL		-8
+#define AMPLAYER_ERROR_WRONG_LIBAV_VERSION	-9
+#define AMPLAYER_ERROR_WAYLAND			-10
+#define AMPLAYER_ERROR_SYSFS			-11
+#define AMPLAYER_ERROR_TIMEOUT			-12
+#define AMPLAYER_ERROR_DEAD			-13
+#define AMPLAYER_ERROR_PERMISSION_DENIED	-14
+#define AMPLAYER_ERROR_DEVICE_MISSING		-15
+#define AMPLAYER_ERROR_DEVICE_BUSY		-16
+#define AMPLAYER_ERROR_WRITE_FAILED		-17
 #define AMPLAYER_BUG				-42
 #define AMPLAYER_UNREACHABLE			-43
 #define AMPLAYER_ERROR_SHUTDOWN			-64
 
+#define AMPLAYER_DISPLAY_PATH_FRAMEBUFFER	0
+#define AMPLAYER_DISPLAY_PATH_COMPOSITOR	1
+#define AMPLAYER_DISPLAY_PATH_HEADLESS		2
+
+#define AMPLAYER_SHOW_HIDE_RAISE_LOWER		0
+#define AMPLAYER_SHOW_HIDE_MAP_UNMAP		1
+
+#define AMPLAYER_OUTPUT_HDMI0			0
+#define AMPLAYER_OUTPUT_HDMI1			1
+#define AMPLAYER_OUTPUT_CVBS			2
+
+#define AMPLAYER_CONTRAST_OFF			0
+#define AMPLAYER_CONTRAST_LOW			1
+#define AMPLAYER_CONTRAST_MEDIUM		2
+#define AMPLAYER_CONTRAST_HIGH			3
+#define AMPLAYER_CONTRAST_CUSTOM		4
+
+#define AMPLAYER_STATE_IDLE			0
+#define AMPLAYER_STATE_PLAYING			1
+#define AMPLAYER_STATE_PAUSED			2
+#define AMPLAYER_STATE_FINISHING		3
+
+#define AMPLAYER_COLOR_TEMP_WARM		0
+#define AMPLAYER_COLOR_TEMP_NEUTRAL		1
+#define AMPLAYER_COLOR_TEMP_COOL		2
+#define AMPLAYER_COLOR_TEMP_CUSTOM		3
+
+#define AMPLAYER_EVENT_END_OF_STREAM		0
+#define AMPLAYER_EVENT_FATAL_ERROR		1
+#define AMPLAYER_EVENT_STALLED			2
+#define AMPLAYER_EVENT_RESUMED			3
+#define AMPLAYER_EVENT_FIRST_FRAME_DISPLAYED	4
+#define AMPLAYER_EVENT_WINDOW_CLOSE_REQUESTED	5
+#define AMPLAYER_EVENT_PRELOAD_FAILED		6
+#define AMPLAYER_EVENT_SUBTITLE			7
+#define AMPLAYER_EVENT_LOOP_WRAPPED		8
+#define AMPLAYER_EVENT_SEEK_COMPLETED		9
+// la vidéo s'est arrêtée alors que le reste du flux
+// continuait (un flux TS qui change de programme) :
+// un autre flux HEVC du fichier est lu à la place,
+// code est son index
+#define AMPLAYER_EVENT_STREAM_CHANGED		10
+
 // Créé une instance du lecteur
 //
 // Renvoie NULL si une erreur s'est produite,
 // le pointeur du lecteur sinon
 video_player_ptr aml_video_player_create();
 
+// Créé une instance du lecteur sans aucune fenêtre,
+// même si X11 ou Wayland sont disponibles: la vidéo
+// est affichée directement sur la couche du VPU
+//
+// Renvoie NULL si une erreur s'est produite,
+// le pointeur du lecteur sinon
+video_player_ptr aml_video_player_create_offscreen();
+
+// Comme aml_video_player_create, avec les réglages
+// de config au lieu de ceux par défaut
+//
+// Renvoie NULL si une erreur s'est produite ou si un
+// réglage est invalide (aml_video_player_create_error
+// renvoie alors AMPLAYER_ERROR_INVALID_COMMAND)
+video_player_ptr aml_video_player_create_with_config(const AmlPlayerConfig* config);
+
+// Indique pourquoi le dernier appel à
+// aml_video_player_create ou
+// aml_video_player_create_offscreen (ou _with_config)
+// de ce thread a renvoyé NULL, par exemple
+// AMPLAYER_ERROR_PERMISSION_DENIED s'il manque une
+// règle udev pour /dev/amstream_hevc, ou
+// AMPLAYER_ERROR_DEVICE_BUSY si un autre lecteur
+// l'utilise déjà.
+//
+// Renvoie 0 si le dernier appel a réussi
+int aml_video_player_create_error();
+
+// Renvoie la description (en anglais) d'un code
+// d'erreur renvoyé par n'importe quelle fonction, ou
+// "unknown code". La chaîne est statique : elle ne
+// doit pas être libérée.
+const char* aml_video_player_error_string(int code);
+
+// Écrit dans buf la dernière erreur rencontrée par un
+// des threads du lecteur, avec toutes ses causes et le
+// thread concerné, par exemple "amcodec_thread: ...
+// (DeviceBusy, errno 16)". Fonctionne comme snprintf :
+// la chaîne est tronquée si len est trop petit, et la
+// longueur complète (sans le 0 final) est renvoyée.
+// Une chaîne vide est écrite si rien n'a échoué.
+int aml_video_player_last_error(void* player, char* buf, unsigned int len);
+
+// Code (AMPLAYER_ERROR_*) de la dernière erreur du
+// lecteur, qu'elle ait été renvoyée par une fonction
+// ou envoyée sous forme d'événement FATAL_ERROR ou
+// PRELOAD_FAILED. Une réussite ne l'efface pas : 0
+// tant que rien n'a échoué depuis la création du
+// lecteur ou le dernier clear_last_error.
+int aml_video_player_get_last_error_code(void* player);
+
+// Efface la dernière erreur : son code et le message
+// de aml_video_player_last_error
+//
+// Renvoie toujours 0
+int aml_video_player_clear_last_error(void* player);
+
+// Écrit dans buf les informations sur le matériel et
+// libavformat, sous la forme {"soc":"S905X3",
+// "vpu_ver":"v2.1","avformat_ver":"57.71.100",
+// "avformat_abi":"57"}, avec "unknown" pour ce qui
+// n'a pas été trouvé. avformat_abi est la version
+// dont les structures sont utilisées (56 ou 57), ou
+// "unsupported" si aucun lecteur ne peut être créé.
+// Ne nécessite pas de lecteur.
+//
+// Comme snprintf: buf est toujours terminé par un
+// octet nul (tronqué si besoin) si len > 0.
+// Renvoie la longueur de la chaîne complète, sans
+// l'octet nul: si elle est >= len, buf est trop petit
+int aml_video_player_get_sys_info(char* buf, unsigned int len);
+
+// Écrit dans buf la version de la bibliothèque, par
+// exemple "0.1.0". Ne nécessite pas de lecteur.
+//
+// Même convention que aml_video_player_get_sys_info,
+// sauf que la taille du buffer nécessaire est renvoyée,
+// octet nul compris: si elle est > len, buf est trop
+// petit. buf peut être NULL si len vaut 0 pour
+// n'obtenir que cette taille
+int aml_video_player_get_version(char* buf, unsigned int len);
+
+// Écrit dans buf la version, la cible, la date de
+// compilation et le commit git de la bibliothèque,
+// par exemple "c2player 0.1.0 (aarch64-unknown-linux-gnu,
+// built 2017-06-01, commit 1a2b3c4)". Ne nécessite
+// pas de lecteur.
+//
+// Même convention que aml_video_player_get_version
+int aml_video_player_get_build_info(char* buf, unsigned int len);
+
+#define AML_CAP_HEVC			1
+#define AML_CAP_H264			2
+#define AML_CAP_VP9			4
+#define AML_CAP_HDR10			8
+#define AML_CAP_HLG			16
+#define AML_CAP_DEINTERLACE		32
+#define AML_CAP_AUDIO_PASSTHROUGH	64
+#define AML_CAP_CLOSED_CAPTIONS		128
+
+// Renvoie les AML_CAP_* supportés par la carte (et
+// par la TV pour HDR10, HLG et AUDIO_PASSTHROUGH),
+// combinés avec |. Toujours 0 ailleurs que sur
+// aarch64. Le matériel est lu au premier appel
+// seulement. Ne nécessite pas de lecteur.
+unsigned int aml_video_player_get_capabilities(void);
+
+// Ouvre url et en lit les paquets pendant duration_secs
+// secondes (moins si la vidéo se termine avant), sans les
+// envoyer au VPU, puis remplit out avec les durées
+// d'ouverture et le débit du démultiplexeur. Bloque le
+// thread appelant pendant environ duration_secs secondes.
+// Ne nécessite pas de lecteur. Sur aarch64, les 2 premiers
+// Mo de vidéo sont ensuite écrits dans le VPU pour en
+// mesurer le débit : aucun lecteur ne doit l'utiliser en
+// même temps.
+//
+// Renvoie <0 en cas d'erreur, >0 si url ou out est NULL
+// ou si duration_secs vaut 0
+int aml_video_player_benchmark(const char* url, unsigned int duration_secs, AmlBenchmarkResult* out);
+
+// Lit url avec un lecteur sans fenêtre créé pour
+// l'occasion pendant duration_secs secondes (moins si
+// la vidéo se termine avant), puis remplit out avec le
+// débit du démultiplexeur jusqu'au VPU et le temps que
+// chaque thread a passé à attendre. Hors aarch64, les
+// paquets vont au VPU factice : seul le coût des
+// threads est mesuré. Sur aarch64, aucun lecteur ne
+// doit utiliser le VPU en même temps.
+//
+// Renvoie <0 en cas d'erreur, >0 si url ou out est NULL
+// ou si duration_secs vaut 0
+int aml_video_player_benchmark_pipeline(const char* url, unsigned int duration_secs, AmlPipelineBenchmarkResult* out);
+
 // Charge la vidéo depuis l'URL donnée
 // l'URL peut être une adresse web délivrant
 // du mp4 valide,
 // ou un chemin sur le système de fichier courant
 //
-// Renvoie <0 en cas d'erreur
+// Ne rend la main qu'une fois le premier paquet de la
+// vidéo écrit dans le VPU.
+//
+// Renvoie <0 en cas d'erreur,
+// AMPLAYER_ERROR_VIDEO_DECODING si le VPU refuse la
+// vidéo ou si rien n'a pu y être écrit après 5 secondes
 int aml_video_player_load(video_player_ptr, const char* video_url);
 
+#define AMPLAYER_CODEC_AUTO			0
+#define AMPLAYER_CODEC_HEVC			1
+
+// ATTENTION, DANGEREUX : seulement pour les fichiers
+// mal étiquetés. Le prochain load (et lui seul)
+// utilise le premier flux vidéo du fichier comme du
+// `codec` (AMPLAYER_CODEC_*), quel que soit son codec
+// réel. Si ce n'en est pas, le VPU décode n'importe
+// quoi : image corrompue, erreurs de décodage, voire
+// VPU bloqué jusqu'au redémarrage sur certains
+// noyaux. AMPLAYER_CODEC_AUTO rétablit la détection
+// automatique. La vidéo préchargée n'est pas utilisée.
+//
+// Renvoie <0 en cas d'erreur, >0 si codec est inconnu
+int aml_video_player_force_video_format(video_player_ptr, unsigned int codec);
+
+// Comme timeshift_path de AmlPlayerConfig, mais choisi
+// pendant la lecture : à la prochaine pause d'un
+// direct, il continue d'être enregistré dans
+// buffer_path, en gardant au plus max_duration_secs
+// secondes de vidéo (0 : autant que la taille du
+// fichier le permet). NULL désactive le timeshift. Un
+// timeshift en cours continue jusqu'au prochain
+// chargement.
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_enable_time_shift(video_player_ptr, const char* buffer_path, unsigned int max_duration_secs);
+
+// Avance de delta_secs secondes dans le timeshift
+// (recule si négatif), au plus jusqu'à sa plus
+// ancienne image clé ou jusqu'au direct
+//
+// Renvoie <0 en cas d'erreur,
+// AMPLAYER_ERROR_INVALID_COMMAND si aucun timeshift
+// n'est en cours
+int aml_video_player_seek_time_shift(video_player_ptr, float delta_secs);
+
+// Enregistre le flux vidéo dans le fichier `path` en
+// même temps qu'il est lu, sous forme de flux HEVC brut
+// (Annex B, lisible par ffmpeg -f hevc), jusqu'à
+// aml_video_player_stop_recording. L'enregistrement
+// continue d'une vidéo à l'autre, et remplace celui en
+// cours s'il y en a un. Le fichier commence à la
+// prochaine image clé.
+//
+// Renvoie <0 en cas d'erreur (fichier impossible à
+// créer), AMPLAYER_ERROR_INVALID_COMMAND si path est
+// la vidéo en cours de lecture
+int aml_video_player_start_recording(video_player_ptr, const char* path);
+
+// Termine l'enregistrement et ferme son fichier
+//
+// Renvoie <0 en cas d'erreur,
+// AMPLAYER_ERROR_INVALID_COMMAND si rien n'est
+// enregistré
+int aml_video_player_stop_recording(video_player_ptr);
+
+#define AMPLAYER_RTSP_TRANSPORT_UDP		0
+#define AMPLAYER_RTSP_TRANSPORT_TCP		1
+#define AMPLAYER_RTSP_TRANSPORT_UDP_MULTICAST	2
+
+// Transport (AMPLAYER_RTSP_TRANSPORT_*) des flux RTSP
+// ouverts à partir de maintenant, pour tous les load et
+// preload suivants. Par défaut libav essaie d'abord
+// l'UDP, que certains NAT bloquent.
+//
+// Renvoie <0 en cas d'erreur, >0 si transport est inconnu
+int aml_video_player_set_rtsp_transport(video_player_ptr, unsigned int transport);
+
+// Taille en octets des buffers des sockets UDP des flux
+// ouverts à partir de maintenant : un flux RTSP en UDP à
+// haut débit perd des paquets s'ils sont trop petits.
+// 0 rétablit la valeur par défaut du système.
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_set_rtsp_buffer_size(video_player_ptr, unsigned int bytes);
+
+// Ouvre la vidéo à l'avance, sans interrompre la
+// lecture en cours. Un seul préchargement à la fois:
+// le précédent est remplacé. Les playlists
+// préchargent automatiquement la vidéo suivante.
+//
+// Renvoie <0 en cas d'erreur,
+// AMPLAYER_ERROR_INVALID_COMMAND si video_url est NULL
+int aml_video_player_preload(video_player_ptr, const char* video_url);
+
+// Lance la vidéo préchargée par
+// aml_video_player_preload. Un aml_video_player_load
+// de la même URL a le même effet.
+//
+// Renvoie <0 en cas d'erreur (y compris si le
+// préchargement a échoué), >0 si rien n'a été
+// préchargé
+int aml_video_player_play_preloaded(video_player_ptr);
+
+// Ouvre à l'avance une vidéo qui sera probablement
+// chargée ensuite, comme aml_video_player_preload.
+// Seul un aml_video_player_load de la même URL
+// l'utilise : charger une autre URL la ferme. Elle
+// remplace le préchargement en cours. Un échec n'est
+// pas signalé, la vidéo est rouverte au chargement.
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_prefetch_url(video_player_ptr, const char* url);
+
+// Charge une playlist au format .m3u ou .m3u8
+// depuis le système de fichier courant. Les chemins
+// relatifs sont résolus par rapport au dossier
+// de la playlist.
+//
+// loop_mode == 0: s'arrête après la dernière vidéo
+// loop_mode > 0: reprend à la première vidéo après
+// la dernière
+//
+// Renvoie <0 en cas d'erreur, >0 si la playlist
+// est invalide (une ligne de plus de 64 Kio la rend
+// invalide) ou si m3u_path est NULL
+int aml_video_player_load_playlist_m3u(video_player_ptr, const char* m3u_path, int loop_mode);
+
 // Montre le lecteur vidéo en premier plan
 //
 // Renvoie <0 en cas d'erreur
@@ -38,6 +512,38 @@ int aml_video_player_show(video_player_ptr);
 // Renvoie <0 en cas d'erreur
 int aml_video_player_hide(video_player_ptr);
 
+// Laisse passer les clics à travers la fenêtre
+// du lecteur, vers ce qui se trouve en dessous
+// (X11 uniquement, nécessite l'extension XFixes)
+//
+// enabled == 0: la fenêtre reçoit les clics (par défaut)
+// enabled > 0: les clics passent à travers
+//
+// Renvoie <0 en cas d'erreur, AMPLAYER_ERROR_UNSUPPORTED
+// si XFixes n'est pas disponible
+int aml_video_player_set_click_through(video_player_ptr, int enabled);
+
+// Désactive la couche vidéo lorsque la fenêtre du
+// lecteur est entièrement recouverte par d'autres
+// fenêtres (X11 uniquement)
+//
+// enable == 0: la vidéo reste visible (par défaut)
+// enable > 0: la vidéo est cachée si recouverte
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_set_hide_when_obscured(video_player_ptr, int enable);
+
+// Choisit comment show/hide cachent la fenêtre :
+// AMPLAYER_SHOW_HIDE_RAISE_LOWER: la fenêtre passe
+// derrière les autres mais reçoit toujours les
+// clics (par défaut)
+// AMPLAYER_SHOW_HIDE_MAP_UNMAP: la fenêtre est
+// retirée de l'écran, puis replacée (position,
+// taille, plein écran) quand elle est montrée
+//
+// Renvoie <0 en cas d'erreur, >0 si mode est invalide
+int aml_video_player_set_show_hide_mode(video_player_ptr, int mode);
+
 // Commence la lecture du lecteur vidéo
 //
 // Renvoie <0 en cas d'erreur
@@ -49,23 +555,303 @@ int aml_video_player_play(video_player_ptr);
 int aml_video_player_pause(video_player_ptr);
 
 // Essaie de mettre la position du lecteur à la seconde t
+// Au-delà de la fin de la vidéo, la position est ramenée
+// une seconde avant la fin. Impossible dans un flux sans
+// durée (un direct), sauf dans son timeshift.
+// Un événement AMPLAYER_EVENT_SEEK_COMPLETED est
+// envoyé quand la première image à la nouvelle
+// position est affichée, pour le dernier déplacement
+// seulement s'ils se chevauchent.
 //
-// Renvoie <0 en cas d'erreur
+// Renvoie <0 en cas d'erreur, AMPLAYER_ERROR_INVALID_COMMAND
+// si t < 0, AMPLAYER_ERROR_UNSUPPORTED si le flux n'a pas de
+// durée
 int aml_video_player_seek(video_player_ptr, float t);
 
-// Tente de redimensionner le lecteur à la taille donnée
+// Comme aml_video_player_seek, mais sans attendre la
+// fin du déplacement (voir is_seek_complete). Si
+// plusieurs sont demandés avant la fin du premier,
+// seule la dernière position est utilisée. Les
+// erreurs sont seulement écrites dans les logs.
+//
+// Renvoie toujours 0
+int aml_video_player_async_seek(video_player_ptr, float t);
+
+// Renvoie 1 si le dernier async_seek est terminé (ou a
+// échoué), 0 s'il est encore en cours
+int aml_video_player_is_seek_complete(video_player_ptr);
+
+// Récupère la position actuelle de la vidéo (en
+// secondes) dans out_t. Juste après un seek, c'est
+// la position demandée qui est renvoyée.
+//
+// Renvoie <0 en cas d'erreur, >0 si aucune vidéo
+// n'est chargée
+int aml_video_player_get_position(video_player_ptr, float* out_t);
+
+// Récupère la durée de la vidéo chargée (en secondes)
+// dans out_t.
+//
+// Renvoie <0 en cas d'erreur, AMPLAYER_ERROR_UNSUPPORTED
+// pour un flux en direct, qui n'a pas de durée, >0 si
+// aucune vidéo n'est chargée
+int aml_video_player_get_duration(video_player_ptr, float* out_t);
+
+// Retarde la vidéo lorsqu'elle est en avance sur
+// l'audio : les paquets ne sont jamais envoyés plus
+// de delay_secs secondes en avance sur l'horloge.
+// 0 désactive ce retard (par défaut)
+//
+// Renvoie <0 en cas d'erreur, >0 si delay_secs < 0
+int aml_video_player_set_video_delay(video_player_ptr, float delay_secs);
+
+// Limite le nombre de paquets vidéo envoyés au
+// VPU par seconde, pour économiser de l'énergie.
+// Ne devrait pas être inférieur au nombre d'images
+// par seconde de la vidéo.
+// 0 désactive la limite (par défaut)
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_set_max_decode_rate(video_player_ptr, unsigned int packets_per_sec);
+
+// Lit la vidéo à l'envers à la vitesse `speed` (1.0
+// pour le temps réel) si speed > 0. 0 reprend la
+// lecture normale depuis l'image affichée.
+// Le VPU ne sait pas décoder à l'envers : seules les
+// images clés sont affichées.
+//
+// Renvoie <0 en cas d'erreur (aucune vidéo chargée,
+// speed négatif)
+int aml_video_player_set_backward_speed(video_player_ptr, float speed);
+
+// Nombre de pistes de sous-titres de la vidéo
+// chargée dans out
+//
+// Renvoie <0 en cas d'erreur (aucune vidéo chargée)
+int aml_video_player_get_subtitle_track_count(video_player_ptr, unsigned int* out);
+
+// Envoie les sous-titres de la piste `index` (0 pour
+// la première piste de sous-titres) sous forme
+// d'événements AMPLAYER_EVENT_SUBTITLE, jusqu'au
+// prochain chargement. Ils sont envoyés en avance :
+// l'affichage est à la charge de l'appelant.
+//
+// Renvoie <0 en cas d'erreur, >0 si la piste est en
+// images (DVD, PGS) : seul le texte est supporté
+int aml_video_player_set_subtitle_track(video_player_ptr, unsigned int index);
+
+// N'envoie plus les sous-titres
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_disable_subtitles(video_player_ptr);
+
+// Lit le fichier SRT `path` et envoie ses sous-titres
+// sous forme d'événements AMPLAYER_EVENT_SUBTITLE,
+// au moment où la vidéo les atteint (contrairement
+// aux pistes de la vidéo). Une vidéo doit être
+// chargée : ils sont oubliés au prochain chargement.
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_load_subtitles(video_player_ptr, const char* path);
+
+// Lit la vidéo de `start` à `end` (en secondes) en
+// boucle, avec un événement AMPLAYER_EVENT_LOOP_WRAPPED
+// à chaque retour à `start`. En pause, le retour
+// n'a lieu qu'une fois la lecture reprise. Une vidéo
+// doit être chargée : la boucle est oubliée au
+// prochain chargement.
+//
+// Renvoie <0 en cas d'erreur, >0 si end <= start ou
+// si aucune vidéo n'est chargée
+int aml_video_player_set_loop_range(video_player_ptr, float start, float end);
+
+// Arrête la boucle, la lecture continue après `end`
 //
 // Renvoie <0 en cas d'erreur
+int aml_video_player_clear_loop_range(video_player_ptr);
+
+// Nombre de retours au début de la boucle depuis
+// set_loop_range dans out
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_get_loop_count(video_player_ptr, unsigned int* out);
+
+// Lorsque le buffer du VPU contient moins de
+// `bytes` octets pendant la lecture, les paquets
+// ne sont plus retenus (voir set_video_delay et
+// set_max_decode_rate). 4096 par défaut
+//
+// Renvoie <0 en cas d'erreur, >0 si bytes < 0
+int aml_video_player_set_underflow_threshold(video_player_ptr, int bytes);
+
+// Si enable != 0, la dernière image d'une vidéo
+// reste affichée à la fin de celle-ci, jusqu'à ce
+// que la suivante commence, au lieu d'effacer la
+// couche vidéo. Un nouveau chargement ou la
+// destruction du lecteur l'effacent toujours, sauf
+// avec AMPLAYER_BLACKOUT_KEEP_LAST_FRAME (voir
+// AmlPlayerConfig).
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_set_freeze_on_last_frame(video_player_ptr, int enable);
+
+// Récupère les timestamps du VPU (vidéo, audio,
+// horloge) pour diagnostiquer la synchronisation
+// audio/vidéo
+//
+// Renvoie <0 en cas d'erreur, >0 si aucune vidéo
+// n'est en cours de lecture
+int aml_video_player_get_timing_info(video_player_ptr, AmlTimingInfo* out);
+
+// Récupère l'état du décodeur du VPU dans out: taille
+// et fréquence d'images de la vidéo, erreurs de
+// décodage. Les valeurs n'ont pas de sens si aucune
+// vidéo n'est chargée.
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_get_vpu_state(video_player_ptr, AmlVpuState* out);
+
+#define AMPLAYER_CHROMA_UNKNOWN			0
+#define AMPLAYER_CHROMA_420			1
+#define AMPLAYER_CHROMA_422			2
+#define AMPLAYER_CHROMA_444			3
+#define AMPLAYER_CHROMA_440			4
+// luminance seule
+#define AMPLAYER_CHROMA_GRAY			5
+
+// Récupère dans out le codec du flux vidéo de la
+// vidéo chargée tel que libav le lit : codec, profil,
+// niveau, profondeur et sous-échantillonnage de la
+// chrominance (par exemple pour passer en HDR avec du
+// HEVC Main 10). 0 pour ce que libav ne sait pas.
+//
+// Renvoie <0 en cas d'erreur,
+// AMPLAYER_ERROR_INVALID_COMMAND si aucune vidéo n'est
+// chargée
+int aml_video_player_get_codec_info(video_player_ptr, AmlCodecInfo* out);
+
+// Récupère dans out les compteurs du lecteur depuis
+// sa création (ils ne sont pas remis à zéro au
+// chargement d'une vidéo). Ne passe pas par le thread
+// principal : fonctionne même si le lecteur est
+// occupé.
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_get_stats(video_player_ptr, AmlPlayerStats* out);
+
+// Compare l'image du framebuffer avec l'image PPM
+// (P6) reference_path, pour les bancs de test. La
+// couche vidéo du VPU n'en fait pas partie.
+//
+// Renvoie 0 si l'erreur absolue moyenne est
+// inférieure ou égale à tolerance (de 0 à 255 par
+// canal), l'erreur arrondie au supérieur sinon, <0 en
+// cas d'erreur. Renvoie
+// AMPLAYER_ERROR_INVALID_COMMAND hors d'un Amlogic.
+int aml_video_player_screenshot_compare(video_player_ptr, const char* reference_path, float tolerance);
+
+// Écrit l'image affichée par le VPU dans le fichier
+// PNG path, à la résolution de la vidéo. Seule la
+// couche vidéo est capturée. Hors d'un Amlogic, la
+// même image de test est toujours écrite.
+//
+// Renvoie <0 en cas d'erreur :
+// AMPLAYER_ERROR_DEVICE_MISSING sans amvideocap,
+// AMPLAYER_ERROR_WRITE_FAILED si path ne peut pas être
+// écrit, et AMPLAYER_ERROR_NOTHING_DISPLAYED (>0) si
+// aucune image n'est affichée
+int aml_video_player_snapshot_png(video_player_ptr, const char* path);
+
+// Tente de redimensionner le lecteur à la taille donnée.
+// Le lecteur est déplacé s'il ne tient plus dans
+// l'écran là où il est (voir
+// aml_video_player_set_allow_offscreen)
+//
+// Renvoie <0 en cas d'erreur,
+// AMPLAYER_ERROR_INVALID_COMMAND si width ou height
+// vaut 0
 int aml_video_player_resize(video_player_ptr, unsigned int width, unsigned int height);
 
 // Tente de déplacer le coin haut-gauche du lecteur 
 // à la position (x, y), relativement à la fenêtre
 // "racine" X11  (qui devrait être le point en haut 
-// à gauche de l'écran)
+// à gauche de l'écran). La position est ramenée dans
+// l'écran si le lecteur en sortirait (voir
+// aml_video_player_set_allow_offscreen)
 //
 // Renvoie <0 en cas d'erreur
 int aml_video_player_set_pos(video_player_ptr,int x, int y);
 
+// Autorise set_pos et resize à sortir le lecteur de
+// l'écran, pour les configurations multi-écrans
+//
+// enable == 0: le lecteur est ramené dans l'écran
+// (par défaut)
+// enable > 0: le lecteur peut sortir de l'écran
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_set_allow_offscreen(video_player_ptr, int enable);
+
+// Affiche la vidéo au format
+// width_ratio:height_ratio (16:9, 4:3...) avec des
+// bandes noires dans le lecteur, sans redimensionner
+// sa fenêtre. 0:0 pour que la vidéo remplisse à
+// nouveau le lecteur (par défaut)
+//
+// Renvoie <0 en cas d'erreur,
+// AMPLAYER_ERROR_INVALID_COMMAND si une seule des deux
+// valeurs vaut 0
+int aml_video_player_set_video_aspect_ratio(video_player_ptr, unsigned int width_ratio, unsigned int height_ratio);
+
+// Les vidéos anamorphiques (DVD, certaines chaînes
+// TV) ont des pixels qui ne sont pas carrés : sans
+// format forcé par set_video_aspect_ratio, elles
+// sont affichées au format voulu avec des bandes
+// noires, d'après le format des pixels lu dans le
+// flux. Les vidéos aux pixels carrés remplissent
+// toujours le lecteur.
+//
+// Force le format des pixels de toutes les vidéos à
+// num:den (plus larges que hauts si num > den) au
+// lieu de celui du flux. 0:0 pour revenir à celui du
+// flux (par défaut)
+//
+// Renvoie <0 en cas d'erreur,
+// AMPLAYER_ERROR_INVALID_COMMAND si une seule des deux
+// valeurs vaut 0
+int aml_video_player_set_override_sar(video_player_ptr, unsigned int num, unsigned int den);
+
+// Déplace et redimensionne le lecteur en une seule
+// fois. x et y peuvent être négatifs pour faire
+// sortir les bords de la vidéo de l'écran
+// (compensation de l'overscan), la position n'est
+// donc jamais ramenée dans l'écran
+//
+// Renvoie <0 en cas d'erreur,
+// AMPLAYER_ERROR_INVALID_COMMAND si width ou height
+// vaut 0
+int aml_video_player_set_output_rect(video_player_ptr, int x, int y, unsigned int width, unsigned int height);
+
+#define AMPLAYER_EASING_LINEAR		0
+// lent au début
+#define AMPLAYER_EASING_EASE_IN		1
+// lent à la fin
+#define AMPLAYER_EASING_EASE_OUT	2
+#define AMPLAYER_EASING_EASE_IN_OUT	3
+
+// Comme aml_video_player_set_output_rect, mais la
+// vidéo glisse jusqu'au rectangle en duration_ms au
+// lieu d'y sauter. La fenêtre, elle, y va tout de
+// suite. easing : AMPLAYER_EASING_*. Un nouveau
+// rectangle pendant l'animation la remplace, à partir
+// de là où en est la vidéo. duration_ms == 0 revient à
+// aml_video_player_set_output_rect.
+//
+// Renvoie <0 en cas d'erreur,
+// AMPLAYER_ERROR_INVALID_COMMAND si width ou height
+// vaut 0, ou si easing est inconnu
+int aml_video_player_animate_to_rect(video_player_ptr, int x, int y, unsigned int width, unsigned int height, unsigned int duration_ms, unsigned int easing);
+
 // Active/désactive le plein écran du lecteur
 // 
 // fullscreen == 0: désactive le fullscreen
@@ -74,11 +860,279 @@ int aml_video_player_set_pos(video_player_ptr,int x, int y);
 // Renvoie <0 en cas d'erreur
 int aml_video_player_set_fullscreen(video_player_ptr, int fullscreen);
 
+// Met chaque vidéo en plein écran dès que sa première
+// image est affichée, s'il y a au plus max_screens
+// écrans (voir
+// aml_video_player_set_auto_fullscreen_screen_threshold).
+// Les écrans sont comptés avec XRandR ; sans fenêtre,
+// ou si le nombre d'écrans est inconnu, il y en a 1.
+//
+// enable == 0: désactivé (par défaut)
+// enable > 0: activé
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_set_auto_fullscreen(video_player_ptr, int enable);
+
+// Nombre d'écrans maximum pour le plein écran
+// automatique, 1 par défaut
+//
+// Renvoie <0 en cas d'erreur,
+// AMPLAYER_ERROR_INVALID_COMMAND si max_screens vaut 0
+int aml_video_player_set_auto_fullscreen_screen_threshold(video_player_ptr, unsigned int max_screens);
+
+// Récupère la résolution de l'écran dans out_w et
+// out_h. Sans fenêtre, la résolution du framebuffer
+// est utilisée.
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_get_screen_size(video_player_ptr, unsigned int* out_w, unsigned int* out_h);
+
+// Affiche le lecteur sur tous les bureaux virtuels
+//
+// enable == 0: seulement sur le bureau courant
+// enable > 0: sur tous les bureaux
+//
+// Renvoie <0 en cas d'erreur, AMPLAYER_ERROR_UNSUPPORTED
+// si le backend ne le permet pas
+int aml_video_player_set_sticky(video_player_ptr, int enable);
+
+// Cache le lecteur de la barre des tâches et du pager
+//
+// enable == 0: le lecteur y apparaît normalement
+// enable > 0: le lecteur n'y apparaît pas
+//
+// Renvoie <0 en cas d'erreur, AMPLAYER_ERROR_UNSUPPORTED
+// si le backend ne le permet pas
+int aml_video_player_set_skip_taskbar(video_player_ptr, int enable);
+
+// Temps maximum (en millisecondes) pendant lequel
+// chaque appel attend la réponse du lecteur avant de
+// renvoyer AMPLAYER_ERROR_TIMEOUT. 10000 par défaut,
+// 0 attend indéfiniment.
+// N'affecte pas aml_video_player_wait_until_end.
+//
+// Renvoie AMPLAYER_ERROR_INVALID_COMMAND si le lecteur
+// est NULL
+int aml_video_player_set_reply_timeout(video_player_ptr, unsigned int timeout_ms);
+
 // // Bloque l'appel jusqu'à ce que la vidéo en cours
 // // de lecture arrive à la fin de son flux
 // // 
+// La fin des vidéos chargées avant la vidéo en cours
+// est ignorée, même si personne ne l'a attendue.
 void aml_video_player_wait_until_end(video_player_ptr);
 
+// Récupère le plus ancien événement du lecteur dans
+// out. Attend au plus timeout_ms (indéfiniment si
+// timeout_ms < 0). Les événements de fin
+// (END_OF_STREAM, FATAL_ERROR) sont partagés avec
+// aml_video_player_wait_until_end.
+//
+// Renvoie AMPLAYER_ERROR_TIMEOUT si aucun événement
+// n'est arrivé à temps, <0 en cas d'erreur
+int aml_video_player_next_event(video_player_ptr, AmlEvent* out, int timeout_ms);
+
+// Choisit la sortie vidéo (AMPLAYER_OUTPUT_*).
+// Le mode d'affichage précédent est restauré à la
+// destruction du lecteur.
+//
+// Renvoie <0 en cas d'erreur, >0 si device est invalide
+int aml_video_player_set_output_device(video_player_ptr, unsigned int device);
+
+// Change le mode de l'affichage principal, par
+// exemple "1080p60hz", "4k2k60hz" ou "720p50hz"
+// (voir aml_video_player_get_display_modes). Le
+// mode précédent est restauré à la destruction du
+// lecteur.
+//
+// Renvoie <0 en cas d'erreur, >0 si mode est invalide
+int aml_video_player_set_display_mode_str(video_player_ptr, const char* mode);
+
+// Écrit dans buf les modes supportés par l'affichage
+// principal, un par ligne. Ne nécessite pas de
+// lecteur.
+//
+// Même convention que aml_video_player_get_sys_info,
+// mais renvoie <0 si les modes n'ont pas pu être lus
+int aml_video_player_get_display_modes(char* buf, unsigned int len);
+
+// Choisit la sortie audio ALSA, par exemple "hw:0,0"
+// (HDMI) ou "hw:1,0" (analogique) sur la plupart des
+// cartes. Par défaut, la première carte trouvée.
+// Conservée d'une vidéo à l'autre. L'audio n'est pas
+// encore décodé : ceci ne sert qu'à le préparer. Le
+// volume n'est écrit dans le mixer de la nouvelle
+// carte qu'au prochain aml_video_player_set_volume ou
+// aml_video_player_set_mute.
+//
+// Renvoie <0 en cas d'erreur, >0 si device est invalide
+int aml_video_player_set_audio_output_device(video_player_ptr, const char* device);
+
+// Volume de 0.0 à 1.0, 1.0 par défaut, écrit dans le
+// mixer de la carte de la sortie audio. Conservé d'une
+// vidéo à l'autre. Pendant une coupure du son, il ne
+// sera appliqué qu'une fois le son rétabli.
+//
+// Renvoie >0 si volume n'est pas entre 0.0 et 1.0,
+// AMPLAYER_ERROR_UNSUPPORTED si la carte n'a pas de
+// réglage de volume
+int aml_video_player_set_volume(video_player_ptr, float volume);
+
+// Volume choisi avec aml_video_player_set_volume, même
+// quand le son est coupé
+int aml_video_player_get_volume(video_player_ptr, float* out);
+
+// Coupe le son si mute != 0 (0 dans le mixer), le
+// rétablit au volume d'avant sinon. Le volume choisi
+// n'est pas modifié. Conservé d'une vidéo à l'autre.
+//
+// Mêmes erreurs que aml_video_player_set_volume
+int aml_video_player_set_mute(video_player_ptr, int mute);
+
+// Renvoie 1 si le son est coupé, 0 sinon
+int aml_video_player_is_muted(video_player_ptr);
+
+// Écrit dans buf les cartes ALSA disponibles, une par
+// ligne, sous la forme "hw:0,0" suivi d'une tabulation
+// et du nom de la carte. Ne nécessite pas de lecteur.
+//
+// Même convention que aml_video_player_get_display_modes
+int aml_video_player_get_audio_devices(char* buf, unsigned int len);
+
+// Nombre d'erreurs et d'avertissements écrits dans les
+// logs depuis le chargement de la bibliothèque, tous
+// lecteurs confondus. errors et warnings peuvent être
+// NULL. Ne nécessite pas de lecteur.
+int aml_video_player_get_log_counts(unsigned int* errors, unsigned int* warnings);
+
+// Règle l'amélioration adaptative du contraste
+// (AMPLAYER_CONTRAST_*). custom_value n'est utilisé
+// qu'avec AMPLAYER_CONTRAST_CUSTOM.
+// Dépend du matériel : n'est pas disponible sur
+// tous les SoC Amlogic.
+//
+// Renvoie <0 en cas d'erreur, >0 si mode est invalide
+int aml_video_player_set_contrast_mode(video_player_ptr, unsigned int mode, int custom_value);
+
+// Règle la balance des blancs (AMPLAYER_COLOR_TEMP_*).
+// r_gain, g_gain et b_gain sont les gains des canaux
+// rouge, vert et bleu, entre -32768 et 32767, et ne
+// sont utilisés qu'avec AMPLAYER_COLOR_TEMP_CUSTOM.
+// Dépend du matériel, comme le contraste.
+//
+// Renvoie <0 en cas d'erreur, >0 si preset ou un des
+// gains est invalide
+int aml_video_player_set_color_temperature(video_player_ptr, unsigned int preset, int r_gain, int g_gain, int b_gain);
+
+// Règle la saturation des couleurs, entre -128
+// (noir et blanc) et 127 (couleurs vives). 0 par
+// défaut. Dépend du matériel, comme le contraste.
+//
+// Renvoie <0 en cas d'erreur, >0 si saturation est
+// invalide
+int aml_video_player_set_saturation(video_player_ptr, int saturation);
+
+// Règle la teinte, entre -128 et 127. 0 par défaut.
+//
+// Renvoie <0 en cas d'erreur, >0 si hue est invalide
+int aml_video_player_set_hue(video_player_ptr, int hue);
+
+// Récupère la dernière saturation réglée dans out
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_get_saturation(video_player_ptr, int* out);
+
+// Décale les timestamps de chaque paquet de offset_ms
+// millisecondes avant de les envoyer au VPU, pour les
+// flux MPEG-TS dont la base de PTS le perturbe. 0 par
+// défaut (timestamps inchangés). Conservé d'une vidéo
+// à l'autre.
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_set_ts_offset(video_player_ptr, long offset_ms);
+
+// Récupère le décalage actuel dans out
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_get_ts_offset(video_player_ptr, long* out);
+
+// Règle la réduction de bruit temporelle du VPU,
+// entre 0 (désactivée) et 100 (maximum). Dépend du
+// matériel, comme le contraste.
+//
+// Renvoie <0 en cas d'erreur, >0 si level > 100
+int aml_video_player_set_noise_reduction(video_player_ptr, unsigned int level);
+
+// Récupère la réduction de bruit actuelle (0 à 100)
+// dans out
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_get_noise_reduction(video_player_ptr, unsigned int* out);
+
+// Renforcement de la netteté de 0 (désactivé) à 100
+// (maximum). Dépend du matériel, comme le contraste.
+//
+// Renvoie <0 en cas d'erreur, >0 si level > 100
+int aml_video_player_set_sharpness(video_player_ptr, unsigned int level);
+
+// Transparence de tout l'OSD (ce qui est dessiné sur fb0) par-dessus la
+// vidéo, de 0 (la vidéo est visible à travers) à 255 (opaque, par défaut).
+// Permet de faire apparaître une interface en fondu. L'OSD redevient opaque
+// à la destruction du player. Dépend du matériel, comme le contraste.
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_set_osd_alpha(video_player_ptr, unsigned char alpha);
+
+// Enregistre dans path la réduction de bruit, le
+// contraste, la saturation, la teinte et la netteté
+// choisis avec l'API, une ligne "clé=valeur" chacun.
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_save_video_quality(video_player_ptr, const char* path);
+
+// Applique les réglages enregistrés par
+// aml_video_player_save_video_quality. Rien n'est
+// appliqué si le fichier est invalide.
+//
+// Renvoie <0 en cas d'erreur
+int aml_video_player_load_video_quality(video_player_ptr, const char* path);
+
+// Applique la courbe de gamma sortie = entrée^(1/gamma)
+// aux trois canaux. 1.0 correspond à la table par
+// défaut du pilote, qui est restaurée à la destruction
+// du lecteur. Dépend du matériel, comme le contraste.
+//
+// Renvoie <0 en cas d'erreur, >0 si gamma <= 0
+int aml_video_player_set_gamma(video_player_ptr, float gamma);
+
+// Applique une table de gamma aux trois canaux. table
+// doit contenir exactement count = 256 valeurs sur 10
+// bits (0 à 1023).
+//
+// Renvoie <0 en cas d'erreur, >0 si la table est
+// invalide
+int aml_video_player_set_gamma_table(video_player_ptr, const unsigned short* table, unsigned int count);
+
+#define AMPLAYER_SDR_HDR_OFF			0
+#define AMPLAYER_SDR_HDR_REINHARD		1
+#define AMPLAYER_SDR_HDR_ACES			2
+
+// Convertit les vidéos SDR pour un écran HDR avec le
+// tone mapping mode (AMPLAYER_SDR_HDR_*). Il faudrait
+// le faire avant que le VPU ne décode les images, et
+// il n'y a pas de décodage logiciel : non supporté.
+//
+// Renvoie toujours AMPLAYER_ERROR_INVALID_COMMAND
+int aml_video_player_set_sdr_hdr_mode(video_player_ptr, unsigned int mode);
+
+// Indique comment la vidéo est affichée :
+// AMPLAYER_DISPLAY_PATH_FRAMEBUFFER: fenêtre
+// transparente au niveau du framebuffer (normal)
+// AMPLAYER_DISPLAY_PATH_COMPOSITOR: un compositeur
+// est actif, la vidéo peut être cachée par le bureau
+// AMPLAYER_DISPLAY_PATH_HEADLESS: pas de fenêtre
+int aml_video_player_get_display_path(video_player_ptr);
+
 // Détruit l'instance du lecteur vidéo. Utiliser
 // ce pointeur par la suite est un comportement
 // indéfini.
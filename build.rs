@@ -0,0 +1,41 @@
+// Gives the crate what aml_video_player_get_build_info tells about the build: the target, the date
+// and the git commit it was built from. Nothing here may fail the build, what can't be found is
+// "unknown".
+
+use std::env;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn git_commit() -> String {
+    Command::new("git").args(&["rev-parse", "--short", "HEAD"]).output().ok()
+        .and_then(|output| if output.status.success() { String::from_utf8(output.stdout).ok() } else { None })
+        .map(|hash| hash.trim().to_owned())
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
+/// YYYY-MM-DD (UTC), of SOURCE_DATE_EPOCH if it is set so that builds can be reproduced
+fn build_date() -> String {
+    let secs = env::var("SOURCE_DATE_EPOCH").ok().and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+    // days since 1970-01-01 to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn main() {
+    println!("cargo:rustc-env=C2PLAYER_TARGET={}", env::var("TARGET").unwrap_or_else(|_| String::from("unknown")));
+    println!("cargo:rustc-env=C2PLAYER_BUILD_DATE={}", build_date());
+    println!("cargo:rustc-env=C2PLAYER_GIT_COMMIT={}", git_commit());
+    // a checkout or a new commit changes the hash
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
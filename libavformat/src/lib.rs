@@ -1,6 +1,18 @@
-#![feature(untagged_unions)]
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 #![allow(non_upper_case_globals)]
 mod avformat;
 pub use avformat::*;
+
+/// Layouts of the major versions which can be used at runtime, whatever the version the bindings
+/// above were built for. Only their structs are meant to be used: their functions are declared
+/// with the types of their own module, hence the clashes.
+#[allow(unknown_lints, clashing_extern_declarations)]
+pub mod v56 {
+    include!("avformat-backup-56.rs");
+}
+
+#[allow(unknown_lints, clashing_extern_declarations)]
+pub mod v57 {
+    include!("avformat-backup-57.rs");
+}
@@ -1,20 +1,32 @@
 use error::*;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{TryRecvError, Sender, Receiver};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{thread, mem};
 use std::fs::{File, OpenOptions};
 use std::os::unix::io::AsRawFd;
 use std::path::Path;
 use libc::{c_int, c_uint};
-use libavformat as libav;
-use super::utils::SingleUseSender as SuSender;
+use super::utils::{SingleUseSender as SuSender, Request};
+#[cfg(target_arch = "aarch64")]
+use super::sysfs;
 
 //amcodec_sys contains all the C interface of amcodec and related
 use super::amcodec_sys::*;
 
 use super::libavhelper::PacketWrapper as LibavPacket;
+#[cfg(target_arch = "aarch64")]
+use super::libavhelper::{VideoPacket, AV_NOPTS_VALUE};
+use super::position::PositionReport;
+use super::events::PlayerEvent;
+use super::stats::{self, Stats};
+use super::config::DEFAULT_FINISHING_THRESHOLD;
+use super::audio::AudioOutput;
+use super::quality::VideoQualitySettings;
+use super::settings::{VideoSettings, SeenVersions};
+#[cfg(target_arch = "aarch64")]
+use super::settings::{VideoAxis, Animation};
 
 // This state will allow us to have a pseudo-state machine
 // It is not exactly a state machine, but it still has some very strict rules about the states it
@@ -52,6 +64,11 @@ enum State {
     /// Paused, so when playback resume we will be in "Finishing"
     /// State
     PausedFinishing,
+    /// Paused as far as anyone is concerned, but the decoder runs
+    /// until it shows the first frame fed since a seek (or a load)
+    /// done while paused, then it goes back to Paused. Otherwise
+    /// the screen would keep the frame from before the seek.
+    Stepping(Instant),
     /// The VPU is empty and no video is being buffered at the moment
     /// This means that Amcodec will very soon (next "update") reset
     /// and be in pause state
@@ -61,8 +78,326 @@ enum State {
     Stopped(bool),
 }
 
+impl State {
+    /// see Stats::state
+    fn stats_code(&self) -> usize {
+        match *self {
+            State::InitialState | State::Stopped(_) => stats::STATE_IDLE,
+            State::Playing => stats::STATE_PLAYING,
+            State::Paused | State::PausedFinishing | State::Stepping(_) => stats::STATE_PAUSED,
+            State::Finishing {..} => stats::STATE_FINISHING,
+        }
+    }
+}
+
+/// Resolution of the framebuffer, in pixels
+#[cfg(not(target_arch = "aarch64"))]
+pub fn fb_screen_size() -> Result<(u32, u32)> {
+    Ok((1920, 1080))
+}
+
+/// Resolution of the framebuffer, in pixels
+#[cfg(target_arch = "aarch64")]
+pub fn fb_screen_size() -> Result<(u32, u32)> {
+    let fb0 = OpenOptions::new().read(true).open("/dev/fb0").map_err(|e| Error::open(e, "/dev/fb0"))?;
+    let screeninfo = fb_get_vscreen_info(fb0.as_raw_fd())?;
+    Ok((screeninfo.xres, screeninfo.yres))
+}
+
+/// frequency of the timestamps returned by the VPU
+#[cfg(target_arch = "aarch64")]
+const PTS_FREQUENCY : f64 = 90000.0;
+
+/// above this (in ms), audio and video are considered out of sync
+#[cfg(target_arch = "aarch64")]
+const AV_DISCONTINUITY_THRESHOLD_MS : f32 = 500.0;
+
+/// Below this many bytes in the VPU's buffer while playing, libav is told that we are running
+/// out of data. See aml_video_player_set_underflow_threshold.
+#[cfg(target_arch = "aarch64")]
+pub const DEFAULT_UNDERFLOW_THRESHOLD : i32 = 4096;
+
+/// State::Stepping gives up and pauses anyway after this long, when no frame shows up (a corrupt
+/// stream, or not enough data to decode one)
+#[cfg(target_arch = "aarch64")]
+const STEP_TIMEOUT_MS : u64 = 1000;
+
+/// Consecutive packets are staged and written to the device together, until there are this many
+/// bytes: streams with lots of tiny NALs would otherwise cost a write and a flush for each of them
+#[cfg(target_arch = "aarch64")]
+const MAX_COALESCED_WRITE_LEN : usize = 256 * 1024;
+
+/// ... or until they have been staged for this long, so that the VPU never waits for them
+#[cfg(target_arch = "aarch64")]
+const MAX_COALESCING_TIME_MS : u64 = 5;
+
+/// A Load fails with Error::DecoderRejected when no packet was written this long after libav sent
+/// the first one, see PacketWrapper::ConfirmLoad
+#[cfg(target_arch = "aarch64")]
+const LOAD_CONFIRM_TIMEOUT_MS : u64 = 5000;
+
+/// Given as-is to the API user, see aml_video_player_get_timing_info
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AmlTimingInfo {
+    pub vpts_secs: f64,
+    pub apts_secs: f64,
+    /// (vpts - apts) in milliseconds: > 0 means the video is ahead of the audio
+    pub av_diff_ms: f32,
+    pub pcr_secs: f64,
+    /// number of times audio and video were found more than 500ms apart for this video
+    pub vpts_discontinuity: u32,
+}
+
+/// What the VPU's decoder says about the stream it is decoding, given as-is to the API user (see
+/// aml_video_player_get_vpu_state). The driver resets it when the device is opened again, so
+/// this is for the current video only.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VpuDecoderState {
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: u32,
+    /// in bits per second. Not reported by every driver version, 0 then
+    pub bit_rate: u32,
+    /// frames the decoder failed to decode
+    pub error_count: u32,
+    /// frames decoded. Not reported by every driver version, 0 then
+    pub frame_count: u64,
+}
+
+/// sysfs node holding the mode of the main display (HDMI or CVBS)
+#[cfg(target_arch = "aarch64")]
+const DISPLAY_MODE_PATH : &'static str = "/sys/class/display/mode";
+/// same for the secondary display, which only exists on some boards
+#[cfg(target_arch = "aarch64")]
+const DISPLAY2_MODE_PATH : &'static str = "/sys/class/display2/mode";
+/// modes supported by the HDMI display, one per line, the preferred one ending with a '*'
+#[cfg(target_arch = "aarch64")]
+const DISPLAY_CAP_PATH : &'static str = "/sys/class/amhdmitx/amhdmitx0/disp_cap";
+/// description of the current mode of the main display, starting with "name: <mode>". Only used
+/// when DISPLAY_CAP_PATH doesn't exist (CVBS only boards, older kernels)
+#[cfg(target_arch = "aarch64")]
+const DISPLAY_VINFO_PATH : &'static str = "/sys/class/display/vinfo";
+/// the display takes a moment to switch modes, and ignores what is written in the meantime
+#[cfg(target_arch = "aarch64")]
+const DISPLAY_MODE_SETTLE_MS : u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VideoOutput {
+    Hdmi0 = 0,
+    Hdmi1 = 1,
+    Cvbs = 2,
+}
+
+impl VideoOutput {
+    pub fn from_int(output: u32) -> Option<VideoOutput> {
+        match output {
+            0 => Some(VideoOutput::Hdmi0),
+            1 => Some(VideoOutput::Hdmi1),
+            2 => Some(VideoOutput::Cvbs),
+            _ => None,
+        }
+    }
+}
+
+/// sysfs node of the video enhancement driver (amvecm). This is hardware-specific: it doesn't
+/// exist on every Amlogic SoC, and the range of accepted values may differ between them.
+#[cfg(target_arch = "aarch64")]
+const CONTRAST_PATH : &'static str = "/sys/class/amvecm/contrast";
+
+/// Takes both the saturation and the hue at once ("saturation hue"), each in -128..127
+#[cfg(target_arch = "aarch64")]
+const SATURATION_HUE_PATH : &'static str = "/sys/class/amvecm/saturation_hue";
+
+/// White balance of the display: the gains of the red, green and blue channels, written at once
+/// ("r g b"). Same caveats as CONTRAST_PATH.
+#[cfg(target_arch = "aarch64")]
+const WHITE_BALANCE_PATH : &'static str = "/sys/class/amvecm/wb_gain";
+
+pub const MIN_SATURATION_HUE : i32 = -128;
+pub const MAX_SATURATION_HUE : i32 = 127;
+
+/// Strength of the temporal noise reduction of the deinterlacer, from 0 (off) to
+/// NOISE_REDUCTION_DRIVER_MAX. Some driver versions reset it when the decoder is opened again.
+#[cfg(target_arch = "aarch64")]
+const NOISE_REDUCTION_PATH : &'static str = "/sys/module/di/parameters/nr2_level";
+#[cfg(target_arch = "aarch64")]
+const NOISE_REDUCTION_DRIVER_MAX : u32 = 255;
+
+/// Levels given through the API go from 0 (off) to this
+pub const MAX_NOISE_REDUCTION : u8 = 100;
+
+/// Strength of the sharpness enhancement of the first super-resolution scaler, from 0 (off) to
+/// SHARPNESS_DRIVER_MAX. Same caveats as CONTRAST_PATH.
+#[cfg(target_arch = "aarch64")]
+const SHARPNESS_PATH : &'static str = "/sys/class/amvecm/sharpness0_sr1_en";
+#[cfg(target_arch = "aarch64")]
+const SHARPNESS_DRIVER_MAX : u32 = 255;
+
+/// Same as MAX_NOISE_REDUCTION
+pub const MAX_SHARPNESS : u8 = 100;
+
+/// Gamma LUT of the display, one per channel: "sgr", "sgg" or "sgb" immediately followed by
+/// GAMMA_TABLE_LEN values, each as 3 hexadecimal digits. Same caveats as CONTRAST_PATH.
+#[cfg(target_arch = "aarch64")]
+const GAMMA_PATH : &'static str = "/sys/class/amvecm/gamma";
+
+/// Blending of the whole OSD (fb0) over the video layer, from 0 (the video shows through
+/// completely) to 255 (opaque, the default). Older kernels don't have it.
+#[cfg(target_arch = "aarch64")]
+const OSD_ALPHA_PATH : &'static str = "/sys/class/graphics/fb0/osd_plane_alpha";
+
+pub const GAMMA_TABLE_LEN : usize = 256;
+/// the entries of the gamma table are 10 bits wide
+pub const MAX_GAMMA_VALUE : u16 = 1023;
+
+/// Standard power-law curve, output = input^(1/gamma). A gamma of 1.0 is the identity, which is
+/// what the driver uses by default.
+pub fn gamma_curve(gamma: f32) -> [u16; GAMMA_TABLE_LEN] {
+    let mut table = [0u16; GAMMA_TABLE_LEN];
+    for (i, value) in table.iter_mut().enumerate() {
+        let input = i as f32 / (GAMMA_TABLE_LEN - 1) as f32;
+        *value = (input.powf(1.0 / gamma) * MAX_GAMMA_VALUE as f32).round() as u16;
+    }
+    table
+}
+
+/// What is left on the video layer once a video stops: at its end, when another one is loaded, and
+/// when the player is destroyed alike
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlackoutMode {
+    /// the last frame is cleared
+    Clear,
+    /// cleared, and the video layer is disabled until the next video starts: some firmwares keep
+    /// showing the last frame despite the clear
+    Disable,
+    /// the last frame stays until the next video starts, as a poster frame
+    KeepLastFrame,
+}
+
+impl BlackoutMode {
+    pub fn from_int(mode: u32) -> Option<BlackoutMode> {
+        match mode {
+            0 => Some(BlackoutMode::Clear),
+            1 => Some(BlackoutMode::Disable),
+            2 => Some(BlackoutMode::KeepLastFrame),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContrastMode {
+    Off,
+    Low,
+    Medium,
+    High,
+    /// written as-is to the driver
+    Custom(i32),
+}
+
+impl ContrastMode {
+    pub fn from_int(mode: u32, custom_value: i32) -> Option<ContrastMode> {
+        match mode {
+            0 => Some(ContrastMode::Off),
+            1 => Some(ContrastMode::Low),
+            2 => Some(ContrastMode::Medium),
+            3 => Some(ContrastMode::High),
+            4 => Some(ContrastMode::Custom(custom_value)),
+            _ => None,
+        }
+    }
+
+    /// value expected by the amvecm driver
+    pub fn value(&self) -> i32 {
+        match *self {
+            ContrastMode::Off => 0,
+            ContrastMode::Low => 64,
+            ContrastMode::Medium => 128,
+            ContrastMode::High => 256,
+            ContrastMode::Custom(value) => value,
+        }
+    }
+}
+
+/// Offset applied to the gain of each channel, 0 leaves the channel untouched
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorTemperature {
+    Warm,
+    Neutral,
+    Cool,
+    Custom { r_gain: i16, g_gain: i16, b_gain: i16 },
+}
+
+impl ColorTemperature {
+    /// the custom gains are ignored unless preset is 3
+    pub fn from_int(preset: u32, r_gain: i16, g_gain: i16, b_gain: i16) -> Option<ColorTemperature> {
+        match preset {
+            0 => Some(ColorTemperature::Warm),
+            1 => Some(ColorTemperature::Neutral),
+            2 => Some(ColorTemperature::Cool),
+            3 => Some(ColorTemperature::Custom { r_gain: r_gain, g_gain: g_gain, b_gain: b_gain }),
+            _ => None,
+        }
+    }
+
+    /// (r, g, b) gains expected by the amvecm driver
+    pub fn gains(&self) -> (i16, i16, i16) {
+        match *self {
+            ColorTemperature::Warm => (20, 0, -20),
+            ColorTemperature::Neutral => (0, 0, 0),
+            ColorTemperature::Cool => (-20, 0, 20),
+            ColorTemperature::Custom { r_gain, g_gain, b_gain } => (r_gain, g_gain, b_gain),
+        }
+    }
+}
+
 // All the cfg(not(target_arch = "aarch64")) are dummies so that
 // it can compile for x86_64 architectures.
+#[cfg(not(target_arch = "aarch64"))]
+pub struct DisplayModeWrapper;
+
+#[cfg(not(target_arch = "aarch64"))]
+impl DisplayModeWrapper {
+    pub fn new() -> DisplayModeWrapper {
+        DisplayModeWrapper
+    }
+}
+
+/// Same idea as FbWrapper: stores the display modes as they were when the player was created, so
+/// that they can be restored if set_video_output changed them.
+#[cfg(target_arch = "aarch64")]
+pub struct DisplayModeWrapper {
+    display_mode: Option<String>,
+    display2_mode: Option<String>,
+}
+
+#[cfg(target_arch = "aarch64")]
+impl DisplayModeWrapper {
+    pub fn new() -> DisplayModeWrapper {
+        DisplayModeWrapper {
+            display_mode: sysfs::read(DISPLAY_MODE_PATH).ok(),
+            display2_mode: sysfs::read(DISPLAY2_MODE_PATH).ok(),
+        }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+impl Drop for DisplayModeWrapper {
+    fn drop(&mut self) {
+        for &(path, ref mode) in [(DISPLAY_MODE_PATH, &self.display_mode), (DISPLAY2_MODE_PATH, &self.display2_mode)].iter() {
+            if let Some(ref mode) = **mode {
+                if sysfs::read(path).ok().as_ref() != Some(mode) {
+                    if let Err(e) = sysfs::write(path, mode) {
+                        error!("unable to restore the display mode: {}", e.display());
+                    }
+                }
+            }
+        }
+    }
+}
+
 #[cfg(not(target_arch = "aarch64"))]
 pub struct FbWrapper;
 
@@ -83,7 +418,64 @@ pub struct Amcodec {
     hevc_device: File,
     control_device: File,
     state: State,
-    pub status_sender: Sender<EndReason>,
+    vpts_discontinuity: u32,
+    /// in bytes, see DEFAULT_UNDERFLOW_THRESHOLD
+    pub underflow_threshold: i32,
+    /// true while the buffer is below underflow_threshold, so that libav is only told once
+    underrun: bool,
+    /// interrupted writes since the main loop last added them to Stats::write_retries
+    write_retries: usize,
+    /// packets not written to the device yet, see MAX_COALESCED_WRITE_LEN
+    staging: Vec<u8>,
+    /// length of every packet in staging, in order: they are written one by one if writing them
+    /// all at once failed
+    staged_lens: Vec<usize>,
+    /// packets and bytes which reached the device since the main loop last added them to Stats
+    packets_written: usize,
+    bytes_written: usize,
+    /// what was last written to SATURATION_HUE_PATH, both are needed at every write
+    pub saturation: i32,
+    pub hue: i32,
+    /// None until set through the API: the driver's default is left alone
+    pub noise_reduction: Option<u8>,
+    /// same as noise_reduction
+    pub contrast_mode: Option<ContrastMode>,
+    pub sharpness: Option<u8>,
+    /// None until set through the API. The default table is written back when this is dropped,
+    /// unless reopen took it first.
+    gamma_table: Option<[u16; GAMMA_TABLE_LEN]>,
+    /// added to the pts of every packet, see set_ts_offset
+    pub ts_offset_ms: i64,
+    /// see PlayerConfig::finishing_threshold
+    pub finishing_threshold: u32,
+    /// stop() was called while paused: the device opened next starts paused as well, see reopen
+    paused_when_stopped: bool,
+    /// the first packet written starts State::Stepping
+    step_on_next_packet: bool,
+    /// the frame shown after stepping is the keyframe before the position seeked to, which the
+    /// main thread would report instead of the position asked for. Until the next play, the
+    /// vpts isn't reported.
+    hold_position: bool,
+    /// the video layer isn't cleared at the end of the video, its last frame stays until the
+    /// next one is shown
+    pub freeze_on_eof: bool,
+    /// see PlayerConfig::blackout_mode
+    pub blackout_mode: BlackoutMode,
+    /// the video layer was disabled by BlackoutMode::Disable, the next video enables it again.
+    /// It is enabled again when this is dropped too, unless reopen took it first.
+    blanked: bool,
+    /// what SetVideoVisible asked for, applied once the video layer isn't blanked anymore
+    video_visible: bool,
+    /// None until set through the API. The OSD is made opaque again when this is dropped, unless
+    /// reopen took it first.
+    osd_alpha: Option<u8>,
+    /// the reply of the Load being confirmed, see PacketWrapper::ConfirmLoad. Once libav sent a
+    /// packet which couldn't be written, also when that was and why.
+    pending_load: Option<(SuSender<Reply>, Option<(Instant, String)>)>,
+    /// why the extra data of the video was refused, told to the ConfirmLoad which follows it
+    extra_data_error: Option<String>,
+    /// generation of the Seek whose first frame isn't shown yet, see PacketWrapper::Seeked
+    pending_seek: Option<u64>,
 }
 
 /// This structure holds the info of the framebuffer before it went transparent:
@@ -96,31 +488,22 @@ impl FbWrapper {
         let stored_screeninfo;
         match fb0 {
             Ok(fb0) => {
-                unsafe {
-                    let mut screeninfo : FbVarScreeninfo = mem::uninitialized();
-                    let ret = fbio_get_vscreen_info(fb0.as_raw_fd(), &mut screeninfo as *mut _ as *mut u8);
-                    if ret < 0 {
-                        bail!(ErrorKind::Ioctl("fbio_get_vscreen_info"));
-                    }
-                    stored_screeninfo = screeninfo.clone();
-                    screeninfo.red.offset = 16;
-                    screeninfo.red.length = 8;
-                    screeninfo.green.offset = 8;
-                    screeninfo.green.length = 8;
-                    screeninfo.blue.offset = 0;
-                    screeninfo.blue.length = 8;
-                    screeninfo.transp.offset = 24;
-                    screeninfo.transp.length = 8;
-                    screeninfo.nonstd = 1;
-                    screeninfo.activate = 0; // see FB_ACTIVE_NOW
-                    let ret = fbio_set_vscreen_info(fb0.as_raw_fd(),&mut screeninfo as *mut _ as *mut u8);
-                    if ret < 0 {
-                        bail!(ErrorKind::Ioctl("fbio_set_vscreen_info"));
-                    }
-                }
+                let mut screeninfo = fb_get_vscreen_info(fb0.as_raw_fd())?;
+                stored_screeninfo = screeninfo.clone();
+                screeninfo.red.offset = 16;
+                screeninfo.red.length = 8;
+                screeninfo.green.offset = 8;
+                screeninfo.green.length = 8;
+                screeninfo.blue.offset = 0;
+                screeninfo.blue.length = 8;
+                screeninfo.transp.offset = 24;
+                screeninfo.transp.length = 8;
+                screeninfo.nonstd = 1;
+                screeninfo.activate = 0; // see FB_ACTIVE_NOW
+                fb_set_vscreen_info(fb0.as_raw_fd(), &screeninfo)?;
             },
             Err(io_error) => {
-                return Err(io_error).chain_err(|| ErrorKind::FbPermission);
+                return Err(Error::open(io_error, "/dev/fb0"));
             }
         }
         Ok(FbWrapper {
@@ -132,19 +515,47 @@ impl FbWrapper {
 #[cfg(not(target_arch = "aarch64"))]
 pub struct Amcodec {
     state: State,
+    /// frames left to show, out of `frames`, see DUMMY_FRAMES_ENV
     count: u32,
-    sender: Sender<EndReason>,
+    frames: u32,
+    pub saturation: i32,
+    pub hue: i32,
+    pub noise_reduction: Option<u8>,
+    pub contrast_mode: Option<ContrastMode>,
+    pub sharpness: Option<u8>,
+    gamma_table: Option<[u16; GAMMA_TABLE_LEN]>,
+    pub ts_offset_ms: i64,
+    pub finishing_threshold: u32,
+    pub blackout_mode: BlackoutMode,
+    osd_alpha: Option<u8>,
+    /// see DUMMY_DUMP_ENV
+    dump: Option<PacketDump>,
+    /// see PacketWrapper::Seeked
+    pending_seek: Option<u64>,
 }
 
 /// A dummy for x86_64 and other architectures. Doesn't play a video, but "simulates" one for tests
 /// and other stuff.
 #[cfg(not(target_arch = "aarch64"))]
 impl Amcodec {
-    pub fn new(status_sender: Sender<EndReason>) -> Result<Amcodec> {
+    pub fn new() -> Result<Amcodec> {
+        let frames = dummy_frames();
         Ok(Amcodec {
-            sender: status_sender,
             state: State::InitialState,
-            count: 1000,
+            count: frames,
+            frames: frames,
+            saturation: 0,
+            hue: 0,
+            noise_reduction: None,
+            contrast_mode: None,
+            sharpness: None,
+            gamma_table: None,
+            ts_offset_ms: 0,
+            finishing_threshold: DEFAULT_FINISHING_THRESHOLD,
+            blackout_mode: BlackoutMode::Clear,
+            osd_alpha: None,
+            dump: PacketDump::from_env()?,
+            pending_seek: None,
         })
     }
 
@@ -152,16 +563,18 @@ impl Amcodec {
         Ok((0, 0))
     }
 
-    pub fn update(&mut self) {
+    /// Returns true when the video just ended
+    pub fn update(&mut self) -> bool {
         if self.state == State::Playing {
             if self.count == 0 {
-                let _r = self.sender.send(EndReason::EOF);
                 self.state = State::InitialState;
-                self.count = 1000;
+                self.count = self.frames;
+                return true;
             } else {
                 self.count -= 1;
             }
         }
+        false
     }
 
     pub fn play(&mut self) {
@@ -171,28 +584,408 @@ impl Amcodec {
     pub fn pause(&mut self) {
         self.state = State::Paused;
     }
+
+    /// After a Stop (a Load, a Seek, ...) the VPU counts from 0 again, so the vpts and the end of
+    /// the video start over as well
+    pub fn restart(&mut self) {
+        self.count = self.frames;
+    }
+
+    /// the dummy shows nothing, there is no frame to step to
+    pub fn holds_position(&self) -> bool {
+        false
+    }
+
+    pub fn set_video_output(&mut self, output: VideoOutput) -> Result<()> {
+        debug!("(dummy) switching to {:?}", output);
+        Ok(())
+    }
+
+    pub fn set_display_mode(&mut self, mode: &str) -> Result<()> {
+        debug!("(dummy) switching the display to {}", mode);
+        Ok(())
+    }
+
+    pub fn get_available_display_modes() -> Result<Vec<String>> {
+        Ok(vec![String::from("1080p60hz"), String::from("720p60hz")])
+    }
+
+    pub fn set_contrast_mode(&mut self, mode: ContrastMode) -> Result<()> {
+        debug!("(dummy) contrast set to {}", mode.value());
+        self.contrast_mode = Some(mode);
+        Ok(())
+    }
+
+    pub fn set_color_temperature(&mut self, temp: ColorTemperature) -> Result<()> {
+        debug!("(dummy) white balance gains set to {:?}", temp.gains());
+        Ok(())
+    }
+
+    pub fn set_saturation_hue(&mut self, saturation: i32, hue: i32) -> Result<()> {
+        debug!("(dummy) saturation set to {}, hue set to {}", saturation, hue);
+        self.saturation = saturation;
+        self.hue = hue;
+        Ok(())
+    }
+
+    pub fn set_noise_reduction(&mut self, level: u8) -> Result<()> {
+        debug!("(dummy) noise reduction set to {}", level);
+        self.noise_reduction = Some(level);
+        Ok(())
+    }
+
+    pub fn noise_reduction(&self) -> Result<u8> {
+        Ok(self.noise_reduction.unwrap_or(0))
+    }
+
+    pub fn set_sharpness(&mut self, level: u8) -> Result<()> {
+        debug!("(dummy) sharpness set to {}", level);
+        self.sharpness = Some(level);
+        Ok(())
+    }
+
+    pub fn set_osd_alpha(&mut self, alpha: u8) -> Result<()> {
+        debug!("(dummy) OSD alpha set to {}", alpha);
+        self.osd_alpha = Some(alpha);
+        Ok(())
+    }
+
+    pub fn set_gamma_table(&mut self, table: &[u16; GAMMA_TABLE_LEN]) -> Result<()> {
+        debug!("(dummy) gamma table set, from {} to {}", table[0], table[GAMMA_TABLE_LEN - 1]);
+        self.gamma_table = Some(*table);
+        Ok(())
+    }
+
+    pub fn set_gamma_value(&mut self, gamma: f32) -> Result<()> {
+        self.set_gamma_table(&gamma_curve(gamma))
+    }
+
+    pub fn set_ts_offset(&mut self, offset_ms: i64) -> Result<()> {
+        debug!("(dummy) pts offset set to {}ms", offset_ms);
+        self.ts_offset_ms = offset_ms;
+        Ok(())
+    }
+
+    /// pretends a 1080p video is being decoded at 60fps
+    pub fn get_decoder_state(&self) -> Result<VpuDecoderState> {
+        if self.state == State::InitialState {
+            return Ok(VpuDecoderState::default());
+        }
+        Ok(VpuDecoderState {
+            width: 1920,
+            height: 1080,
+            frame_rate: 60,
+            bit_rate: 8000000,
+            error_count: 0,
+            frame_count: (self.frames - self.count) as u64,
+        })
+    }
+
+    /// pretends the video is playing at 60fps
+    pub fn timing_info(&mut self) -> Result<Option<AmlTimingInfo>> {
+        if self.state == State::InitialState {
+            return Ok(None);
+        }
+        let pts_secs = (self.frames - self.count) as f64 / 60.0;
+        Ok(Some(AmlTimingInfo {
+            vpts_secs: pts_secs,
+            apts_secs: pts_secs,
+            av_diff_ms: 0.0,
+            pcr_secs: pts_secs,
+            vpts_discontinuity: 0,
+        }))
+    }
+}
+
+// this s ia key step for the video processing of the VPU, if we don't do this step the VPU
+// only outputs pitch black
+//
+// my guess is that 0001 (on 4 bytes) acts as a "delimiter" of some kind for the VPU, but we
+// receive the length of the frame from libavformat, so we just need to override the length of
+// the frame by 0001.
+//
+// The dummy does it as well, so that its dump is what the VPU would have been given.
+//
+// The lengths come from the file: a packet whose lengths don't add up to its size is refused
+// (and skipped by the caller) instead of being written. Packets already starting with 0001
+// (MPEG-TS) are left as they are.
+fn process_nal_packets(data: &mut [u8]) -> Result<()> {
+    if data.starts_with(&[0, 0, 0, 1]) {
+        return Ok(());
+    }
+    let mut offset : usize = 0;
+    while offset < data.len() {
+        let left = data.len() - offset;
+        if left < 4 {
+            return Err(Error::Malformed(format!("{} bytes after the last NAL unit of a packet", left)));
+        }
+        let nal_len = ((data[offset] as usize) << 24) | ((data[offset + 1] as usize) << 16)
+            | ((data[offset + 2] as usize) << 8) | data[offset + 3] as usize;
+        if nal_len > left - 4 {
+            return Err(Error::Malformed(format!("NAL unit of {} bytes with {} bytes left in its packet", nal_len, left - 4)));
+        }
+        data[offset..offset + 4].copy_from_slice(&[0, 0, 0, 1]);
+        offset += nal_len + 4;
+    }
+    Ok(())
+}
+
+/// What the dummy would have written to /dev/amstream_hevc is appended to the file named by this
+/// variable, if it is set, see PacketDump
+#[cfg(not(target_arch = "aarch64"))]
+pub const DUMMY_DUMP_ENV : &'static str = "AMPLAYER_DUMMY_DUMP";
+
+/// The dummy shows this many frames of every video, at about 60 fps, if it is set: 1000 otherwise.
+/// Tests going through the end of many videos set it lower.
+#[cfg(not(target_arch = "aarch64"))]
+pub const DUMMY_FRAMES_ENV : &'static str = "AMPLAYER_DUMMY_FRAMES";
+
+#[cfg(not(target_arch = "aarch64"))]
+fn dummy_frames() -> u32 {
+    match ::std::env::var(DUMMY_FRAMES_ENV).ok().and_then(|frames| frames.parse().ok()) {
+        Some(frames) if frames > 0 => frames,
+        _ => 1000,
+    }
+}
+
+/// true while a test uses the dummy: only one does at a time, as on the real VPU, and the
+/// environment variables it reads (DUMMY_FRAMES_ENV, ...) are process-wide
+#[cfg(all(test, not(target_arch = "aarch64")))]
+pub static DUMMY_IN_USE : AtomicBool = AtomicBool::new(false);
+
+/// Every record is a tag of 4 bytes ("XDAT" for ExtraData, "PKT " for a packet, "EOF ", "STOP"),
+/// the generation as a big endian u32, the length of the payload as a big endian u32, then the
+/// payload: the extra data, or the packet with its NAL lengths rewritten. The generation goes up
+/// with every Stop (a Load, a Seek, ...), so that a packet of an older one can be told apart.
+#[cfg(not(target_arch = "aarch64"))]
+struct PacketDump {
+    path: String,
+    file: File,
+    generation: u32,
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+impl PacketDump {
+    /// None if DUMMY_DUMP_ENV isn't set
+    fn from_env() -> Result<Option<PacketDump>> {
+        let path = match ::std::env::var(DUMMY_DUMP_ENV) {
+            Ok(ref path) if !path.is_empty() => path.clone(),
+            _ => return Ok(None),
+        };
+        let file = OpenOptions::new().append(true).create(true).open(&path)
+            .map_err(|e| Error::Io { what: format!("failed to open {}", path), source: e })?;
+        info!("(dummy) dumping the packets to {}", path);
+        Ok(Some(PacketDump {
+            path: path,
+            file: file,
+            generation: 0,
+        }))
+    }
+
+    fn record(&mut self, tag: &[u8; 4], payload: &[u8]) -> Result<()> {
+        use std::io::Write;
+        let mut header = [0u8; 12];
+        header[..4].copy_from_slice(tag);
+        for (i, value) in [self.generation, payload.len() as u32].iter().enumerate() {
+            for byte in 0..4 {
+                header[4 + i * 4 + byte] = (value >> (24 - byte * 8)) as u8;
+            }
+        }
+        let path = &self.path;
+        let file = &mut self.file;
+        file.write_all(&header)
+            .and_then(|_| file.write_all(payload))
+            .map_err(|e| Error::Io { what: format!("failed to write to {}", path), source: e })
+    }
+
+    fn process_packet(&mut self, packet: &mut LibavPacket) -> Result<()> {
+        match *packet {
+            LibavPacket::ExtraData(ref extra_data) => self.record(b"XDAT", extra_data),
+            LibavPacket::Packet(ref mut packet) => {
+                // the VPU wouldn't have been given it either
+                if let Err(e) = process_nal_packets(&mut packet.data) {
+                    warn!("(dummy) packet skipped: {}", e.display());
+                    return Ok(());
+                }
+                self.record(b"PKT ", &packet.data)
+            },
+            LibavPacket::EOF => self.record(b"EOF ", &[]),
+            LibavPacket::Stop => {
+                self.generation += 1;
+                self.record(b"STOP", &[])
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// how often the vpts is sent to the main thread
+const POSITION_REPORT_INTERVAL_MS : u64 = 250;
+
+/// Sends the vpts to the main thread if it hasn't been done for POSITION_REPORT_INTERVAL_MS. Nothing
+/// is sent while no video is playing.
+///
+/// This is also where the first frame of a video is detected (the vpts only moves once a frame has
+/// been shown), so FirstFrameDisplayed may come up to POSITION_REPORT_INTERVAL_MS late. The same
+/// goes for the first frame after a seek, since the VPU counts from 0 again.
+fn report_position(amcodec: &mut Amcodec,
+                   position_sender: &Sender<PositionReport>,
+                   status_sender: &Sender<PlayerEvent>,
+                   last_report: &mut Instant,
+                   first_frame_shown: &mut bool) {
+    if last_report.elapsed() < Duration::from_millis(POSITION_REPORT_INTERVAL_MS) {
+        return;
+    }
+    *last_report = Instant::now();
+    match amcodec.timing_info() {
+        Ok(Some(info)) => {
+            if !*first_frame_shown && info.vpts_secs > 0.0 {
+                *first_frame_shown = true;
+                // before the event, so that the main thread already knows it
+                match amcodec.get_decoder_state() {
+                    Ok(ref state) if state.width > 0 && state.height > 0 => {
+                        let _r = position_sender.send(PositionReport::VideoSize(state.width, state.height));
+                    },
+                    Ok(_) => {},
+                    Err(e) => warn!("failed to read the size of the video: {}", e.display()),
+                }
+                let _r = status_sender.send(PlayerEvent::FirstFrameDisplayed);
+            }
+            if !amcodec.holds_position() {
+                let _r = position_sender.send(PositionReport::Presented(info.vpts_secs));
+            }
+            // after Presented, so that the main thread already has the new position
+            if info.vpts_secs > 0.0 {
+                if let Some(generation) = amcodec.pending_seek.take() {
+                    let _r = position_sender.send(PositionReport::SeekShown(generation));
+                }
+            }
+        },
+        // nothing is playing, the next video will have a first frame of its own
+        Ok(None) => *first_frame_shown = false,
+        Err(_) => {},
+    };
 }
 
 /// dummy version of the main loop
 #[cfg(not(target_arch = "aarch64"))]
 pub fn main_loop(mut amcodec: Amcodec,
-                   rx: Receiver<(Message, SuSender<FfiErrorCode>)>,
+                   rx: Receiver<Request<Message, Reply>>,
                    packet_channel: Receiver<LibavPacket>,
-                   status_sender: Sender<EndReason>,
+                   feedback_channel: Sender<LibavPacket>,
+                   status_sender: Sender<PlayerEvent>,
+                   position_sender: Sender<PositionReport>,
+                   stats: Arc<Stats>,
+                   settings: Arc<VideoSettings>,
+                   audio: Arc<Mutex<AudioOutput>>,
                    keep_running: Arc<AtomicBool>) {
+    let mut last_position_report = Instant::now();
+    let mut first_frame_shown = false;
+    let mut seen = SeenVersions::default();
     while keep_running.load(Ordering::SeqCst) == true {
+        if let Some(axis) = settings.axis.changed(&mut seen.axis) {
+            debug!("(dummy) video axis set to {:?}", axis);
+        }
         match rx.try_recv() {
-            Ok((Message::Fullscreen, tx)) => {
+            Ok(Request { query: Message::Play, reply: tx, .. }) => {
+                amcodec.play();
                 tx.send(FfiErrorCode::None);
-            }
-            Ok((Message::Resize(x, y, width, height), tx)) => {
+            },
+            Ok(Request { query: Message::Pause, reply: tx, .. }) => {
+                amcodec.pause();
                 tx.send(FfiErrorCode::None);
             },
-            Ok((Message::Play, tx)) => {
-                amcodec.play();
+            Ok(Request { query: Message::SetVideoVisible(_), reply: tx, .. }) => {
+                tx.send(FfiErrorCode::None);
+            },
+            Ok(Request { query: Message::GetTimingInfo(info_tx), reply: tx, .. }) => {
+                match amcodec.timing_info() {
+                    Ok(Some(info)) => {
+                        info_tx.send(info);
+                        tx.send(FfiErrorCode::None);
+                    },
+                    Ok(None) => tx.send(FfiErrorCode::InvalidCommand),
+                    Err(e) => tx.send(e),
+                };
+            },
+            Ok(Request { query: Message::GetDecoderState(state_tx), reply: tx, .. }) => {
+                tx.respond_value_with(Some(state_tx), "reading the decoder's state", || amcodec.get_decoder_state());
+            },
+            Ok(Request { query: Message::SetVideoOutput(output), reply: tx, .. }) => {
+                tx.respond_with("switching the video output", || amcodec.set_video_output(output));
+            },
+            Ok(Request { query: Message::SetDisplayMode(mode), reply: tx, .. }) => {
+                tx.respond_with("setting the display mode", || amcodec.set_display_mode(&mode));
+            },
+            Ok(Request { query: Message::SetUnderflowThreshold(_), reply: tx, .. }) => {
+                tx.send(FfiErrorCode::None);
+            },
+            Ok(Request { query: Message::SetFreezeOnEof(enable), reply: tx, .. }) => {
+                debug!("(dummy) freeze on the last frame: {}", enable);
+                tx.send(FfiErrorCode::None);
+            },
+            Ok(Request { query: Message::SetContrastMode(mode), reply: tx, .. }) => {
+                tx.respond_with("setting the contrast mode", || amcodec.set_contrast_mode(mode));
+            },
+            Ok(Request { query: Message::SetColorTemperature(temp), reply: tx, .. }) => {
+                tx.respond_with("setting the color temperature", || amcodec.set_color_temperature(temp));
+            },
+            Ok(Request { query: Message::SetSaturation(saturation), reply: tx, .. }) => {
+                let hue = amcodec.hue;
+                tx.respond_with("setting the saturation", || amcodec.set_saturation_hue(saturation, hue));
+            },
+            Ok(Request { query: Message::SetHue(hue), reply: tx, .. }) => {
+                let saturation = amcodec.saturation;
+                tx.respond_with("setting the hue", || amcodec.set_saturation_hue(saturation, hue));
+            },
+            Ok(Request { query: Message::GetSaturation(saturation_tx), reply: tx, .. }) => {
+                saturation_tx.send(amcodec.saturation);
+                tx.send(FfiErrorCode::None);
+            },
+            Ok(Request { query: Message::SetTsOffset(offset_ms), reply: tx, .. }) => {
+                tx.respond_with("setting the ts offset", || amcodec.set_ts_offset(offset_ms));
+            },
+            Ok(Request { query: Message::GetTsOffset(offset_tx), reply: tx, .. }) => {
+                offset_tx.send(amcodec.ts_offset_ms);
                 tx.send(FfiErrorCode::None);
             },
-            Ok((Message::Pause, tx)) => {
+            Ok(Request { query: Message::SetVolume(volume), reply: tx, .. }) => {
+                let mut audio = audio.lock().unwrap_or_else(|e| e.into_inner());
+                tx.respond_with("setting the volume", || audio.set_volume(volume));
+            },
+            Ok(Request { query: Message::SetMute(muted), reply: tx, .. }) => {
+                let mut audio = audio.lock().unwrap_or_else(|e| e.into_inner());
+                tx.respond_with(if muted { "muting the audio" } else { "unmuting the audio" }, || audio.set_muted(muted));
+            },
+            Ok(Request { query: Message::SetNoiseReduction(level), reply: tx, .. }) => {
+                tx.respond_with("setting the noise reduction", || amcodec.set_noise_reduction(level));
+            },
+            Ok(Request { query: Message::GetNoiseReduction(level_tx), reply: tx, .. }) => {
+                tx.respond_value_with(Some(level_tx), "reading the noise reduction", || amcodec.noise_reduction());
+            },
+            Ok(Request { query: Message::SetSharpness(level), reply: tx, .. }) => {
+                tx.respond_with("setting the sharpness", || amcodec.set_sharpness(level));
+            },
+            Ok(Request { query: Message::SetOsdAlpha(alpha), reply: tx, .. }) => {
+                tx.respond_with("setting the OSD alpha", || amcodec.set_osd_alpha(alpha));
+            },
+            Ok(Request { query: Message::GetVideoQuality(settings_tx), reply: tx, .. }) => {
+                settings_tx.send(amcodec.video_quality());
+                tx.send(FfiErrorCode::None);
+            },
+            Ok(Request { query: Message::SetVideoQuality(settings), reply: tx, .. }) => {
+                tx.respond_with("applying the video quality settings", || amcodec.set_video_quality(&settings));
+            },
+            Ok(Request { query: Message::SetGamma(gamma), reply: tx, .. }) => {
+                tx.respond_with("setting the gamma", || amcodec.set_gamma_value(gamma));
+            },
+            Ok(Request { query: Message::SetGammaTable(values), reply: tx, .. }) => {
+                let mut table = [0u16; GAMMA_TABLE_LEN];
+                table.copy_from_slice(&values);
+                tx.respond_with("setting the gamma table", || amcodec.set_gamma_table(&table));
+            },
+            Ok(Request { query: Message::Shutdown, reply: tx, .. }) => {
+                while let Ok(_) = packet_channel.try_recv() {}
                 amcodec.pause();
                 tx.send(FfiErrorCode::None);
             },
@@ -201,10 +994,42 @@ pub fn main_loop(mut amcodec: Amcodec,
             },
             Err(_) => {}
         };
-        amcodec.update();
-        thread::sleep(Duration::from_millis(15));
+        // nothing is decoded, but the packets are taken out of the channel as they would be
+        while let Ok(mut p) = packet_channel.try_recv() {
+            let failed = match amcodec.dump {
+                Some(ref mut dump) => dump.process_packet(&mut p).err(),
+                None => None,
+            };
+            if let Some(e) = failed {
+                error!("(dummy) packets aren't dumped anymore: {}", e.display());
+                amcodec.dump = None;
+            }
+            match p {
+                LibavPacket::Packet(packet) => {
+                    trace!("(dummy) writing the packet at {:.3}s", packet.pts as f64 * packet.time_base);
+                    stats::add(&stats.packets_dequeued, 1);
+                    stats::add(&stats.packets_written, 1);
+                    stats::add(&stats.bytes_written, packet.data.len());
+                },
+                // there is no decoder to refuse anything
+                LibavPacket::ConfirmLoad(tx) => tx.send(FfiErrorCode::None),
+                LibavPacket::Seeked(generation) => amcodec.pending_seek = Some(generation),
+                LibavPacket::Stop => {
+                    amcodec.restart();
+                    // see the same in the real main_loop
+                    first_frame_shown = false;
+                },
+                _ => {},
+            }
+        }
+        report_position(&mut amcodec, &position_sender, &status_sender, &mut last_position_report, &mut first_frame_shown);
+        if amcodec.update() {
+            let _r = status_sender.send(PlayerEvent::EndOfStream);
+        }
+        stats.state.store(amcodec.state.stats_code(), Ordering::Relaxed);
+        stats::sleep(&stats.amcodec_idle_us, Duration::from_millis(15));
     }
-    println!("amcodec_thread: shutting down ...");
+    debug!("shutting down ...");
 }
 
 /// the main loop for the amcodec thread
@@ -213,7 +1038,9 @@ pub fn main_loop(mut amcodec: Amcodec,
 /// error-reporting (such as the driver does not exist)
 /// * rx: various messages such as Play, Pause, Resize, ... are sent to this channel
 /// this channel also includes a way to answers those requests via a SingleUsageChannel
+/// * feedback_channel: hints sent back to the libav thread, such as Underrun
 /// * status_sender: allows us to notify the API's user when an EOF has happened
+/// * position_sender: the vpts is sent there periodically, see report_position
 /// * keep_running: if this becomes false then this thread must abort as soon as possible
 #[cfg(target_arch = "aarch64")]
 impl Amcodec {
@@ -223,141 +1050,291 @@ impl Amcodec {
     /// If we get this error, wait a little bit and try once more.
     /// After a number of tries, we can assume the device is dead and give up
     fn try_open<P: AsRef<Path>>(open_options: &OpenOptions, path: P, tries: u32) -> Result<File> {
+        let display_path = path.as_ref().display().to_string();
         if tries == 0 {
-            bail!("{} is busy (os error 16), stopping after multiple tries", path.as_ref().display());
+            return Err(Error::DeviceBusy { path: display_path, source: None });
         };
         match open_options.open(path.as_ref()) {
             Err(ref e) if e.raw_os_error() == Some(16) => {
                 thread::sleep(Duration::from_millis(50));
                 Self::try_open(open_options, path.as_ref(), tries - 1)
             },
-            o => o.chain_err(|| format!("failed to open {}", path.as_ref().display()))
+            o => o.map_err(|e| Error::open(e, &display_path)),
         }
     }
 
     /// This Amcodec creationis kind of cheating: we already know in advance that we only support
     /// HEVC, hence we can make it so HEVC is always enabled. 
-    pub fn new(status_sender: Sender<EndReason>) -> Result<Amcodec> {
-        let hevc_device = Self::try_open(OpenOptions::new().write(true).read(false), "/dev/amstream_hevc", 100)
-            .chain_err(|| ErrorKind::Amcodec)?;
-        let control_device = Self::try_open(OpenOptions::new().write(true).read(true), "/dev/amvideo", 100)
-            .chain_err(|| ErrorKind::Amcodec)?;
-        unsafe {
-            let mut aml_ioctl_parm : am_ioctl_parm = mem::zeroed();
-            let mut am_sysinfo : dec_sysinfo_t = mem::zeroed();
-            aml_ioctl_parm.union.data_vformat = vformat_t::VFORMAT_HEVC;
-            aml_ioctl_parm.cmd = AMSTREAM_SET_VFORMAT;
-            am_sysinfo.format = vdec_type_t::VIDEO_DEC_FORMAT_HEVC as c_uint;
-            let r = amstream_ioc_set(hevc_device.as_raw_fd(), &aml_ioctl_parm as *const _);
-            if r < 0 {
-                bail!(ErrorKind::Ioctl("amstream_ioc_set"));
-            }
-            // see amstream_ioc_sysinfo declaration in amcodec_sys for why we need to cast to a c_int
-            let r = amstream_ioc_sysinfo(hevc_device.as_raw_fd(), &am_sysinfo as *const _ as *const c_int);
-            if r < 0 {
-                bail!(ErrorKind::Ioctl("amstream_ioc_sysinfo"));
-            }
-        }
+    pub fn new() -> Result<Amcodec> {
+        let hevc_device = Self::try_open(OpenOptions::new().write(true).read(false), "/dev/amstream_hevc", 100)?;
+        let control_device = Self::try_open(OpenOptions::new().write(true).read(true), "/dev/amvideo", 100)?;
+        let mut am_sysinfo : dec_sysinfo_t = unsafe { mem::zeroed() };
+        am_sysinfo.format = vdec_type_t::VIDEO_DEC_FORMAT_HEVC as c_uint;
+        amstream_set_vformat(hevc_device.as_raw_fd(), vformat_t::VFORMAT_HEVC)?;
+        amstream_set_sysinfo(hevc_device.as_raw_fd(), &am_sysinfo)?;
         let amcodec = Amcodec {
             hevc_device: hevc_device,
             control_device: control_device,
             state: State::InitialState,
-            status_sender: status_sender,
+            vpts_discontinuity: 0,
+            underflow_threshold: DEFAULT_UNDERFLOW_THRESHOLD,
+            underrun: false,
+            write_retries: 0,
+            packets_written: 0,
+            bytes_written: 0,
+            staged_lens: Vec::new(),
+            staging: Vec::with_capacity(MAX_COALESCED_WRITE_LEN),
+            saturation: 0,
+            hue: 0,
+            noise_reduction: None,
+            contrast_mode: None,
+            sharpness: None,
+            gamma_table: None,
+            ts_offset_ms: 0,
+            finishing_threshold: DEFAULT_FINISHING_THRESHOLD,
+            paused_when_stopped: false,
+            step_on_next_packet: false,
+            hold_position: false,
+            freeze_on_eof: false,
+            blackout_mode: BlackoutMode::Clear,
+            blanked: false,
+            video_visible: true,
+            osd_alpha: None,
+            pending_load: None,
+            extra_data_error: None,
+            pending_seek: None,
         };
         Ok(amcodec)
     }
 
-    pub fn set_fullscreen(&mut self) -> Result<()> {
-        let fb0 = OpenOptions::new().read(true).open("/dev/fb0");
-        match fb0 {
-            Ok(fb0) => {
-                unsafe {
-                    let mut screeninfo : FbVarScreeninfo = mem::uninitialized();
-                    let ret = fbio_get_vscreen_info(fb0.as_raw_fd(), &mut screeninfo as *mut _ as *mut u8);
-                    if ret < 0 {
-                        bail!(ErrorKind::Ioctl("get_vscreeninfo"));
-                    }
-                    self.set_video_axis((0, 0, screeninfo.width as u16, screeninfo.height as u16))
-                }
-            },
-            e => e.map(|_| ()).chain_err(|| ErrorKind::FbPermission)
-        }
+    pub fn set_fullscreen(&mut self) -> Result<()> {
+        let fb0 = OpenOptions::new().read(true).open("/dev/fb0");
+        match fb0 {
+            Ok(fb0) => {
+                let screeninfo = fb_get_vscreen_info(fb0.as_raw_fd())?;
+                self.set_video_axis((0, 0, screeninfo.width as u32, screeninfo.height as u32))
+            },
+            Err(e) => Err(Error::open(e, "/dev/fb0")),
+        }
+    }
+
+    /// (x, y, width, height). x and y may be negative, to push the edges of the video out of the
+    /// screen (overscan compensation)
+    pub fn set_video_axis(&mut self, rect: (i32, i32, u32, u32)) -> Result<()> {
+        amstream_set_video_axis(self.control_device.as_raw_fd(), &video_axis_edges(rect))
+    }
+
+    pub fn play(&mut self) -> Result<()> {
+        let new_state = match self.state {
+            State::PausedFinishing => State::Finishing {
+                prev_data_len: 0,
+                same_data_len_count: 0,
+            },
+            _ => State::Playing,
+        };
+        self.step_on_next_packet = false;
+        self.hold_position = false;
+        self.set_state(new_state)
+    }
+
+    pub fn pause(&mut self) -> Result<()> {
+        let new_state = match self.state {
+            State::Finishing { .. } => State::PausedFinishing,
+            _ => State::Paused,
+        };
+        self.set_state(new_state)
+    }
+
+    /// false : play
+    /// true : pause
+    fn vpause(&mut self, value: bool) -> Result<()> {
+        amstream_vpause(self.control_device.as_raw_fd(), value)
+    }
+
+    /// Switches the display to the given output. HDMI outputs keep their current mode if they
+    /// already are in an HDMI mode, and are set to 1080p60hz otherwise.
+    pub fn set_video_output(&mut self, output: VideoOutput) -> Result<()> {
+        let path = match output {
+            VideoOutput::Hdmi1 => DISPLAY2_MODE_PATH,
+            VideoOutput::Hdmi0 | VideoOutput::Cvbs => DISPLAY_MODE_PATH,
+        };
+        let current_mode = sysfs::read(path)?;
+        let is_cvbs_mode = current_mode.ends_with("cvbs");
+        let mode = match output {
+            VideoOutput::Cvbs if is_cvbs_mode => return Ok(()),
+            // 576cvbs is PAL, 480cvbs is NTSC
+            VideoOutput::Cvbs => "576cvbs",
+            _ if !is_cvbs_mode && current_mode != "null" && !current_mode.is_empty() => return Ok(()),
+            _ => "1080p60hz",
+        };
+        info!("switching {} from {} to {}", path, current_mode, mode);
+        sysfs::write(path, mode)
+    }
+
+    /// Sets the mode of the main display ("1080p60hz", "4k2k60hz", "720p50hz", ...), see
+    /// get_available_display_modes. Like set_video_output, the previous mode is restored by
+    /// DisplayModeWrapper.
+    pub fn set_display_mode(&mut self, mode: &str) -> Result<()> {
+        info!("switching the display to {}", mode);
+        sysfs::write(DISPLAY_MODE_PATH, mode)?;
+        thread::sleep(Duration::from_millis(DISPLAY_MODE_SETTLE_MS));
+        Ok(())
+    }
+
+    /// Modes supported by the display connected to the main HDMI output. If this isn't known,
+    /// only the current mode is returned.
+    pub fn get_available_display_modes() -> Result<Vec<String>> {
+        if let Ok(disp_cap) = sysfs::read(DISPLAY_CAP_PATH) {
+            return Ok(disp_cap.lines()
+                .map(|mode| mode.trim().trim_right_matches('*').to_owned())
+                .filter(|mode| !mode.is_empty())
+                .collect());
+        }
+        let vinfo = sysfs::read(DISPLAY_VINFO_PATH)?;
+        Ok(vinfo.lines()
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, ':');
+                match (parts.next(), parts.next()) {
+                    (Some(key), Some(value)) if key.trim() == "name" => Some(value.trim().to_owned()),
+                    _ => None,
+                }
+            })
+            .take(1)
+            .collect())
+    }
+
+    /// Sets the adaptive contrast enhancement of the display. See CONTRAST_PATH: this may not
+    /// be available on every SoC.
+    pub fn set_contrast_mode(&mut self, mode: ContrastMode) -> Result<()> {
+        sysfs::write(CONTRAST_PATH, &mode.value().to_string())?;
+        self.contrast_mode = Some(mode);
+        Ok(())
+    }
+
+    /// Sets the white balance of the display. See WHITE_BALANCE_PATH: this may not be available
+    /// on every SoC.
+    pub fn set_color_temperature(&mut self, temp: ColorTemperature) -> Result<()> {
+        let (r_gain, g_gain, b_gain) = temp.gains();
+        sysfs::write(WHITE_BALANCE_PATH, &format!("{} {} {}", r_gain, g_gain, b_gain))
+    }
+
+    /// Sets both the color saturation and the hue of the display in a single write. See
+    /// SATURATION_HUE_PATH: this may not be available on every SoC.
+    pub fn set_saturation_hue(&mut self, saturation: i32, hue: i32) -> Result<()> {
+        sysfs::write(SATURATION_HUE_PATH, &format!("{} {}", saturation, hue))?;
+        self.saturation = saturation;
+        self.hue = hue;
+        Ok(())
+    }
+
+    /// level goes from 0 (off) to MAX_NOISE_REDUCTION, and is scaled to what the driver expects.
+    /// See NOISE_REDUCTION_PATH.
+    pub fn set_noise_reduction(&mut self, level: u8) -> Result<()> {
+        let value = level as u32 * NOISE_REDUCTION_DRIVER_MAX / MAX_NOISE_REDUCTION as u32;
+        sysfs::write(NOISE_REDUCTION_PATH, &value.to_string())?;
+        self.noise_reduction = Some(level);
+        Ok(())
+    }
+
+    /// level goes from 0 (off) to MAX_SHARPNESS, and is scaled to what the driver expects. See
+    /// SHARPNESS_PATH.
+    pub fn set_sharpness(&mut self, level: u8) -> Result<()> {
+        let value = level as u32 * SHARPNESS_DRIVER_MAX / MAX_SHARPNESS as u32;
+        sysfs::write(SHARPNESS_PATH, &value.to_string())?;
+        self.sharpness = Some(level);
+        Ok(())
+    }
+
+    /// 0 lets the video show through the whole OSD, 255 hides it behind the OSD. See
+    /// OSD_ALPHA_PATH.
+    pub fn set_osd_alpha(&mut self, alpha: u8) -> Result<()> {
+        sysfs::write(OSD_ALPHA_PATH, &alpha.to_string())?;
+        self.osd_alpha = Some(alpha);
+        Ok(())
+    }
+
+    /// Writes the same table to the red, green and blue channels. See GAMMA_PATH.
+    pub fn set_gamma_table(&mut self, table: &[u16; GAMMA_TABLE_LEN]) -> Result<()> {
+        write_gamma_table(table)?;
+        self.gamma_table = Some(*table);
+        Ok(())
+    }
+
+    pub fn set_gamma_value(&mut self, gamma: f32) -> Result<()> {
+        self.set_gamma_table(&gamma_curve(gamma))
     }
 
-    /// (x, y, width, height)
-    pub fn set_video_axis(&mut self, (x, y, width, height): (i16, i16, u16, u16)) -> Result<()> {
-        let mut values : [c_int; 4] = [0; 4];
-        values[0] = x as c_int;
-        values[1] = y as c_int;
-        values[2] = x as c_int + width as c_int;
-        values[3] = y as c_int + height as c_int;
-        let r = unsafe {
-            amstream_ioc_set_video_axis(self.control_device.as_raw_fd(), &values as *const c_int)
-        };
-        if r < 0 {
-            bail!(ErrorKind::Ioctl("amstream_ioc_set_video_axis"));
+    /// Reads the level back from the driver, since it may have been changed behind our back
+    pub fn noise_reduction(&self) -> Result<u8> {
+        let content = sysfs::read(NOISE_REDUCTION_PATH)?;
+        let value : u32 = content.parse()
+            .map_err(|e| Error::Sysfs {
+                path: String::from(NOISE_REDUCTION_PATH),
+                source: ::std::io::Error::new(::std::io::ErrorKind::InvalidData, e),
+            })?;
+        // rounded to the nearest level
+        let level = (value.min(NOISE_REDUCTION_DRIVER_MAX) * MAX_NOISE_REDUCTION as u32 + NOISE_REDUCTION_DRIVER_MAX / 2) / NOISE_REDUCTION_DRIVER_MAX;
+        Ok(level as u8)
+    }
+
+    /// Shows or hides the VPU's video layer, without touching the playback itself
+    /// While the video layer is blanked, this is only applied once the next video starts
+    pub fn set_video_visible(&mut self, visible: bool) -> Result<()> {
+        self.video_visible = visible;
+        if self.blanked {
+            return Ok(());
         }
-        Ok(())
+        self.disable_video(!visible)
     }
 
-    pub fn play(&mut self) -> Result<()> {
-        let new_state = match self.state {
-            State::PausedFinishing => State::Finishing {
-                prev_data_len: 0,
-                same_data_len_count: 0,
-            },
-            _ => State::Playing,
-        };
-        self.set_state(new_state)
+    fn disable_video(&self, disable: bool) -> Result<()> {
+        amstream_set_video_disable(self.control_device.as_raw_fd(), disable)
     }
 
-    pub fn pause(&mut self) -> Result<()> {
-        let new_state = match self.state {
-            State::Finishing { .. } => State::PausedFinishing,
-            _ => State::Paused,
-        };
-        self.set_state(new_state)
+    fn read_pts(&self, kind: PtsKind) -> Result<f64> {
+        let pts = amstream_get_pts(self.hevc_device.as_raw_fd(), kind)?;
+        Ok(pts as f64 / PTS_FREQUENCY)
     }
 
-    /// false : play
-    /// true : pause
-    fn vpause(&mut self, value: bool) -> Result<()> {
-        let value : *const c_int = match value {
-            false => 0usize,
-            true => 1usize,
-        } as *const c_int;
-        let r = unsafe {
-            amstream_ioc_vpause(self.control_device.as_raw_fd(), value)
+    /// Returns None if no video is playing
+    pub fn timing_info(&mut self) -> Result<Option<AmlTimingInfo>> {
+        match self.state {
+            State::InitialState | State::Stopped(_) => return Ok(None),
+            _ => {},
         };
-        if r < 0 {
-            bail!(ErrorKind::Ioctl("ioc_vpause"));
+        let vpts_secs = self.read_pts(PtsKind::Video)?;
+        let apts_secs = self.read_pts(PtsKind::Audio)?;
+        let pcr_secs = self.read_pts(PtsKind::Pcr)?;
+        let av_diff_ms = ((vpts_secs - apts_secs) * 1000.0) as f32;
+        if av_diff_ms.abs() > AV_DISCONTINUITY_THRESHOLD_MS {
+            self.vpts_discontinuity += 1;
         }
-        Ok(())
+        Ok(Some(AmlTimingInfo {
+            vpts_secs: vpts_secs,
+            apts_secs: apts_secs,
+            av_diff_ms: av_diff_ms,
+            pcr_secs: pcr_secs,
+            vpts_discontinuity: self.vpts_discontinuity,
+        }))
     }
 
-    // mainly for debug purposes
-    #[allow(unused)]
-    pub fn get_vb_status(&self) -> Result<String> {
-        let mut vb_status : am_ioctl_parm_ex = unsafe { mem::zeroed()};
-        vb_status.cmd = AMSTREAM_GET_EX_VDECSTAT;
-        let r = unsafe {
-            amstream_ioc_get_vb_status(self.hevc_device.as_raw_fd(), &mut vb_status)
-        };
-        if r < 0 {
-            bail!(ErrorKind::Ioctl("amstream_ioc_get_vb_status"));
-        };
-        Ok(format!("{:#?}", unsafe {vb_status.union.vstatus} ))
+    /// The vdec_status returned by AMSTREAM_GET_EX_VDECSTAT only has room for the size, the
+    /// frame rate and the errors: bit_rate and frame_count are left to 0.
+    pub fn get_decoder_state(&self) -> Result<VpuDecoderState> {
+        let vb_status = amstream_get_ex(self.hevc_device.as_raw_fd(), AMSTREAM_GET_EX_VDECSTAT)?;
+        let vstatus = unsafe {vb_status.union.vstatus};
+        Ok(VpuDecoderState {
+            width: vstatus.width,
+            height: vstatus.height,
+            frame_rate: vstatus.fps,
+            bit_rate: 0,
+            error_count: vstatus.error_count,
+            frame_count: 0,
+        })
     }
 
     pub fn get_buf_status(&self) -> Result<BufStatus> {
-        let mut vb_status : am_ioctl_parm_ex = unsafe { mem::zeroed()};
-        vb_status.cmd = AMSTREAM_GET_EX_VB_STATUS;
-        let r = unsafe {
-            amstream_ioc_get_vb_status(self.hevc_device.as_raw_fd(), &mut vb_status)
-        };
-        if r < 0 {
-            bail!(ErrorKind::Ioctl("amstream_ioc_get_vb_status"));
-        };
+        let vb_status = amstream_get_ex(self.hevc_device.as_raw_fd(), AMSTREAM_GET_EX_VB_STATUS)?;
         Ok(unsafe {vb_status.union.status})
     }
 
@@ -366,13 +1343,11 @@ impl Amcodec {
             return Ok(())
         };
         match state {
-            State::Stopped(b) => {
-                self.clear_video()?;
-                if b {
-                    // this will unblock "wait_until_end" calls from the API
-                    self.status_sender.send(EndReason::EOF)
-                        .chain_err(|| ErrorKind::Disconnected)?;
-                } 
+            State::Stopped(eof) => {
+                // EndOfStream is only sent by the main loop once the device has been opened again
+                if !(eof && self.freeze_on_eof) {
+                    self.blank()?;
+                }
             },
             State::Paused => {
                 self.vpause(true)?;
@@ -383,15 +1358,36 @@ impl Amcodec {
             State::PausedFinishing => {
                 self.vpause(true)?;
             },
+            State::Stepping(_) => {
+                self.vpause(false)?;
+            },
             _ => {}
         };
         self.state = state;
         Ok(())
     }
 
+    /// Returns true when the VPU's buffer just went below underflow_threshold while playing.
+    ///
+    /// This is not the end of the video (EOF detection is done in update_state once libav has
+    /// sent everything), only a sign that libav isn't sending packets fast enough.
+    pub fn check_underrun(&mut self) -> Result<bool> {
+        if self.state != State::Playing {
+            self.underrun = false;
+            return Ok(false);
+        }
+        let data_len = self.get_buf_status()?.data_len;
+        let was_underrun = self.underrun;
+        self.underrun = data_len < self.underflow_threshold;
+        Ok(self.underrun && !was_underrun)
+    }
+
     // we talked about a pseudo state machine up there, this is the method that allows it
     // to update itself
-    pub fn update_state(&mut self) -> Result<bool> {
+    //
+    // Returns Some(eof) once stopped: the device must then be opened again before anything else
+    // is written to it, and eof tells whether this is the end of the video or an explicit Stop
+    pub fn update_state(&mut self) -> Result<Option<bool>> {
         let new_state : State = match &self.state {
             &State::Finishing {
                 prev_data_len,
@@ -399,7 +1395,7 @@ impl Amcodec {
             } => {
                 let buf_status = self.get_buf_status()?;
                 if buf_status.data_len <= 0 ||
-                    (prev_data_len == buf_status.data_len && same_data_len_count >= 3) {
+                    (prev_data_len == buf_status.data_len && same_data_len_count >= self.finishing_threshold) {
                     State::Stopped(true)
                 } else {
                     if prev_data_len == buf_status.data_len {
@@ -415,13 +1411,25 @@ impl Amcodec {
                     }
                 }
             },
+            &State::Stepping(started) => {
+                let vpts_secs = self.read_pts(PtsKind::Video)?;
+                // the vpts counts from the first packet fed, see report_position
+                if vpts_secs > 0.0 {
+                    State::Paused
+                } else if started.elapsed() >= Duration::from_millis(STEP_TIMEOUT_MS) {
+                    warn!("no frame was shown after {}ms, pausing anyway", STEP_TIMEOUT_MS);
+                    State::Paused
+                } else {
+                    State::Stepping(started)
+                }
+            },
             s => *s,
         };
         self.set_state(new_state)?;
-        if let State::Stopped(_) = self.state {
-            Ok(true)
+        if let State::Stopped(eof) = self.state {
+            Ok(Some(eof))
         } else {
-            Ok(false)
+            Ok(None)
         }
     }
 
@@ -430,84 +1438,177 @@ impl Amcodec {
     // this can sometimes fail with an "unavailable" error, sometimes within the middle of a
     // playback even, but this doesn't stop us from playing the video at all
     fn write_codec(&mut self, data: &[u8]) -> Result<()> {
-        use std::io::Write;
-        // calls `write` until the whole buffer has been written in the file
-        self.hevc_device.write_all(data).chain_err(|| ErrorKind::Amcodec)?;
+        let mut written = 0;
+        self.write_codec_counted(data, &mut written)
+    }
+
+    /// Same as write_codec, `written` is how much of `data` reached the device even if it failed
+    fn write_codec_counted(&mut self, data: &[u8], written: &mut usize) -> Result<()> {
+        use std::io::{self, Write};
+        let write_error = |e: io::Error| Error::Io {
+            what: String::from("failed to write to /dev/amstream_hevc"),
+            source: e,
+        };
+        let mut data = data;
+        // calls `write` until the whole buffer has been written in the file, like write_all but
+        // counting the interrupted writes
+        while !data.is_empty() {
+            match self.hevc_device.write(data) {
+                Ok(0) => {
+                    let e = io::Error::new(io::ErrorKind::WriteZero, "failed to write the whole buffer");
+                    return Err(write_error(e));
+                },
+                Ok(n) => {
+                    data = &data[n..];
+                    *written += n;
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => self.write_retries += 1,
+                Err(e) => return Err(write_error(e)),
+            }
+        }
         // ensures that all data writen has been sent to the true sink
-        self.hevc_device.flush().chain_err(|| ErrorKind::Amcodec)?;
+        self.hevc_device.flush().map_err(write_error)?;
         Ok(())
     }
 
     // writing extra_data is actually writing data to the codec ... the only thing is that it must
     // be done before any other data
+    //
+    // This is where every video starts, so the video layer blanked by the previous one is enabled
+    // again here.
     #[inline]
     fn write_extra_data(&mut self, extra_data: &[u8]) -> Result<()> {
+        self.flush_staging()?;
+        if self.blanked {
+            self.blanked = false;
+            if self.video_visible {
+                self.disable_video(false)?;
+            }
+        }
         self.write_codec(extra_data)
     }
 
+    /// What is done to the video layer once a video stops, see BlackoutMode
+    fn blank(&mut self) -> Result<()> {
+        match self.blackout_mode {
+            BlackoutMode::Clear => self.clear_video(),
+            BlackoutMode::Disable => {
+                self.clear_video()?;
+                if !self.blanked {
+                    self.disable_video(true)?;
+                    self.blanked = true;
+                }
+                Ok(())
+            },
+            BlackoutMode::KeepLastFrame => Ok(()),
+        }
+    }
+
     // clears the buffer output (on the screen), but it doesn't look like it clears the VPU's inner
     // memory
     fn clear_video(&mut self) -> Result<()> {
-        let v : c_int = 1;
-        let r = unsafe {
-            amstream_ioc_clear_video(self.control_device.as_raw_fd(), &v as *const _)
-        };
-        if r < 0 {
-            bail!(ErrorKind::Ioctl("amstream_clear_video"));
-        }
-        Ok(())
+        amstream_clear_video(self.control_device.as_raw_fd())
     }
 
-    // unused when operating on video only
-    // this was implemented when trying to get the driver working, but is unused now
-    #[allow(unused)]
+    // pts is in 90kHz units, like in MPEG streams. Only used when a ts offset is set: otherwise the
+    // VPU finds the timestamps in the stream by itself.
     fn set_tstamp(&mut self, pts: u32) -> Result<()> {
-        let mut parm : am_ioctl_parm = unsafe { mem::zeroed() };
-        parm.cmd = AMSTREAM_SET_TSTAMP;
-        unsafe {
-            parm.union.data_32 = pts;
-        }
-        let r = unsafe {
-            amstream_ioc_set(self.hevc_device.as_raw_fd(), &parm)
-        };
-        if r < 0 {
-            bail!(ErrorKind::Ioctl("set_tstamp"));
-        };
+        amstream_set_tstamp(self.hevc_device.as_raw_fd(), pts)
+    }
+
+    /// Some IPTV streams have a PTS base the VPU doesn't cope with: the timestamps of every packet
+    /// are moved by offset_ms, which is applied by set_tstamp before the packet is written.
+    /// 0 leaves the timestamps alone.
+    pub fn set_ts_offset(&mut self, offset_ms: i64) -> Result<()> {
+        self.ts_offset_ms = offset_ms;
         Ok(())
     }
 
-    // this s ia key step for the video processing of the VPU, if we don't do this step the VPU
-    // only outputs pitch black
-    //
-    // my guess is that 0001 (on 4 bytes) acts as a "delimiter" of some kind for the VPU, but we
-    // receive the length of the frame from libavformat, so we just need to override the length of
-    // the frame by 0001.
-    fn process_nal_packets(data: &mut [u8]) -> Result<()> {
-        let mut offset : usize = 0;
-        while offset < data.len() {
-            let (_, mut data) = data.split_at_mut(0);
-            let nal_len : u32 = ((data[0] as u32) << 24) | ((data[1] as u32) << 16) | ((data[2] as u32) << 8) | (data[3] as u32);
-            data[0] = 0;
-            data[1] = 0;
-            data[2] = 0;
-            data[3] = 1;
-            offset += nal_len as usize + 4;
+    /// The NAL lengths are rewritten in place: packet.data is our own copy, the AVPacket it came
+    /// from (whose buffer libav may still share with the demuxer) was never written to.
+    fn process_libavpacket(&mut self, packet: &mut VideoPacket) -> Result<()> {
+        if self.ts_offset_ms != 0 && packet.pts != AV_NOPTS_VALUE && packet.time_base > 0.0 {
+            let pts_90khz = (packet.pts as f64 * packet.time_base * 90000.0) as i64 + self.ts_offset_ms * 90;
+            // the PTS is 33 bits wide in the stream, but the driver only takes the lower 32
+            if pts_90khz >= 0 {
+                // the timestamp is checked in at the current offset of the stream: what is staged
+                // must be written before it, and this packet right after
+                process_nal_packets(&mut packet.data)?;
+                self.flush_staging()?;
+                self.set_tstamp(pts_90khz as u32)?;
+                self.write_codec(&packet.data)?;
+                self.packets_written += 1;
+                self.bytes_written += packet.data.len();
+                return Ok(());
+            }
+        }
+        process_nal_packets(&mut packet.data)?;
+        self.staging.extend_from_slice(&packet.data);
+        self.staged_lens.push(packet.data.len());
+        if self.staging.len() >= MAX_COALESCED_WRITE_LEN {
+            self.flush_staging()?;
         }
         Ok(())
     }
 
-    fn process_libavpacket<'p>(&mut self, pkt: &'p libav::AVPacket) -> Result<()> {
-        let mut data : &'p mut [u8] = unsafe {
-            ::std::slice::from_raw_parts_mut(pkt.data, pkt.size as usize)
-        };
-        Self::process_nal_packets(&mut data)?;
-        self.write_codec(data)?;
-        Ok(())
+    /// Writes the packets staged by process_libavpacket. The main loop calls this at the end of
+    /// every iteration, and it must be called before anything else goes to the device.
+    ///
+    /// When writing them all at once fails, what wasn't written yet is written again packet by
+    /// packet: only the packets the device refuses are lost, not all the ones staged with them.
+    pub fn flush_staging(&mut self) -> Result<()> {
+        if self.staging.is_empty() {
+            return Ok(());
+        }
+        let mut staging = mem::replace(&mut self.staging, Vec::new());
+        let mut staged_lens = mem::replace(&mut self.staged_lens, Vec::new());
+        let mut written = 0;
+        let mut r = self.write_codec_counted(&staging, &mut written);
+        if let Err(ref e) = r {
+            if written < staging.len() {
+                warn!("failed to write {} packets at once ({}), writing them one by one", staged_lens.len(), e.display());
+            }
+        }
+        let mut start = 0;
+        for &len in &staged_lens {
+            let end = start + len;
+            if end <= written {
+                self.packets_written += 1;
+                self.bytes_written += len;
+            } else {
+                // the packet cut by the failed write goes on from where it stopped
+                let from = ::std::cmp::max(start, written);
+                let mut packet_written = 0;
+                match self.write_codec_counted(&staging[from..end], &mut packet_written) {
+                    Ok(()) => {
+                        self.packets_written += 1;
+                        self.bytes_written += len;
+                    },
+                    Err(e) => {
+                        error!("dropping a packet of {} bytes: {}", len, e.display());
+                        r = Err(e);
+                    },
+                }
+            }
+            start = end;
+        }
+        // the capacity is kept for the next packets
+        staging.clear();
+        staged_lens.clear();
+        self.staging = staging;
+        self.staged_lens = staged_lens;
+        r
+    }
+
+    /// Drops the packets staged but not written yet, they belong to a video which stopped
+    fn drop_staging(&mut self) {
+        self.staging.clear();
+        self.staged_lens.clear();
     }
 
     fn finish(&mut self) -> Result<()> {
         let new_state = match self.state {
-            State::Paused | State::PausedFinishing => State::PausedFinishing,
+            State::Paused | State::PausedFinishing | State::Stepping(_) => State::PausedFinishing,
             State::InitialState | State::Playing | State::Finishing {..} => State::Finishing {
                     prev_data_len: 0,
                     same_data_len_count: 0,
@@ -519,27 +1620,113 @@ impl Amcodec {
 
     pub fn stop(&mut self) -> Result<()> {
         if self.state != State::InitialState {
+            self.paused_when_stopped = match self.state {
+                State::Paused | State::PausedFinishing | State::Stepping(_) => true,
+                _ => false,
+            };
             self.set_state(State::Stopped(false))?;
         };
         Ok(())
     }
 
+    /// Same as stop, but the screen is cleared even if nothing was playing, so that no frozen
+    /// frame stays on the video layer once the player is destroyed
+    pub fn shutdown(&mut self) -> Result<()> {
+        self.set_state(State::Stopped(false))
+    }
+
     pub fn process_packet(&mut self, data: LibavPacket) -> Result<()> {
         match data {
-            LibavPacket::ExtraData(extra_data) => self.write_extra_data(&*extra_data),
-            LibavPacket::Packet(p) => self.process_libavpacket(&p.inner),
-            LibavPacket::EOF => self.finish(),
-            LibavPacket::Stop => self.stop(),
+            LibavPacket::ExtraData(extra_data) => {
+                let r = self.write_extra_data(&extra_data);
+                self.extra_data_error = r.as_ref().err().map(|e| e.display().to_string());
+                r
+            },
+            LibavPacket::ConfirmLoad(tx) => {
+                if let Some((previous, _)) = self.pending_load.take() {
+                    // replaced before anything was written, like the load used to be answered
+                    previous.send(FfiErrorCode::None);
+                }
+                match self.extra_data_error.take() {
+                    Some(reason) => tx.send(Error::DecoderRejected(format!("the extra data was refused: {}", reason))),
+                    // the clock only starts with the first packet: libav may take its time
+                    // reading it, from the network for example
+                    None => self.pending_load = Some((tx, None)),
+                };
+                Ok(())
+            },
+            LibavPacket::Seeked(generation) => {
+                // an older seek which isn't shown yet never will be
+                self.pending_seek = Some(generation);
+                Ok(())
+            },
+            LibavPacket::Packet(mut p) => {
+                let mut r = self.process_libavpacket(&mut p);
+                if let Some((tx, failed)) = self.pending_load.take() {
+                    // the first packet of the video must really reach the device, the next ones
+                    // may still get through until LOAD_CONFIRM_TIMEOUT_MS is over
+                    r = r.and_then(|()| self.flush_staging());
+                    match r {
+                        Ok(()) => tx.send(FfiErrorCode::None),
+                        Err(ref e) => {
+                            let since = failed.map_or_else(Instant::now, |(since, _)| since);
+                            self.pending_load = Some((tx, Some((since, e.display().to_string()))));
+                        },
+                    };
+                }
+                r?;
+                if self.step_on_next_packet {
+                    // the frame to step to must be in the VPU's buffer
+                    self.flush_staging()?;
+                    self.step_on_next_packet = false;
+                    self.hold_position = true;
+                    self.set_state(State::Stepping(Instant::now()))?;
+                }
+                Ok(())
+            },
+            LibavPacket::EOF => {
+                // a video without any packet: there is nothing the VPU could have refused
+                if let Some((tx, _)) = self.pending_load.take() {
+                    tx.send(FfiErrorCode::None);
+                }
+                self.flush_staging()?;
+                self.finish()
+            },
+            LibavPacket::Stop => {
+                // what is staged belongs to the video being stopped
+                self.drop_staging();
+                self.extra_data_error = None;
+                self.pending_seek = None;
+                self.stop()
+            },
             LibavPacket::Error(e) => Err(e),
+            // only ever sent by us to libav
+            LibavPacket::Underrun => Ok(()),
         }
     }
 
-    pub fn version(&self) -> Result<(u16, u16)> {
-        let mut amstream_version : c_int = 0;
-        let ret = unsafe {amstream_ioc_get_version(self.hevc_device.as_raw_fd(), &mut amstream_version)};
-        if ret != 0 {
-            bail!(ErrorKind::Ioctl("amstream_ioc_get_version"));
+    /// Fails the pending Load once LOAD_CONFIRM_TIMEOUT_MS passed since libav sent its first
+    /// packet, without any of them being written
+    pub fn check_pending_load(&mut self) {
+        let expired = match self.pending_load {
+            Some((_, Some((since, _)))) => since.elapsed() >= Duration::from_millis(LOAD_CONFIRM_TIMEOUT_MS),
+            _ => false,
         };
+        if expired {
+            if let Some((tx, Some((_, reason)))) = self.pending_load.take() {
+                warn!("no packet of the video was written after {}ms: {}", LOAD_CONFIRM_TIMEOUT_MS, reason);
+                tx.send(Error::DecoderRejected(reason));
+            }
+        }
+    }
+
+    /// see hold_position
+    pub fn holds_position(&self) -> bool {
+        self.hold_position
+    }
+
+    pub fn version(&self) -> Result<(u16, u16)> {
+        let amstream_version = amstream_get_version(self.hevc_device.as_raw_fd())?;
         let lower_v = (amstream_version & 0xFFFF) as u16;
         let upper_v = ((amstream_version & 0x7FFF0000) >> 16) as u16;
         Ok((upper_v, lower_v))
@@ -552,78 +1739,359 @@ impl Drop for FbWrapper {
         let fb0 = OpenOptions::new().write(true).open("/dev/fb0");
         // restore screen settings
         if let Ok(fb0) = fb0 {
-            let ret = unsafe {
-                fbio_set_vscreen_info(fb0.as_raw_fd(), &mut self.screeninfo as *mut _ as *mut u8)
-            };
-            if ret < 0 {
-                println!("amcodec: ioctl call to fbio_set_vscreen_info went wrong, status code {}", ret);
+            if let Err(e) = fb_set_vscreen_info(fb0.as_raw_fd(), &self.screeninfo) {
+                error!("failed to restore the screen settings: {}", e.display());
             }
         } else {
             // if this happens then this is very weird ... we had permission to set it at the
             // beginning but we can't do it after we're done ? Did someone change our rights while
             // we were playing ?
-            println!("amcodec: Unable to restore screen settings for fb0, permission denied");
+            error!("unable to restore screen settings for fb0, permission denied");
         }
     }
 }
 
-#[derive(Debug)]
-pub enum EndReason {
-    EOF,
-    // the EndReason "Error" is unused for now, but we might find a use later:
-    // I haven't found yet an error that was so fatal in the middle of the playback that it stopped
-    // the playback totally
-    #[allow(unused)]
-    Error(String),
-}
-
 #[derive(Debug)]
 pub enum Message {
     Play,
     Pause,
-    Resize(i16, i16, u16, u16),
-    Fullscreen,
+    /// only used when there is no window to show or hide
+    SetVideoVisible(bool),
+    /// the info is sent in the given channel, the other one only receives the error code
+    GetTimingInfo(SuSender<AmlTimingInfo>),
+    /// same as GetTimingInfo
+    GetDecoderState(SuSender<VpuDecoderState>),
+    SetVideoOutput(VideoOutput),
+    /// see Amcodec::set_display_mode
+    SetDisplayMode(String),
+    /// in bytes
+    SetUnderflowThreshold(i32),
+    /// see Amcodec::freeze_on_eof
+    SetFreezeOnEof(bool),
+    SetContrastMode(ContrastMode),
+    SetColorTemperature(ColorTemperature),
+    /// -128..127, the hue is left as-is
+    SetSaturation(i32),
+    /// -128..127, the saturation is left as-is
+    SetHue(i32),
+    GetSaturation(SuSender<i32>),
+    /// in milliseconds, see Amcodec::set_ts_offset
+    SetTsOffset(i64),
+    GetTsOffset(SuSender<i64>),
+    /// 0..MAX_NOISE_REDUCTION
+    SetNoiseReduction(u8),
+    GetNoiseReduction(SuSender<u8>),
+    /// 0..MAX_SHARPNESS
+    SetSharpness(u8),
+    SetOsdAlpha(u8),
+    /// what was set through the API, to be saved
+    GetVideoQuality(SuSender<VideoQualitySettings>),
+    /// applies everything which is set, in one go
+    SetVideoQuality(VideoQualitySettings),
+    /// must be > 0, see gamma_curve
+    SetGamma(f32),
+    /// GAMMA_TABLE_LEN values, up to MAX_GAMMA_VALUE
+    SetGammaTable(Vec<u16>),
+    /// 0.0..1.0, see AudioOutput::set_volume
+    SetVolume(f32),
+    /// see AudioOutput::set_muted
+    SetMute(bool),
+    /// Sent by the main thread before the player is destroyed, once libav doesn't send anything
+    /// anymore: the video is stopped and cleared, and the device is left alone until this thread
+    /// exits
+    Shutdown,
+}
+
+/// Loads `table` in the red, green and blue channels of the gamma correction of the VPU
+#[cfg(target_arch = "aarch64")]
+fn write_gamma_table(table: &[u16; GAMMA_TABLE_LEN]) -> Result<()> {
+    let values : String = table.iter().map(|value| format!("{:03x}", value)).collect();
+    for channel in &["sgr", "sgg", "sgb"] {
+        sysfs::write(GAMMA_PATH, &format!("{}{}", channel, values))?;
+    }
+    Ok(())
+}
+
+/// Same for both versions, since they only go through the setters
+impl Amcodec {
+    pub fn video_quality(&self) -> VideoQualitySettings {
+        VideoQualitySettings {
+            noise_reduction: self.noise_reduction,
+            contrast_mode: self.contrast_mode,
+            saturation_hue: Some((self.saturation, self.hue)),
+            sharpness: self.sharpness,
+        }
+    }
+
+    /// Stops at the first setting which can't be applied, the ones before it are kept
+    pub fn set_video_quality(&mut self, settings: &VideoQualitySettings) -> Result<()> {
+        if let Some(level) = settings.noise_reduction {
+            self.set_noise_reduction(level)?;
+        }
+        if let Some(mode) = settings.contrast_mode {
+            self.set_contrast_mode(mode)?;
+        }
+        if let Some((saturation, hue)) = settings.saturation_hue {
+            self.set_saturation_hue(saturation, hue)?;
+        }
+        if let Some(level) = settings.sharpness {
+            self.set_sharpness(level)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+impl Drop for Amcodec {
+    /// Otherwise our gamma table would outlive the player
+    fn drop(&mut self) {
+        if self.gamma_table.is_some() {
+            if let Err(e) = write_gamma_table(&gamma_curve(1.0)) {
+                error!("unable to restore the default gamma table: {}", e.display());
+            }
+        }
+        if self.osd_alpha.is_some() {
+            if let Err(e) = sysfs::write(OSD_ALPHA_PATH, "255") {
+                error!("unable to make the OSD opaque again: {}", e.display());
+            }
+        }
+        // it was cleared before being disabled, the next user of the VPU must be able to show
+        // something
+        if self.blanked {
+            if let Err(e) = self.disable_video(false) {
+                error!("unable to enable the video layer again: {}", e.display());
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+impl Drop for Amcodec {
+    fn drop(&mut self) {
+        if self.gamma_table.is_some() {
+            debug!("(dummy) restoring the default gamma table");
+        }
+        if self.osd_alpha.is_some() {
+            debug!("(dummy) making the OSD opaque again");
+        }
+    }
+}
+
+/// Replaces amcodec by a new one, to "clear" the VPU's buffer: I couldn't find any other or better
+/// way than to close and reopen the device again to "flush".
+///
+/// Our settings are carried over to the new one.
+#[cfg(target_arch = "aarch64")]
+fn reopen(mut amcodec: Amcodec) -> Result<Amcodec> {
+    let underflow_threshold = amcodec.underflow_threshold;
+    let (saturation, hue) = (amcodec.saturation, amcodec.hue);
+    let noise_reduction = amcodec.noise_reduction;
+    let (contrast_mode, sharpness) = (amcodec.contrast_mode, amcodec.sharpness);
+    // taken so that the old one doesn't restore the default table: the driver keeps ours
+    let gamma_table = amcodec.gamma_table.take();
+    let osd_alpha = amcodec.osd_alpha.take();
+    // a Seek stops the device too, the Load is still waiting for its first packet
+    let pending_load = amcodec.pending_load.take();
+    let pending_seek = amcodec.pending_seek;
+    let ts_offset_ms = amcodec.ts_offset_ms;
+    let finishing_threshold = amcodec.finishing_threshold;
+    let freeze_on_eof = amcodec.freeze_on_eof;
+    let blackout_mode = amcodec.blackout_mode;
+    // taken so that the old one doesn't enable the video layer again
+    let blanked = mem::replace(&mut amcodec.blanked, false);
+    let video_visible = amcodec.video_visible;
+    let paused = amcodec.paused_when_stopped;
+    drop(amcodec);
+    let mut amcodec = Amcodec::new()?;
+    amcodec.underflow_threshold = underflow_threshold;
+    // the driver still has them, only our copy must be carried over
+    amcodec.saturation = saturation;
+    amcodec.hue = hue;
+    amcodec.contrast_mode = contrast_mode;
+    amcodec.sharpness = sharpness;
+    amcodec.gamma_table = gamma_table;
+    amcodec.osd_alpha = osd_alpha;
+    amcodec.pending_load = pending_load;
+    amcodec.pending_seek = pending_seek;
+    amcodec.ts_offset_ms = ts_offset_ms;
+    amcodec.finishing_threshold = finishing_threshold;
+    amcodec.freeze_on_eof = freeze_on_eof;
+    amcodec.blackout_mode = blackout_mode;
+    amcodec.blanked = blanked;
+    amcodec.video_visible = video_visible;
+    if paused {
+        // the new video must not start playing on its own, only show its first frame
+        amcodec.set_state(State::Paused)?;
+        amcodec.step_on_next_packet = true;
+    }
+    // but some drivers reset this one when the decoder is opened again
+    if let Some(level) = noise_reduction {
+        if let Err(e) = amcodec.set_noise_reduction(level) {
+            error!("error when setting the noise reduction again: {}", e.display());
+        }
+    }
+    Ok(amcodec)
 }
 
 #[cfg(target_arch = "aarch64")]
 pub fn main_loop(mut amcodec: Amcodec,
-                   rx: Receiver<(Message, SuSender<FfiErrorCode>)>,
+                   rx: Receiver<Request<Message, Reply>>,
                    packet_channel: Receiver<LibavPacket>,
-                   status_sender: Sender<EndReason>,
+                   feedback_channel: Sender<LibavPacket>,
+                   status_sender: Sender<PlayerEvent>,
+                   position_sender: Sender<PositionReport>,
+                   stats: Arc<Stats>,
+                   settings: Arc<VideoSettings>,
+                   audio: Arc<Mutex<AudioOutput>>,
                    keep_running: Arc<AtomicBool>) {
+    let mut last_position_report = Instant::now();
+    let mut first_frame_shown = false;
+    // set by Shutdown
+    let mut shutting_down = false;
+    let mut seen = SeenVersions::default();
+    // (the animation of the video axis, the rectangle applied last)
+    let mut animation : Option<(Animation, Option<(i32, i32, u32, u32)>)> = None;
     while keep_running.load(Ordering::SeqCst) == true {
-        match rx.try_recv() {
-            Ok((Message::Fullscreen, tx)) => {
-                if let Err(e) = amcodec.set_fullscreen() {
-                    println!("amcodec_thread: error when setting fullscreen: {}", e.display());
-                    tx.send(error_to_ecode(e));
-                } else {
-                    tx.send(FfiErrorCode::None);
-                }
+        if let Some(axis) = settings.axis.changed(&mut seen.axis) {
+            // a new axis replaces the animation going on, if any
+            animation = None;
+            let r = match axis {
+                VideoAxis::Fullscreen => amcodec.set_fullscreen(),
+                VideoAxis::Rect(x, y, width, height) => amcodec.set_video_axis((x, y, width, height)),
+                VideoAxis::Animated(new_animation) => {
+                    animation = Some((new_animation, None));
+                    Ok(())
+                },
+            };
+            if let Err(e) = r {
+                error!("error when setting the video axis to {:?}: {}", axis, e.display());
             }
-            Ok((Message::Resize(x, y, width, height), tx)) => {
-                if let Err(e) = amcodec.set_video_axis((x, y, width, height)) {
-                    println!("amcodec_thread: error when setting position: {}", e.display());
-                    tx.send(error_to_ecode(e));
-                } else {
-                    tx.send(FfiErrorCode::None);
+        }
+        let finished = match animation {
+            Some((ref current, ref mut last_rect)) => {
+                let (rect, finished) = current.at(Instant::now());
+                // the ioctl only when the rounded rectangle moved
+                if *last_rect != Some(rect) {
+                    if let Err(e) = amcodec.set_video_axis(rect) {
+                        error!("error when moving the video to {:?}: {}", rect, e.display());
+                    }
+                    *last_rect = Some(rect);
                 }
+                finished
             },
-            Ok((Message::Play, tx)) => {
-                if let Err(e) = amcodec.play() {
-                    println!("amcodec_thread: error setting playing state: {}", e.display());
-                    tx.send(error_to_ecode(e));
-                } else {
-                    tx.send(FfiErrorCode::None);
-                }
+            None => false,
+        };
+        if finished {
+            animation = None;
+        }
+        match rx.try_recv() {
+            Ok(Request { query: Message::Play, reply: tx, .. }) => {
+                tx.respond_with("setting the playing state", || amcodec.play());
             },
-            Ok((Message::Pause, tx)) => {
-                if let Err(e) = amcodec.pause() {
-                    println!("amcodec_thread: error setting paused state: {}", e.display());
-                    tx.send(error_to_ecode(e));
-                } else {
-                    tx.send(FfiErrorCode::None);
-                }
+            Ok(Request { query: Message::Pause, reply: tx, .. }) => {
+                tx.respond_with("setting the paused state", || amcodec.pause());
+            },
+            Ok(Request { query: Message::SetVideoVisible(visible), reply: tx, .. }) => {
+                tx.respond_with("showing/hiding the video layer", || amcodec.set_video_visible(visible));
+            },
+            Ok(Request { query: Message::GetTimingInfo(info_tx), reply: tx, .. }) => {
+                match amcodec.timing_info() {
+                    Ok(Some(info)) => {
+                        info_tx.send(info);
+                        tx.send(FfiErrorCode::None);
+                    },
+                    // no video is playing
+                    Ok(None) => tx.send(FfiErrorCode::InvalidCommand),
+                    Err(e) => {
+                        error!("error when reading the timestamps: {}", e.display());
+                        tx.send(e);
+                    }
+                };
+            },
+            Ok(Request { query: Message::GetDecoderState(state_tx), reply: tx, .. }) => {
+                tx.respond_value_with(Some(state_tx), "reading the decoder's state", || amcodec.get_decoder_state());
+            },
+            Ok(Request { query: Message::SetVideoOutput(output), reply: tx, .. }) => {
+                tx.respond_with("switching the video output", || amcodec.set_video_output(output));
+            },
+            Ok(Request { query: Message::SetDisplayMode(mode), reply: tx, .. }) => {
+                tx.respond_with("setting the display mode", || amcodec.set_display_mode(&mode));
+            },
+            Ok(Request { query: Message::SetUnderflowThreshold(bytes), reply: tx, .. }) => {
+                amcodec.underflow_threshold = bytes;
+                tx.send(FfiErrorCode::None);
+            },
+            Ok(Request { query: Message::SetFreezeOnEof(enable), reply: tx, .. }) => {
+                amcodec.freeze_on_eof = enable;
+                tx.send(FfiErrorCode::None);
+            },
+            Ok(Request { query: Message::SetContrastMode(mode), reply: tx, .. }) => {
+                tx.respond_with("setting the contrast mode", || amcodec.set_contrast_mode(mode));
+            },
+            Ok(Request { query: Message::SetColorTemperature(temp), reply: tx, .. }) => {
+                tx.respond_with("setting the color temperature", || amcodec.set_color_temperature(temp));
+            },
+            Ok(Request { query: Message::SetSaturation(saturation), reply: tx, .. }) => {
+                let hue = amcodec.hue;
+                tx.respond_with("setting the saturation", || amcodec.set_saturation_hue(saturation, hue));
+            },
+            Ok(Request { query: Message::SetHue(hue), reply: tx, .. }) => {
+                let saturation = amcodec.saturation;
+                tx.respond_with("setting the hue", || amcodec.set_saturation_hue(saturation, hue));
+            },
+            Ok(Request { query: Message::GetSaturation(saturation_tx), reply: tx, .. }) => {
+                saturation_tx.send(amcodec.saturation);
+                tx.send(FfiErrorCode::None);
+            },
+            Ok(Request { query: Message::SetTsOffset(offset_ms), reply: tx, .. }) => {
+                tx.respond_with("setting the ts offset", || amcodec.set_ts_offset(offset_ms));
+            },
+            Ok(Request { query: Message::GetTsOffset(offset_tx), reply: tx, .. }) => {
+                offset_tx.send(amcodec.ts_offset_ms);
+                tx.send(FfiErrorCode::None);
+            },
+            Ok(Request { query: Message::SetVolume(volume), reply: tx, .. }) => {
+                let mut audio = audio.lock().unwrap_or_else(|e| e.into_inner());
+                tx.respond_with("setting the volume", || audio.set_volume(volume));
+            },
+            Ok(Request { query: Message::SetMute(muted), reply: tx, .. }) => {
+                let mut audio = audio.lock().unwrap_or_else(|e| e.into_inner());
+                tx.respond_with(if muted { "muting the audio" } else { "unmuting the audio" }, || audio.set_muted(muted));
+            },
+            Ok(Request { query: Message::SetNoiseReduction(level), reply: tx, .. }) => {
+                tx.respond_with("setting the noise reduction", || amcodec.set_noise_reduction(level));
+            },
+            Ok(Request { query: Message::GetNoiseReduction(level_tx), reply: tx, .. }) => {
+                tx.respond_value_with(Some(level_tx), "reading the noise reduction", || amcodec.noise_reduction());
+            },
+            Ok(Request { query: Message::SetSharpness(level), reply: tx, .. }) => {
+                tx.respond_with("setting the sharpness", || amcodec.set_sharpness(level));
+            },
+            Ok(Request { query: Message::SetOsdAlpha(alpha), reply: tx, .. }) => {
+                tx.respond_with("setting the OSD alpha", || amcodec.set_osd_alpha(alpha));
+            },
+            Ok(Request { query: Message::GetVideoQuality(settings_tx), reply: tx, .. }) => {
+                settings_tx.send(amcodec.video_quality());
+                tx.send(FfiErrorCode::None);
+            },
+            Ok(Request { query: Message::SetVideoQuality(settings), reply: tx, .. }) => {
+                tx.respond_with("applying the video quality settings", || amcodec.set_video_quality(&settings));
+            },
+            Ok(Request { query: Message::SetGamma(gamma), reply: tx, .. }) => {
+                tx.respond_with("setting the gamma", || amcodec.set_gamma_value(gamma));
+            },
+            Ok(Request { query: Message::SetGammaTable(values), reply: tx, .. }) => {
+                let mut table = [0u16; GAMMA_TABLE_LEN];
+                table.copy_from_slice(&values);
+                tx.respond_with("setting the gamma table", || amcodec.set_gamma_table(&table));
+            },
+            Ok(Request { query: Message::Shutdown, reply: tx, .. }) => {
+                // whatever is still queued belongs to a video nobody will ever watch, and would
+                // only fill the VPU's buffer again
+                while let Ok(_) = packet_channel.try_recv() {}
+                amcodec.drop_staging();
+                shutting_down = true;
+                tx.respond_with("stopping the video", || amcodec.shutdown());
             },
             Err(TryRecvError::Disconnected) => {
                 // the other end of the channel has hung up
@@ -633,53 +2101,222 @@ pub fn main_loop(mut amcodec: Amcodec,
                 // we received the fact that keep_running became false
                 //
                 // in both cases breaking the loop is the correct thing to do here
-                println!("amcodec_thread: uh oh ...");
+                error!("uh oh ...");
                 break;
             },
             // no message
             Err(_) => {}
         };
-        match packet_channel.try_recv() {
-            Ok(p) => {
-                if let Err(e) = amcodec.process_packet(p) {
-                    println!("amcodec_thread: error when processing packet: {}", e.display());
-                };
+        // consecutive packets already queued are written together, see MAX_COALESCED_WRITE_LEN
+        let coalescing_started = Instant::now();
+        loop {
+            let p = match packet_channel.try_recv() {
+                Ok(p) => p,
+                Err(TryRecvError::Disconnected) => {
+                    // the packet channel is disconnected, but it doesn't mean we should stop palyback
+                    // yet. Maybe the other thread crashed or something, but we can still keep going
+                    // our playback
+                    // However, maybe we would check here if the state is "InitialState", and if it is,
+                    // we would break our loop as well.
+                    break;
+                },
+                // no packet
+                Err(_) => break,
+            };
+            let is_packet = match p {
+                LibavPacket::Packet(_) => true,
+                // the next video has a first frame of its own, even if the next report comes
+                // after it already started
+                LibavPacket::Stop => {
+                    first_frame_shown = false;
+                    false
+                },
+                _ => false,
+            };
+            if is_packet {
+                stats::add(&stats.packets_dequeued, 1);
+            }
+            if let Err(e) = amcodec.process_packet(p) {
+                error!("error when processing packet: {}", e.display());
+            };
+            // anything else than a packet may change the state, which the rest of this iteration
+            // must see before the next packet. The VPU running dry can't wait for more either.
+            if !is_packet || amcodec.underrun
+                || coalescing_started.elapsed() >= Duration::from_millis(MAX_COALESCING_TIME_MS) {
+                break;
+            }
+        }
+        if let Err(e) = amcodec.flush_staging() {
+            error!("error when writing packets: {}", e.display());
+        }
+        let write_retries = mem::replace(&mut amcodec.write_retries, 0);
+        stats::add(&stats.write_retries, write_retries);
+        // counted once they reached the device, not when they were staged
+        stats::add(&stats.packets_written, mem::replace(&mut amcodec.packets_written, 0));
+        stats::add(&stats.bytes_written, mem::replace(&mut amcodec.bytes_written, 0));
+        let was_underrun = amcodec.underrun;
+        match amcodec.check_underrun() {
+            Ok(true) => {
+                stats::add(&stats.stalls, 1);
+                let _r = feedback_channel.send(LibavPacket::Underrun);
+                let _r = status_sender.send(PlayerEvent::Stalled);
             },
-            Err(TryRecvError::Disconnected) => {
-                // the packet channel is disconnected, but it doesn't mean we should stop palyback
-                // yet. Maybe the other thread crashed or something, but we can still keep going
-                // our playback
-                // However, maybe we would check here if the state is "InitialState", and if it is,
-                // we would break our loop as well.
+            Ok(false) => {
+                if was_underrun && !amcodec.underrun {
+                    let _r = status_sender.send(PlayerEvent::Resumed);
+                }
             },
-            // no message
-            Err(_) => {}
-        }
+            Err(e) => {
+                error!("error when checking the buffer's status: {}", e.display());
+            }
+        };
+        report_position(&mut amcodec, &position_sender, &status_sender, &mut last_position_report, &mut first_frame_shown);
+        amcodec.check_pending_load();
         // Update Amcodec's internal pseudo state machine
         match amcodec.update_state() {
             Err(e) => {
-                println!("amcodec_thread: error when updating internal state: {}", e.display());
+                error!("error when updating internal state: {}", e.display());
             },
-            Ok(true) => {
-                // if it returns Ok(true), we should replace this by a new Amcodec (to "clear" the
-                // buffer)
-                // I couldn't find any other or better way than to close and reopen the device
-                // again to "flush".
-                drop(amcodec);
-                amcodec = match Amcodec::new(status_sender.clone()) {
-                    Ok(amcodec) => amcodec,
+            // the device is closed for good when this thread exits, opening it again now would
+            // only make the next player wait for it
+            Ok(Some(_)) if shutting_down => {},
+            Ok(Some(eof)) => {
+                // this is done in the same iteration as the Stop or the end of the video, so no
+                // packet of the next video can be written to the device we are about to close: the
+                // ExtraData sent by libav right after a Stop would be lost with it, and the next
+                // video would only be pitch black
+                amcodec = match reopen(amcodec) {
+                    Ok(amcodec) => {
+                        stats::add(&stats.device_reopens, 1);
+                        amcodec
+                    },
                     Err(e) => {
-                        println!("amcodec_thread: error when opening amcodec: {}\nAborting.", e.display());
+                        error!("error when opening amcodec: {}\nAborting.", e.display());
                         return ();
                     }
                 };
+                if eof {
+                    // this will unblock "wait_until_end" calls from the API, which may load the
+                    // next video right away: the new device must be ready by then
+                    let _r = status_sender.send(PlayerEvent::EndOfStream);
+                }
             },
-            Ok(_) => {},
+            Ok(None) => {},
         }
+        stats.state.store(amcodec.state.stats_code(), Ordering::Relaxed);
         // small sleep time avoids active waiting
-        thread::sleep(Duration::from_millis(10));
+        stats::sleep(&stats.amcodec_idle_us, Duration::from_millis(10));
+    }
+    debug!("shutting down ...");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::process_nal_packets;
+    use super::super::libavhelper::Packet;
+    use super::super::pool::BufferPool;
+    use libavformat as libav;
+    use std::{mem, ptr, slice};
+    use std::sync::Arc;
+
+    #[cfg(not(target_arch = "aarch64"))]
+    #[test]
+    fn frame_count_follows_the_dummy_frames() {
+        use super::{Amcodec, DUMMY_FRAMES_ENV, DUMMY_IN_USE};
+        use std::env;
+        use std::sync::atomic::Ordering;
+        use std::thread;
+        use std::time::Duration;
+
+        while DUMMY_IN_USE.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        env::set_var(DUMMY_FRAMES_ENV, "2000");
+        let amcodec = Amcodec::new();
+        env::remove_var(DUMMY_FRAMES_ENV);
+        DUMMY_IN_USE.store(false, Ordering::SeqCst);
+        let mut amcodec = amcodec.unwrap();
+        amcodec.play();
+        assert_eq!(amcodec.get_decoder_state().unwrap().frame_count, 0);
+        // past the 1000 frames of the default
+        for _ in 0..1500 {
+            assert!(!amcodec.update());
+        }
+        assert_eq!(amcodec.get_decoder_state().unwrap().frame_count, 1500);
+        assert_eq!(amcodec.timing_info().unwrap().unwrap().vpts_secs, 25.0);
+        for _ in 0..500 {
+            assert!(!amcodec.update());
+        }
+        assert_eq!(amcodec.get_decoder_state().unwrap().frame_count, 2000);
+        assert!(amcodec.update());
+    }
+
+    #[test]
+    fn nal_lengths_become_start_codes() {
+        let mut data = vec![0, 0, 0, 2, 0x26, 0x01, 0, 0, 0, 0, 0, 0, 0, 1, 0x02];
+        process_nal_packets(&mut data).unwrap();
+        assert_eq!(data, vec![0, 0, 0, 1, 0x26, 0x01, 0, 0, 0, 1, 0, 0, 0, 1, 0x02]);
+        let mut empty = vec![];
+        process_nal_packets(&mut empty).unwrap();
+    }
+
+    #[test]
+    fn start_codes_are_left_alone() {
+        let mut data = vec![0, 0, 0, 1, 0x26, 0x01, 0xff, 0xff, 0xff, 0xff];
+        process_nal_packets(&mut data).unwrap();
+        assert_eq!(data, vec![0, 0, 0, 1, 0x26, 0x01, 0xff, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn nal_lengths_past_the_end() {
+        let cases : &[&[u8]] = &[
+            &[0xff, 0xff, 0xff, 0xff, 0x26, 0x01],
+            &[0xff, 0xff, 0xff, 0xff],
+            &[0, 0, 0, 3, 0x26, 0x01],
+            &[0, 0, 0, 2, 0x26, 0x01, 0x80, 0, 0, 0, 0],
+        ];
+        for case in cases {
+            let mut data = case.to_vec();
+            assert!(process_nal_packets(&mut data).is_err(), "{:?}", case);
+        }
+    }
+
+    #[test]
+    fn fewer_than_4_trailing_bytes() {
+        for trailing in 1..4 {
+            let mut data = vec![0, 0, 0, 2, 0x26, 0x01];
+            data.extend(vec![0; trailing]);
+            assert!(process_nal_packets(&mut data).is_err(), "{} trailing bytes", trailing);
+            let mut data = vec![0; trailing];
+            assert!(process_nal_packets(&mut data).is_err(), "{} bytes alone", trailing);
+        }
     }
-    if cfg!(debug_assertions) {
-        println!("amcodec_thread: shutting down ...");
+
+    /// The demuxer keeps a reference to the buffer of the packets it returns: the NAL rewrite
+    /// used to go through pkt.data into that shared buffer
+    #[test]
+    fn rewrite_leaves_a_shared_buffer_alone() {
+        let payload = [0, 0, 0, 3, 0x26, 0x01, 0xaf, 0, 0, 0, 2, 0x02, 0x01];
+        unsafe {
+            let mut demuxer_ref = libav::av_buffer_alloc(payload.len() as i32);
+            assert!(!demuxer_ref.is_null());
+            ptr::copy_nonoverlapping(payload.as_ptr(), (*demuxer_ref).data, payload.len());
+            let mut packet = Packet {
+                inner: mem::zeroed(),
+                time_base: 1.0 / 25.0,
+            };
+            packet.inner.buf = libav::av_buffer_ref(demuxer_ref) as *mut _;
+            packet.inner.data = (*demuxer_ref).data;
+            packet.inner.size = payload.len() as i32;
+            assert_eq!(libav::av_buffer_get_ref_count(demuxer_ref), 2);
+            let mut video = packet.to_video(&Arc::new(BufferPool::new(4)));
+            // the copy doesn't keep the buffer alive
+            drop(packet);
+            assert_eq!(libav::av_buffer_get_ref_count(demuxer_ref), 1);
+            process_nal_packets(&mut video.data).unwrap();
+            assert_eq!(&video.data[..], &[0, 0, 0, 1, 0x26, 0x01, 0xaf, 0, 0, 0, 1, 0x02, 0x01][..]);
+            assert_eq!(slice::from_raw_parts((*demuxer_ref).data, payload.len()), &payload[..]);
+            libav::av_buffer_unref(&mut demuxer_ref);
+        }
     }
 }
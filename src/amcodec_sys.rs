@@ -6,7 +6,10 @@
 #![allow(non_snake_case)]
 #![allow(non_upper_case_globals)]
 #![allow(dead_code)]
-use libc::{c_int, c_uint, c_ulong, c_ulonglong, c_void};
+use error::*;
+use libc::{c_int, c_uint, c_long, c_uchar, c_ulong, c_ulonglong, c_void};
+use std::mem::{self, MaybeUninit};
+use std::os::unix::io::RawFd;
 
 // const are equivalent to #DEFINE in C: they don't hold a place in memory,
 // they are automatically replaced by the associated value every time this
@@ -38,6 +41,228 @@ ioctl!(readwrite amstream_ioc_get_vb_status with b'S', 0xc3; am_ioctl_parm_ex);
 ioctl!(write amstream_ioc_sysinfo with b'S', 0x0a; c_int);
 ioctl!(write amstream_ioc_clear_video with b'S', 0x1f; c_int);
 ioctl!(write amstream_ioc_vpause with b'S', 0x17; c_int);
+// these return a 32 bit timestamp in 90kHz units
+ioctl!(read amstream_ioc_apts with b'S', 0x40; c_uint);
+ioctl!(read amstream_ioc_vpts with b'S', 0x41; c_uint);
+ioctl!(read amstream_ioc_pcrscr with b'S', 0x42; c_uint);
+// like vpause, the value is passed directly instead of a pointer to it
+// 0 enables the video layer, 1 disables it
+ioctl!(write amstream_ioc_set_video_disable with b'S', 0x49; c_int);
+
+// ALSA's control interface, see <sound/asound.h>: /dev/snd/controlC{card} has one element per
+// setting of the card's mixer, looked up by name
+ioctl!(readwrite sndrv_ctl_ioctl_elem_info with b'U', 0x11; snd_ctl_elem_info);
+ioctl!(readwrite sndrv_ctl_ioctl_elem_write with b'U', 0x13; snd_ctl_elem_value);
+pub const SNDRV_CTL_ELEM_IFACE_MIXER : c_int = 2;
+pub const SNDRV_CTL_ELEM_TYPE_INTEGER : c_int = 2;
+pub const SNDRV_CTL_ELEM_ID_NAME_MAXLEN : usize = 44;
+
+// /dev/amvideocap0 scales the frame shown by the VPU with the ge2d, then read() returns it. The
+// wanted format and size are passed directly instead of a pointer, like vpause.
+ioctl!(write amvideocap_iow_set_wantframe_format with b'V', 0x01; c_int);
+ioctl!(write amvideocap_iow_set_wantframe_width with b'V', 0x02; c_int);
+ioctl!(write amvideocap_iow_set_wantframe_height with b'V', 0x03; c_int);
+// size of the frame being shown, before any scaling
+ioctl!(read amvideocap_ior_get_srcframe_width with b'V', 0x21; c_int);
+ioctl!(read amvideocap_ior_get_srcframe_height with b'V', 0x22; c_int);
+// GE2D_FMT_S24_RGB | GE2D_COLOR_MAP_RGB888 in ge2d.h: 3 bytes per pixel, R first
+pub const GE2D_FORMAT_S24_RGB : c_int = 0x00200;
+
+// Typed wrappers around the ioctls above: the functions generated by ioctl! take raw pointers
+// (and sometimes values disguised as pointers), these take what the driver actually expects.
+// The only unsafe left is in here.
+
+/// errno is read right away, before anything else can change it
+fn check(ret: c_int, call: &'static str) -> Result<()> {
+    if ret < 0 {
+        return Err(Error::ioctl(call));
+    }
+    Ok(())
+}
+
+/// for the ioctls which take their value directly instead of a pointer to it
+fn by_value(value: c_int) -> *const c_int {
+    value as usize as *const c_int
+}
+
+pub fn fb_get_vscreen_info(fd: RawFd) -> Result<FbVarScreeninfo> {
+    let mut screeninfo = MaybeUninit::<FbVarScreeninfo>::uninit();
+    check(unsafe { fbio_get_vscreen_info(fd, screeninfo.as_mut_ptr() as *mut u8) }, "fbio_get_vscreen_info")?;
+    Ok(unsafe { screeninfo.assume_init() })
+}
+
+pub fn fb_set_vscreen_info(fd: RawFd, screeninfo: &FbVarScreeninfo) -> Result<()> {
+    check(unsafe { fbio_set_vscreen_info(fd, screeninfo as *const _ as *mut u8) }, "fbio_set_vscreen_info")
+}
+
+/// AMSTREAM_SET_VFORMAT
+pub fn amstream_set_vformat(fd: RawFd, format: vformat_t) -> Result<()> {
+    let mut parm : am_ioctl_parm = unsafe { mem::zeroed() };
+    parm.cmd = AMSTREAM_SET_VFORMAT;
+    parm.union.data_vformat = format;
+    check(unsafe { amstream_ioc_set(fd, &parm) }, "amstream_ioc_set")
+}
+
+/// AMSTREAM_SET_TSTAMP, pts in 90kHz units
+pub fn amstream_set_tstamp(fd: RawFd, pts: u32) -> Result<()> {
+    let mut parm : am_ioctl_parm = unsafe { mem::zeroed() };
+    parm.cmd = AMSTREAM_SET_TSTAMP;
+    parm.union.data_32 = pts;
+    check(unsafe { amstream_ioc_set(fd, &parm) }, "amstream_ioc_set")
+}
+
+pub fn amstream_set_sysinfo(fd: RawFd, info: &dec_sysinfo_t) -> Result<()> {
+    // see the declaration of amstream_ioc_sysinfo for the cast
+    check(unsafe { amstream_ioc_sysinfo(fd, info as *const _ as *const c_int) }, "amstream_ioc_sysinfo")
+}
+
+/// The [left, top, right, bottom] amstream_set_video_axis takes for (x, y, width, height). The
+/// right and bottom edges stop at c_int::MAX instead of wrapping around.
+pub fn video_axis_edges((x, y, width, height): (i32, i32, u32, u32)) -> [c_int; 4] {
+    let edge = |pos: i32, size: u32| -> c_int {
+        let edge = pos as i64 + size as i64;
+        if edge > c_int::max_value() as i64 { c_int::max_value() } else { edge as c_int }
+    };
+    [x as c_int, y as c_int, edge(x, width), edge(y, height)]
+}
+
+/// [left, top, right, bottom]
+pub fn amstream_set_video_axis(fd: RawFd, axis: &[c_int; 4]) -> Result<()> {
+    check(unsafe { amstream_ioc_set_video_axis(fd, axis.as_ptr()) }, "amstream_ioc_set_video_axis")
+}
+
+pub fn amstream_clear_video(fd: RawFd) -> Result<()> {
+    let value : c_int = 1;
+    check(unsafe { amstream_ioc_clear_video(fd, &value) }, "amstream_ioc_clear_video")
+}
+
+pub fn amstream_vpause(fd: RawFd, pause: bool) -> Result<()> {
+    check(unsafe { amstream_ioc_vpause(fd, by_value(pause as c_int)) }, "amstream_ioc_vpause")
+}
+
+pub fn amstream_set_video_disable(fd: RawFd, disable: bool) -> Result<()> {
+    check(unsafe { amstream_ioc_set_video_disable(fd, by_value(disable as c_int)) }, "amstream_ioc_set_video_disable")
+}
+
+/// AMSTREAM_GET_EX_*, the answer is in the union of the returned value
+pub fn amstream_get_ex(fd: RawFd, cmd: c_uint) -> Result<am_ioctl_parm_ex> {
+    let mut parm : am_ioctl_parm_ex = unsafe { mem::zeroed() };
+    parm.cmd = cmd;
+    check(unsafe { amstream_ioc_get_vb_status(fd, &mut parm) }, "amstream_ioc_get_vb_status")?;
+    Ok(parm)
+}
+
+pub fn amstream_get_version(fd: RawFd) -> Result<c_int> {
+    let mut version : c_int = 0;
+    check(unsafe { amstream_ioc_get_version(fd, &mut version) }, "amstream_ioc_get_version")?;
+    Ok(version)
+}
+
+/// The timestamps the VPU is at, in 90kHz units
+#[derive(Debug, Clone, Copy)]
+pub enum PtsKind {
+    Video,
+    Audio,
+    /// the system clock the video is synced to
+    Pcr,
+}
+
+pub fn amstream_get_pts(fd: RawFd, kind: PtsKind) -> Result<c_uint> {
+    let mut pts : c_uint = 0;
+    let ret = match kind {
+        PtsKind::Video => check(unsafe { amstream_ioc_vpts(fd, &mut pts) }, "amstream_ioc_vpts"),
+        PtsKind::Audio => check(unsafe { amstream_ioc_apts(fd, &mut pts) }, "amstream_ioc_apts"),
+        PtsKind::Pcr => check(unsafe { amstream_ioc_pcrscr(fd, &mut pts) }, "amstream_ioc_pcrscr"),
+    };
+    ret.map(|_| pts)
+}
+
+/// (width, height) of the frame shown
+pub fn amvideocap_get_srcframe_size(fd: RawFd) -> Result<(c_int, c_int)> {
+    let (mut width, mut height) : (c_int, c_int) = (0, 0);
+    check(unsafe { amvideocap_ior_get_srcframe_width(fd, &mut width) }, "amvideocap_ior_get_srcframe_width")?;
+    check(unsafe { amvideocap_ior_get_srcframe_height(fd, &mut height) }, "amvideocap_ior_get_srcframe_height")?;
+    Ok((width, height))
+}
+
+/// format is GE2D_FORMAT_*
+pub fn amvideocap_set_wantframe(fd: RawFd, format: c_int, width: c_int, height: c_int) -> Result<()> {
+    check(unsafe { amvideocap_iow_set_wantframe_format(fd, by_value(format)) }, "amvideocap_iow_set_wantframe_format")?;
+    check(unsafe { amvideocap_iow_set_wantframe_width(fd, by_value(width)) }, "amvideocap_iow_set_wantframe_width")?;
+    check(unsafe { amvideocap_iow_set_wantframe_height(fd, by_value(height)) }, "amvideocap_iow_set_wantframe_height")
+}
+
+/// The mixer element called `name`, the name being truncated to what ALSA allows
+fn snd_ctl_mixer_elem_id(name: &str) -> snd_ctl_elem_id {
+    let mut id : snd_ctl_elem_id = unsafe { mem::zeroed() };
+    id.iface = SNDRV_CTL_ELEM_IFACE_MIXER;
+    // the last byte is left to 0
+    for (dst, src) in id.name.iter_mut().zip(name.bytes().take(SNDRV_CTL_ELEM_ID_NAME_MAXLEN - 1)) {
+        *dst = src;
+    }
+    id
+}
+
+/// (min, max, number of channels) of the integer element `name` of the mixer. Fails with ENOENT
+/// if the card doesn't have it.
+pub fn snd_ctl_integer_info(fd: RawFd, name: &str) -> Result<(c_long, c_long, c_uint)> {
+    let mut info : snd_ctl_elem_info = unsafe { mem::zeroed() };
+    info.id = snd_ctl_mixer_elem_id(name);
+    check(unsafe { sndrv_ctl_ioctl_elem_info(fd, &mut info) }, "sndrv_ctl_ioctl_elem_info")?;
+    if info.type_ != SNDRV_CTL_ELEM_TYPE_INTEGER {
+        return Err(Error::Unsupported(format!("the mixer element `{}` is not an integer", name)));
+    }
+    // value.integer.min and value.integer.max
+    Ok((info.value[0], info.value[1], info.count))
+}
+
+/// One value per channel of the integer element `name`, the extra ones are ignored
+pub fn snd_ctl_write_integer(fd: RawFd, name: &str, values: &[c_long]) -> Result<()> {
+    let mut value : snd_ctl_elem_value = unsafe { mem::zeroed() };
+    value.id = snd_ctl_mixer_elem_id(name);
+    for (dst, src) in value.value.iter_mut().zip(values) {
+        *dst = *src;
+    }
+    check(unsafe { sndrv_ctl_ioctl_elem_write(fd, &mut value) }, "sndrv_ctl_ioctl_elem_write")
+}
+
+// see snd_ctl_elem_id at <sound/asound.h>
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct snd_ctl_elem_id {
+    pub numid: c_uint,
+    pub iface: c_int,
+    pub device: c_uint,
+    pub subdevice: c_uint,
+    pub name: [c_uchar; SNDRV_CTL_ELEM_ID_NAME_MAXLEN],
+    pub index: c_uint,
+}
+
+// see snd_ctl_elem_info at <sound/asound.h>, for 64-bit longs
+#[repr(C)]
+pub struct snd_ctl_elem_info {
+    pub id: snd_ctl_elem_id,
+    pub type_: c_int,
+    pub access: c_uint,
+    pub count: c_uint,
+    pub owner: c_int,
+    /// a union of 128 bytes, only value.integer (min, max, step) is used here
+    pub value: [c_long; 16],
+    /// dimen, and the reserved bytes after it
+    pub reserved: [c_uchar; 64],
+}
+
+// see snd_ctl_elem_value at <sound/asound.h>, for 64-bit longs
+#[repr(C)]
+pub struct snd_ctl_elem_value {
+    pub id: snd_ctl_elem_id,
+    /// a 1-bit field, obsolete
+    pub indirect: c_uint,
+    /// a union, only value.integer.value is used here
+    pub value: [c_long; 128],
+    /// tstamp on older kernels, unused either way
+    pub reserved: [c_uchar; 128],
+}
 
 // see fb_var_screeninfo at <linux/fb.h>
 #[repr(C)]
@@ -218,8 +443,10 @@ impl Clone for buf_status {
 pub struct vdec_status {
     pub width: ::std::os::raw::c_uint,
     pub height: ::std::os::raw::c_uint,
+    /// frames per second, rounded
     pub fps: ::std::os::raw::c_uint,
     pub error_count: ::std::os::raw::c_uint,
+    /// bit field of the decoder's internal state, undocumented
     pub status: ::std::os::raw::c_uint,
 }
 
@@ -277,3 +504,29 @@ impl Clone for am_ioctl_parm_ex_union {
 impl Clone for am_ioctl_parm_ex {
     fn clone(&self) -> Self { *self }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what sizeof gives with the kernel's headers, which the ioctl numbers depend on
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn alsa_struct_sizes() {
+        assert_eq!(mem::size_of::<snd_ctl_elem_id>(), 64);
+        assert_eq!(mem::size_of::<snd_ctl_elem_info>(), 272);
+        assert_eq!(mem::size_of::<snd_ctl_elem_value>(), 1224);
+    }
+
+    #[test]
+    fn video_axis_edges_of_large_rects() {
+        // a 4K rectangle pushed to the bottom right, which didn't fit in 16 bits
+        assert_eq!(video_axis_edges((2000, 1500, 3840, 2160)), [2000, 1500, 5840, 3660]);
+        assert_eq!(video_axis_edges((40000, 0, 1920, 1080)), [40000, 0, 41920, 1080]);
+        // overscan compensation
+        assert_eq!(video_axis_edges((-20, -12, 1960, 1104)), [-20, -12, 1940, 1092]);
+        assert_eq!(video_axis_edges((i32::min_value(), 0, u32::max_value(), 1)), [i32::min_value(), 0, i32::max_value(), 1]);
+        assert_eq!(video_axis_edges((i32::max_value(), i32::max_value(), 1, u32::max_value())),
+                   [i32::max_value(), i32::max_value(), i32::max_value(), i32::max_value()]);
+    }
+}
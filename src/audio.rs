@@ -0,0 +1,254 @@
+/*
+ * Nothing plays audio yet, only the HEVC stream is demuxed. The ALSA device and the volume can
+ * still be chosen through the API, and are kept by FfiPlayer across loads: HDMI and the analog
+ * output are two different cards on most Amlogic boards ("hw:0,0" and "hw:1,0" for instance).
+ *
+ * The volume goes to the mixer of the device's card, so that whatever else plays on it follows.
+ */
+
+use error::*;
+use sysfs;
+#[cfg(target_arch = "aarch64")]
+use amcodec_sys::{snd_ctl_integer_info, snd_ctl_write_integer};
+#[cfg(target_arch = "aarch64")]
+use libc::c_long;
+#[cfg(target_arch = "aarch64")]
+use std::fs::OpenOptions;
+#[cfg(target_arch = "aarch64")]
+use std::os::unix::io::AsRawFd;
+
+/// One card per entry, as " 0 [AMLAUGESOUND  ]: AML-AUGESOUND - AML-AUGESOUND", followed by a
+/// line with the long name of the card
+const ASOUND_CARDS_PATH : &'static str = "/proc/asound/cards";
+
+/// Used when no card is listed at all
+const FALLBACK_DEVICE : &'static str = "default";
+
+/// The volume elements looked for in the mixer of the card, the first one the card has is used
+#[cfg(target_arch = "aarch64")]
+const MIXER_ELEMENTS : [&'static str; 3] = ["Master Playback Volume", "PCM Playback Volume", "DAC Playback Volume"];
+
+pub struct AudioDevice {
+    /// can be given as-is to ALSA, "hw:{card},0"
+    pub device: String,
+    pub name: String,
+}
+
+pub fn available_devices() -> Result<Vec<AudioDevice>> {
+    let cards = sysfs::read(ASOUND_CARDS_PATH)?;
+    Ok(cards.lines()
+        .filter_map(|line| {
+            let line = line.trim_left();
+            let mut parts = line.splitn(2, ' ');
+            let card : u32 = match parts.next().map(|index| index.parse()) {
+                Some(Ok(card)) => card,
+                // the long name of the previous card
+                _ => return None,
+            };
+            let name = parts.next().and_then(|rest| rest.splitn(2, "]:").nth(1)).unwrap_or("").trim();
+            Some(AudioDevice {
+                device: format!("hw:{},0", card),
+                name: name.to_owned(),
+            })
+        })
+        .collect())
+}
+
+/// The first card, or ALSA's default device if none could be found
+pub fn default_device() -> String {
+    match available_devices() {
+        Ok(ref devices) if !devices.is_empty() => devices[0].device.clone(),
+        _ => FALLBACK_DEVICE.to_owned(),
+    }
+}
+
+/// Volume of the player from 0.0 to 1.0, kept by FfiPlayer across loads. Muting remembers the
+/// volume chosen before, so that unmuting goes back to it.
+pub struct Volume {
+    /// what the mixer is set to, 0 while muted
+    volume: f32,
+    muted: bool,
+    /// what `volume` was when the player was muted
+    pre_mute_volume: f32,
+}
+
+impl Volume {
+    pub fn new() -> Volume {
+        Volume {
+            volume: 1.0,
+            muted: false,
+            pre_mute_volume: 1.0,
+        }
+    }
+
+    /// While muted, the volume is only applied once unmuted
+    pub fn set(&mut self, volume: f32) {
+        if self.muted {
+            self.pre_mute_volume = volume;
+        } else {
+            self.volume = volume;
+        }
+    }
+
+    /// The volume chosen by the user, even while muted
+    pub fn get(&self) -> f32 {
+        if self.muted { self.pre_mute_volume } else { self.volume }
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        if muted == self.muted {
+            return;
+        }
+        if muted {
+            self.pre_mute_volume = self.volume;
+            self.volume = 0.0;
+        } else {
+            self.volume = self.pre_mute_volume;
+        }
+        self.muted = muted;
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+}
+
+/// The audio settings of a player. FfiPlayer reads them, the amcodec thread changes them and
+/// applies the volume to the mixer.
+pub struct AudioOutput {
+    /// ALSA device, such as "hw:1,0"
+    pub device: String,
+    pub volume: Volume,
+}
+
+impl AudioOutput {
+    pub fn new() -> AudioOutput {
+        AudioOutput {
+            device: default_device(),
+            volume: Volume::new(),
+        }
+    }
+
+    /// Written to the mixer right away unless muted. Nothing changes if the mixer refuses it.
+    pub fn set_volume(&mut self, volume: f32) -> Result<()> {
+        if !self.volume.is_muted() {
+            write_mixer_volume(&self.device, volume)?;
+        }
+        self.volume.set(volume);
+        Ok(())
+    }
+
+    /// The mixer goes to 0 when muting, and back to the volume chosen before when unmuting
+    pub fn set_muted(&mut self, muted: bool) -> Result<()> {
+        if muted == self.volume.is_muted() {
+            return Ok(());
+        }
+        let level = if muted { 0.0 } else { self.volume.get() };
+        write_mixer_volume(&self.device, level)?;
+        self.volume.set_muted(muted);
+        Ok(())
+    }
+}
+
+/// Card of an ALSA device such as "hw:1,0" or "plughw:1". Devices without a card, such as
+/// "default", are the first card.
+fn card_of(device: &str) -> Result<u32> {
+    match device.splitn(2, ':').nth(1) {
+        Some(rest) => rest.split(',').next().unwrap_or("").parse().map_err(|_| {
+            Error::Unsupported(format!("can't tell the card of the audio device `{}`", device))
+        }),
+        None => Ok(0),
+    }
+}
+
+/// volume from 0.0 to 1.0, scaled to the range of the first element of MIXER_ELEMENTS the card
+/// of `device` has, on every channel
+#[cfg(target_arch = "aarch64")]
+fn write_mixer_volume(device: &str, volume: f32) -> Result<()> {
+    let path = format!("/dev/snd/controlC{}", card_of(device)?);
+    let control = OpenOptions::new().read(true).write(true).open(&path).map_err(|e| Error::open(e, &path))?;
+    for name in MIXER_ELEMENTS.iter() {
+        let (min, max, channels) = match snd_ctl_integer_info(control.as_raw_fd(), name) {
+            Ok(info) => info,
+            // not on this card
+            Err(Error::Ioctl { errno: ::libc::ENOENT, .. }) => continue,
+            Err(e) => return Err(e),
+        };
+        let value = min + ((max - min) as f64 * volume as f64).round() as c_long;
+        debug!("setting `{}` of {} to {} ({}..{})", name, path, value, min, max);
+        return snd_ctl_write_integer(control.as_raw_fd(), name, &vec![value; channels as usize]);
+    }
+    Err(Error::Unsupported(format!("the mixer of {} has no volume", path)))
+}
+
+/// There's no mixer to write to, only the card is checked
+#[cfg(not(target_arch = "aarch64"))]
+fn write_mixer_volume(device: &str, volume: f32) -> Result<()> {
+    let card = card_of(device)?;
+    debug!("(dummy) volume of card {} set to {}", card, volume);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output() -> AudioOutput {
+        AudioOutput {
+            device: String::from("hw:1,0"),
+            volume: Volume::new(),
+        }
+    }
+
+    #[test]
+    fn unmuting_restores_the_volume() {
+        let mut audio = output();
+        audio.set_volume(0.4).unwrap();
+        audio.set_muted(true).unwrap();
+        assert!(audio.volume.is_muted());
+        assert_eq!(audio.volume.get(), 0.4);
+        audio.set_muted(false).unwrap();
+        assert!(!audio.volume.is_muted());
+        assert_eq!(audio.volume.get(), 0.4);
+    }
+
+    #[test]
+    fn volume_set_while_muted_applies_once_unmuted() {
+        let mut audio = output();
+        audio.set_muted(true).unwrap();
+        audio.set_volume(0.7).unwrap();
+        assert!(audio.volume.is_muted());
+        assert_eq!(audio.volume.get(), 0.7);
+        audio.set_muted(false).unwrap();
+        assert_eq!(audio.volume.get(), 0.7);
+    }
+
+    #[test]
+    fn muting_twice_keeps_the_volume() {
+        let mut audio = output();
+        audio.set_volume(0.5).unwrap();
+        audio.set_muted(true).unwrap();
+        audio.set_muted(true).unwrap();
+        audio.set_muted(false).unwrap();
+        assert_eq!(audio.volume.get(), 0.5);
+    }
+
+    #[test]
+    fn unknown_card_changes_nothing() {
+        let mut audio = output();
+        audio.device = String::from("hw:CARD=HDMI,DEV=0");
+        assert!(audio.set_muted(true).is_err());
+        assert!(!audio.volume.is_muted());
+        assert!(audio.set_volume(0.2).is_err());
+        assert_eq!(audio.volume.get(), 1.0);
+    }
+
+    #[test]
+    fn cards() {
+        assert_eq!(card_of("hw:1,0").ok(), Some(1));
+        assert_eq!(card_of("hw:0").ok(), Some(0));
+        assert_eq!(card_of("plughw:2,3").ok(), Some(2));
+        assert_eq!(card_of("default").ok(), Some(0));
+        assert!(card_of("hw:").is_err());
+    }
+}
@@ -0,0 +1,176 @@
+/*
+ * aml_video_player_benchmark: how fast a file is opened and demuxed on this board, for system
+ * integrators comparing hardware. Everything runs in the calling thread, without any player: the
+ * packets are only counted, never sent to the VPU. On Amlogic hardware, the first packets of the
+ * video are then written to the VPU in one go, to measure how fast it takes them.
+ *
+ * aml_video_player_benchmark_pipeline measures the whole pipeline instead: a headless player
+ * plays the file, and its counters (see stats.rs) tell how many packets went through the libav
+ * and amcodec threads and how long each of them waited. Off Amlogic hardware the dummy amcodec
+ * takes the packets, so this measures the per-packet overhead of the threads alone.
+ */
+
+use error::*;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+use super::libavhelper::{self, Context, NetworkOptions};
+use super::utils::{duration_to_secs, single_use_channel};
+use super::player::{self, Message};
+use super::stats::AmlPlayerStats;
+#[cfg(target_arch = "aarch64")]
+use super::libavhelper::{VideoPacket, PacketWrapper};
+#[cfg(target_arch = "aarch64")]
+use super::pool::BufferPool;
+
+/// Video packets kept for the write benchmark, few enough for the VPU's buffer to take them all
+/// without waiting for anything to be decoded
+#[cfg(target_arch = "aarch64")]
+const WRITE_BENCHMARK_LEN : usize = 2 * 1024 * 1024;
+
+/// Given as-is to the API user, see aml_video_player_benchmark
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct AmlBenchmarkResult {
+    /// avformat_open_input
+    pub open_ms: u32,
+    /// avformat_find_stream_info
+    pub stream_info_ms: u32,
+    /// from the start of the benchmark to the first packet of the video stream
+    pub first_packet_ms: u32,
+    /// packets of every stream
+    pub packets_per_sec: f32,
+    pub bytes_per_sec: f32,
+    /// written to the VPU, 0 when not running on Amlogic hardware or if it couldn't be opened
+    pub write_bytes_per_sec: f32,
+}
+
+/// Given as-is to the API user, see aml_video_player_benchmark_pipeline
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct AmlPipelineBenchmarkResult {
+    /// from the end of the load to the end of the video, or to the end of the benchmark
+    pub elapsed_ms: u32,
+    /// of the video stream, written to the VPU (to the dummy one off Amlogic hardware)
+    pub packets_per_sec: f32,
+    pub bytes_per_sec: f32,
+    /// how long each thread waited for something to do, it was working the rest of elapsed_ms
+    pub libav_idle_ms: u32,
+    pub amcodec_idle_ms: u32,
+}
+
+fn to_ms(duration: Duration) -> u32 {
+    (duration_to_secs(duration) * 1000.0).round() as u32
+}
+
+fn per_sec(count: u64, duration: Duration) -> f32 {
+    let secs = duration_to_secs(duration);
+    if secs > 0.0 { (count as f64 / secs) as f32 } else { 0.0 }
+}
+
+/// Reads the packets of url for `duration`, or until its end
+pub fn run(url: &str, duration: Duration) -> Result<AmlBenchmarkResult> {
+    libavhelper::register_all();
+    let started = Instant::now();
+    let (mut context, timings) = Context::new_timed(url, Arc::new(AtomicBool::new(false)), NetworkOptions::default(), None)?;
+    let mut result = AmlBenchmarkResult {
+        open_ms: to_ms(timings.open),
+        stream_info_ms: to_ms(timings.stream_info),
+        ..AmlBenchmarkResult::default()
+    };
+    #[cfg(target_arch = "aarch64")]
+    let (pool, mut kept, mut kept_len) = (Arc::new(BufferPool::new(0)), Vec::<VideoPacket>::new(), 0);
+    let (mut packets, mut bytes) = (0u64, 0u64);
+    let mut first_packet = true;
+    let demux_started = Instant::now();
+    while demux_started.elapsed() < duration {
+        let packet = match context.next_frame() {
+            Ok(packet) => packet,
+            Err(Error::Eof) => break,
+            Err(e) => return Err(e),
+        };
+        packets += 1;
+        bytes += packet.inner.size.max(0) as u64;
+        if packet.inner.stream_index as usize != context.hevc_stream {
+            continue;
+        }
+        if first_packet {
+            first_packet = false;
+            result.first_packet_ms = to_ms(started.elapsed());
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if kept_len < WRITE_BENCHMARK_LEN {
+                kept_len += packet.inner.size.max(0) as usize;
+                kept.push(packet.to_video(&pool));
+            }
+        }
+    }
+    let demux_time = demux_started.elapsed();
+    result.packets_per_sec = per_sec(packets, demux_time);
+    result.bytes_per_sec = per_sec(bytes, demux_time);
+    #[cfg(target_arch = "aarch64")]
+    {
+        let extra_data = context.get_extra_data()?;
+        result.write_bytes_per_sec = write_throughput(extra_data, kept).unwrap_or_else(|e| {
+            warn!("failed to measure the writes to the VPU: {}", e.display());
+            0.0
+        });
+    }
+    debug!("benchmark of {}: {:?}", url, result);
+    Ok(result)
+}
+
+/// Fails if a player is using the VPU
+#[cfg(target_arch = "aarch64")]
+fn write_throughput(extra_data: Vec<u8>, packets: Vec<VideoPacket>) -> Result<f32> {
+    use amcodec::Amcodec;
+
+    let mut amcodec = Amcodec::new()?;
+    let bytes : usize = packets.iter().map(|packet| packet.data.len()).sum();
+    let started = Instant::now();
+    amcodec.process_packet(PacketWrapper::ExtraData(extra_data))?;
+    for packet in packets {
+        amcodec.process_packet(PacketWrapper::Packet(packet))?;
+    }
+    amcodec.flush_staging()?;
+    let elapsed = started.elapsed();
+    // none of these frames may stay on the screen
+    amcodec.shutdown()?;
+    Ok(per_sec(bytes as u64, elapsed))
+}
+
+/// Plays url in a headless player for `duration`, or until its end. On Amlogic hardware no other
+/// player may be using the VPU.
+pub fn run_pipeline(url: &str, duration: Duration) -> Result<AmlPipelineBenchmarkResult> {
+    let mut player = player::player_start_offscreen()?;
+    let (tx, rx) = single_use_channel();
+    player.send_message(Message::Load(url.to_owned()), tx);
+    let reply = rx.recv().map_err(|_| Error::Other(String::from("the player stopped during the load")))?;
+    if let Some(report) = reply.report {
+        let _r = player.shutdown();
+        return Err(Error::Other(format!("failed to load {}: {}", url, report)));
+    }
+    let before = player.stats.snapshot(0);
+    let started = Instant::now();
+    while started.elapsed() < duration {
+        if let Some(event) = player.events.try_take_end() {
+            debug!("pipeline benchmark of {} ended early: {:?}", url, event);
+            break;
+        }
+        ::std::thread::sleep(Duration::from_millis(10));
+    }
+    let elapsed = started.elapsed();
+    let after = player.stats.snapshot(0);
+    let _r = player.shutdown();
+    let delta = |field: fn(&AmlPlayerStats) -> u64| field(&after).saturating_sub(field(&before));
+    let result = AmlPipelineBenchmarkResult {
+        elapsed_ms: to_ms(elapsed),
+        packets_per_sec: per_sec(delta(|stats| stats.packets_written), elapsed),
+        bytes_per_sec: per_sec(delta(|stats| stats.bytes_written), elapsed),
+        libav_idle_ms: delta(|stats| stats.libav_idle_ms) as u32,
+        amcodec_idle_ms: delta(|stats| stats.amcodec_idle_ms) as u32,
+    };
+    debug!("pipeline benchmark of {}: {:?}", url, result);
+    Ok(result)
+}
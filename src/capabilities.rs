@@ -0,0 +1,101 @@
+/*
+ * What the board can do, as a bitmask of AML_CAP_*, so that API users can hide what isn't
+ * available instead of trying every call. Everything is probed from the drivers' nodes, and
+ * only once: the hardware doesn't change while we run.
+ *
+ * Nothing is decoded in software, so nothing is reported on other architectures.
+ */
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const AML_CAP_HEVC : u32 = 1;
+pub const AML_CAP_H264 : u32 = 2;
+pub const AML_CAP_VP9 : u32 = 4;
+pub const AML_CAP_HDR10 : u32 = 8;
+pub const AML_CAP_HLG : u32 = 16;
+pub const AML_CAP_DEINTERLACE : u32 = 32;
+pub const AML_CAP_AUDIO_PASSTHROUGH : u32 = 64;
+pub const AML_CAP_CLOSED_CAPTIONS : u32 = 128;
+
+/// usize::MAX until the first probe
+static CAPABILITIES : AtomicUsize = AtomicUsize::new(::std::usize::MAX);
+
+/// Probed on the first call only. Two threads calling this at the same time both probe, which is
+/// harmless.
+pub fn get() -> u32 {
+    let cached = CAPABILITIES.load(Ordering::SeqCst);
+    if cached != ::std::usize::MAX {
+        return cached as u32;
+    }
+    let capabilities = probe();
+    debug!("capabilities: {:#x}", capabilities);
+    CAPABILITIES.store(capabilities as usize, Ordering::SeqCst);
+    capabilities
+}
+
+#[cfg(target_arch = "aarch64")]
+fn probe() -> u32 {
+    use std::path::Path;
+    use sysfs;
+
+    /// one "codec:profiles;" per line, the codecs the VPU's firmware can decode
+    const VCODEC_PROFILE_PATH : &'static str = "/sys/class/amstream/vcodec_profile";
+    /// what the TV connected says it can show, "SMPTE ST 2084: 1" and the like
+    const HDR_CAP_PATH : &'static str = "/sys/class/amhdmitx/amhdmitx0/hdr_cap";
+    /// audio formats of the TV's EDID, PCM is always there
+    const AUD_CAP_PATH : &'static str = "/sys/class/amhdmitx/amhdmitx0/aud_cap";
+    /// the deinterlacer, whose nr2_level is also the noise reduction
+    const DI_PATH : &'static str = "/sys/module/di";
+    /// the CEA-608/708 user data of the video stream is read from there
+    const USERDATA_PATH : &'static str = "/dev/amstream_userdata";
+
+    let mut capabilities = 0;
+    let codecs : Vec<String> = sysfs::read(VCODEC_PROFILE_PATH)
+        .map(|content| content.lines()
+            .filter_map(|line| line.split(':').next())
+            .map(|codec| codec.trim().to_lowercase())
+            .collect())
+        .unwrap_or_else(|e| {
+            warn!("failed to read the codecs of the VPU: {}", e.display());
+            Vec::new()
+        });
+    // older kernels don't have vcodec_profile, but we know how to open the HEVC decoder
+    if codecs.iter().any(|codec| codec == "hevc") || Path::new("/dev/amstream_hevc").exists() {
+        capabilities |= AML_CAP_HEVC;
+    }
+    if codecs.iter().any(|codec| codec == "h264") {
+        capabilities |= AML_CAP_H264;
+    }
+    if codecs.iter().any(|codec| codec == "vp9") {
+        capabilities |= AML_CAP_VP9;
+    }
+    if let Ok(hdr_cap) = sysfs::read(HDR_CAP_PATH) {
+        let supports = |name: &str| hdr_cap.lines().any(|line| {
+            line.starts_with(name) && line.trim_right().ends_with('1')
+        });
+        if supports("SMPTE ST 2084") {
+            capabilities |= AML_CAP_HDR10;
+        }
+        if supports("Hybrid Log-Gamma") {
+            capabilities |= AML_CAP_HLG;
+        }
+    }
+    if Path::new(DI_PATH).exists() {
+        capabilities |= AML_CAP_DEINTERLACE;
+    }
+    if let Ok(aud_cap) = sysfs::read(AUD_CAP_PATH) {
+        // "CodingType MaxChannels SamplingFreq SampleSize" then one format per line
+        if aud_cap.lines().skip(1).any(|line| !line.trim().is_empty() && !line.trim_left().starts_with("PCM")) {
+            capabilities |= AML_CAP_AUDIO_PASSTHROUGH;
+        }
+    }
+    if Path::new(USERDATA_PATH).exists() {
+        capabilities |= AML_CAP_CLOSED_CAPTIONS;
+    }
+    capabilities
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+fn probe() -> u32 {
+    0
+}
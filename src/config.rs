@@ -0,0 +1,183 @@
+/*
+ * Settings which can only be chosen when the player is created, since they are given to the
+ * threads and the window as they are spawned. Everything is checked by the builder, so that a
+ * wrong value is refused by aml_video_player_create_with_config instead of misbehaving deep in a
+ * worker thread.
+ */
+
+use amcodec::BlackoutMode;
+use error::*;
+use libc::{c_char, c_uint, c_ushort};
+use std::ffi::CStr;
+use std::time::Duration;
+
+/// see PlayerConfig::finishing_threshold
+pub const DEFAULT_FINISHING_THRESHOLD : u32 = 3;
+/// below this, libav would wait for amcodec after almost every packet
+pub const MIN_PACKET_BUFFER_BOUND : u32 = 4;
+pub const DEFAULT_WINDOW_SIZE : (u16, u16) = (800, 600);
+const MIN_NETWORK_TIMEOUT_SECS : u64 = 1;
+const MAX_NETWORK_TIMEOUT_SECS : u64 = 120;
+pub const DEFAULT_TIMESHIFT_SIZE_MB : u32 = 512;
+/// a few seconds of a high bitrate stream
+const MIN_TIMESHIFT_SIZE_MB : u32 = 16;
+
+#[derive(Debug, Clone)]
+pub struct PlayerConfig {
+    /// how many checks in a row the VPU's buffer must stay the same after EOF before the video is
+    /// considered finished, see amcodec::State::Finishing
+    pub finishing_threshold: u32,
+    /// maximum number of packets waiting between libav and amcodec, None means unbounded
+    pub packet_buffer_bound: Option<u32>,
+    /// (width, height) of the window when it is created
+    pub window_size: (u16, u16),
+    /// blocking reads and opens of network streams fail after this long, None means never
+    pub network_timeout: Option<Duration>,
+    /// file a paused live stream is spooled to, see timeshift.rs. None disables the timeshift.
+    pub timeshift_path: Option<String>,
+    /// most that file takes on disk, in bytes
+    pub timeshift_size: u64,
+    /// most seconds of video that file keeps, None means as many as fit in timeshift_size
+    pub timeshift_max_duration: Option<Duration>,
+    /// what is left on the screen once a video stops, whether it ended, another one was loaded or
+    /// the player was destroyed
+    pub blackout_mode: BlackoutMode,
+}
+
+impl Default for PlayerConfig {
+    fn default() -> PlayerConfig {
+        PlayerConfig {
+            finishing_threshold: DEFAULT_FINISHING_THRESHOLD,
+            packet_buffer_bound: None,
+            window_size: DEFAULT_WINDOW_SIZE,
+            network_timeout: None,
+            timeshift_path: None,
+            timeshift_size: DEFAULT_TIMESHIFT_SIZE_MB as u64 * 1024 * 1024,
+            timeshift_max_duration: None,
+            blackout_mode: BlackoutMode::Clear,
+        }
+    }
+}
+
+impl PlayerConfig {
+    pub fn builder() -> PlayerConfigBuilder {
+        PlayerConfigBuilder::default()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PlayerConfigBuilder {
+    config: PlayerConfig,
+}
+
+impl PlayerConfigBuilder {
+    pub fn finishing_threshold(mut self, n: u32) -> Result<Self> {
+        if n < 1 {
+            return Err(Error::InvalidConfig(String::from("finishing_threshold must be at least 1")));
+        }
+        self.config.finishing_threshold = n;
+        Ok(self)
+    }
+
+    pub fn packet_buffer_bound(mut self, n: u32) -> Result<Self> {
+        if n < MIN_PACKET_BUFFER_BOUND {
+            return Err(Error::InvalidConfig(format!("packet_buffer_bound must be at least {}", MIN_PACKET_BUFFER_BOUND)));
+        }
+        self.config.packet_buffer_bound = Some(n);
+        Ok(self)
+    }
+
+    pub fn window_size(mut self, w: u16, h: u16) -> Result<Self> {
+        if w == 0 || h == 0 {
+            return Err(Error::InvalidConfig(format!("invalid window size {}x{}", w, h)));
+        }
+        self.config.window_size = (w, h);
+        Ok(self)
+    }
+
+    /// Clamped between 1 and 120 seconds
+    pub fn network_timeout(mut self, d: Duration) -> Self {
+        let min = Duration::from_secs(MIN_NETWORK_TIMEOUT_SECS);
+        let max = Duration::from_secs(MAX_NETWORK_TIMEOUT_SECS);
+        self.config.network_timeout = Some(if d < min { min } else if d > max { max } else { d });
+        self
+    }
+
+    pub fn timeshift(mut self, path: &str, size_mb: u32) -> Result<Self> {
+        if path.is_empty() {
+            return Err(Error::InvalidConfig(String::from("timeshift_path can't be empty")));
+        }
+        if size_mb < MIN_TIMESHIFT_SIZE_MB {
+            return Err(Error::InvalidConfig(format!("timeshift_size_mb must be at least {}", MIN_TIMESHIFT_SIZE_MB)));
+        }
+        self.config.timeshift_path = Some(path.to_owned());
+        self.config.timeshift_size = size_mb as u64 * 1024 * 1024;
+        Ok(self)
+    }
+
+    pub fn blackout_mode(mut self, mode: BlackoutMode) -> Self {
+        self.config.blackout_mode = mode;
+        self
+    }
+
+    pub fn build(self) -> Result<PlayerConfig> {
+        let config = self.config;
+        if config.finishing_threshold < 1 {
+            return Err(Error::InvalidConfig(String::from("finishing_threshold must be at least 1")));
+        }
+        if let Some(bound) = config.packet_buffer_bound {
+            if bound < MIN_PACKET_BUFFER_BOUND {
+                return Err(Error::InvalidConfig(format!("packet_buffer_bound must be at least {}", MIN_PACKET_BUFFER_BOUND)));
+            }
+        }
+        if config.window_size.0 == 0 || config.window_size.1 == 0 {
+            return Err(Error::InvalidConfig(format!("invalid window size {}x{}", config.window_size.0, config.window_size.1)));
+        }
+        Ok(config)
+    }
+}
+
+/// C version of PlayerConfig, given to aml_video_player_create_with_config. 0 means the default
+/// value for every field.
+#[repr(C)]
+pub struct AmlPlayerConfig {
+    pub finishing_threshold: c_uint,
+    pub packet_buffer_bound: c_uint,
+    pub window_width: c_ushort,
+    pub window_height: c_ushort,
+    pub network_timeout_ms: c_uint,
+    /// NULL disables the timeshift
+    pub timeshift_path: *const c_char,
+    pub timeshift_size_mb: c_uint,
+    /// see BlackoutMode::from_int
+    pub blackout_mode: c_uint,
+}
+
+impl AmlPlayerConfig {
+    pub fn to_config(&self) -> Result<PlayerConfig> {
+        let mut builder = PlayerConfig::builder();
+        if self.finishing_threshold != 0 {
+            builder = builder.finishing_threshold(self.finishing_threshold as u32)?;
+        }
+        if self.packet_buffer_bound != 0 {
+            builder = builder.packet_buffer_bound(self.packet_buffer_bound as u32)?;
+        }
+        if self.window_width != 0 || self.window_height != 0 {
+            builder = builder.window_size(self.window_width as u16, self.window_height as u16)?;
+        }
+        if self.network_timeout_ms != 0 {
+            builder = builder.network_timeout(Duration::from_millis(self.network_timeout_ms as u64));
+        }
+        if !self.timeshift_path.is_null() {
+            let path = unsafe { CStr::from_ptr(self.timeshift_path) }.to_string_lossy().into_owned();
+            let size_mb = if self.timeshift_size_mb == 0 { DEFAULT_TIMESHIFT_SIZE_MB } else { self.timeshift_size_mb as u32 };
+            builder = builder.timeshift(&path, size_mb)?;
+        }
+        if self.blackout_mode != 0 {
+            let mode = BlackoutMode::from_int(self.blackout_mode as u32)
+                .ok_or_else(|| Error::InvalidConfig(format!("unknown blackout_mode {}", self.blackout_mode)))?;
+            builder = builder.blackout_mode(mode);
+        }
+        builder.build()
+    }
+}
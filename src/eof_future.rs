@@ -0,0 +1,74 @@
+/*
+ * Async counterpart of aml_video_player_wait_until_end, for Rust callers using an async runtime.
+ *
+ * The future doesn't spawn anything by itself: the main thread (which is the one sending the end
+ * of a video to the API) wakes the registered task right after pushing an event.
+ */
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+use super::events::{EventQueue, PlayerEvent};
+
+/// A slot holding the Waker of the last task which polled a PlayerEofFuture
+///
+/// Only one task can wait for the end of a video at a time, the same way only one thread can call
+/// aml_video_player_wait_until_end at a time.
+#[derive(Default)]
+pub struct WakerSlot {
+    waker: Mutex<Option<Waker>>,
+}
+
+impl WakerSlot {
+    pub fn register(&self, waker: &Waker) {
+        let mut slot = self.waker.lock().unwrap_or_else(|e| e.into_inner());
+        let needs_update = match *slot {
+            Some(ref w) => !w.will_wake(waker),
+            None => true,
+        };
+        if needs_update {
+            *slot = Some(waker.clone());
+        }
+    }
+
+    pub fn wake(&self) {
+        let waker = self.waker.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Resolves once the current video (or playlist) is done playing
+///
+/// Created by `FfiPlayer::wait_async`. It resolves to an end event (EndOfStream or FatalError),
+/// a FatalError if the player is shut down before that.
+pub struct PlayerEofFuture<'a> {
+    events: &'a EventQueue,
+}
+
+impl<'a> PlayerEofFuture<'a> {
+    pub fn new(events: &'a EventQueue) -> PlayerEofFuture<'a> {
+        PlayerEofFuture {
+            events: events,
+        }
+    }
+}
+
+impl<'a> Future for PlayerEofFuture<'a> {
+    type Output = PlayerEvent;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<PlayerEvent> {
+        if let Some(event) = self.events.try_take_end() {
+            return Poll::Ready(event);
+        }
+        self.events.register_waker(cx.waker());
+        // the main thread may have pushed something between our first try and the registration
+        // of the waker, in which case nobody would ever wake us up
+        match self.events.try_take_end() {
+            Some(event) => Poll::Ready(event),
+            None => Poll::Pending,
+        }
+    }
+}
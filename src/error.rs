@@ -1,3 +1,7 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+
 #[repr(i32)]
 #[derive(Debug, Clone, Copy)]
 /// These are the errors we will return when calling the .so API.
@@ -8,6 +12,10 @@
 /// * ret > 0 : API user error
 /// * ret < 0 : unexpected error coming from this software
 pub enum FfiErrorCode {
+    /// there is no frame to capture, see aml_video_player_snapshot_png
+    NothingDisplayed = 4,
+    Unsupported = 3,
+    InvalidPlaylist = 2,
     InvalidCommand = 1,
     None = 0,
     Unknown = -1,
@@ -18,35 +26,153 @@ pub enum FfiErrorCode {
     NoHevcStream = -6,
     X11DLOpenError = -7,
     X11Internal = -8,
-    /// this is detected at initialisation,
-    /// however we can only return NULL or a pointer right now
-    /// (and no error code), so this is unused
-    // WrongLibavVersion = -9,
+    WaylandError = -10,
+    SysfsError = -11,
+    /// a thread didn't answer in time, see aml_video_player_set_reply_timeout
+    Timeout = -12,
+    /// a worker thread died, the player can only be destroyed
+    Dead = -13,
+    /// a device couldn't be opened because of its permissions, see Error::open
+    PermissionDenied = -14,
+    DeviceMissing = -15,
+    DeviceBusy = -16,
+    /// a file couldn't be written
+    WriteFailed = -17,
+    /// this is detected at initialisation, see aml_video_player_create_error
+    WrongLibavVersion = -9,
     Bug = -42,
     Unreachable = -43,
     ShutdownError = -64,
 }
 
+impl FfiErrorCode {
+    pub fn from_int(code: i32) -> Option<FfiErrorCode> {
+        match code {
+            4 => Some(FfiErrorCode::NothingDisplayed),
+            3 => Some(FfiErrorCode::Unsupported),
+            2 => Some(FfiErrorCode::InvalidPlaylist),
+            1 => Some(FfiErrorCode::InvalidCommand),
+            0 => Some(FfiErrorCode::None),
+            -1 => Some(FfiErrorCode::Unknown),
+            -2 => Some(FfiErrorCode::Disconnected),
+            -3 => Some(FfiErrorCode::LibAvDisconnected),
+            -4 => Some(FfiErrorCode::LibAvInternal),
+            -5 => Some(FfiErrorCode::VideoDecodingError),
+            -6 => Some(FfiErrorCode::NoHevcStream),
+            -7 => Some(FfiErrorCode::X11DLOpenError),
+            -8 => Some(FfiErrorCode::X11Internal),
+            -9 => Some(FfiErrorCode::WrongLibavVersion),
+            -10 => Some(FfiErrorCode::WaylandError),
+            -11 => Some(FfiErrorCode::SysfsError),
+            -12 => Some(FfiErrorCode::Timeout),
+            -13 => Some(FfiErrorCode::Dead),
+            -14 => Some(FfiErrorCode::PermissionDenied),
+            -15 => Some(FfiErrorCode::DeviceMissing),
+            -16 => Some(FfiErrorCode::DeviceBusy),
+            -17 => Some(FfiErrorCode::WriteFailed),
+            -42 => Some(FfiErrorCode::Bug),
+            -43 => Some(FfiErrorCode::Unreachable),
+            -64 => Some(FfiErrorCode::ShutdownError),
+            _ => None,
+        }
+    }
+
+    /// Null-terminated, for aml_video_player_error_string. There must be no wildcard here, so that
+    /// a new code can't be added without its message.
+    pub fn message(&self) -> &'static str {
+        match *self {
+            FfiErrorCode::NothingDisplayed => "no video frame is displayed\0",
+            FfiErrorCode::Unsupported => "not supported by this backend or this hardware\0",
+            FfiErrorCode::InvalidPlaylist => "invalid playlist\0",
+            FfiErrorCode::InvalidCommand => "invalid argument\0",
+            FfiErrorCode::None => "no error\0",
+            FfiErrorCode::Unknown => "unknown error\0",
+            FfiErrorCode::Disconnected => "the player is shut down\0",
+            FfiErrorCode::LibAvDisconnected => "the demuxing thread is not running anymore\0",
+            FfiErrorCode::LibAvInternal => "libav failed to open or read the video\0",
+            FfiErrorCode::VideoDecodingError => "the VPU failed to decode the video\0",
+            FfiErrorCode::NoHevcStream => "the video has no HEVC stream\0",
+            FfiErrorCode::X11DLOpenError => "failed to load the X11 libraries\0",
+            FfiErrorCode::X11Internal => "X11 error\0",
+            FfiErrorCode::WrongLibavVersion => "the linked libavformat is not a supported version\0",
+            FfiErrorCode::WaylandError => "Wayland error\0",
+            FfiErrorCode::SysfsError => "failed to access a driver setting in /sys\0",
+            FfiErrorCode::Timeout => "the player didn't answer in time\0",
+            FfiErrorCode::Dead => "a thread of the player died, it can only be destroyed\0",
+            FfiErrorCode::PermissionDenied => "permission denied on a device\0",
+            FfiErrorCode::DeviceMissing => "a device doesn't exist\0",
+            FfiErrorCode::DeviceBusy => "a device is already in use\0",
+            FfiErrorCode::WriteFailed => "failed to write a file\0",
+            FfiErrorCode::Bug => "internal error (bug)\0",
+            FfiErrorCode::Unreachable => "internal error (unreachable)\0",
+            FfiErrorCode::ShutdownError => "a thread panicked while the player was shut down\0",
+        }
+    }
+}
+
 // ecode stands for error_code
 pub fn error_to_ecode(error: Error) -> FfiErrorCode {
-    match error {
-        Error(ErrorKind::LibavInternal(_, _), _) => FfiErrorCode::LibAvInternal,
-        Error(ErrorKind::X11Other(_), _) => FfiErrorCode::Bug,
-        Error(ErrorKind::X11Internal(_), _) => FfiErrorCode::X11Internal,
-        Error(ErrorKind::EOF, _) => FfiErrorCode::Unreachable,
-        Error(ErrorKind::NoValidVideoStream, _) => FfiErrorCode::NoHevcStream,
-        Error(ErrorKind::X11DLOpenError(_), _) => FfiErrorCode::X11DLOpenError,
-        Error(ErrorKind::WrongLibavVersion, _) => FfiErrorCode::Unreachable,
-        Error(_, _) => FfiErrorCode::Unknown,
+    error.code()
+}
+
+/// What is left of an Error once it crossed a thread boundary: the io::Errors it holds aren't
+/// Clone, and the API user only needs to read it anyway.
+#[derive(Debug, Clone)]
+pub struct ErrorReport {
+    pub code: FfiErrorCode,
+    /// every error of the chain, outermost first
+    pub message: String,
+    /// of the io::Error at the root of the chain, if there is one
+    pub errno: Option<i32>,
+    /// the thread the error happened in
+    pub thread: String,
+}
+
+impl ErrorReport {
+    pub fn new(error: &Error) -> ErrorReport {
+        ErrorReport {
+            code: error.code(),
+            message: error.display().to_string(),
+            errno: error.errno(),
+            thread: ::std::thread::current().name().unwrap_or("api").to_owned(),
+        }
     }
 }
 
-// ecode stands for error_code
-#[inline]
-pub fn result_to_ecode(result: Result<()>) -> FfiErrorCode {
-    match result {
-        Ok(_) => FfiErrorCode::None,
-        Err(e) => error_to_ecode(e),
+impl fmt::Display for ErrorReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} ({:?}", self.thread, self.message, self.code)?;
+        if let Some(errno) = self.errno {
+            write!(f, ", errno {}", errno)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Sent back by the threads over the single use channels, in answer to every command
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub code: FfiErrorCode,
+    /// None on success, and for the errors which don't come from an Error (Dead, Timeout, ...)
+    pub report: Option<ErrorReport>,
+}
+
+impl From<FfiErrorCode> for Reply {
+    fn from(code: FfiErrorCode) -> Reply {
+        Reply {
+            code: code,
+            report: None,
+        }
+    }
+}
+
+impl From<Error> for Reply {
+    fn from(error: Error) -> Reply {
+        let report = ErrorReport::new(&error);
+        Reply {
+            code: report.code,
+            report: Some(report),
+        }
     }
 }
 
@@ -60,49 +186,228 @@ pub fn ffi_result_to_int(ffi_result: FfiResult) -> ::std::os::raw::c_int {
     r as ::std::os::raw::c_int
 }
 
-pub use error_chain::ChainedError;
+// Every error of this crate. Those which come from the OS keep the io::Error as their source, so
+// that the errno reaches the API user through ErrorReport.
+//
+// Display only describes the error itself, use display() to get the whole chain.
+#[derive(Debug)]
+pub enum Error {
+    /// a libav function returned a negative code
+    Libav { call: &'static str, code: i32 },
+    /// errno is read right after the failed call, see Error::ioctl
+    Ioctl { call: &'static str, errno: i32 },
+    /// an Xlib call failed without telling why
+    X11(String),
+    /// an Xlib call returned a non-zero status
+    X11Status(u8),
+    X11DlOpen(::x11_dl::error::OpenError),
+    Wayland(String),
+    /// any other I/O error, `what` being what we were trying to do
+    Io { what: String, source: io::Error },
+    Sysfs { path: String, source: io::Error },
+    PermissionDenied { path: String, source: io::Error },
+    DeviceMissing { path: String, source: io::Error },
+    /// no source when the device was found busy without even trying to open it
+    DeviceBusy { path: String, source: Option<io::Error> },
+    WriteFailed { path: String, source: io::Error },
+    /// the VPU shows no frame that could be captured
+    NothingDisplayed,
+    /// a channel between two threads was closed
+    Disconnected,
+    WrongLibavVersion,
+    InvalidPlaylist { reason: String, source: Option<io::Error> },
+    Unsupported(String),
+    /// refused by PlayerConfigBuilder
+    InvalidConfig(String),
+    /// the VPU refused the video being loaded, or nothing of it could be written in time
+    DecoderRejected(String),
+    /// libav reached the end of the file
+    Eof,
+    NoVideoStream,
+    /// a length read from the file runs past what it describes (a NAL unit longer than its packet,
+    /// a truncated hvcC, ...)
+    Malformed(String),
+    Other(String),
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
 
-// error_chain is a very cool rust package (or crate) that allows us to handle errors in a
-// friendly fashion. It allows chaining errors with result.chain_err(|| ErrorKind::XX ), and
-// returning early errors with bail!(ErrorKind::XX ); (which is a rough equivalent of return
-// Err(ErrorKind::XX) with some extra code to be more generic
-error_chain!{
-    errors {
-        LibavInternal(code: i32,s: &'static str) {
-            description("libav call failed")
-                display("libav call `{}` failed with {}", s, code)
+impl Error {
+    /// For a failed ioctl, must be called before anything else can change errno
+    pub fn ioctl(call: &'static str) -> Error {
+        Error::Ioctl {
+            call: call,
+            errno: io::Error::last_os_error().raw_os_error().unwrap_or(0),
         }
-        X11Other(s: String) {
-            description("unexpected X11 result")
-            display("unexpected X11 result: {}", s)
+    }
+
+    /// Classifies the failure to open `path` between the reasons the API user can do something
+    /// about (a missing udev rule, the wrong kernel, or another player already using the device)
+    /// and the others
+    pub fn open(source: io::Error, path: &str) -> Error {
+        let path = path.to_owned();
+        match source.kind() {
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied { path: path, source: source },
+            io::ErrorKind::NotFound => Error::DeviceMissing { path: path, source: source },
+            _ if source.raw_os_error() == Some(::libc::EBUSY) => Error::DeviceBusy { path: path, source: Some(source) },
+            _ => Error::Io { what: format!("failed to open {}", path), source: source },
         }
-        X11Internal(code: u8) {
-            description("X11 returned non-zero status code")
-            display("internal X11 error: {}", code)
+    }
+
+    pub fn code(&self) -> FfiErrorCode {
+        match *self {
+            Error::Libav { .. } => FfiErrorCode::LibAvInternal,
+            Error::X11(_) => FfiErrorCode::Bug,
+            Error::X11Status(_) => FfiErrorCode::X11Internal,
+            Error::X11DlOpen(_) => FfiErrorCode::X11DLOpenError,
+            Error::Wayland(_) => FfiErrorCode::WaylandError,
+            Error::Sysfs { .. } => FfiErrorCode::SysfsError,
+            Error::PermissionDenied { .. } => FfiErrorCode::PermissionDenied,
+            Error::DeviceMissing { .. } => FfiErrorCode::DeviceMissing,
+            Error::DeviceBusy { .. } => FfiErrorCode::DeviceBusy,
+            Error::WriteFailed { .. } => FfiErrorCode::WriteFailed,
+            Error::NothingDisplayed => FfiErrorCode::NothingDisplayed,
+            Error::Disconnected => FfiErrorCode::Disconnected,
+            Error::WrongLibavVersion => FfiErrorCode::WrongLibavVersion,
+            Error::InvalidPlaylist { .. } => FfiErrorCode::InvalidPlaylist,
+            Error::Unsupported(_) => FfiErrorCode::Unsupported,
+            Error::InvalidConfig(_) => FfiErrorCode::InvalidCommand,
+            Error::DecoderRejected(_) | Error::Malformed(_) => FfiErrorCode::VideoDecodingError,
+            Error::Eof => FfiErrorCode::Unreachable,
+            Error::NoVideoStream => FfiErrorCode::NoHevcStream,
+            Error::Ioctl { .. } | Error::Io { .. } | Error::Other(_) => FfiErrorCode::Unknown,
         }
-        Ioctl(which: &'static str) {
-            description("ioctl call failed")
-            display("ioctl call to `{}` failed", which)
+    }
+
+    /// The errno of the failed system call, if this error comes from one
+    pub fn errno(&self) -> Option<i32> {
+        match *self {
+            Error::Ioctl { errno, .. } => Some(errno),
+            Error::Io { ref source, .. } | Error::Sysfs { ref source, .. }
+            | Error::PermissionDenied { ref source, .. } | Error::DeviceMissing { ref source, .. }
+            | Error::DeviceBusy { source: Some(ref source), .. } | Error::WriteFailed { ref source, .. }
+            | Error::InvalidPlaylist { source: Some(ref source), .. } => source.raw_os_error(),
+            _ => None,
+        }
+    }
+
+    /// The error followed by all of its causes, "a: b: c"
+    pub fn display<'a>(&'a self) -> DisplayChain<'a> {
+        DisplayChain(self)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Libav { call, code } => write!(f, "libav call `{}` failed with {}", call, code),
+            Error::Ioctl { call, errno } => write!(f, "ioctl call to `{}` failed (errno {})", call, errno),
+            Error::X11(ref s) => write!(f, "unexpected X11 result: {}", s),
+            Error::X11Status(code) => write!(f, "internal X11 error: {}", code),
+            Error::X11DlOpen(_) => write!(f, "failed to load the X11 libraries"),
+            Error::Wayland(ref s) => write!(f, "wayland error: {}", s),
+            Error::Io { ref what, .. } => write!(f, "{}", what),
+            Error::Sysfs { ref path, .. } => write!(f, "failed to access {}", path),
+            Error::PermissionDenied { ref path, .. } => write!(f, "permission denied on {}", path),
+            Error::DeviceMissing { ref path, .. } => write!(f, "{} doesn't exist", path),
+            Error::DeviceBusy { ref path, .. } => write!(f, "{} is busy", path),
+            Error::WriteFailed { ref path, .. } => write!(f, "failed to write {}", path),
+            Error::NothingDisplayed => write!(f, "no video frame is displayed"),
+            Error::Disconnected => write!(f, "channel disconnected"),
+            Error::WrongLibavVersion => write!(f, "wrong libav version"),
+            Error::InvalidPlaylist { ref reason, .. } => write!(f, "invalid playlist: {}", reason),
+            Error::Unsupported(ref s) => write!(f, "unsupported: {}", s),
+            Error::InvalidConfig(ref s) => write!(f, "invalid configuration: {}", s),
+            Error::DecoderRejected(ref s) => write!(f, "the decoder rejected the stream: {}", s),
+            Error::Eof => write!(f, "end of file"),
+            Error::NoVideoStream => write!(f, "no HEVC stream found"),
+            Error::Malformed(ref s) => write!(f, "malformed stream: {}", s),
+            Error::Other(ref s) => write!(f, "{}", s),
         }
-        Amcodec {
-            description("amcodec error")
-            display("a call to amcodec driver failed")
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(StdError + 'static)> {
+        match *self {
+            Error::X11DlOpen(ref source) => Some(source),
+            Error::Io { ref source, .. } | Error::Sysfs { ref source, .. }
+            | Error::PermissionDenied { ref source, .. } | Error::DeviceMissing { ref source, .. }
+            | Error::DeviceBusy { source: Some(ref source), .. } | Error::WriteFailed { ref source, .. }
+            | Error::InvalidPlaylist { source: Some(ref source), .. } => Some(source),
+            _ => None,
         }
-        FbPermission {
-            description("not enough permissions to write on fb0")
-            display("not enough permissions to write on fb0")
+    }
+}
+
+pub struct DisplayChain<'a>(&'a Error);
+
+impl<'a> fmt::Display for DisplayChain<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut source = self.0.source();
+        while let Some(error) = source {
+            write!(f, ": {}", error)?;
+            source = error.source();
         }
-        Disconnected {
-            description("channel disconnected")
+        Ok(())
+    }
+}
+
+impl From<::x11_dl::error::OpenError> for Error {
+    fn from(e: ::x11_dl::error::OpenError) -> Error {
+        Error::X11DlOpen(e)
+    }
+}
+
+impl<'a> From<&'a str> for Error {
+    fn from(s: &'a str) -> Error {
+        Error::Other(s.to_owned())
+    }
+}
+
+impl From<String> for Error {
+    fn from(s: String) -> Error {
+        Error::Other(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// every code from_int knows about, as (value, code)
+    fn every_code() -> Vec<(i32, FfiErrorCode)> {
+        (-128..129).filter_map(|value| FfiErrorCode::from_int(value).map(|code| (value, code))).collect()
+    }
+
+    #[test]
+    fn from_int_round_trips() {
+        for (value, code) in every_code() {
+            assert_eq!(code as i32, value, "{:?}", code);
         }
-        WrongLibavVersion {
-            description("wrong libav version")
+    }
+
+    #[test]
+    fn messages_are_c_strings() {
+        for (_, code) in every_code() {
+            let message = code.message();
+            assert!(message.len() > 1, "{:?} has no message", code);
+            assert!(message.ends_with('\0'), "{:?}'s message isn't NUL-terminated", code);
+            assert!(!message[..message.len() - 1].contains('\0'), "{:?}'s message is cut short", code);
         }
-        EOF
-        NoValidVideoStream
     }
 
-    foreign_links {
-        X11DLOpenError(::x11_dl::error::OpenError);
+    #[test]
+    fn every_define_of_the_header_is_known() {
+        let header = include_str!("../aml_player.h");
+        let mut defines = 0;
+        let prefixes = ["#define AMPLAYER_ERROR_", "#define AMPLAYER_BUG", "#define AMPLAYER_UNREACHABLE"];
+        for line in header.lines().filter(|line| prefixes.iter().any(|prefix| line.starts_with(prefix))) {
+            let value : i32 = line.split_whitespace().nth(2).unwrap().parse().unwrap();
+            assert!(FfiErrorCode::from_int(value).is_some(), "{}", line);
+            defines += 1;
+        }
+        assert_eq!(defines, every_code().len());
     }
 }
@@ -0,0 +1,344 @@
+/*
+ * Everything the player has to tell the API user without being asked goes through a single
+ * EventQueue: the main thread pushes, and the API user consumes with
+ * aml_video_player_next_event, or only waits for the end of the video with
+ * aml_video_player_wait_until_end.
+ *
+ * Nobody is forced to read the events, so the queue is bounded: when it is full the oldest event
+ * is dropped, and the next one read tells how many were lost.
+ *
+ * Every event belongs to the generation it was pushed in, and a new generation starts every time
+ * a video is loaded: aml_video_player_wait_until_end ignores the end of the videos loaded before
+ * the current one, which nobody waited for.
+ */
+
+use error::FfiErrorCode;
+use libc::{c_char, c_int, c_uint};
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+use std::task::Waker;
+use super::eof_future::WakerSlot;
+
+/// Events kept at most in the queue
+pub const EVENT_QUEUE_CAPACITY : usize = 64;
+
+#[derive(Debug)]
+pub enum PlayerEvent {
+    /// The video (or the whole playlist) is done playing
+    EndOfStream,
+    /// Playback stopped for good
+    FatalError {
+        code: FfiErrorCode,
+        message: String,
+    },
+    /// The VPU's buffer went below the underflow threshold while playing
+    Stalled,
+    /// The VPU's buffer is above the underflow threshold again
+    Resumed,
+    FirstFrameDisplayed,
+    /// The window manager asked our window to close. The player doesn't do anything about it.
+    WindowCloseRequested,
+    /// The current video keeps playing, aml_video_player_play_preloaded will fail
+    PreloadFailed {
+        url: String,
+        code: FfiErrorCode,
+    },
+    /// A cue of the subtitle track selected, sent when it is demuxed: ahead of the video
+    Subtitle {
+        start_ms: i64,
+        /// -1 if the cue lasts until the next one
+        end_ms: i64,
+        text: String,
+    },
+    /// The end of the A-B repeat was reached, and the video went back to its start. `count` is
+    /// the number of times this happened since the range was set.
+    LoopWrapped {
+        count: u32,
+    },
+    /// The first frame after the latest seek is on screen: the position from then on is the one
+    /// of the new content. Not sent for the seeks of the A-B repeat.
+    SeekCompleted {
+        position_ms: i64,
+    },
+    /// The video stream stopped while the others went on, and another HEVC stream of the file is
+    /// played instead: a transport stream which switched programs
+    StreamChanged {
+        stream_index: u32,
+    },
+}
+
+impl PlayerEvent {
+    /// AMPLAYER_EVENT_* in aml_player.h
+    pub fn kind(&self) -> c_int {
+        match *self {
+            PlayerEvent::EndOfStream => 0,
+            PlayerEvent::FatalError {..} => 1,
+            PlayerEvent::Stalled => 2,
+            PlayerEvent::Resumed => 3,
+            PlayerEvent::FirstFrameDisplayed => 4,
+            PlayerEvent::WindowCloseRequested => 5,
+            PlayerEvent::PreloadFailed {..} => 6,
+            PlayerEvent::Subtitle {..} => 7,
+            PlayerEvent::LoopWrapped {..} => 8,
+            PlayerEvent::SeekCompleted {..} => 9,
+            PlayerEvent::StreamChanged {..} => 10,
+        }
+    }
+
+    /// The code of the events telling that something failed
+    pub fn error_code(&self) -> Option<FfiErrorCode> {
+        match *self {
+            PlayerEvent::FatalError { code, .. } | PlayerEvent::PreloadFailed { code, .. } => Some(code),
+            _ => None,
+        }
+    }
+
+    /// true for the events aml_video_player_wait_until_end waits for
+    pub fn is_end(&self) -> bool {
+        match *self {
+            PlayerEvent::EndOfStream | PlayerEvent::FatalError {..} => true,
+            _ => false,
+        }
+    }
+}
+
+/// Size of AmlEvent::message, including the trailing null byte
+pub const AML_EVENT_MESSAGE_LEN : usize = 256;
+
+/// C version of a PlayerEvent
+#[repr(C)]
+pub struct AmlEvent {
+    /// see PlayerEvent::kind
+    pub kind: c_int,
+    /// error code of FatalError and PreloadFailed, count of LoopWrapped, index of the stream of
+    /// StreamChanged, 0 otherwise
+    pub code: c_int,
+    /// number of events dropped because the queue was full, since the previous event read
+    pub dropped: c_uint,
+    /// null-terminated, truncated if needed: the message of FatalError, the url of
+    /// PreloadFailed, the text of Subtitle, empty otherwise
+    pub message: [c_char; AML_EVENT_MESSAGE_LEN],
+    /// position in the video of a Subtitle, or of a SeekCompleted in start_ms, in milliseconds, 0
+    /// for the other events
+    pub start_ms: i64,
+    pub end_ms: i64,
+}
+
+impl AmlEvent {
+    pub fn new(event: &PlayerEvent, dropped: u32) -> AmlEvent {
+        let (code, message) = match *event {
+            PlayerEvent::FatalError { code, ref message } => (code as c_int, message.as_str()),
+            PlayerEvent::PreloadFailed { code, ref url } => (code as c_int, url.as_str()),
+            PlayerEvent::Subtitle { ref text, .. } => (0, text.as_str()),
+            PlayerEvent::LoopWrapped { count } => (count as c_int, ""),
+            PlayerEvent::StreamChanged { stream_index } => (stream_index as c_int, ""),
+            _ => (0, ""),
+        };
+        let (start_ms, end_ms) = match *event {
+            PlayerEvent::Subtitle { start_ms, end_ms, .. } => (start_ms, end_ms),
+            PlayerEvent::SeekCompleted { position_ms } => (position_ms, 0),
+            _ => (0, 0),
+        };
+        let mut aml_event = AmlEvent {
+            kind: event.kind(),
+            code: code,
+            dropped: dropped as c_uint,
+            message: [0; AML_EVENT_MESSAGE_LEN],
+            start_ms: start_ms,
+            end_ms: end_ms,
+        };
+        for (dest, src) in aml_event.message.iter_mut().zip(message.bytes().take(AML_EVENT_MESSAGE_LEN - 1)) {
+            *dest = src as c_char;
+        }
+        aml_event
+    }
+}
+
+struct Queue {
+    /// (generation, event)
+    events: VecDeque<(u64, PlayerEvent)>,
+    generation: u64,
+    /// reset every time an event is read with next
+    dropped: u32,
+    /// never reset, see dropped_total
+    dropped_total: u64,
+    /// no event will ever be pushed anymore
+    closed: bool,
+}
+
+pub struct EventQueue {
+    queue: Mutex<Queue>,
+    condvar: Condvar,
+    /// for PlayerEofFuture
+    waker_slot: WakerSlot,
+}
+
+impl EventQueue {
+    pub fn new() -> EventQueue {
+        EventQueue {
+            queue: Mutex::new(Queue {
+                events: VecDeque::with_capacity(EVENT_QUEUE_CAPACITY),
+                generation: 0,
+                dropped: 0,
+                dropped_total: 0,
+                closed: false,
+            }),
+            condvar: Condvar::new(),
+            waker_slot: WakerSlot::default(),
+        }
+    }
+
+    pub fn push(&self, event: PlayerEvent) {
+        {
+            let mut queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+            if queue.events.len() >= EVENT_QUEUE_CAPACITY {
+                queue.events.pop_front();
+                queue.dropped = queue.dropped.saturating_add(1);
+                queue.dropped_total += 1;
+            }
+            let generation = queue.generation;
+            queue.events.push_back((generation, event));
+        }
+        self.condvar.notify_all();
+        self.waker_slot.wake();
+    }
+
+    /// Called by the main thread every time a video is loaded (but not when a playlist goes to its
+    /// next entry). The end events pushed before are still returned by next, but try_take_end and
+    /// wait_end skip them.
+    pub fn new_generation(&self) {
+        let mut queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        queue.generation += 1;
+    }
+
+    /// Number of events dropped since the queue was created
+    pub fn dropped_total(&self) -> u64 {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner()).dropped_total
+    }
+
+    /// Called when the main thread exits, wakes up everyone waiting for an event
+    pub fn close(&self) {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner()).closed = true;
+        self.condvar.notify_all();
+        self.waker_slot.wake();
+    }
+
+    /// Removes the oldest event, waiting at most `timeout` (forever if None) for one. The number
+    /// of events dropped since the previous call is returned along with it.
+    ///
+    /// Fails with Timeout if nothing came in time, or Disconnected once the player is shut down.
+    pub fn next(&self, timeout: Option<Duration>) -> Result<(PlayerEvent, u32), FfiErrorCode> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some((_, event)) = queue.events.pop_front() {
+                let dropped = queue.dropped;
+                queue.dropped = 0;
+                return Ok((event, dropped));
+            }
+            if queue.closed {
+                return Err(FfiErrorCode::Disconnected);
+            }
+            queue = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(FfiErrorCode::Timeout);
+                    }
+                    self.condvar.wait_timeout(queue, deadline - now).unwrap_or_else(|e| e.into_inner()).0
+                },
+                None => self.condvar.wait(queue).unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+    }
+
+    /// Removes the oldest end event (see PlayerEvent::is_end) without blocking, the other
+    /// events are left in the queue. Once the player is shut down, a FatalError is returned.
+    pub fn try_take_end(&self) -> Option<PlayerEvent> {
+        let mut queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        Self::take_end(&mut queue)
+    }
+
+    /// Same as try_take_end, but blocks until there is one
+    pub fn wait_end(&self) -> PlayerEvent {
+        let mut queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(event) = Self::take_end(&mut queue) {
+                return event;
+            }
+            queue = self.condvar.wait(queue).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn take_end(queue: &mut Queue) -> Option<PlayerEvent> {
+        // the end of a previous video must not be taken for the end of the current one
+        let generation = queue.generation;
+        if let Some(index) = queue.events.iter().position(|&(event_generation, ref event)| event_generation == generation && event.is_end()) {
+            return queue.events.remove(index).map(|(_, event)| event);
+        }
+        if queue.closed {
+            Some(PlayerEvent::FatalError {
+                code: FfiErrorCode::Disconnected,
+                message: String::from("the player has been shut down"),
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn register_waker(&self, waker: &Waker) {
+        self.waker_slot.register(waker);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn end_of_a_previous_generation_is_left_to_next() {
+        let events = EventQueue::new();
+        events.push(PlayerEvent::EndOfStream);
+        events.new_generation();
+        assert!(events.try_take_end().is_none());
+        events.push(PlayerEvent::FirstFrameDisplayed);
+        events.push(PlayerEvent::EndOfStream);
+        match events.try_take_end() {
+            Some(PlayerEvent::EndOfStream) => {},
+            other => panic!("{:?} instead of the end of the current video", other),
+        }
+        // the old end is still returned to the API user, and only once
+        let kinds : Vec<c_int> = (0..2).map(|_| events.next(Some(Duration::from_millis(0))).unwrap().0.kind()).collect();
+        assert_eq!(kinds, vec![PlayerEvent::EndOfStream.kind(), PlayerEvent::FirstFrameDisplayed.kind()]);
+        assert!(events.next(Some(Duration::from_millis(0))).is_err());
+    }
+
+    #[test]
+    fn wait_end_skips_the_previous_generation() {
+        let events = Arc::new(EventQueue::new());
+        events.push(PlayerEvent::EndOfStream);
+        events.new_generation();
+        let (tx, rx) = mpsc::channel();
+        let waiter = {
+            let events = events.clone();
+            thread::spawn(move || tx.send(events.wait_end()).unwrap())
+        };
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        events.push(PlayerEvent::Stalled);
+        events.push(PlayerEvent::EndOfStream);
+        match rx.recv_timeout(Duration::from_secs(5)) {
+            Ok(PlayerEvent::EndOfStream) => {},
+            other => panic!("{:?} instead of the end of the current video", other),
+        }
+        waiter.join().unwrap();
+        events.close();
+        match events.wait_end() {
+            PlayerEvent::FatalError { code: FfiErrorCode::Disconnected, .. } => {},
+            other => panic!("{:?} once closed", other),
+        }
+    }
+}
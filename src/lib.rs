@@ -3,38 +3,177 @@
  */
 
 
-// nightly is required because of this
-// maybe stable 1.18 will stabilize this ?
-#![feature(untagged_unions)]
-
-// necessary for error-chain
-#![recursion_limit = "1024"]
-
 extern crate libavformat;
 
-#[macro_use]
-extern crate error_chain;
-
 #[macro_use]
 extern crate ioctl;
 extern crate libc;
 extern crate x11_dl;
 
+#[macro_use]
+mod log;
 mod utils;
 mod amcodec_sys;
 mod amcodec;
 mod error;
 mod player;
 mod x11helper;
+mod waylandhelper;
+mod stubwindow;
+mod window;
+mod xfixes;
 mod libavhelper;
+mod playlist;
+mod sysfs;
+mod eof_future;
+mod events;
+mod position;
+mod sysinfo;
+mod stats;
+mod audio;
+mod config;
+mod screenshot;
+mod quality;
+mod subtitles;
+mod capabilities;
+mod timeshift;
+mod pool;
+mod benchmark;
+mod settings;
+mod recording;
+
+use player::Message;
+use libavhelper::{CodecKind, RtspTransport, Message as LibavMessage};
+use playlist::Playlist;
+use window::ShowHideMode;
+use amcodec::{Amcodec, Message as AmcodecMessage, VideoOutput, ContrastMode, ColorTemperature, MIN_SATURATION_HUE, MAX_SATURATION_HUE, MAX_NOISE_REDUCTION, MAX_SHARPNESS, GAMMA_TABLE_LEN, MAX_GAMMA_VALUE};
 
-use player::{FfiPlayer, Message};
+// Rust-native API, for Rust programs linking against this library directly
+pub use player::FfiPlayer;
+pub use eof_future::PlayerEofFuture;
+pub use amcodec::{AmlTimingInfo, VpuDecoderState};
+pub use libavhelper::AmlCodecInfo;
+pub use events::{PlayerEvent, AmlEvent};
+pub use stats::AmlPlayerStats;
+pub use benchmark::{AmlBenchmarkResult, AmlPipelineBenchmarkResult};
+pub use capabilities::{AML_CAP_HEVC, AML_CAP_H264, AML_CAP_VP9, AML_CAP_HDR10, AML_CAP_HLG, AML_CAP_DEINTERLACE, AML_CAP_AUDIO_PASSTHROUGH, AML_CAP_CLOSED_CAPTIONS};
+use config::{PlayerConfig, AmlPlayerConfig};
+use quality::VideoQualitySettings;
+use settings::Easing;
 
-use libc::{c_int, c_uint, c_char, c_void, c_float};
+use libc::{c_int, c_uint, c_char, c_uchar, c_void, c_float, c_ushort, c_long};
+use std::cell::Cell;
 use std::mem;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
 use utils::*;
 use error::*;
 
+// Waits for the reply of another thread, for `timeout` at most (None waits forever).
+//
+// Every call creates its own reply channel, so a reply arriving after the timeout is simply
+// dropped along with the channel.
+fn recv_reply<T>(rx: SingleUseReceiver<T>, timeout: Option<Duration>) -> ::std::result::Result<T, FfiErrorCode> {
+    let id = rx.id();
+    match timeout {
+        Some(timeout) => match rx.recv_timeout(timeout) {
+            Ok(t) => Ok(t),
+            Err(RecvTimeoutError::Timeout) => {
+                warn!("request #{}: no reply after {:?}, giving up", id, timeout);
+                Err(FfiErrorCode::Timeout)
+            },
+            Err(RecvTimeoutError::Disconnected) => Err(FfiErrorCode::Disconnected),
+        },
+        None => rx.recv().map_err(|_| FfiErrorCode::Disconnected),
+    }
+}
+
+// Waits for the error code of a command, and keeps the whole error for aml_video_player_last_error
+// if the thread which handled it sent one
+fn recv_code(ffi_player: &FfiPlayer, rx: SingleUseReceiver<Reply>) -> ::std::result::Result<FfiErrorCode, FfiErrorCode> {
+    let reply = recv_reply(rx, ffi_player.reply_timeout()).map_err(|code| {
+        keep_error_code(ffi_player, code);
+        code
+    })?;
+    if let Some(report) = reply.report {
+        if let Ok(mut last_error) = ffi_player.last_error.lock() {
+            *last_error = Some(report);
+        }
+    }
+    keep_error_code(ffi_player, reply.code);
+    Ok(reply.code)
+}
+
+// For aml_video_player_get_last_error_code, successes don't clear it
+fn keep_error_code(ffi_player: &FfiPlayer, code: FfiErrorCode) {
+    match code {
+        FfiErrorCode::None => {},
+        code => ffi_player.last_error_code.store(code as i32, Ordering::SeqCst),
+    }
+}
+
+// Same as recv_code, for the errors which happened in the thread of the caller
+fn keep_error(ffi_player: &FfiPlayer, error: Error) -> c_int {
+    let reply = Reply::from(error);
+    if let Ok(mut last_error) = ffi_player.last_error.lock() {
+        *last_error = reply.report;
+    }
+    keep_error_code(ffi_player, reply.code);
+    reply.code as c_int
+}
+
+fn wait_reply(ffi_player: &FfiPlayer, rx: SingleUseReceiver<Reply>) -> c_int {
+    match recv_code(ffi_player, rx) {
+        Ok(error_code) | Err(error_code) => error_code as c_int,
+    }
+}
+
+// Sends a command to the main thread and waits for its error code
+fn call(ffi_player: &FfiPlayer, message: Message) -> c_int {
+    let (tx, rx) = single_use_channel::<Reply>();
+    ffi_player.send_message(message, tx);
+    wait_reply(ffi_player, rx)
+}
+
+// Same as call, for the queries which return something: `message` makes the query out of the
+// channel its value is sent to. The error code is returned instead if it isn't None.
+fn query<V, F: FnOnce(SingleUseSender<V>) -> Message>(ffi_player: &FfiPlayer, message: F) -> ::std::result::Result<V, c_int> {
+    let (tx, rx) = single_use_channel::<Reply>();
+    let (value_tx, value_rx) = single_use_channel::<V>();
+    ffi_player.send_message(message(value_tx), tx);
+    match recv_code(ffi_player, rx) {
+        // the value is always sent before the error code, so it is already there
+        Ok(FfiErrorCode::None) => value_rx.try_recv().map_err(|_| FfiErrorCode::Disconnected as c_int),
+        Ok(error_code) | Err(error_code) => Err(error_code as c_int),
+    }
+}
+
+// Copies s in buf the way snprintf would: truncated and null-terminated if len is too small, and
+// the length of the whole string is returned, without the null byte.
+fn write_c_string(s: &str, buf: *mut c_char, len: c_uint) -> c_int {
+    let len = len as usize;
+    if !buf.is_null() && len > 0 {
+        let copied = ::std::cmp::min(s.len(), len - 1);
+        unsafe {
+            ::std::ptr::copy_nonoverlapping(s.as_ptr() as *const c_char, buf, copied);
+            *buf.offset(copied as isize) = 0;
+        }
+    }
+    s.len() as c_int
+}
+
+// Same as write_c_string, but the size of the buffer the whole string needs is returned: its
+// length plus the null byte
+fn write_sized_c_string(s: &str, buf: *mut c_char, len: c_uint) -> c_int {
+    write_c_string(s, buf, len) + 1
+}
+
+thread_local! {
+    /// error code of the last aml_video_player_create* call of this thread, 0 if it succeeded
+    static CREATE_ERROR : Cell<c_int> = Cell::new(0);
+}
+
 // When this function is called, a struct named FfiPlayer is crated,
 // initialized and allocated on the Heap. Its initialization takes
 // care of spawning other threads which will communicate between each
@@ -46,13 +185,15 @@ use error::*;
 // memory-wise the Box, so it isn't deallocated right now
 #[no_mangle]
 pub extern fn aml_video_player_create() -> *mut c_void {
-    let player : FfiPlayer = match player::player_start() {
+    let player : FfiPlayer = match player::player_start(PlayerConfig::default()) {
         Ok(player) => player,
         Err(e) => {
-            println!("Error when initializing Player : {}", e.display());
+            error!("failed to initialize the player: {}", e.display());
+            CREATE_ERROR.with(|code| code.set(error_to_ecode(e) as c_int));
             return ::std::ptr::null_mut();
         }
     };
+    CREATE_ERROR.with(|code| code.set(FfiErrorCode::None as c_int));
     let player = Box::new(player);
 
     // transform Box (= unique_ptr) into a raw pointer,
@@ -61,6 +202,182 @@ pub extern fn aml_video_player_create() -> *mut c_void {
     Box::into_raw(player) as *mut c_void
 }
 
+// Same as aml_video_player_create, with the settings of config instead of the default ones (see
+// AmlPlayerConfig, 0 meaning the default for every field). NULL is returned if one of them is
+// invalid, aml_video_player_create_error then returns AMPLAYER_ERROR_INVALID_COMMAND.
+#[no_mangle]
+pub extern fn aml_video_player_create_with_config(config: *const AmlPlayerConfig) -> *mut c_void {
+    if config.is_null() {
+        CREATE_ERROR.with(|code| code.set(FfiErrorCode::InvalidCommand as c_int));
+        return ::std::ptr::null_mut();
+    }
+    let player = unsafe {&*config}.to_config().and_then(player::player_start);
+    let player : FfiPlayer = match player {
+        Ok(player) => player,
+        Err(e) => {
+            error!("failed to initialize the player: {}", e.display());
+            CREATE_ERROR.with(|code| code.set(error_to_ecode(e) as c_int));
+            return ::std::ptr::null_mut();
+        }
+    };
+    CREATE_ERROR.with(|code| code.set(FfiErrorCode::None as c_int));
+    Box::into_raw(Box::new(player)) as *mut c_void
+}
+
+// Same as aml_video_player_create, but no window is ever created: Show, Hide, SetPos, SetSize and
+// SetFullscreen only act on the VPU's video layer
+#[no_mangle]
+pub extern fn aml_video_player_create_offscreen() -> *mut c_void {
+    let player : FfiPlayer = match player::player_start_offscreen() {
+        Ok(player) => player,
+        Err(e) => {
+            error!("failed to initialize the player: {}", e.display());
+            CREATE_ERROR.with(|code| code.set(error_to_ecode(e) as c_int));
+            return ::std::ptr::null_mut();
+        }
+    };
+    CREATE_ERROR.with(|code| code.set(FfiErrorCode::None as c_int));
+    Box::into_raw(Box::new(player)) as *mut c_void
+}
+
+// Description of an error code returned by any other function, "unknown code" if it isn't one.
+// The string is static: it must not be freed.
+#[no_mangle]
+pub extern fn aml_video_player_error_string(code: c_int) -> *const c_char {
+    let message = match FfiErrorCode::from_int(code as i32) {
+        Some(error_code) => error_code.message(),
+        None => "unknown code\0",
+    };
+    message.as_ptr() as *const c_char
+}
+
+// Writes the last error a worker thread reported for this player in buf, with every cause and
+// the thread it happened in, for instance "amcodec_thread: failed to open /dev/amstream_hevc:
+// Device or resource busy (DeviceBusy, errno 16)". Works like snprintf, see write_c_string; an
+// empty string is written if nothing failed yet. Plain invalid arguments don't count.
+#[no_mangle]
+pub extern fn aml_video_player_last_error(player: *mut c_void, buf: *mut c_char, len: c_uint) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let message = match ffi_player.last_error.lock() {
+        Ok(last_error) => last_error.as_ref().map(|report| report.to_string()).unwrap_or_default(),
+        Err(_) => String::new(),
+    };
+    mem::forget(ffi_player);
+    write_c_string(&message, buf, len)
+}
+
+// FfiErrorCode of the last error of the player, be it returned by a function or sent as an
+// AMPLAYER_EVENT_FATAL_ERROR or AMPLAYER_EVENT_PRELOAD_FAILED event. 0 if nothing failed since the
+// player was created or aml_video_player_clear_last_error was called.
+#[no_mangle]
+pub extern fn aml_video_player_get_last_error_code(player: *mut c_void) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let code = ffi_player.last_error_code.load(Ordering::SeqCst);
+    mem::forget(ffi_player);
+    code as c_int
+}
+
+// Forgets the last error, both its code and the message of aml_video_player_last_error
+#[no_mangle]
+pub extern fn aml_video_player_clear_last_error(player: *mut c_void) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    ffi_player.last_error_code.store(FfiErrorCode::None as i32, Ordering::SeqCst);
+    if let Ok(mut last_error) = ffi_player.last_error.lock() {
+        *last_error = None;
+    }
+    mem::forget(ffi_player);
+    FfiErrorCode::None as c_int
+}
+
+// Why the last aml_video_player_create or aml_video_player_create_offscreen call of the calling
+// thread returned NULL, 0 if it didn't
+#[no_mangle]
+pub extern fn aml_video_player_create_error() -> c_int {
+    CREATE_ERROR.with(|code| code.get())
+}
+
+// Writes something like {"soc":"S905X3","vpu_ver":"v2.1"} in buf, "unknown" being used for what
+// could not be found. No player is needed for this.
+//
+// Same convention as snprintf: the output is always null-terminated (and truncated if needed) as
+// long as len > 0, and the length of the whole string is returned, without the null byte. If this
+// is >= len, the buffer was too small.
+#[no_mangle]
+pub extern fn aml_video_player_get_sys_info(buf: *mut c_char, len: c_uint) -> c_int {
+    write_c_string(&sysinfo::SysInfo::read().to_json(), buf, len)
+}
+
+// Writes the version of the library in buf, "0.1.0" for instance. No player is needed for this.
+// Same convention as aml_video_player_get_sys_info, except that the size of the buffer needed is
+// returned: the length of the string plus the null byte. If this is > len, the buffer was too
+// small.
+#[no_mangle]
+pub extern fn aml_video_player_get_version(buf: *mut c_char, len: c_uint) -> c_int {
+    write_sized_c_string(env!("CARGO_PKG_VERSION"), buf, len)
+}
+
+// Writes the version, the target, the build date and the git commit the library was built from
+// in buf, see build.rs. Same convention as aml_video_player_get_version.
+#[no_mangle]
+pub extern fn aml_video_player_get_build_info(buf: *mut c_char, len: c_uint) -> c_int {
+    let build_info = format!("c2player {} ({}, built {}, commit {})",
+                             env!("CARGO_PKG_VERSION"), env!("C2PLAYER_TARGET"),
+                             env!("C2PLAYER_BUILD_DATE"), env!("C2PLAYER_GIT_COMMIT"));
+    write_sized_c_string(&build_info, buf, len)
+}
+
+// Bitmask of the AML_CAP_* the board supports, probed on the first call. No player is needed for
+// this.
+#[no_mangle]
+pub extern fn aml_video_player_get_capabilities() -> c_uint {
+    capabilities::get() as c_uint
+}
+
+// Opens url and reads its packets for duration_secs seconds (less if the video ends before), then
+// fills out with how long each step took and the demuxing throughput. No player is needed: this
+// runs in the calling thread, which it blocks for about duration_secs seconds. On Amlogic
+// hardware, the first 2 MB of video are then written to the VPU to measure the write throughput:
+// no player may be using it at the same time, or write_bytes_per_sec is left to 0.
+#[no_mangle]
+pub extern fn aml_video_player_benchmark(url: *const c_char, duration_secs: c_uint, out: *mut AmlBenchmarkResult) -> c_int {
+    if url.is_null() || out.is_null() || duration_secs == 0 {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let url = unsafe { ::std::ffi::CStr::from_ptr(url) }.to_string_lossy().into_owned();
+    match benchmark::run(&url, Duration::from_secs(duration_secs as u64)) {
+        Ok(result) => {
+            unsafe { *out = result; }
+            FfiErrorCode::None as c_int
+        },
+        Err(e) => {
+            error!("benchmark of {} failed: {}", url, e.display());
+            error_to_ecode(e) as c_int
+        }
+    }
+}
+
+// Plays url in a headless player of its own for duration_secs (or until its end), and fills out
+// with the throughput of the libav and amcodec threads and how long they waited. Off Amlogic
+// hardware the packets go to the dummy amcodec. On Amlogic hardware no other player may be
+// using the VPU.
+#[no_mangle]
+pub extern fn aml_video_player_benchmark_pipeline(url: *const c_char, duration_secs: c_uint, out: *mut AmlPipelineBenchmarkResult) -> c_int {
+    if url.is_null() || out.is_null() || duration_secs == 0 {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let url = unsafe { ::std::ffi::CStr::from_ptr(url) }.to_string_lossy().into_owned();
+    match benchmark::run_pipeline(&url, Duration::from_secs(duration_secs as u64)) {
+        Ok(result) => {
+            unsafe { *out = result; }
+            FfiErrorCode::None as c_int
+        },
+        Err(e) => {
+            error!("pipeline benchmark of {} failed: {}", url, e.display());
+            error_to_ecode(e) as c_int
+        }
+    }
+}
+
 // For almost every other call, we need to retrieve FfiPlayer from the given pointer. It is of
 // course very risky since the API user can send us a totally unrelated pointer, but we don't
 // really have a choice here ...
@@ -78,108 +395,1294 @@ pub extern fn aml_video_player_load(player: *mut c_void, video_url: *const c_cha
     let video_url = unsafe {
         ::std::ffi::CStr::from_ptr(video_url)
     };
-    let (tx, rx) = single_use_channel::<FfiErrorCode>();
-    ffi_player.send_message(
-        Message::Load(tx, video_url.to_string_lossy().into_owned())
-    );
+    let ret = call(&ffi_player, Message::Load(video_url.to_string_lossy().into_owned()));
     mem::forget(ffi_player);
-    rx.recv().unwrap_or(FfiErrorCode::Disconnected) as c_int
+    ret
 }
 
+// RISKY, for files libav gets wrong only: the next load (and only that one) uses the first video
+// stream of the file as `codec` (an AMPLAYER_CODEC_*), whatever its codec id says. If it is
+// anything else, the VPU is fed garbage: expect corrupt pictures, decoding errors, or a VPU which
+// needs a reboot on some kernels. 0 goes back to the auto-detection. The preloaded video isn't
+// used by this load.
 #[no_mangle]
-pub extern fn aml_video_player_seek(player: *mut c_void, pos: c_float) -> c_int {
+pub extern fn aml_video_player_force_video_format(player: *mut c_void, codec: c_uint) -> c_int {
+    let codec = match (codec, CodecKind::from_int(codec as u32)) {
+        (0, _) => None,
+        (_, Some(codec)) => Some(codec),
+        (_, None) => return FfiErrorCode::InvalidCommand as c_int,
+    };
     let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
-    let (tx, rx) = single_use_channel::<FfiErrorCode>();
-    ffi_player.send_message(
-        Message::Seek(tx, pos as f64)
-    );
+    let ret = call(&ffi_player, Message::Libav(LibavMessage::ForceVideoFormat(codec)));
     mem::forget(ffi_player);
-    rx.recv().unwrap_or(FfiErrorCode::Disconnected) as c_int
+    ret
 }
 
-// This function is rather special, since we are blocking until an "end of video" message is sent
-// to us. Basically this message (which is at the moment always returned when the VPU hits EOF)
-// allows us to get the exact moment where a video is finished, so that we can queue the next one
-// right up, or shutdown the program right after the video's done.
+// Same as the timeshift of AmlPlayerConfig, but chosen while the player runs: from the next pause
+// of a live stream, it goes on being demuxed to `buffer_path`, keeping max_duration_secs seconds
+// of video at most (0 for as much as the size of the file allows). NULL disables the timeshift.
+// A timeshift going on is kept until the next load.
 #[no_mangle]
-pub extern fn aml_video_player_wait_until_end(player: *mut c_void) -> c_int {
-    let mut ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
-    let ret = ffi_player.wait_for_video_status();
+pub extern fn aml_video_player_enable_time_shift(player: *mut c_void, buffer_path: *const c_char, max_duration_secs: c_uint) -> c_int {
+    let path = if buffer_path.is_null() {
+        None
+    } else {
+        let path = unsafe { ::std::ffi::CStr::from_ptr(buffer_path) }.to_string_lossy().into_owned();
+        if path.is_empty() {
+            return FfiErrorCode::InvalidCommand as c_int;
+        }
+        Some(path)
+    };
+    let max_duration = match max_duration_secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs as u64)),
+    };
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Libav(LibavMessage::SetTimeshift(path, max_duration)));
     mem::forget(ffi_player);
     ret
 }
 
+// Moves delta_secs seconds from the current position in the timeshift buffer (backward if
+// negative), as far as its oldest keyframe or the live edge. Only while timeshifting.
 #[no_mangle]
-pub extern fn aml_video_player_show(player: *mut c_void) -> c_int {
+pub extern fn aml_video_player_seek_time_shift(player: *mut c_void, delta_secs: c_float) -> c_int {
+    if !delta_secs.is_finite() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
     let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
-    let (tx, rx) = single_use_channel::<FfiErrorCode>();
-    ffi_player.send_message(Message::Show(tx));
+    let ret = call(&ffi_player, Message::Libav(LibavMessage::SeekTimeshift(delta_secs as f64)));
     mem::forget(ffi_player);
-    rx.recv().unwrap_or(FfiErrorCode::Disconnected) as c_int
+    ret
 }
 
+// Writes the video stream to `path` as it is played, as a raw HEVC elementary stream (Annex B),
+// until aml_video_player_stop_recording. The recording goes on across loads, and replaces the one
+// going on if any. `path` must not be the video being played.
 #[no_mangle]
-pub extern fn aml_video_player_hide(player: *mut c_void) -> c_int {
+pub extern fn aml_video_player_start_recording(player: *mut c_void, path: *const c_char) -> c_int {
+    if path.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let path = unsafe { ::std::ffi::CStr::from_ptr(path) }.to_string_lossy().into_owned();
     let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
-    let (tx, rx) = single_use_channel::<FfiErrorCode>();
-    ffi_player.send_message(Message::Hide(tx));
+    let ret = call(&ffi_player, Message::Libav(LibavMessage::StartRecording(path)));
     mem::forget(ffi_player);
-    rx.recv().unwrap_or(FfiErrorCode::Disconnected) as c_int
+    ret
 }
 
+// Writes what is left of the recording and closes its file
 #[no_mangle]
-pub extern fn aml_video_player_play(player: *mut c_void) -> c_int {
+pub extern fn aml_video_player_stop_recording(player: *mut c_void) -> c_int {
     let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
-    let (tx, rx) = single_use_channel::<FfiErrorCode>();
-    ffi_player.send_message(Message::Play(tx));
+    let ret = call(&ffi_player, Message::Libav(LibavMessage::StopRecording));
     mem::forget(ffi_player);
-    rx.recv().unwrap_or(FfiErrorCode::Disconnected) as c_int
+    ret
 }
+
+// How the RTSP streams opened from now on carry their packets (an AMPLAYER_RTSP_TRANSPORT_*),
+// for every load and preload until it is changed again. libav tries UDP first by default, which
+// some NATs don't let through.
 #[no_mangle]
-pub extern fn aml_video_player_pause(player: *mut c_void) -> c_int {
+pub extern fn aml_video_player_set_rtsp_transport(player: *mut c_void, transport: c_uint) -> c_int {
+    let transport = match RtspTransport::from_int(transport as u32) {
+        Some(transport) => transport,
+        None => return FfiErrorCode::InvalidCommand as c_int,
+    };
     let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
-    let (tx, rx) = single_use_channel::<FfiErrorCode>();
-    ffi_player.send_message(Message::Pause(tx));
+    let ret = call(&ffi_player, Message::Libav(LibavMessage::SetRtspTransport(transport)));
     mem::forget(ffi_player);
-    rx.recv().unwrap_or(FfiErrorCode::Disconnected) as c_int
+    ret
 }
 
+// Size of the UDP socket buffers of the streams opened from now on, in bytes: high-bitrate RTSP
+// over UDP loses packets when it is too small. 0 goes back to the system's default.
 #[no_mangle]
-pub extern fn aml_video_player_set_fullscreen(player: *mut c_void, fullscreen: c_int) -> c_int {
+pub extern fn aml_video_player_set_rtsp_buffer_size(player: *mut c_void, bytes: c_uint) -> c_int {
+    let size = if bytes == 0 { None } else { Some(bytes as u32) };
     let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
-    let (tx, rx) = single_use_channel::<FfiErrorCode>();
-    ffi_player.send_message(Message::SetFullscreen(tx, fullscreen >= 1));
+    let ret = call(&ffi_player, Message::Libav(LibavMessage::SetNetworkBufferSize(size)));
     mem::forget(ffi_player);
-    rx.recv().unwrap_or(FfiErrorCode::Disconnected) as c_int
+    ret
 }
 
+// Returns as soon as the libav thread has received the url: the video is opened in the
+// background, the current one keeps playing. Loading the same url later (or calling
+// aml_video_player_play_preloaded) uses it right away.
 #[no_mangle]
-pub extern fn aml_video_player_resize(player: *mut c_void, width: c_uint, height: c_uint) -> c_int {
+pub extern fn aml_video_player_preload(player: *mut c_void, video_url: *const c_char) -> c_int {
+    if video_url.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
     let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
-    let (tx, rx) = single_use_channel::<FfiErrorCode>();
-    ffi_player.send_message(Message::SetSize(tx, (width as u16, height as u16)));
+    let video_url = unsafe {
+        ::std::ffi::CStr::from_ptr(video_url)
+    };
+    let ret = call(&ffi_player, Message::Preload(video_url.to_string_lossy().into_owned()));
     mem::forget(ffi_player);
-    rx.recv().unwrap_or(FfiErrorCode::Disconnected) as c_int
+    ret
 }
 
+// Opens the url in the background like aml_video_player_preload, for a video which will probably
+// be loaded next. Only aml_video_player_load of the same url uses it: loading another url closes
+// it, and it replaces any preload. Failures are only logged, the next load simply opens the url
+// again.
 #[no_mangle]
-pub extern fn aml_video_player_set_pos(player: *mut c_void, x: c_int, y: c_int) -> c_int {
+pub extern fn aml_video_player_prefetch_url(player: *mut c_void, url: *const c_char) -> c_int {
+    if url.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let url = unsafe { ::std::ffi::CStr::from_ptr(url) }.to_string_lossy().into_owned();
     let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
-    let (tx, rx) = single_use_channel::<FfiErrorCode>();
-    ffi_player.send_message(Message::SetPos(tx, (x as i16, y as i16)));
+    let ret = call(&ffi_player, Message::Libav(LibavMessage::Prefetch(url)));
     mem::forget(ffi_player);
-    rx.recv().unwrap_or(FfiErrorCode::Disconnected) as c_int
+    ret
 }
 
-// this is the opposite from "create", we are dereferencing the given pointer,
-// sending a Shutdown message (more on that in player.rs), and then we wait for every thread to
-// finish and return the appropiate status code if some threads failed to finish properly.
+// If the preload failed, its error is returned here
+#[no_mangle]
+pub extern fn aml_video_player_play_preloaded(player: *mut c_void) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::PlayPreloaded);
+    mem::forget(ffi_player);
+    ret
+}
+
+// The playlist file is parsed right here, in the API user's thread: this allows us to report a
+// malformed playlist right away, before anything is sent to the other threads.
 //
-// The FfiPlayer allocated on the Heap is deallocated automatically at the end of this function,
-// because its destructor deallocates the memory in this case.
+// loop_mode == 0: stop after the last entry
+// loop_mode >= 1: play the first entry again after the last one
 #[no_mangle]
-pub extern fn aml_video_player_destroy(player: *mut c_void) -> c_int {
+pub extern fn aml_video_player_load_playlist_m3u(player: *mut c_void, m3u_path: *const c_char, loop_mode: c_int) -> c_int {
+    if m3u_path.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let m3u_path = unsafe {
+        ::std::ffi::CStr::from_ptr(m3u_path)
+    };
+    let playlist = match Playlist::from_m3u(&*m3u_path.to_string_lossy(), loop_mode >= 1) {
+        Ok(playlist) => playlist,
+        Err(e) => {
+            error!("failed to load the playlist: {}", e.display());
+            mem::forget(ffi_player);
+            return error_to_ecode(e) as c_int;
+        }
+    };
+    let ret = call(&ffi_player, Message::SetPlaylist(playlist));
+    mem::forget(ffi_player);
+    ret
+}
+
+#[no_mangle]
+pub extern fn aml_video_player_seek(player: *mut c_void, pos: c_float) -> c_int {
+    // !(pos >= 0.0) also rejects NaN, before anything is sent
+    if !(pos >= 0.0) {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Seek(pos as f64));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Same as aml_video_player_seek, without waiting for the seek to be done: see
+// aml_video_player_is_seek_complete. When several are requested before the first one is done, only
+// the last position is seeked to. Errors are only logged.
+#[no_mangle]
+pub extern fn aml_video_player_async_seek(player: *mut c_void, pos: c_float) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    if ffi_player.async_seek.request(pos as f64) {
+        // nobody listens to the reply
+        let (tx, _rx) = single_use_channel::<Reply>();
+        ffi_player.send_message(Message::AsyncSeek, tx);
+    }
+    mem::forget(ffi_player);
+    FfiErrorCode::None as c_int
+}
+
+// 1 once the last async seek is done (or failed), 0 while it is still pending
+#[no_mangle]
+pub extern fn aml_video_player_is_seek_complete(player: *mut c_void) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let complete = ffi_player.async_seek.is_complete();
+    mem::forget(ffi_player);
+    complete as c_int
+}
+
+// Holds the video back when it is ahead of the audio: the libav thread will never send a packet
+// more than `delay_secs` seconds ahead of the wall clock. 0 disables this (the default).
+#[no_mangle]
+pub extern fn aml_video_player_set_video_delay(player: *mut c_void, delay_secs: c_float) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Libav(LibavMessage::SetVideoDelay(delay_secs as f64)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Plays the video backward at `speed` (1.0 being real time) when speed > 0, 0 goes back to the
+// normal playback from the frame shown. The VPU can't decode backward, so only keyframes are
+// shown: this is meant for rewinding, not for watching. A video must be playing.
+#[no_mangle]
+pub extern fn aml_video_player_set_backward_speed(player: *mut c_void, speed: c_float) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Libav(LibavMessage::SetBackwardSpeed(speed as f64)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Number of subtitle tracks of the video loaded, bitmap ones included. Returns InvalidCommand if
+// no video is loaded.
+#[no_mangle]
+pub extern fn aml_video_player_get_subtitle_track_count(player: *mut c_void, out: *mut c_uint) -> c_int {
+    if out.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let reply = query(&ffi_player, |count_tx| Message::Libav(LibavMessage::GetSubtitleTrackCount(count_tx)));
+    mem::forget(ffi_player);
+    match reply {
+        Ok(count) => {
+            unsafe { *out = count as c_uint; }
+            FfiErrorCode::None as c_int
+        },
+        Err(error_code) => error_code,
+    }
+}
+
+// The cues of the track `index` (0 being the first subtitle track, not the first stream) are
+// sent as AMPLAYER_EVENT_SUBTITLE events until the next load. Drawing them is up to the caller.
+// Returns Unsupported for bitmap tracks, and InvalidCommand if index is out of range.
+#[no_mangle]
+pub extern fn aml_video_player_set_subtitle_track(player: *mut c_void, index: c_uint) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Libav(LibavMessage::SetSubtitleTrack(Some(index as usize))));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Stops sending subtitle events
+#[no_mangle]
+pub extern fn aml_video_player_disable_subtitles(player: *mut c_void) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Libav(LibavMessage::SetSubtitleTrack(None)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Reads the SRT file at `path` and sends its cues as AMPLAYER_EVENT_SUBTITLE events when the
+// position of the video reaches them, which is independent of set_subtitle_track. They are
+// dropped by the next load, so a video must be loaded first.
+#[no_mangle]
+pub extern fn aml_video_player_load_subtitles(player: *mut c_void, path: *const c_char) -> c_int {
+    if path.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let path = unsafe { ::std::ffi::CStr::from_ptr(path) }.to_string_lossy().into_owned();
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = match subtitles::load_srt(&path) {
+        Ok(cues) => {
+            call(&ffi_player, Message::LoadSubtitles(cues))
+        },
+        Err(e) => {
+            error!("failed to load the subtitles: {}", e.display());
+            keep_error(&ffi_player, e)
+        }
+    };
+    mem::forget(ffi_player);
+    ret
+}
+
+// Plays the video from `start` to `end` (in seconds) over and over, with an
+// AMPLAYER_EVENT_LOOP_WRAPPED event every time it goes back to `start`. Nothing happens before
+// the video reaches `end`, and a paused video only goes back once it plays again. The range is
+// dropped by the next load, so a video must be loaded first.
+#[no_mangle]
+pub extern fn aml_video_player_set_loop_range(player: *mut c_void, start: c_float, end: c_float) -> c_int {
+    if !(start >= 0.0 && end > start) {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::SetLoopRange(Some((start as f64, end as f64))));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Stops repeating, the video plays on past the end of the range
+#[no_mangle]
+pub extern fn aml_video_player_clear_loop_range(player: *mut c_void) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::SetLoopRange(None));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Number of times the video went back to the start of the loop range since it was set. It is
+// kept once the range is cleared, and reset by the next set_loop_range.
+#[no_mangle]
+pub extern fn aml_video_player_get_loop_count(player: *mut c_void, out: *mut c_uint) -> c_int {
+    if out.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let reply = query(&ffi_player, Message::GetLoopCount);
+    mem::forget(ffi_player);
+    match reply {
+        Ok(count) => {
+            unsafe { *out = count as c_uint; }
+            FfiErrorCode::None as c_int
+        },
+        Err(error_code) => error_code,
+    }
+}
+
+// Limits the number of video packets sent to the VPU per second, to save some power on
+// constrained devices. 0 means unlimited (the default). This should not be set below the video's
+// framerate.
+#[no_mangle]
+pub extern fn aml_video_player_set_max_decode_rate(player: *mut c_void, packets_per_sec: c_uint) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Libav(LibavMessage::SetMaxDecodeRate(packets_per_sec as u32)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Fills `out` with the timestamps of the VPU, to diagnose A/V synchronization issues.
+// Returns InvalidCommand if no video is playing, `out` is left untouched in case of error.
+#[no_mangle]
+pub extern fn aml_video_player_get_timing_info(player: *mut c_void, out: *mut AmlTimingInfo) -> c_int {
+    if out.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let reply = query(&ffi_player, |info_tx| Message::Amcodec(AmcodecMessage::GetTimingInfo(info_tx)));
+    mem::forget(ffi_player);
+    match reply {
+        Ok(info) => {
+            unsafe { *out = info; }
+            FfiErrorCode::None as c_int
+        },
+        Err(error_code) => error_code,
+    }
+}
+
+// Counters since the player was created, see stats.rs. Doesn't go through the main thread, so this
+// also works while the player is busy or broken.
+#[no_mangle]
+pub extern fn aml_video_player_get_stats(player: *mut c_void, out: *mut AmlPlayerStats) -> c_int {
+    if out.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let snapshot = ffi_player.stats.snapshot(ffi_player.events.dropped_total());
+    mem::forget(ffi_player);
+    unsafe { *out = snapshot; }
+    FfiErrorCode::None as c_int
+}
+
+// Compares what is on the framebuffer right now with the PPM image at reference_path, for test
+// benches (see screenshot.rs: the video layer itself isn't captured). Returns 0 if their mean
+// absolute error is at most tolerance (0 to 255 per channel), the rounded up error otherwise.
+// AMPLAYER_ERROR_INVALID_COMMAND is returned when not running on Amlogic hardware.
+#[no_mangle]
+pub extern fn aml_video_player_screenshot_compare(player: *mut c_void, reference_path: *const c_char, tolerance: c_float) -> c_int {
+    if !cfg!(target_arch = "aarch64") || reference_path.is_null() || !(tolerance >= 0.0) {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let reference_path = unsafe { ::std::ffi::CStr::from_ptr(reference_path) }.to_string_lossy().into_owned();
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let images = screenshot::load_reference(&reference_path)
+        .and_then(|reference| Ok((screenshot::capture_framebuffer()?, reference)));
+    let ret = match images {
+        Ok((captured, reference)) => {
+            let distance = screenshot::mean_absolute_error(&captured, &reference);
+            debug!("screenshot differs from {} by {:.2}", reference_path, distance);
+            if distance <= tolerance as f64 {
+                FfiErrorCode::None as c_int
+            } else {
+                (distance.ceil() as c_int).max(1)
+            }
+        },
+        Err(e) => {
+            error!("failed to compare the screen with {}: {}", reference_path, e.display());
+            keep_error(&ffi_player, e)
+        },
+    };
+    mem::forget(ffi_player);
+    ret
+}
+
+// Writes the frame shown by the VPU to `path` as a PNG file, at the resolution of the video. Only
+// the video layer is captured, unlike screenshot_compare. Off Amlogic hardware, the same
+// synthetic image is written every time. Returns AMPLAYER_ERROR_NOTHING_DISPLAYED if no frame is
+// shown, AMPLAYER_ERROR_DEVICE_MISSING without amvideocap, and AMPLAYER_ERROR_WRITE_FAILED if path
+// can't be written.
+#[no_mangle]
+pub extern fn aml_video_player_snapshot_png(player: *mut c_void, path: *const c_char) -> c_int {
+    if path.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let path = unsafe { ::std::ffi::CStr::from_ptr(path) }.to_string_lossy().into_owned();
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = match screenshot::capture_video().and_then(|image| screenshot::save_png(&image, &path)) {
+        Ok(()) => FfiErrorCode::None as c_int,
+        Err(e) => {
+            error!("failed to save a snapshot to {}: {}", path, e.display());
+            keep_error(&ffi_player, e)
+        },
+    };
+    mem::forget(ffi_player);
+    ret
+}
+
+// Fills out with what the video stream of the file loaded is encoded with, as libav reads it:
+// codec, profile, level, bit depth and chroma format. Returns InvalidCommand if no video is loaded.
+#[no_mangle]
+pub extern fn aml_video_player_get_codec_info(player: *mut c_void, out: *mut AmlCodecInfo) -> c_int {
+    if out.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let reply = query(&ffi_player, |info_tx| Message::Libav(LibavMessage::GetCodecInfo(info_tx)));
+    mem::forget(ffi_player);
+    match reply {
+        Ok(info) => {
+            unsafe { *out = info; }
+            FfiErrorCode::None as c_int
+        },
+        Err(error_code) => error_code,
+    }
+}
+
+// Fills out with what the VPU's decoder knows about the current video. Unlike the timing info,
+// this doesn't fail when nothing is playing, the values are just meaningless then.
+#[no_mangle]
+pub extern fn aml_video_player_get_vpu_state(player: *mut c_void, out: *mut VpuDecoderState) -> c_int {
+    if out.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
     let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
-    ffi_player.send_message(Message::Shutdown);
-    ffi_result_to_int(ffi_player.join())
+    let reply = query(&ffi_player, |state_tx| Message::Amcodec(AmcodecMessage::GetDecoderState(state_tx)));
+    mem::forget(ffi_player);
+    match reply {
+        Ok(state) => {
+            unsafe { *out = state; }
+            FfiErrorCode::None as c_int
+        },
+        Err(error_code) => error_code,
+    }
+}
+
+// When the VPU's buffer goes below `bytes` while playing, the libav thread stops holding packets
+// back (see set_video_delay and set_max_decode_rate) to fill it again. Defaults to 4096.
+#[no_mangle]
+pub extern fn aml_video_player_set_underflow_threshold(player: *mut c_void, bytes: c_int) -> c_int {
+    if bytes < 0 {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Amcodec(AmcodecMessage::SetUnderflowThreshold(bytes as i32)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// When enabled, the last frame of a video stays on screen once it ended, until the next video is
+// shown, instead of the video layer being cleared. Stopping, loading another video and
+// destroying the player still clear it.
+#[no_mangle]
+pub extern fn aml_video_player_set_freeze_on_last_frame(player: *mut c_void, enable: c_int) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Amcodec(AmcodecMessage::SetFreezeOnEof(enable != 0)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// 0 disables the timeout. Does not apply to aml_video_player_wait_until_end, which is meant to
+// block for a long time.
+#[no_mangle]
+pub extern fn aml_video_player_set_reply_timeout(player: *mut c_void, timeout_ms: c_uint) -> c_int {
+    if player.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    ffi_player.reply_timeout_ms.store(timeout_ms as u64, Ordering::SeqCst);
+    mem::forget(ffi_player);
+    FfiErrorCode::None as c_int
+}
+
+// This function is rather special, since we are blocking until an "end of video" message is sent
+// to us. Basically this message (which is at the moment always returned when the VPU hits EOF)
+// allows us to get the exact moment where a video is finished, so that we can queue the next one
+// right up, or shutdown the program right after the video's done.
+#[no_mangle]
+pub extern fn aml_video_player_wait_until_end(player: *mut c_void) -> c_int {
+    let mut ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = ffi_player.wait_for_video_status();
+    mem::forget(ffi_player);
+    ret
+}
+
+// Writes the oldest event of the player in `out` and removes it from the queue. Waits at most
+// timeout_ms for one: forever if negative, not at all if 0. Returns Timeout if nothing came in
+// time, `out` is then left untouched.
+//
+// The end events are shared with aml_video_player_wait_until_end: each of them is only returned
+// by one of these two functions.
+#[no_mangle]
+pub extern fn aml_video_player_next_event(player: *mut c_void, out: *mut AmlEvent, timeout_ms: c_int) -> c_int {
+    if out.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let timeout = if timeout_ms < 0 {
+        None
+    } else {
+        Some(Duration::from_millis(timeout_ms as u64))
+    };
+    let ret = match ffi_player.events.next(timeout) {
+        Ok((event, dropped)) => {
+            unsafe { *out = AmlEvent::new(&event, dropped); }
+            FfiErrorCode::None
+        },
+        Err(error_code) => error_code,
+    };
+    mem::forget(ffi_player);
+    ret as c_int
+}
+
+#[no_mangle]
+pub extern fn aml_video_player_show(player: *mut c_void) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Show);
+    mem::forget(ffi_player);
+    ret
+}
+
+#[no_mangle]
+pub extern fn aml_video_player_hide(player: *mut c_void) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Hide);
+    mem::forget(ffi_player);
+    ret
+}
+
+// enabled > 0 lets every pointer event go through the video window (X11 only)
+#[no_mangle]
+pub extern fn aml_video_player_set_click_through(player: *mut c_void, enabled: c_int) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::SetClickThrough(enabled > 0));
+    mem::forget(ffi_player);
+    ret
+}
+
+// enable > 0 disables the video layer while the window is entirely covered by other windows
+// (X11 only). Disabled by default, since some users overlay semi-transparent UI on purpose.
+#[no_mangle]
+pub extern fn aml_video_player_set_hide_when_obscured(player: *mut c_void, enable: c_int) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::SetHideWhenObscured(enable > 0));
+    mem::forget(ffi_player);
+    ret
+}
+
+// mode is 0 for raise/lower (default), 1 for map/unmap. See window::ShowHideMode
+#[no_mangle]
+pub extern fn aml_video_player_set_show_hide_mode(player: *mut c_void, mode: c_int) -> c_int {
+    let mode = match ShowHideMode::from_int(mode as i32) {
+        Some(mode) => mode,
+        None => return FfiErrorCode::InvalidCommand as c_int,
+    };
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::SetShowHideMode(mode));
+    mem::forget(ffi_player);
+    ret
+}
+
+#[no_mangle]
+pub extern fn aml_video_player_play(player: *mut c_void) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Play);
+    mem::forget(ffi_player);
+    ret
+}
+#[no_mangle]
+pub extern fn aml_video_player_pause(player: *mut c_void) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Pause);
+    mem::forget(ffi_player);
+    ret
+}
+
+#[no_mangle]
+pub extern fn aml_video_player_set_fullscreen(player: *mut c_void, fullscreen: c_int) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::SetFullscreen(fullscreen >= 1));
+    mem::forget(ffi_player);
+    ret
+}
+
+// enable > 0 makes every video go fullscreen once its first frame is shown, when there are at
+// most as many screens as the threshold (1 by default)
+#[no_mangle]
+pub extern fn aml_video_player_set_auto_fullscreen(player: *mut c_void, enable: c_int) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::SetAutoFullscreen(enable > 0));
+    mem::forget(ffi_player);
+    ret
+}
+
+#[no_mangle]
+pub extern fn aml_video_player_set_auto_fullscreen_screen_threshold(player: *mut c_void, max_screens: c_uint) -> c_int {
+    if max_screens == 0 {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::SetAutoFullscreenThreshold(max_screens as u32));
+    mem::forget(ffi_player);
+    ret
+}
+
+#[no_mangle]
+pub extern fn aml_video_player_resize(player: *mut c_void, width: c_uint, height: c_uint) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::SetSize((width as u32, height as u32)));
+    mem::forget(ffi_player);
+    ret
+}
+
+#[no_mangle]
+pub extern fn aml_video_player_set_pos(player: *mut c_void, x: c_int, y: c_int) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::SetPos((x as i32, y as i32)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// enable > 0 lets set_pos and resize put the window partly or entirely out of the screen, for
+// multi-head setups. Disabled by default: the window is moved back on the screen instead.
+#[no_mangle]
+pub extern fn aml_video_player_set_allow_offscreen(player: *mut c_void, enable: c_int) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::SetAllowOffscreen(enable > 0));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Letterboxes the video in the window with the aspect ratio width_ratio:height_ratio, without
+// resizing the window. 0:0 makes the video fill the window again.
+#[no_mangle]
+pub extern fn aml_video_player_set_video_aspect_ratio(player: *mut c_void, width_ratio: c_uint, height_ratio: c_uint) -> c_int {
+    let aspect = match (width_ratio, height_ratio) {
+        (0, 0) => None,
+        (0, _) | (_, 0) => return FfiErrorCode::InvalidCommand as c_int,
+        (w, h) => Some((w as u32, h as u32)),
+    };
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::SetVideoAspect(aspect));
+    mem::forget(ffi_player);
+    ret
+}
+
+// The pixels of every video are num:den (wider than high if num > den) instead of what the stream
+// says. 0:0 goes back to the stream's. A video whose pixels aren't square is letterboxed to the
+// shape it is meant to be shown at, unless an aspect ratio was set with set_video_aspect_ratio.
+#[no_mangle]
+pub extern fn aml_video_player_set_override_sar(player: *mut c_void, num: c_uint, den: c_uint) -> c_int {
+    let sar = match (num, den) {
+        (0, 0) => None,
+        (0, _) | (_, 0) => return FfiErrorCode::InvalidCommand as c_int,
+        (num, den) => Some((num as u32, den as u32)),
+    };
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::SetOverrideSar(sar));
+    mem::forget(ffi_player);
+    ret
+}
+
+// set_output_rect, but the video slides to the rectangle over duration_ms instead of jumping there.
+// The window goes there right away. easing is AMPLAYER_EASING_*, a duration of 0 jumps.
+#[no_mangle]
+pub extern fn aml_video_player_animate_to_rect(player: *mut c_void, x: c_int, y: c_int, width: c_uint, height: c_uint, duration_ms: c_uint, easing: c_uint) -> c_int {
+    let easing = match Easing::from_int(easing as u32) {
+        Some(easing) => easing,
+        None => return FfiErrorCode::InvalidCommand as c_int,
+    };
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::AnimateToRect((x as i32, y as i32, width as u32, height as u32), Duration::from_millis(duration_ms as u64), easing));
+    mem::forget(ffi_player);
+    ret
+}
+
+// set_pos and resize at once, so that the video isn't shown at a wrong size in between. x and y
+// may be negative, to push the edges of the video out of the screen (overscan compensation).
+#[no_mangle]
+pub extern fn aml_video_player_set_output_rect(player: *mut c_void, x: c_int, y: c_int, width: c_uint, height: c_uint) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::SetRect((x as i32, y as i32, width as u32, height as u32)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Makes the window visible on every virtual desktop (enable > 0), or only on the current one
+#[no_mangle]
+pub extern fn aml_video_player_set_sticky(player: *mut c_void, enable: c_int) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::SetSticky(enable > 0));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Keeps the window out of the taskbar and the pager (enable > 0), or puts it back in them
+#[no_mangle]
+pub extern fn aml_video_player_set_skip_taskbar(player: *mut c_void, enable: c_int) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::SetSkipTaskbar(enable > 0));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Writes the resolution of the screen in out_w and out_h. They are left untouched if an error
+// occurs.
+#[no_mangle]
+pub extern fn aml_video_player_get_screen_size(player: *mut c_void, out_w: *mut c_uint, out_h: *mut c_uint) -> c_int {
+    if out_w.is_null() || out_h.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let reply = query(&ffi_player, Message::GetScreenSize);
+    mem::forget(ffi_player);
+    match reply {
+        Ok((w, h)) => {
+            unsafe {
+                *out_w = w as c_uint;
+                *out_h = h as c_uint;
+            }
+            FfiErrorCode::None as c_int
+        },
+        Err(error_code) => error_code,
+    }
+}
+
+// Writes the current position of the video in seconds in out_pos. It is left untouched if an
+// error occurs, or if nothing is loaded (InvalidCommand)
+#[no_mangle]
+pub extern fn aml_video_player_get_position(player: *mut c_void, out_pos: *mut c_float) -> c_int {
+    if out_pos.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let reply = query(&ffi_player, Message::GetPosition);
+    mem::forget(ffi_player);
+    match reply {
+        Ok(pos) => {
+            unsafe {
+                *out_pos = pos as c_float;
+            }
+            FfiErrorCode::None as c_int
+        },
+        Err(error_code) => error_code,
+    }
+}
+
+// Writes the duration of the video loaded in seconds in out_duration. Unsupported for live streams,
+// which have none, InvalidCommand if nothing is loaded.
+#[no_mangle]
+pub extern fn aml_video_player_get_duration(player: *mut c_void, out_duration: *mut c_float) -> c_int {
+    if out_duration.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let reply = query(&ffi_player, |duration_tx| Message::Libav(LibavMessage::GetDuration(duration_tx)));
+    mem::forget(ffi_player);
+    match reply {
+        Ok(duration) => {
+            unsafe {
+                *out_duration = duration as c_float;
+            }
+            FfiErrorCode::None as c_int
+        },
+        Err(error_code) => error_code,
+    }
+}
+
+// device is 0 for the main HDMI output, 1 for the secondary HDMI output, 2 for the composite
+// output. The previous display mode is restored when the player is destroyed.
+#[no_mangle]
+pub extern fn aml_video_player_set_output_device(player: *mut c_void, device: c_uint) -> c_int {
+    let output = match VideoOutput::from_int(device as u32) {
+        Some(output) => output,
+        None => return FfiErrorCode::InvalidCommand as c_int,
+    };
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Amcodec(AmcodecMessage::SetVideoOutput(output)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// mode is given as-is to the driver ("1080p60hz", "4k2k60hz", "720p50hz", ...), see
+// aml_video_player_get_display_modes. The previous mode is restored when the player is destroyed.
+#[no_mangle]
+pub extern fn aml_video_player_set_display_mode_str(player: *mut c_void, mode: *const c_char) -> c_int {
+    let mode : String = unsafe {
+        ::std::ffi::CStr::from_ptr(mode)
+    }.to_string_lossy().into_owned();
+    // this is written in a sysfs node, where a newline would end it
+    if mode.is_empty() || mode.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Amcodec(AmcodecMessage::SetDisplayMode(mode)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// device is an ALSA device, such as "hw:0,0" (HDMI) or "hw:1,0" (analog) on most boards, see
+// aml_video_player_get_audio_devices. It is kept when another video is loaded. Its mixer only gets
+// the volume at the next aml_video_player_set_volume or aml_video_player_set_mute.
+#[no_mangle]
+pub extern fn aml_video_player_set_audio_output_device(player: *mut c_void, device: *const c_char) -> c_int {
+    if device.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let device : String = unsafe {
+        ::std::ffi::CStr::from_ptr(device)
+    }.to_string_lossy().into_owned();
+    if device.is_empty() || device.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    ffi_player.audio.lock().unwrap_or_else(|e| e.into_inner()).device = device;
+    mem::forget(ffi_player);
+    FfiErrorCode::None as c_int
+}
+
+// volume goes from 0.0 to 1.0, and is written to the mixer of the audio device's card. It is kept
+// when another video is loaded, and while muted it is only applied once unmuted. Unsupported is
+// returned if the card has no volume control.
+#[no_mangle]
+pub extern fn aml_video_player_set_volume(player: *mut c_void, volume: c_float) -> c_int {
+    // !(volume >= 0.0) also rejects NaN
+    if !(volume >= 0.0) || volume > 1.0 {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Amcodec(AmcodecMessage::SetVolume(volume as f32)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// The volume set by aml_video_player_set_volume, even while muted
+#[no_mangle]
+pub extern fn aml_video_player_get_volume(player: *mut c_void, out: *mut c_float) -> c_int {
+    if out.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let volume = ffi_player.audio.lock().unwrap_or_else(|e| e.into_inner()).volume.get();
+    unsafe { *out = volume as c_float; }
+    mem::forget(ffi_player);
+    FfiErrorCode::None as c_int
+}
+
+// Any non-zero value mutes by writing 0 to the mixer, 0 writes the volume set before back. The
+// volume itself is left untouched. Same errors as aml_video_player_set_volume, nothing changes
+// if the mixer can't be written.
+#[no_mangle]
+pub extern fn aml_video_player_set_mute(player: *mut c_void, mute: c_int) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Amcodec(AmcodecMessage::SetMute(mute != 0)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// 1 if muted, 0 otherwise
+#[no_mangle]
+pub extern fn aml_video_player_is_muted(player: *mut c_void) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let muted = ffi_player.audio.lock().unwrap_or_else(|e| e.into_inner()).volume.is_muted();
+    mem::forget(ffi_player);
+    muted as c_int
+}
+
+// Writes the ALSA cards in buf, one per line, as "hw:0,0" followed by a tab and the name of the
+// card. No player is needed for this.
+//
+// Same convention as aml_video_player_get_display_modes.
+#[no_mangle]
+pub extern fn aml_video_player_get_audio_devices(buf: *mut c_char, len: c_uint) -> c_int {
+    match audio::available_devices() {
+        Ok(devices) => {
+            let lines : Vec<String> = devices.iter().map(|d| format!("{}\t{}", d.device, d.name)).collect();
+            write_c_string(&lines.join("\n"), buf, len)
+        },
+        Err(e) => {
+            error!("failed to read the audio devices: {}", e.display());
+            error_to_ecode(e) as c_int
+        }
+    }
+}
+
+// Writes the modes supported by the main display in buf, one per line. No player is needed for
+// this.
+//
+// Same convention as aml_video_player_get_sys_info, but a negative error code is returned if the
+// modes could not be read at all.
+#[no_mangle]
+pub extern fn aml_video_player_get_display_modes(buf: *mut c_char, len: c_uint) -> c_int {
+    match Amcodec::get_available_display_modes() {
+        Ok(modes) => write_c_string(&modes.join("\n"), buf, len),
+        Err(e) => {
+            error!("failed to read the display modes: {}", e.display());
+            error_to_ecode(e) as c_int
+        }
+    }
+}
+
+// Number of errors and warnings logged since the library was loaded, by every player. Both
+// pointers may be null.
+#[no_mangle]
+pub extern fn aml_video_player_get_log_counts(errors: *mut c_uint, warnings: *mut c_uint) -> c_int {
+    let (error_count, warning_count) = log::counts();
+    unsafe {
+        if !errors.is_null() {
+            *errors = error_count as c_uint;
+        }
+        if !warnings.is_null() {
+            *warnings = warning_count as c_uint;
+        }
+    }
+    0
+}
+
+// mode: 0 off, 1 low, 2 medium, 3 high, 4 custom (custom_value is then given as-is to the
+// driver, and ignored otherwise). This is hardware-specific, see amcodec::CONTRAST_PATH.
+#[no_mangle]
+pub extern fn aml_video_player_set_contrast_mode(player: *mut c_void, mode: c_uint, custom_value: c_int) -> c_int {
+    let mode = match ContrastMode::from_int(mode as u32, custom_value as i32) {
+        Some(mode) => mode,
+        None => return FfiErrorCode::InvalidCommand as c_int,
+    };
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Amcodec(AmcodecMessage::SetContrastMode(mode)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// preset: 0 warm, 1 neutral, 2 cool, 3 custom (the gains are then given as-is to the driver, and
+// ignored otherwise). This is hardware-specific, see amcodec::WHITE_BALANCE_PATH.
+#[no_mangle]
+pub extern fn aml_video_player_set_color_temperature(player: *mut c_void, preset: c_uint, r_gain: c_int, g_gain: c_int, b_gain: c_int) -> c_int {
+    let in_range = |gain: c_int| gain >= ::std::i16::MIN as c_int && gain <= ::std::i16::MAX as c_int;
+    if !(in_range(r_gain) && in_range(g_gain) && in_range(b_gain)) {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let temp = match ColorTemperature::from_int(preset as u32, r_gain as i16, g_gain as i16, b_gain as i16) {
+        Some(temp) => temp,
+        None => return FfiErrorCode::InvalidCommand as c_int,
+    };
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Amcodec(AmcodecMessage::SetColorTemperature(temp)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// saturation is in -128..127, 0 being the default. This is hardware-specific, see
+// amcodec::SATURATION_HUE_PATH.
+#[no_mangle]
+pub extern fn aml_video_player_set_saturation(player: *mut c_void, saturation: c_int) -> c_int {
+    if saturation < MIN_SATURATION_HUE || saturation > MAX_SATURATION_HUE {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Amcodec(AmcodecMessage::SetSaturation(saturation as i32)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Same as aml_video_player_set_saturation, for the hue
+#[no_mangle]
+pub extern fn aml_video_player_set_hue(player: *mut c_void, hue: c_int) -> c_int {
+    if hue < MIN_SATURATION_HUE || hue > MAX_SATURATION_HUE {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Amcodec(AmcodecMessage::SetHue(hue as i32)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Writes the last saturation set in `out` (0 if it has never been set), which is left untouched
+// in case of error
+#[no_mangle]
+pub extern fn aml_video_player_get_saturation(player: *mut c_void, out: *mut c_int) -> c_int {
+    if out.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let reply = query(&ffi_player, |saturation_tx| Message::Amcodec(AmcodecMessage::GetSaturation(saturation_tx)));
+    mem::forget(ffi_player);
+    match reply {
+        Ok(saturation) => {
+            unsafe { *out = saturation as c_int; }
+            FfiErrorCode::None as c_int
+        },
+        Err(error_code) => error_code,
+    }
+}
+
+// Moves the timestamps of every packet by offset_ms before they reach the VPU, for the transport
+// streams whose PTS base it doesn't handle. 0 (the default) leaves them alone. It is kept when
+// another video is loaded.
+#[no_mangle]
+pub extern fn aml_video_player_set_ts_offset(player: *mut c_void, offset_ms: c_long) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Amcodec(AmcodecMessage::SetTsOffset(offset_ms as i64)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Writes the current offset in `out`, which is left untouched in case of error
+#[no_mangle]
+pub extern fn aml_video_player_get_ts_offset(player: *mut c_void, out: *mut c_long) -> c_int {
+    if out.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let reply = query(&ffi_player, |offset_tx| Message::Amcodec(AmcodecMessage::GetTsOffset(offset_tx)));
+    mem::forget(ffi_player);
+    match reply {
+        Ok(offset_ms) => {
+            unsafe { *out = offset_ms as c_long; }
+            FfiErrorCode::None as c_int
+        },
+        Err(error_code) => error_code,
+    }
+}
+
+// level goes from 0 (off) to 100 (strongest). It is applied again every time the decoder is
+// reopened, since some drivers reset it. This is hardware-specific, see
+// amcodec::NOISE_REDUCTION_PATH.
+#[no_mangle]
+pub extern fn aml_video_player_set_noise_reduction(player: *mut c_void, level: c_uint) -> c_int {
+    if level > MAX_NOISE_REDUCTION as c_uint {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Amcodec(AmcodecMessage::SetNoiseReduction(level as u8)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Writes the level currently used by the driver in `out`, which is left untouched in case of
+// error
+#[no_mangle]
+pub extern fn aml_video_player_get_noise_reduction(player: *mut c_void, out: *mut c_uint) -> c_int {
+    if out.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let reply = query(&ffi_player, |level_tx| Message::Amcodec(AmcodecMessage::GetNoiseReduction(level_tx)));
+    mem::forget(ffi_player);
+    match reply {
+        Ok(level) => {
+            unsafe { *out = level as c_uint; }
+            FfiErrorCode::None as c_int
+        },
+        Err(error_code) => error_code,
+    }
+}
+
+// level goes from 0 (off) to 100 (strongest). This is hardware-specific, see
+// amcodec::SHARPNESS_PATH.
+#[no_mangle]
+pub extern fn aml_video_player_set_sharpness(player: *mut c_void, level: c_uint) -> c_int {
+    if level > MAX_SHARPNESS as c_uint {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Amcodec(AmcodecMessage::SetSharpness(level as u8)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Transparency of the whole OSD (what is drawn on fb0) over the video: 0 lets the video show
+// through completely, 255 (the default) covers it. Fading it lets an UI appear over the video
+// smoothly. The OSD is made opaque again when the player is destroyed. This is
+// hardware-specific, see amcodec::OSD_ALPHA_PATH.
+#[no_mangle]
+pub extern fn aml_video_player_set_osd_alpha(player: *mut c_void, alpha: c_uchar) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Amcodec(AmcodecMessage::SetOsdAlpha(alpha as u8)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Writes the noise reduction, contrast, saturation, hue and sharpness set through the API to
+// path, see quality.rs for the format
+#[no_mangle]
+pub extern fn aml_video_player_save_video_quality(player: *mut c_void, path: *const c_char) -> c_int {
+    if path.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let path = unsafe { ::std::ffi::CStr::from_ptr(path) }.to_string_lossy().into_owned();
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = match query(&ffi_player, |settings_tx| Message::Amcodec(AmcodecMessage::GetVideoQuality(settings_tx))) {
+        Ok(settings) => match settings.save(&path) {
+            Ok(()) => FfiErrorCode::None as c_int,
+            Err(e) => {
+                error!("failed to save the video quality settings: {}", e.display());
+                keep_error(&ffi_player, e)
+            }
+        },
+        Err(error_code) => error_code,
+    };
+    mem::forget(ffi_player);
+    ret
+}
+
+// Applies the settings saved by aml_video_player_save_video_quality. Nothing is applied if the
+// file is invalid.
+#[no_mangle]
+pub extern fn aml_video_player_load_video_quality(player: *mut c_void, path: *const c_char) -> c_int {
+    if path.is_null() {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let path = unsafe { ::std::ffi::CStr::from_ptr(path) }.to_string_lossy().into_owned();
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = match VideoQualitySettings::load(&path) {
+        Ok(settings) => {
+            call(&ffi_player, Message::Amcodec(AmcodecMessage::SetVideoQuality(settings)))
+        },
+        Err(e) => {
+            error!("failed to load the video quality settings: {}", e.display());
+            keep_error(&ffi_player, e)
+        }
+    };
+    mem::forget(ffi_player);
+    ret
+}
+
+// output = input^(1/gamma) on every channel, 1.0 being the driver's default. The default table is
+// restored when the player is destroyed. This is hardware-specific, see amcodec::GAMMA_PATH.
+#[no_mangle]
+pub extern fn aml_video_player_set_gamma(player: *mut c_void, gamma: c_float) -> c_int {
+    if !(gamma.is_finite() && gamma > 0.0) {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Amcodec(AmcodecMessage::SetGamma(gamma)));
+    mem::forget(ffi_player);
+    ret
+}
+
+// table must hold exactly GAMMA_TABLE_LEN 10-bit values, applied to every channel
+#[no_mangle]
+pub extern fn aml_video_player_set_gamma_table(player: *mut c_void, table: *const c_ushort, count: c_uint) -> c_int {
+    if table.is_null() || count as usize != GAMMA_TABLE_LEN {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let values = unsafe { ::std::slice::from_raw_parts(table, GAMMA_TABLE_LEN) };
+    if values.iter().any(|&value| value > MAX_GAMMA_VALUE) {
+        return FfiErrorCode::InvalidCommand as c_int;
+    }
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let ret = call(&ffi_player, Message::Amcodec(AmcodecMessage::SetGammaTable(values.to_vec())));
+    mem::forget(ffi_player);
+    ret
+}
+
+// Tone maps SDR videos for an HDR display: mode is 0 (off), 1 (Reinhard) or 2 (ACES). The frames
+// would have to be tone mapped before the VPU decodes them, and there is no software decoding
+// path to do it on: this always returns InvalidCommand.
+#[no_mangle]
+pub extern fn aml_video_player_set_sdr_hdr_mode(_player: *mut c_void, mode: c_uint) -> c_int {
+    debug!("SDR to HDR mode {} refused, the VPU decodes every frame", mode);
+    FfiErrorCode::InvalidCommand as c_int
+}
+
+// Tells how the video is displayed (see window::DisplayPath), mostly useful to find out why
+// nothing is shown on screen. This never changes during the lifetime of a player.
+#[no_mangle]
+pub extern fn aml_video_player_get_display_path(player: *mut c_void) -> c_int {
+    let ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    let display_path = ffi_player.display_path as c_int;
+    mem::forget(ffi_player);
+    display_path
+}
+
+// this is the opposite from "create", we are dereferencing the given pointer,
+// sending a Shutdown message (more on that in player.rs), and then we wait for every thread to
+// finish and return the appropiate status code if some threads failed to finish properly.
+//
+// The FfiPlayer allocated on the Heap is deallocated automatically at the end of this function,
+// because its destructor deallocates the memory in this case.
+#[no_mangle]
+pub extern fn aml_video_player_destroy(player: *mut c_void) -> c_int {
+    let mut ffi_player = unsafe {Box::from_raw(player as *mut FfiPlayer)};
+    // dropping ffi_player would shut it down as well, but we want the status code
+    ffi_result_to_int(ffi_player.shutdown())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::ptr;
+
+    /// Asks for the size first, then writes in a buffer of exactly that size
+    fn read_sized(f: extern fn(*mut c_char, c_uint) -> c_int) -> String {
+        let size = f(ptr::null_mut(), 0);
+        assert!(size > 1, "{} bytes needed", size);
+        let mut buf = vec![0x55 as c_char; size as usize];
+        assert_eq!(f(buf.as_mut_ptr(), size as c_uint), size);
+        let s = unsafe { CStr::from_ptr(buf.as_ptr()) }.to_str().unwrap().to_owned();
+        // not truncated
+        assert_eq!(s.len() + 1, size as usize);
+        s
+    }
+
+    #[test]
+    fn version_fits_in_the_size_returned() {
+        assert_eq!(read_sized(aml_video_player_get_version), env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn build_info_fits_in_the_size_returned() {
+        let build_info = read_sized(aml_video_player_get_build_info);
+        assert!(build_info.starts_with(&format!("c2player {} (", env!("CARGO_PKG_VERSION"))), "{}", build_info);
+    }
+
+    #[test]
+    fn sized_strings_one_byte_short_are_truncated() {
+        let mut buf = [0x55 as c_char; 4];
+        assert_eq!(write_sized_c_string("abcd", buf.as_mut_ptr(), 4), 5);
+        assert_eq!(unsafe { CStr::from_ptr(buf.as_ptr()) }.to_bytes(), b"abc");
+        assert_eq!(write_sized_c_string("", ptr::null_mut(), 0), 1);
+        // the other strings keep the convention of snprintf
+        assert_eq!(write_c_string("abcd", ptr::null_mut(), 0), 4);
+    }
+
+    #[test]
+    fn reply_timeout_of_a_null_player() {
+        assert_eq!(aml_video_player_set_reply_timeout(ptr::null_mut(), 100), FfiErrorCode::InvalidCommand as c_int);
+    }
+
+    #[test]
+    fn sdr_hdr_mode_is_refused() {
+        for mode in 0..4 {
+            assert_eq!(aml_video_player_set_sdr_hdr_mode(ptr::null_mut(), mode), FfiErrorCode::InvalidCommand as c_int);
+        }
+    }
+
+    #[test]
+    fn preload_of_a_null_url() {
+        // refused before the player is looked at
+        assert_eq!(aml_video_player_preload(ptr::null_mut(), ptr::null()), FfiErrorCode::InvalidCommand as c_int);
+    }
+
+    #[test]
+    fn playlist_of_a_null_path() {
+        assert_eq!(aml_video_player_load_playlist_m3u(ptr::null_mut(), ptr::null(), 0), FfiErrorCode::InvalidCommand as c_int);
+    }
 }
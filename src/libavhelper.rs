@@ -1,28 +1,36 @@
 use error::*;
 use std::sync::Arc;
-use std::sync::mpsc::{TryRecvError, Sender, Receiver};
+use std::sync::mpsc::{self, TryRecvError, RecvTimeoutError, Sender, Receiver};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::thread;
 use std::ptr;
 use std::ffi::CString;
-use std::mem;
-use std::os::raw::c_int;
-use super::utils::SingleUseSender as SuSender;
+use std::mem::MaybeUninit;
+use std::os::raw::{c_int, c_void};
+use super::utils::{SingleUseSender as SuSender, Request, TokenBucket, KeyframeHistory, duration_to_secs, secs_to_duration};
+use super::position::{PositionReport, AsyncSeek};
+use super::events::PlayerEvent;
+use super::stats::{self, Stats};
+use super::config::PlayerConfig;
+use super::subtitles::{self, SubtitleFormat};
+use super::timeshift::{Timeshift, SpooledPacket};
+use super::pool::{BufferPool, PooledBuffer};
+use super::recording::{self, Recording};
 use libavformat as libav;
 
 // helper function which reduces the code by a few lines
 macro_rules! handle_channel_error {
     ( $x: expr, $tx: expr) => {
         if let Err(e) = $x {
-            println!("libavthread: channel disconnected: ({})", e);
+            error!("channel disconnected: ({})", e);
             $tx.send(FfiErrorCode::Disconnected);
             break;
         }
     };
     ( $x: expr) => {
         if let Err(e) = $x {
-            println!("libavthread: channel disconnected: ({})", e);
+            error!("channel disconnected: ({})", e);
             break;
         }
     };
@@ -31,13 +39,240 @@ macro_rules! handle_channel_error {
 // "EOF" error from libav
 const EOF : i32 = -1 * (((b'E' as u32) | (('O' as u32) << 8) | (('F' as u32) << 16) | ((' ' as u32) << 24)) as i32);
 
+// "no timestamp" value from libav, bindgen can't translate this one since it is a casted macro
+pub const AV_NOPTS_VALUE : i64 = 0x8000000000000000u64 as i64;
+
 /// libav context
 ///
 /// We only need the context itself and which index the hevc_stream is at. Everything else can be
 /// retrieved directly from the context itself
-struct Context {
+pub struct Context {
     pub ctx: *mut libav::AVFormatContext,
     pub hevc_stream: usize,
+    /// (index of the stream, format) of every subtitle stream, in the order of the file
+    pub subtitle_streams: Vec<(usize, SubtitleFormat)>,
+    /// number of streams when replacing_hevc_stream last looked, libav adds the ones of a
+    /// transport stream as it finds them
+    streams_seen: usize,
+    abi: Abi,
+    /// read by interrupt_callback, must live as long as ctx
+    _abort_io: Arc<AtomicBool>,
+}
+
+/// Called by libav every once in a while when it blocks on I/O (network reads mostly): returning 1
+/// makes the blocking call fail right away
+unsafe extern "C" fn interrupt_callback(abort_io: *mut c_void) -> c_int {
+    (*(abort_io as *const AtomicBool)).load(Ordering::SeqCst) as c_int
+}
+
+/// Major versions of libavformat whose structs we know the layout of. Most of the fields we read
+/// are at the same place in all of them, the others go through the methods below.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Abi {
+    V56,
+    V57,
+}
+
+impl Abi {
+    /// Layout of the libavformat this process is linked with, which isn't necessarily the one the
+    /// default bindings were built for. Fails if its major version isn't supported.
+    pub fn detect() -> Result<Abi> {
+        match avformat_version().0 {
+            56 => Ok(Abi::V56),
+            57 => Ok(Abi::V57),
+            _ => Err(Error::WrongLibavVersion),
+        }
+    }
+
+    pub fn major(self) -> u16 {
+        match self {
+            Abi::V56 => 56,
+            Abi::V57 => 57,
+        }
+    }
+
+    unsafe fn set_interrupt_callback(self, ctx: *mut libav::AVFormatContext, opaque: *mut c_void) {
+        match self {
+            Abi::V56 => {
+                (*(ctx as *mut libav::v56::AVFormatContext)).interrupt_callback = libav::v56::AVIOInterruptCB {
+                    callback: Some(interrupt_callback),
+                    opaque: opaque,
+                };
+            },
+            Abi::V57 => {
+                (*(ctx as *mut libav::v57::AVFormatContext)).interrupt_callback = libav::v57::AVIOInterruptCB {
+                    callback: Some(interrupt_callback),
+                    opaque: opaque,
+                };
+            },
+        }
+    }
+
+    /// Codec of `stream` as libavcodec numbers it, which changes between versions: it must not be
+    /// read as an AVCodecID of the default bindings
+    unsafe fn codec_id(self, stream: *const libav::AVStream) -> u32 {
+        let codec = (*stream).codec;
+        *(&(*codec).codec_id as *const libav::AVCodecID as *const u32)
+    }
+
+    /// (profile, level, pixel format) of the codec context of `stream`, as libav numbers them.
+    /// The pixel format is read as a u32 for the same reason as codec_id.
+    unsafe fn codec_params(self, stream: *const libav::AVStream) -> (i32, i32, u32) {
+        match self {
+            Abi::V56 => {
+                let codec = (*(stream as *const libav::v56::AVStream)).codec;
+                ((*codec).profile, (*codec).level, *(&(*codec).pix_fmt as *const libav::v56::AVPixelFormat as *const u32))
+            },
+            Abi::V57 => {
+                let codec = (*(stream as *const libav::v57::AVStream)).codec;
+                ((*codec).profile, (*codec).level, *(&(*codec).pix_fmt as *const libav::v57::AVPixelFormat as *const u32))
+            },
+        }
+    }
+
+    /// (bit depth, chroma format) of a pixel format as codec_params reads it, None for the ones
+    /// an HEVC stream doesn't decode to
+    fn pixel_format(self, pix_fmt: u32) -> Option<(u32, ChromaFormat)> {
+        // the formats are numbered differently from one version to the other
+        macro_rules! pixel_formats {
+            () => {
+                [(AV_PIX_FMT_YUV420P as u32, 8, ChromaFormat::Yuv420), (AV_PIX_FMT_YUVJ420P as u32, 8, ChromaFormat::Yuv420),
+                 (AV_PIX_FMT_NV12 as u32, 8, ChromaFormat::Yuv420), (AV_PIX_FMT_NV21 as u32, 8, ChromaFormat::Yuv420),
+                 (AV_PIX_FMT_YUV422P as u32, 8, ChromaFormat::Yuv422), (AV_PIX_FMT_YUVJ422P as u32, 8, ChromaFormat::Yuv422),
+                 (AV_PIX_FMT_YUV444P as u32, 8, ChromaFormat::Yuv444), (AV_PIX_FMT_YUVJ444P as u32, 8, ChromaFormat::Yuv444),
+                 (AV_PIX_FMT_YUV440P as u32, 8, ChromaFormat::Yuv440), (AV_PIX_FMT_YUVJ440P as u32, 8, ChromaFormat::Yuv440),
+                 (AV_PIX_FMT_GRAY8 as u32, 8, ChromaFormat::Gray),
+                 (AV_PIX_FMT_YUV420P10LE as u32, 10, ChromaFormat::Yuv420), (AV_PIX_FMT_YUV420P10BE as u32, 10, ChromaFormat::Yuv420),
+                 (AV_PIX_FMT_YUV422P10LE as u32, 10, ChromaFormat::Yuv422), (AV_PIX_FMT_YUV422P10BE as u32, 10, ChromaFormat::Yuv422),
+                 (AV_PIX_FMT_YUV444P10LE as u32, 10, ChromaFormat::Yuv444), (AV_PIX_FMT_YUV444P10BE as u32, 10, ChromaFormat::Yuv444),
+                 (AV_PIX_FMT_YUV440P10LE as u32, 10, ChromaFormat::Yuv440), (AV_PIX_FMT_YUV440P10BE as u32, 10, ChromaFormat::Yuv440),
+                 (AV_PIX_FMT_YUV420P12LE as u32, 12, ChromaFormat::Yuv420), (AV_PIX_FMT_YUV420P12BE as u32, 12, ChromaFormat::Yuv420),
+                 (AV_PIX_FMT_YUV422P12LE as u32, 12, ChromaFormat::Yuv422), (AV_PIX_FMT_YUV422P12BE as u32, 12, ChromaFormat::Yuv422),
+                 (AV_PIX_FMT_YUV444P12LE as u32, 12, ChromaFormat::Yuv444), (AV_PIX_FMT_YUV444P12BE as u32, 12, ChromaFormat::Yuv444),
+                 (AV_PIX_FMT_YUV440P12LE as u32, 12, ChromaFormat::Yuv440), (AV_PIX_FMT_YUV440P12BE as u32, 12, ChromaFormat::Yuv440)]
+            };
+        }
+        let formats = match self {
+            Abi::V56 => {
+                use libavformat::v56::AVPixelFormat::*;
+                pixel_formats!()
+            },
+            Abi::V57 => {
+                use libavformat::v57::AVPixelFormat::*;
+                pixel_formats!()
+            },
+        };
+        formats.iter()
+            .find(|&&(format, _, _)| format == pix_fmt)
+            .map(|&(_, bit_depth, chroma)| (bit_depth, chroma))
+    }
+
+    /// Shape of the pixels of `stream` as (num, den): the one of the stream if the container sets
+    /// it, else the one of the codec context. num is 0 if neither does.
+    unsafe fn sample_aspect_ratio(self, stream: *const libav::AVStream) -> (i32, i32) {
+        let (stream_sar, codec_sar) = match self {
+            Abi::V56 => {
+                let stream = stream as *const libav::v56::AVStream;
+                let (sar, codec) = ((*stream).sample_aspect_ratio, (*(*stream).codec).sample_aspect_ratio);
+                ((sar.num, sar.den), (codec.num, codec.den))
+            },
+            Abi::V57 => {
+                let stream = stream as *const libav::v57::AVStream;
+                let (sar, codec) = ((*stream).sample_aspect_ratio, (*(*stream).codec).sample_aspect_ratio);
+                ((sar.num, sar.den), (codec.num, codec.den))
+            },
+        };
+        if stream_sar.0 > 0 && stream_sar.1 > 0 { stream_sar } else { codec_sar }
+    }
+
+    fn is_hevc(self, codec_id: u32) -> bool {
+        match self {
+            Abi::V56 => codec_id == libav::v56::AVCodecID::AV_CODEC_ID_HEVC as u32,
+            Abi::V57 => codec_id == libav::v57::AVCodecID::AV_CODEC_ID_HEVC as u32,
+        }
+    }
+
+    /// Every subtitle codec which isn't a text one is considered a bitmap one
+    fn subtitle_format(self, codec_id: u32) -> SubtitleFormat {
+        let (mov_text, plain, ass) = match self {
+            Abi::V56 => {
+                use libavformat::v56::AVCodecID::*;
+                (AV_CODEC_ID_MOV_TEXT as u32,
+                 [AV_CODEC_ID_SUBRIP as u32, AV_CODEC_ID_SRT as u32, AV_CODEC_ID_TEXT as u32, AV_CODEC_ID_WEBVTT as u32],
+                 [AV_CODEC_ID_ASS as u32, AV_CODEC_ID_SSA as u32])
+            },
+            Abi::V57 => {
+                use libavformat::v57::AVCodecID::*;
+                (AV_CODEC_ID_MOV_TEXT as u32,
+                 [AV_CODEC_ID_SUBRIP as u32, AV_CODEC_ID_SRT as u32, AV_CODEC_ID_TEXT as u32, AV_CODEC_ID_WEBVTT as u32],
+                 [AV_CODEC_ID_ASS as u32, AV_CODEC_ID_SSA as u32])
+            },
+        };
+        if codec_id == mov_text {
+            SubtitleFormat::MovText
+        } else if plain.contains(&codec_id) {
+            SubtitleFormat::Plain
+        } else if ass.contains(&codec_id) {
+            SubtitleFormat::Ass
+        } else {
+            SubtitleFormat::Bitmap
+        }
+    }
+
+    /// in time_base units of the packet's stream, 0 if unknown. An int in 56, an int64 since.
+    fn packet_duration(self, packet: &libav::v56::AVPacket) -> i64 {
+        match self {
+            Abi::V56 => packet.duration as i64,
+            Abi::V57 => unsafe { (*(packet as *const _ as *const libav::v57::AVPacket)).duration },
+        }
+    }
+
+    /// (id, indexes of its streams) of every program of `ctx`, only transport streams have some.
+    /// Where the programs are moves between versions, the first fields of AVProgram don't.
+    unsafe fn programs(self, ctx: *const libav::AVFormatContext) -> Vec<(i32, Vec<usize>)> {
+        let (count, programs) = match self {
+            Abi::V56 => {
+                let ctx = ctx as *const libav::v56::AVFormatContext;
+                ((*ctx).nb_programs, (*ctx).programs as *const *const libav::AVProgram)
+            },
+            Abi::V57 => {
+                let ctx = ctx as *const libav::v57::AVFormatContext;
+                ((*ctx).nb_programs, (*ctx).programs as *const *const libav::AVProgram)
+            },
+        };
+        if programs.is_null() {
+            return Vec::new();
+        }
+        (0..count as isize).map(|i| {
+            let program = *programs.offset(i);
+            let streams = if (*program).stream_index.is_null() {
+                Vec::new()
+            } else {
+                ::std::slice::from_raw_parts((*program).stream_index, (*program).nb_stream_indexes as usize)
+                    .iter().map(|&index| index as usize).collect()
+            };
+            ((*program).id, streams)
+        }).collect()
+    }
+
+    /// extradata of the codec context of `stream`, empty if there is none
+    unsafe fn extradata<'a>(self, stream: *const libav::AVStream) -> &'a [u8] {
+        let (data, size) = match self {
+            Abi::V56 => {
+                let codec = (*(stream as *const libav::v56::AVStream)).codec;
+                ((*codec).extradata, (*codec).extradata_size)
+            },
+            Abi::V57 => {
+                let codec = (*(stream as *const libav::v57::AVStream)).codec;
+                ((*codec).extradata, (*codec).extradata_size)
+            },
+        };
+        if data.is_null() || size <= 0 {
+            &[]
+        } else {
+            ::std::slice::from_raw_parts(data, size as usize)
+        }
+    }
 }
 
 pub fn avformat_version() -> (u16, u16) {
@@ -49,126 +284,431 @@ pub fn avformat_version() -> (u16, u16) {
     }
 }
 
+/// What the VPU is told to decode, see Message::ForceVideoFormat. There is only HEVC for now.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CodecKind {
+    Hevc,
+}
+
+impl CodecKind {
+    /// AMPLAYER_CODEC_* in aml_player.h, 0 meaning none
+    pub fn from_int(codec: u32) -> Option<CodecKind> {
+        match codec {
+            1 => Some(CodecKind::Hevc),
+            _ => None,
+        }
+    }
+
+    /// The reverse of from_int
+    pub fn to_int(self) -> u32 {
+        match self {
+            CodecKind::Hevc => 1,
+        }
+    }
+}
+
+/// How the chroma of a video is subsampled, AMPLAYER_CHROMA_* in aml_player.h
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChromaFormat {
+    Unknown = 0,
+    Yuv420 = 1,
+    Yuv422 = 2,
+    Yuv444 = 3,
+    Yuv440 = 4,
+    /// luma only
+    Gray = 5,
+}
+
+/// The video stream of the file loaded as libav reads it, given as-is to the API user (see
+/// aml_video_player_get_codec_info). 0 means unknown everywhere.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AmlCodecInfo {
+    /// AMPLAYER_CODEC_*, AUTO being any other codec forced with ForceVideoFormat
+    pub codec_id: u32,
+    /// general_profile_idc for HEVC: 1 Main, 2 Main 10, 3 Main Still Picture, 4 Range Extensions
+    pub profile: u32,
+    /// general_level_idc for HEVC, 30 times the level (153 for 5.1)
+    pub level: u32,
+    /// of the luma samples
+    pub bit_depth: u32,
+    /// a ChromaFormat
+    pub chroma_format: u32,
+}
+
+/// How RTSP streams carry their packets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RtspTransport {
+    /// lowest latency, but packets can be lost, and some NATs let nothing through
+    Udp,
+    /// interleaved in the RTSP connection itself
+    Tcp,
+    UdpMulticast,
+}
+
+impl RtspTransport {
+    /// AMPLAYER_RTSP_TRANSPORT_* in aml_player.h
+    pub fn from_int(transport: u32) -> Option<RtspTransport> {
+        match transport {
+            0 => Some(RtspTransport::Udp),
+            1 => Some(RtspTransport::Tcp),
+            2 => Some(RtspTransport::UdpMulticast),
+            _ => None,
+        }
+    }
+
+    /// value of the rtsp_transport option of libav
+    fn option(self) -> &'static str {
+        match self {
+            RtspTransport::Udp => "udp",
+            RtspTransport::Tcp => "tcp",
+            RtspTransport::UdpMulticast => "udp_multicast",
+        }
+    }
+}
+
+/// What urls are opened with, kept by the libav thread from one Load to the next
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NetworkOptions {
+    /// see PlayerConfig::network_timeout
+    pub timeout: Option<Duration>,
+    /// None leaves libav's choice (UDP, then TCP if nothing comes)
+    pub rtsp_transport: Option<RtspTransport>,
+    /// of the UDP sockets, in bytes. None leaves the system's default.
+    pub buffer_size: Option<u32>,
+}
+
+/// `key` must end with a null byte
+unsafe fn dict_set(options: &mut *mut libav::AVDictionary, key: &'static [u8], value: &str) {
+    let value = CString::new(value).unwrap();
+    libav::av_dict_set(options as *mut _, key.as_ptr() as *const _, value.as_ptr(), 0);
+}
+
 /// the context will be able to open both file on the filesysttem and urls (because
 /// avformat_open_input allows us to do this)
 ///
 /// It fails if the input is incorrect of if the video does not have an HEVC stream
 impl Context {
-    pub fn new<S: AsRef<str>>(url: S) -> Result<Context> {
-        let mut ctx : *mut libav::AVFormatContext = ptr::null_mut();
+    /// Every blocking call on this context is aborted once abort_io is true, or after
+    /// network.timeout for network streams
+    ///
+    /// With forced_codec, the first video stream is used whatever libav thinks its codec is.
+    pub fn new<S: AsRef<str>>(url: S, abort_io: Arc<AtomicBool>, network: NetworkOptions, forced_codec: Option<CodecKind>) -> Result<Context> {
+        Self::new_timed(url, abort_io, network, forced_codec).map(|(context, _)| context)
+    }
+
+    /// Same as new, but also tells how long opening the url and finding its streams took
+    pub fn new_timed<S: AsRef<str>>(url: S, abort_io: Arc<AtomicBool>, network: NetworkOptions, forced_codec: Option<CodecKind>) -> Result<(Context, OpenTimings)> {
+        // the context must be allocated by us for the interrupt callback to be set before
+        // anything blocks. avformat_open_input frees it if it fails.
+        let abi = Abi::detect()?;
+        let mut ctx : *mut libav::AVFormatContext = unsafe { libav::avformat_alloc_context() };
+        if ctx.is_null() {
+            return Err(Error::Libav { call: "avformat_alloc_context", code: 0 });
+        }
+        unsafe {
+            abi.set_interrupt_callback(ctx, &*abort_io as *const AtomicBool as *mut c_void);
+        }
         // the &str -> CString automatically adds a null trailing character, so if that doesn't
         // happen the whole language is in trouble ...
         let url = CString::new(url.as_ref())
             .expect("FATAL: expected null-trailing byte, but none found!\
                     File an issue to the Rust core team on github!");
+        // the options libav didn't use are left in the dictionary, rw_timeout being ignored by
+        // the protocols which never block (local files), and the rtsp_* ones by anything else
+        // than RTSP
+        let mut options : *mut libav::AVDictionary = ptr::null_mut();
+        unsafe {
+            if let Some(timeout) = network.timeout {
+                let micros = timeout.as_secs() * 1_000_000 + (timeout.subsec_nanos() / 1000) as u64;
+                dict_set(&mut options, b"rw_timeout\0", &micros.to_string());
+            }
+            if let Some(transport) = network.rtsp_transport {
+                dict_set(&mut options, b"rtsp_transport\0", transport.option());
+                if transport == RtspTransport::Tcp {
+                    // some servers only give TCP when it is asked for first
+                    dict_set(&mut options, b"rtsp_flags\0", "prefer_tcp");
+                }
+            }
+            if let Some(buffer_size) = network.buffer_size {
+                dict_set(&mut options, b"buffer_size\0", &buffer_size.to_string());
+            }
+        }
+        let open_started = Instant::now();
         let ret = unsafe {
-            libav::avformat_open_input(&mut ctx as *mut *mut libav::AVFormatContext, url.as_ptr(), ptr::null_mut(), ptr::null_mut())
+            let ret = libav::avformat_open_input(&mut ctx as *mut *mut libav::AVFormatContext, url.as_ptr(), ptr::null_mut(), &mut options as *mut _);
+            libav::av_dict_free(&mut options as *mut _);
+            ret
         };
         if ret < 0 {
             // TODO create another error "FileNotFound" and check
             // if libav's return value is file not found
             
             // bail returns an error: abort if open_input failed
-            bail!(ErrorKind::LibavInternal(ret, "avformat_open_input"));
+            return Err(Error::Libav { call: "avformat_open_input", code: ret });
         }
-        if let Some(hevc_stream) = Self::retrieve_hevc_stream(ctx) {
-            Ok(Context {
+        let stream_info_started = Instant::now();
+        if let Some(hevc_stream) = Self::retrieve_hevc_stream(ctx, abi, forced_codec) {
+            let timings = OpenTimings {
+                open: stream_info_started - open_started,
+                stream_info: stream_info_started.elapsed(),
+            };
+            Ok((Context {
                 ctx: ctx,
                 hevc_stream: hevc_stream,
-            })
+                subtitle_streams: Self::find_subtitle_streams(ctx, abi),
+                streams_seen: unsafe { (*ctx).nb_streams as usize },
+                abi: abi,
+                _abort_io: abort_io,
+            }, timings))
+        } else {
+            Err(Error::NoVideoStream)
+        }
+    }
+
+    /// Live streams have no duration, and can't be seeked in (HLS doesn't even have a pb). The
+    /// fields read are at the same place in every Abi.
+    pub fn is_live(&self) -> bool {
+        unsafe {
+            let pb = (*self.ctx).pb;
+            (*self.ctx).duration == AV_NOPTS_VALUE && (pb.is_null() || (*pb).seekable == 0)
+        }
+    }
+
+    /// In seconds, None if libav doesn't know it (live streams, some raw streams)
+    pub fn duration(&self) -> Option<f64> {
+        let duration = unsafe { (*self.ctx).duration };
+        if duration == AV_NOPTS_VALUE || duration <= 0 {
+            None
         } else {
-            bail!(ErrorKind::NoValidVideoStream)
+            Some(duration as f64 / libav::AV_TIME_BASE as f64)
         }
     }
 
     /// Seeks the context at a position starting from the beginning of the file
     pub fn seek(&mut self, pos: f64) -> Result<()> {
+        self.seek_with_flags(pos, libav::AVFMT_SEEK_TO_PTS as c_int)
+    }
+
+    /// Same as seek, but to the keyframe before pos: there may be none after it near the end
+    pub fn seek_backward(&mut self, pos: f64) -> Result<()> {
+        self.seek_with_flags(pos, libav::AVSEEK_FLAG_BACKWARD as c_int)
+    }
+
+    fn seek_with_flags(&mut self, pos: f64, flags: c_int) -> Result<()> {
         let r = unsafe {
-            libav::av_seek_frame(self.ctx, -1, (pos * (libav::AV_TIME_BASE as f64)) as i64, libav::AVFMT_SEEK_TO_PTS as c_int)
+            libav::av_seek_frame(self.ctx, -1, (pos * (libav::AV_TIME_BASE as f64)) as i64, flags)
         };
         if r < 0 {
-            bail!(ErrorKind::LibavInternal(r, "av_seek_frame"))
+            return Err(Error::Libav { call: "av_seek_frame", code: r });
         }
         Ok(())
     }
 
+    /// Seeks to the last keyframe at or before pos (in seconds, like seek), and returns it
+    pub fn seek_keyframe_before(&mut self, pos: f64) -> Result<Packet> {
+        let r = unsafe {
+            libav::av_seek_frame(self.ctx, -1, (pos * (libav::AV_TIME_BASE as f64)) as i64, libav::AVSEEK_FLAG_BACKWARD as c_int)
+        };
+        if r < 0 {
+            return Err(Error::Libav { call: "av_seek_frame", code: r });
+        }
+        loop {
+            let packet = self.next_frame()?;
+            if packet.inner.stream_index as usize == self.hevc_stream && packet.is_keyframe() {
+                return Ok(packet);
+            }
+        }
+    }
+
+    /// Returns the duration of one "tick" of the HEVC stream's timestamps, in seconds
+    pub fn time_base(&self) -> f64 {
+        self.stream_time_base(self.hevc_stream)
+    }
+
+    /// Same as time_base, for any stream
+    pub fn stream_time_base(&self, index: usize) -> f64 {
+        unsafe {
+            let stream : *mut _ = *(*self.ctx).streams.offset(index as isize);
+            let time_base = (*stream).time_base;
+            if time_base.den == 0 {
+                0.0
+            } else {
+                time_base.num as f64 / time_base.den as f64
+            }
+        }
+    }
+
+    /// Time of a packet of any stream in seconds, None if it has no pts
+    pub fn packet_time(&self, packet: &Packet) -> Option<f64> {
+        if packet.inner.pts == AV_NOPTS_VALUE {
+            return None;
+        }
+        Some(packet.inner.pts as f64 * self.stream_time_base(packet.inner.stream_index as usize))
+    }
+
+    /// True if the stream at index is an HEVC video stream. libav adds the streams of a transport
+    /// stream as it finds them, so there may be more than when the context was created.
+    pub fn is_hevc_stream(&self, index: usize) -> bool {
+        unsafe {
+            if index >= (*self.ctx).nb_streams as usize {
+                return false;
+            }
+            let stream : *const libav::AVStream = *(*self.ctx).streams.offset(index as isize);
+            (*(*stream).codec).codec_type == libav::AVMediaType::AVMEDIA_TYPE_VIDEO
+                && self.abi.is_hevc(self.abi.codec_id(stream))
+        }
+    }
+
+    /// An HEVC stream which libav added since the last call, in a program the video stream is part
+    /// of: the PMT of a transport stream changed and moved the video to another PID. The old
+    /// stream stays in the program, libav never removes any.
+    pub fn replacing_hevc_stream(&mut self) -> Option<usize> {
+        let count = unsafe { (*self.ctx).nb_streams as usize };
+        if count <= self.streams_seen {
+            return None;
+        }
+        let added = self.streams_seen..count;
+        self.streams_seen = count;
+        let programs = unsafe { self.abi.programs(self.ctx) };
+        let shares_a_program = |index: usize| programs.iter()
+            .any(|&(_, ref streams)| streams.contains(&index) && streams.contains(&self.hevc_stream));
+        for index in added {
+            if self.is_hevc_stream(index) && shares_a_program(index) {
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    /// Returns the timestamp of the first frame of the HEVC stream in time_base units, 0 if it
+    /// is unknown
+    pub fn start_time(&self) -> i64 {
+        unsafe {
+            let stream : *mut _ = *(*self.ctx).streams.offset(self.hevc_stream as isize);
+            if (*stream).start_time == AV_NOPTS_VALUE {
+                0
+            } else {
+                (*stream).start_time
+            }
+        }
+    }
+
+    /// What the stream is encoded with, as found by avformat_find_stream_info
+    pub fn codec_info(&self) -> AmlCodecInfo {
+        let stream : *const libav::AVStream = unsafe { *(*self.ctx).streams.offset(self.hevc_stream as isize) };
+        let (codec_id, (profile, level, pix_fmt)) = unsafe {
+            (self.abi.codec_id(stream), self.abi.codec_params(stream))
+        };
+        // FF_PROFILE_UNKNOWN and FF_LEVEL_UNKNOWN are negative
+        let (bit_depth, chroma) = self.abi.pixel_format(pix_fmt).unwrap_or((0, ChromaFormat::Unknown));
+        AmlCodecInfo {
+            codec_id: if self.abi.is_hevc(codec_id) { CodecKind::Hevc.to_int() } else { 0 },
+            profile: if profile < 0 { 0 } else { profile as u32 },
+            level: if level < 0 { 0 } else { level as u32 },
+            bit_depth: bit_depth,
+            chroma_format: chroma as u32,
+        }
+    }
+
+    /// Shape of the pixels of the HEVC stream as (num, den), (1, 1) if it is unset: anamorphic
+    /// video (DVDs, some broadcasts) is stored narrower or wider than it is meant to be shown
+    pub fn get_sample_aspect_ratio(&self) -> (u32, u32) {
+        let (num, den) = unsafe {
+            let stream : *const libav::AVStream = *(*self.ctx).streams.offset(self.hevc_stream as isize);
+            self.abi.sample_aspect_ratio(stream)
+        };
+        if num <= 0 || den <= 0 {
+            (1, 1)
+        } else {
+            (num as u32, den as u32)
+        }
+    }
+
     /// Will try to get extra_data
     ///
     /// It looks like sometimes there is no extra_data associated, but I have yet to find a file in
     /// HEVC with no extra_data in it
-    pub fn get_extra_data(&self) -> Result<Arc<Vec<u8>>> {
+    pub fn get_extra_data(&self) -> Result<Vec<u8>> {
         // this code is shamelessly inspired from OtherCrashOverride/c2play
         // it works for now, so only change it if it doesn't anymore
-        unsafe {
+        let data : &[u8] = unsafe {
             let stream : *mut _ = *(*self.ctx).streams.offset(self.hevc_stream as isize);
-            let codec : *mut _ = (*stream).codec;
-            let mut extra_data = Vec::with_capacity((*codec).extradata_size as usize);
-            let data : &[u8] = ::std::slice::from_raw_parts((*codec).extradata, (*codec).extradata_size as usize);
-            let mut offset = 21;
-            let _length_size = (data[offset] & 3) + 1;
-            offset += 1;
-            let num_arrays = data[offset];
-            offset += 1;
-            for _ in 0..num_arrays {
-                let _type = data[offset] & 0x3f;
-                offset += 1;
-                let mut cnt : u32 = (data[offset] as u32) << 8;
-                offset += 1;
-                cnt |= data[offset] as u32;
-                offset += 1;
-                for _ in 0..cnt {
-                    extra_data.push(0);
-                    extra_data.push(0);
-                    extra_data.push(0);
-                    extra_data.push(1);
-                    let mut nalu_len = (data[offset] as u32) << 8;
-                    offset += 1;
-                    nalu_len |= data[offset] as u32;
-                    offset += 1;
-                    for _ in 0..nalu_len {
-                        extra_data.push(data[offset]);
-                        offset += 1;
-                    }
-                }
-            }
-            // we will need to send extra_data across a thread, but we don't have the guarentee
-            // that this will live long enough to the extra_data to be still alive, so we just copy
-            // it to a Vec and sahre it across threads
-            Ok(Arc::new(extra_data))
-        }
+            self.abi.extradata(stream)
+        };
+        // we will need to send extra_data across a thread, but we don't have the guarentee
+        // that this will live long enough to the extra_data to be still alive, so we just copy
+        // it to a Vec. It is only sent once, Seek calls this again instead of keeping it.
+        hvcc_to_annexb(data)
     }
 
     /// returns Some(i) where i is the index of the HEVC stream,
     /// None if the HEVC has been found
     ///
     /// THis typically means the end of the playback
-    fn retrieve_hevc_stream(ctx: *mut libav::AVFormatContext) -> Option<usize> {
+    fn retrieve_hevc_stream(ctx: *mut libav::AVFormatContext, abi: Abi, forced_codec: Option<CodecKind>) -> Option<usize> {
         unsafe {
             let ret = libav::avformat_find_stream_info(ctx, ptr::null_mut());
             if ret < 0 {
-                println!("avformat_find_stream_info returned {}", ret);
+                warn!("avformat_find_stream_info returned {}", ret);
                 return None
             } else {
                 'hevc_search: for i in 0..((*ctx).nb_streams as usize) {
                     let stream : *const libav::AVStream = *(*ctx).streams.offset(i as isize);
                     let codec : *const _ = (*stream).codec;
-                    let codec_id = (*codec).codec_id;
+                    let codec_id = abi.codec_id(stream);
                     let codec_type = (*codec).codec_type;
-                    match (codec_type, codec_id) {
-                        (libav::AVMediaType::AVMEDIA_TYPE_VIDEO, libav::AVCodecID::AV_CODEC_ID_HEVC) => {
-                            println!("libav_thread: Stream {} is HEVC ! ({:?}, {:?})", i, libav::AVMediaType::AVMEDIA_TYPE_VIDEO, libav::AVCodecID::AV_CODEC_ID_HEVC);
-                            return Some(i);
-                        },
-                        _ => {
-                            println!("libav_thread: Ignoring media_type {:?} and codec {:?}: not HEVC", codec_type, codec_id);
-                        }
-                    };
+                    if codec_type == libav::AVMediaType::AVMEDIA_TYPE_VIDEO && abi.is_hevc(codec_id) {
+                        info!("Stream {} is HEVC ! ({:?}, {})", i, codec_type, codec_id);
+                        return Some(i);
+                    } else if codec_type == libav::AVMediaType::AVMEDIA_TYPE_VIDEO && forced_codec.is_some() {
+                        warn!("Stream {} (codec {}) is decoded as {:?}, as forced", i, codec_id, forced_codec.unwrap());
+                        return Some(i);
+                    } else {
+                        debug!("Ignoring media_type {:?} and codec {}: not HEVC", codec_type, codec_id);
+                    }
                 }
             }
         };
         None
     }
     
+    /// Called once avformat_find_stream_info was
+    fn find_subtitle_streams(ctx: *mut libav::AVFormatContext, abi: Abi) -> Vec<(usize, SubtitleFormat)> {
+        unsafe {
+            (0..((*ctx).nb_streams as usize))
+                .filter_map(|i| {
+                    let stream : *const libav::AVStream = *(*ctx).streams.offset(i as isize);
+                    if (*(*stream).codec).codec_type != libav::AVMediaType::AVMEDIA_TYPE_SUBTITLE {
+                        return None;
+                    }
+                    let format = abi.subtitle_format(abi.codec_id(stream));
+                    debug!("Stream {} is a subtitle stream ({:?})", i, format);
+                    Some((i, format))
+                })
+                .collect()
+        }
+    }
+
+    /// The cue of a packet of a subtitle stream, None if it has no text
+    pub fn subtitle_cue(&self, packet: &Packet, format: SubtitleFormat) -> Option<PlayerEvent> {
+        let data : &[u8] = if packet.inner.data.is_null() || packet.inner.size <= 0 {
+            &[]
+        } else {
+            unsafe { ::std::slice::from_raw_parts(packet.inner.data, packet.inner.size as usize) }
+        };
+        let text = subtitles::decode_text(format, data)?;
+        let time_base = self.stream_time_base(packet.inner.stream_index as usize);
+        let to_ms = |ticks: i64| (ticks as f64 * time_base * 1000.0).round() as i64;
+        let start_ms = if packet.inner.pts == AV_NOPTS_VALUE { 0 } else { to_ms(packet.inner.pts) };
+        let duration = self.abi.packet_duration(&packet.inner);
+        Some(PlayerEvent::Subtitle {
+            start_ms: start_ms,
+            end_ms: if duration > 0 { start_ms + to_ms(duration) } else { -1 },
+            text: text,
+        })
+    }
+
     /// Tries to get the next frame from the context
     ///
     /// The fundamental call behind this is "av_read_frame" which is a blocking call. On a
@@ -176,25 +716,159 @@ impl Context {
     /// so beware.
     pub fn next_frame(&mut self) -> Result<Packet> {
         unsafe {
-            let mut packet : libav::AVPacket = mem::uninitialized();
-            let ret = libav::av_read_frame(self.ctx as *mut _, &mut packet as *mut _);
+            let mut packet = MaybeUninit::<libav::v56::AVPacket>::uninit();
+            let ret = libav::av_read_frame(self.ctx as *mut _, packet.as_mut_ptr() as *mut libav::AVPacket);
             match ret {
                 // if we get the EOF constant (defined as a cosnt up there),
                 // return a custom EOF error
-                EOF => bail!(ErrorKind::EOF),
+                EOF => Err(Error::Eof),
                 _ if ret >= 0 => {
                     Ok(Packet {
-                        inner: packet
+                        inner: packet.assume_init(),
+                        time_base: self.time_base(),
                     })
                 },
-                ret => {
-                    bail!("libav: error when reading frame, returned {0:x} ({0})", ret);
-                }
+                ret => Err(Error::Libav { call: "av_read_frame", code: ret }),
             }
         }
     }
 }
 
+/// Rewrites an hvcC header (ISO/IEC 14496-15) into the parameter sets, in Annex B, that the VPU
+/// wants before the first packet
+fn hvcc_to_annexb(data: &[u8]) -> Result<Vec<u8>> {
+    // a forced stream may not even have an hvcC header
+    if data.len() < 23 {
+        return Err(Error::Malformed(format!("{} bytes of extra data is too short for hvcC", data.len())));
+    }
+    // every count and length comes from the file, and may run past the extra data
+    let truncated = || Error::Malformed(format!("hvcC truncated at {} bytes", data.len()));
+    let read_u8 = |offset: usize| data.get(offset).cloned().ok_or_else(&truncated);
+    let read_u16 = |offset: usize| -> Result<usize> {
+        Ok(((read_u8(offset)? as usize) << 8) | read_u8(offset + 1)? as usize)
+    };
+    let mut extra_data = Vec::with_capacity(data.len());
+    // byte 21 is the length size (its 2 lower bits, minus one)
+    let mut offset = 22;
+    let num_arrays = read_u8(offset)?;
+    offset += 1;
+    for _ in 0..num_arrays {
+        // the NAL unit type of the array, in the 6 lower bits
+        offset += 1;
+        let cnt = read_u16(offset)?;
+        offset += 2;
+        for _ in 0..cnt {
+            let nalu_len = read_u16(offset)?;
+            offset += 2;
+            let nalu = data.get(offset..offset + nalu_len).ok_or_else(&truncated)?;
+            extra_data.extend_from_slice(&[0, 0, 0, 1]);
+            extra_data.extend_from_slice(nalu);
+            offset += nalu_len;
+        }
+    }
+    Ok(extra_data)
+}
+
+/// See Context::new_timed
+pub struct OpenTimings {
+    /// avformat_open_input
+    pub open: Duration,
+    /// avformat_find_stream_info
+    pub stream_info: Duration,
+}
+
+/// Same as the start of main_thread, for what opens a Context without a player. Calling it again
+/// does nothing.
+pub fn register_all() {
+    unsafe {
+        libav::av_register_all();
+        libav::avformat_network_init();
+    }
+}
+
+// the context is only ever used by one thread at a time: it is moved from the preloading thread to
+// the libav thread once opened
+unsafe impl Send for Context {}
+
+/// Opens the url and extracts its extra_data, everything that can be done before the video
+/// actually starts
+fn open(url: &str, abort_io: Arc<AtomicBool>, network: NetworkOptions, forced_codec: Option<CodecKind>) -> Result<(Context, Option<Vec<u8>>)> {
+    let context = Context::new(url, abort_io, network, forced_codec)?;
+    let extra_data = match context.get_extra_data() {
+        Ok(extra_data) => Some(extra_data),
+        Err(e) => {
+            warn!("get_extra_data failed: {}", e.display());
+            None
+        }
+    };
+    Ok((context, extra_data))
+}
+
+enum PreloadState {
+    Pending(Receiver<Result<(Context, Option<Vec<u8>>)>>),
+    Done(Result<(Context, Option<Vec<u8>>)>),
+}
+
+/// The next video, opened in its own thread so that the current one keeps being demuxed in the
+/// meantime (opening an url over HTTP can take a while). It is used by the next Load of the
+/// same url.
+struct Preloaded {
+    url: String,
+    state: PreloadState,
+    /// opened by Prefetch: dropped by a Load of another url, and its failures are not reported
+    speculative: bool,
+}
+
+impl Preloaded {
+    fn spawn(url: String, abort_io: Arc<AtomicBool>, network: NetworkOptions, speculative: bool) -> Preloaded {
+        let (tx, rx) = mpsc::channel();
+        let thread_url = url.clone();
+        // if the preload is replaced before it is done, the send fails and the context is simply
+        // dropped
+        thread::Builder::new().name(String::from("preload_thread")).spawn(move || {
+            let _r = tx.send(open(thread_url.as_str(), abort_io, network, None));
+        }).expect("failed to spawn thread");
+        Preloaded {
+            url: url,
+            state: PreloadState::Pending(rx),
+            speculative: speculative,
+        }
+    }
+
+    /// Checks if the preloading thread is done, without blocking. Returns the error if it just
+    /// failed.
+    fn poll(&mut self) -> Option<FfiErrorCode> {
+        let result = match self.state {
+            PreloadState::Pending(ref rx) => match rx.try_recv() {
+                Ok(result) => result,
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => Err("the preloading thread died".into()),
+            },
+            PreloadState::Done(_) => return None,
+        };
+        let error_code = match result {
+            Err(ref e) => {
+                warn!("failed to preload `{}`: {}", self.url, e.display());
+                Some(e.code())
+            },
+            Ok(_) => None,
+        };
+        self.state = PreloadState::Done(result);
+        error_code
+    }
+
+    /// Blocks until the preloading thread is done
+    fn wait(self) -> Result<(Context, Option<Vec<u8>>)> {
+        match self.state {
+            PreloadState::Pending(rx) => match rx.recv() {
+                Ok(result) => result,
+                Err(_) => Err("the preloading thread died".into()),
+            },
+            PreloadState::Done(result) => result,
+        }
+    }
+}
+
 impl Drop for Context {
     fn drop(&mut self) {
         unsafe {
@@ -214,20 +888,193 @@ impl Drop for Context {
 #[derive(Debug)]
 pub enum Message {
     Load(String),
-    Seek(f64),
+    /// Opens the url in the background, the next Load of this url will be instant. Replaces the
+    /// previous preload, if any.
+    Preload(String),
+    /// Same as Preload, but only a guess: it is dropped by a Load of another url, and it doesn't
+    /// send PreloadFailed
+    Prefetch(String),
+    /// (position in seconds, generation of the seek)
+    Seek(f64, u64),
+    /// in seconds, 0 disables the pacing
+    SetVideoDelay(f64),
+    /// maximum number of video packets sent to amcodec per second, 0 means unlimited
+    SetMaxDecodeRate(u32),
+    /// Plays the video backward at this speed (1.0 being real time), by showing keyframes only.
+    /// 0 goes back to the normal playback, from the keyframe currently shown.
+    SetBackwardSpeed(f64),
+    /// of the video currently loaded
+    GetSubtitleTrackCount(SuSender<u32>),
+    /// of the video currently loaded, see Context::codec_info
+    GetCodecInfo(SuSender<AmlCodecInfo>),
+    /// of the video currently loaded in seconds, see Context::duration
+    GetDuration(SuSender<f64>),
+    /// index in Context::subtitle_streams, None stops sending cues. Reset by every Load.
+    SetSubtitleTrack(Option<usize>),
+    /// Used by the next Load only, None goes back to what libav detects
+    ForceVideoFormat(Option<CodecKind>),
+    /// Used by every Load and Preload from now on
+    SetRtspTransport(RtspTransport),
+    /// Same as SetRtspTransport, None goes back to the system's default
+    SetNetworkBufferSize(Option<u32>),
+    /// Writes the video stream to this file as it is demuxed, see recording.rs. Replaces the
+    /// recording going on, if any.
+    StartRecording(String),
+    StopRecording,
+    /// Sent by the main thread when the API user pauses: a live stream starts timeshifting, if
+    /// PlayerConfig::timeshift_path is set. Nothing else cares about it.
+    Pause,
+    /// Replaces PlayerConfig::timeshift_path and timeshift_max_duration, for the next pause: a
+    /// timeshift going on isn't stopped. A None path disables the timeshift.
+    SetTimeshift(Option<String>, Option<Duration>),
+    /// Moves by this many seconds in the timeshift buffer, backward if negative
+    SeekTimeshift(f64),
+    /// Sent by the main thread before the player is destroyed: nothing is demuxed anymore, so
+    /// that amcodec can empty its queue for good
+    Shutdown,
+}
+
+/// A seek at or past the end of the video goes this far before it instead, so that its last
+/// seconds are shown rather than the video ending right away
+const SEEK_END_MARGIN_SECS : f64 = 1.0;
+
+/// Pacer::wait_for checks abort_io and the commands at least this often
+const PACER_SLEEP_SLICE_MS : u64 = 20;
+
+/// A packet this far ahead of video_delay is a jump of the timestamps, not a burst to slow down:
+/// the Pacer starts over from it instead of sleeping until then
+const PACER_MAX_AHEAD_SECS : f64 = 3.0;
+
+/// How far back the keyframes demuxed are remembered, for the backward playback
+const KEYFRAME_HISTORY_SECS : f64 = 30.0;
+
+/// When going backward past the keyframes remembered, how far before the current one the previous
+/// keyframe is looked for
+const BACKWARD_SEEK_STEP_SECS : f64 = 1.0;
+
+/// The backward playback: keyframes are shown one after the other, going back in the stream.
+///
+/// The VPU can only decode forward, so each keyframe is found by seeking right before the one
+/// currently shown, and sent alone. The time between two of them is the time they are apart in
+/// the stream, divided by the speed.
+struct Backward {
+    speed: f64,
+    /// timestamp of the keyframe currently shown, in seconds
+    current: f64,
+    /// None once the start of the stream was reached, the first keyframe then stays on screen
+    next_step: Option<Instant>,
+}
+
+impl Backward {
+    /// Finds the keyframe before the current one, None if the start of the stream was reached
+    fn step(&mut self, context: &mut Context, history: &mut KeyframeHistory) -> Result<Option<Packet>> {
+        let start = context.start_time() as f64 * context.time_base();
+        let mut target = history.previous(self.current).unwrap_or(self.current - BACKWARD_SEEK_STEP_SECS);
+        while self.current > start {
+            let packet = context.seek_keyframe_before(target.max(start))?;
+            let time = packet.inner.pts as f64 * packet.time_base;
+            if packet.inner.pts != AV_NOPTS_VALUE && time < self.current {
+                let gap = self.current - time;
+                self.current = time;
+                self.next_step = Some(Instant::now() + secs_to_duration(gap / self.speed));
+                return Ok(Some(packet));
+            }
+            if target <= start {
+                break;
+            }
+            // no keyframe between target and the current one, look further back
+            target -= BACKWARD_SEEK_STEP_SECS;
+        }
+        Ok(None)
+    }
+}
+
+/// Holds packets back so that the stream is never more than `video_delay` seconds ahead of the
+/// wall clock.
+///
+/// The reference point (the first timestamp and the instant it was sent) is reset every time the
+/// stream is loaded or seeked.
+struct Pacer {
+    video_delay: f64,
+    reference: Option<(Instant, i64)>,
+}
+
+impl Pacer {
+    fn new() -> Pacer {
+        Pacer {
+            video_delay: 0.0,
+            reference: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.reference = None;
+    }
+
+    /// Sleeps until the packet with the given timestamp can be sent, until abort_io is true, or
+    /// until a command arrives: it is returned, to be handled right after the packet is sent.
+    fn wait_for<T>(&mut self, pts: i64, time_base: f64, abort_io: &AtomicBool, rx: &Receiver<T>) -> Option<T> {
+        if self.video_delay <= 0.0 || pts == AV_NOPTS_VALUE || time_base <= 0.0 {
+            return None;
+        }
+        let (start, first_pts) = match self.reference {
+            Some(reference) => reference,
+            None => {
+                self.reference = Some((Instant::now(), pts));
+                return None;
+            }
+        };
+        let stream_time = (pts - first_pts) as f64 * time_base;
+        let ahead = stream_time - duration_to_secs(start.elapsed());
+        if ahead > self.video_delay + PACER_MAX_AHEAD_SECS {
+            debug!("the timestamps jumped {:.3}s ahead, pacing from this packet on", ahead);
+            self.reference = Some((Instant::now(), pts));
+        } else if ahead > self.video_delay {
+            // this can be seconds with a long video_delay, so don't make a shutdown, a Pause or a
+            // Seek wait for it
+            let deadline = Instant::now() + secs_to_duration(ahead - self.video_delay);
+            while !abort_io.load(Ordering::SeqCst) {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                match rx.recv_timeout(::std::cmp::min(deadline - now, Duration::from_millis(PACER_SLEEP_SLICE_MS))) {
+                    Ok(message) => return Some(message),
+                    Err(RecvTimeoutError::Timeout) => {},
+                    // the main loop finds out by itself
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        }
+        None
+    }
 }
 
 #[derive(Debug)]
 pub struct Packet {
-    pub inner: libav::AVPacket,
+    /// the largest of the layouts in Abi, since av_read_frame fills the one of the linked
+    /// libavformat. The fields we read are at the same place in all of them.
+    pub inner: libav::v56::AVPacket,
+    /// of the stream the packet comes from, in seconds, see Context::time_base
+    pub time_base: f64,
+}
+
+/// What amcodec gets of a Packet of the video stream: the AVPacket is unref'd by the libav thread
+/// as soon as its payload is copied, see pool.rs
+#[derive(Debug)]
+pub struct VideoPacket {
+    pub data: PooledBuffer,
+    pub pts: i64,
+    /// see Packet::time_base
+    pub time_base: f64,
 }
 
 #[derive(Debug)]
 pub enum PacketWrapper {
     /// Needed before every new file
-    ExtraData(Arc<Vec<u8>>),
+    ExtraData(Vec<u8>),
     /// A standard packet usually describing one frame
-    Packet(Packet),
+    Packet(VideoPacket),
     /// A message describing that the file's done playing,
     /// after this point it should wait for other ExtraData
     EOF,
@@ -236,6 +1083,16 @@ pub enum PacketWrapper {
     /// Stop the current playback (to load something else instead for
     /// example)
     Stop,
+    /// Sent the other way around, from amcodec to libav: the VPU is running out of data, packets
+    /// should be sent faster
+    Underrun,
+    /// Sent after the ExtraData of a Load, with the reply of the Load: amcodec only answers once
+    /// the first packet of the video was written, so that a video the VPU refuses doesn't load
+    /// "successfully". The Load can wait for libav to read that packet as long as it takes.
+    ConfirmLoad(SuSender<Reply>),
+    /// Sent after the ExtraData of a successful Seek, with its generation: amcodec tells the main
+    /// thread when the first frame after it is shown, see PositionReport::SeekShown
+    Seeked(u64),
 }
 
 impl Drop for Packet {
@@ -244,21 +1101,124 @@ impl Drop for Packet {
             // we don't own the packet, so calling "free" is not appropriate, however libavformat
             // knows we still have a reference of this packet, so calling this allows it to know
             // that we don't need this packet anymore
-            libav::av_packet_unref(&mut self.inner as *mut _);
+            libav::av_packet_unref(&mut self.inner as *mut _ as *mut libav::AVPacket);
         }
     }
 }
 
 unsafe impl Send for Packet {}
 
+impl Packet {
+    pub fn is_keyframe(&self) -> bool {
+        self.inner.flags & libav::AV_PKT_FLAG_KEY as c_int != 0
+    }
+
+    fn data(&self) -> &[u8] {
+        if self.inner.data.is_null() || self.inner.size <= 0 {
+            &[]
+        } else {
+            unsafe { ::std::slice::from_raw_parts(self.inner.data, self.inner.size as usize) }
+        }
+    }
+
+    /// Copies the payload for amcodec, this packet can be dropped right after
+    pub fn to_video(&self, pool: &Arc<BufferPool>) -> VideoPacket {
+        VideoPacket {
+            data: BufferPool::copy(pool, self.data()),
+            pts: self.inner.pts,
+            time_base: self.time_base,
+        }
+    }
+
+    fn to_spooled(&self) -> SpooledPacket {
+        let data = self.data().to_vec();
+        SpooledPacket {
+            pts: self.inner.pts,
+            dts: self.inner.dts,
+            flags: self.inner.flags,
+            keyframe: self.is_keyframe(),
+            time: if self.inner.pts == AV_NOPTS_VALUE { None } else { Some(self.inner.pts as f64 * self.time_base) },
+            data: data,
+        }
+    }
+}
+
+/// Packets queued for amcodec at most while timeshifting, when packet_buffer_bound isn't set: the
+/// others wait on disk
+const TIMESHIFT_QUEUE_BOUND : u32 = 64;
+
+/// Buffers kept for the payload of the packets when packet_buffer_bound isn't set, see pool.rs.
+/// Otherwise there is one per packet the queue can hold.
+const UNBOUNDED_POOL_CAPACITY : usize = 64;
+
+/// How long the libav thread waits for a command when amcodec has enough packets: it can't take
+/// more before its next iteration anyway
+const BACKPRESSURE_WAIT_MS : u64 = 5;
+
+/// When the other streams are this far (in stream time) past the last packet of the video stream,
+/// the video is considered to have moved to another stream: some broadcast recordings switch
+/// programs on the way
+const VIDEO_STREAM_LOST_SECS : f64 = 3.0;
+
+/// Same, when there is nothing to demux at all (no video, or its end was reached)
+const IDLE_WAIT_MS : u64 = 100;
+
+/// True when amcodec has at least `bound` packets waiting: nothing more is demuxed until it takes
+/// some, which also keeps the demuxing from running far ahead of the video
+fn queue_full(stats: &Stats, bound: Option<u32>) -> bool {
+    match bound {
+        Some(bound) => {
+            let demuxed = stats.packets_demuxed.load(Ordering::Relaxed);
+            let dequeued = stats.packets_dequeued.load(Ordering::Relaxed);
+            demuxed.saturating_sub(dequeued) >= bound as usize
+        },
+        None => false,
+    }
+}
+
 /// the main thread which will do the libav work
 ///
 /// rx: Receiver which receives commands and responds to them via a SingleUsageSender<FfiErrorCode>
 /// packet_channel: the channel where the thread must send its packets
+/// feedback_channel: hints from the amcodec thread, see PacketWrapper::Underrun
+/// position_channel: what we know of the stream's timestamps, for the main thread
+/// status_channel: events for the API user, such as PreloadFailed
+/// stats: packets_demuxed is counted here, and the packets still queued for amcodec are read from it
+/// config: packet_buffer_bound, network_timeout and the timeshift are used here
+/// async_seek: told when every seek is done, see aml_video_player_is_seek_complete
+/// abort_io: set by the main thread right before Shutdown, aborts whatever libav is blocked on
+/// (opening an url, reading over a slow network, ...) so that Shutdown is answered right away
 /// keep_running: once in a while check this variable to make sure the program isn't aborting
-pub fn main_thread(rx: Receiver<(Message, SuSender<FfiErrorCode>)>, packet_channel: Sender<PacketWrapper>, keep_running: Arc<AtomicBool>) {
-    println!("libavthread starting");
+pub fn main_thread(rx: Receiver<Request<Message, Reply>>, packet_channel: Sender<PacketWrapper>, feedback_channel: Receiver<PacketWrapper>, position_channel: Sender<PositionReport>, status_channel: Sender<PlayerEvent>, stats: Arc<Stats>, mut config: PlayerConfig, async_seek: Arc<AsyncSeek>, abort_io: Arc<AtomicBool>, keep_running: Arc<AtomicBool>) {
+    debug!("starting");
     let mut allow_next_frame = true;
+    // the pts of the first packet after a Load or a Seek is where the VPU starts from
+    let mut report_first_packet = false;
+    // time of the last packet of the video stream, or of the first packet of any stream since
+    // the last command, see VIDEO_STREAM_LOST_SECS
+    let mut video_time : Option<f64> = None;
+    let mut pacer = Pacer::new();
+    // packets are spread evenly when a rate is set, there is no burst allowed
+    let mut rate_limiter = TokenBucket::new(0, 1);
+    // keyframes demuxed recently, the backward playback starts from the newest one
+    let mut history = KeyframeHistory::new(KEYFRAME_HISTORY_SECS);
+    let mut backward : Option<Backward> = None;
+    // (stream index, format) of the subtitle track whose cues are sent to the API user
+    let mut subtitle_track : Option<(usize, SubtitleFormat)> = None;
+    // see Message::ForceVideoFormat
+    let mut forced_codec : Option<CodecKind> = None;
+    let mut network = NetworkOptions {
+        timeout: config.network_timeout,
+        ..NetworkOptions::default()
+    };
+    let pool = Arc::new(BufferPool::new(config.packet_buffer_bound.map_or(UNBOUNDED_POOL_CAPACITY, |bound| bound as usize)));
+    // the buffer amcodec is fed from once a live stream was paused, see timeshift.rs
+    let mut timeshift : Option<Timeshift> = None;
+    // the source ended while timeshifting, EOF is sent once the buffer is played
+    let mut timeshift_eof = false;
+    let mut recording : Option<Recording> = None;
+    // the url of the video loaded, which can't be recorded to
+    let mut source : Option<String> = None;
     // unsafe tag is required for C functions calls ... since we are almost doing only that,
     // there is no point to write "unsafe" every other line of code, just write it once
     unsafe {
@@ -271,58 +1231,382 @@ pub fn main_thread(rx: Receiver<(Message, SuSender<FfiErrorCode>)>, packet_chann
         // Plus if there is an invalid file opened, we must have a way to know that no file is
         // playing at the moment
         let mut context : Option<Context> = None;
+        let mut preloaded : Option<Preloaded> = None;
+        // how long to wait for a command before the next iteration, None to go on right away: the
+        // thread only sleeps when there is nothing to demux
+        let mut wait : Option<Duration> = None;
+        // a command which arrived while the Pacer was waiting, handled before any other
+        let mut interrupted : Option<Request<Message, Reply>> = None;
         while keep_running.load(Ordering::SeqCst) == true {
-            match rx.try_recv() {
-                Ok((Message::Load(m), tx)) => {
+            let message = match (interrupted.take(), wait.take()) {
+                (Some(message), _) => Ok(message),
+                (None, Some(timeout)) => {
+                    let waiting_since = Instant::now();
+                    let message = rx.recv_timeout(timeout).map_err(|e| match e {
+                        RecvTimeoutError::Timeout => TryRecvError::Empty,
+                        RecvTimeoutError::Disconnected => TryRecvError::Disconnected,
+                    });
+                    stats::add_elapsed(&stats.libav_idle_us, waiting_since);
+                    message
+                },
+                (None, None) => rx.try_recv(),
+            };
+            // set once a packet was read or sent, the next iteration may have another one
+            let mut demuxed = false;
+            match message {
+                Ok(Request { query: Message::Load(m), reply: tx, .. }) => {
                     handle_channel_error!(packet_channel.send(PacketWrapper::Stop), tx);
+                    // the time of the packets jumps, see VIDEO_STREAM_LOST_SECS
+                    video_time = None;
                     // allow_next_frame is a weird name to stop trying to get the next_frame after
                     // EOF or an error. Another solution would be to set the Context to None, but
                     // then we wouldn't be able to Seek at the beginning after a EndOfFile without
                     // reloading the whole file again
                     allow_next_frame = true;
-                    context = match Context::new(m.as_str()) {
-                        Ok(context) => {
-                            match context.get_extra_data() {
-                                Ok(extra_data) => {
-                                    handle_channel_error!(packet_channel.send(PacketWrapper::ExtraData(extra_data)), tx);
-                                },
-                                Err(e) => {
-                                    println!("libav_thread: warning: get_extra_data failed: {}", e.display());
-                                }
+                    pacer.reset();
+                    history.clear();
+                    backward = None;
+                    subtitle_track = None;
+                    timeshift = None;
+                    timeshift_eof = false;
+                    stats.timeshift_span_ms.store(0, Ordering::Relaxed);
+                    if recording.as_ref().map_or(false, |recording| recording::same_file(&recording.path, &m)) {
+                        warn!("`{}` is being recorded to, the recording is stopped", m);
+                        if let Err(e) = recording.take().unwrap().finish() {
+                            error!("{}", e.display());
+                        }
+                    }
+                    source = None;
+                    // whatever happens, so that it can't apply to the wrong video later on
+                    let forced = forced_codec.take();
+                    // the preloaded context was opened without it
+                    let is_preloaded = match preloaded {
+                        Some(ref preloaded) => preloaded.url == m && forced.is_none(),
+                        None => false,
+                    };
+                    let opened = if is_preloaded {
+                        let preload = preloaded.take().unwrap();
+                        let speculative = preload.speculative;
+                        match preload.wait() {
+                            // nobody asked for the prefetch, it may have failed for a reason
+                            // which is gone now
+                            Err(_) if speculative => open(m.as_str(), abort_io.clone(), network, forced),
+                            result => result,
+                        }
+                    } else {
+                        // the prefetch guessed wrong, while a preload is still there for
+                        // play_preloaded
+                        if preloaded.as_ref().map_or(false, |preloaded| preloaded.speculative) {
+                            debug!("dropping the prefetch of `{}`", preloaded.as_ref().unwrap().url);
+                            preloaded = None;
+                        }
+                        open(m.as_str(), abort_io.clone(), network, forced)
+                    };
+                    context = match opened {
+                        Ok((context, extra_data)) => {
+                            if let Some(extra_data) = extra_data {
+                                // the recording goes on with the new video
+                                recording::record_extra_data(&mut recording, &extra_data);
+                                handle_channel_error!(packet_channel.send(PacketWrapper::ExtraData(extra_data)), tx);
                             };
-                            tx.send(FfiErrorCode::None);
+                            source = Some(m.clone());
+                            let _r = position_channel.send(PositionReport::Stream(context.time_base(), context.start_time(), context.get_sample_aspect_ratio()));
+                            report_first_packet = true;
+                            handle_channel_error!(packet_channel.send(PacketWrapper::ConfirmLoad(tx.clone())), tx);
                             Some(context)
                         },
                         Err(e) => {
-                            println!("libav_thread: error when loading url/path `{}`: {}", m.as_str(), e.display());
-                            println!("libav_thread: url will be ignored");
-                            tx.send(error_to_ecode(e));
+                            error!("error when loading url/path `{}`: {}, it will be ignored", m.as_str(), e.display());
+                            tx.send(e);
                             None
                         }
                     };
                 },
+                Ok(Request { query: Message::Preload(url), reply: tx, .. }) => {
+                    preloaded = Some(Preloaded::spawn(url, abort_io.clone(), network, false));
+                    tx.send(FfiErrorCode::None);
+                },
+                Ok(Request { query: Message::Prefetch(url), reply: tx, .. }) => {
+                    preloaded = Some(Preloaded::spawn(url, abort_io.clone(), network, true));
+                    tx.send(FfiErrorCode::None);
+                },
                 // Seek is actually done by stopping totally the decoding in amcodec, and then
                 // loading the same video in Amcodec, and sending directly the packet from the
                 // seeked position. There are ways to directly seek withotu changing amcodec or
                 // this context, but it can lead to visual artifcats or weird behavior, so better
                 // be safe than sorry with discarding the video in the amcodec thread first
-                Ok((Message::Seek(pos), tx)) => {
+                Ok(Request { query: Message::Seek(pos, generation), reply: tx, .. }) => {
+                    // (position, whether it was clamped to the end of the video)
+                    let target = match context {
+                        // !(pos >= 0.0) also rejects NaN
+                        Some(_) if !(pos >= 0.0) => Err(Reply::from(FfiErrorCode::InvalidCommand)),
+                        // positions in the timeshift buffer, which has no end
+                        Some(_) if timeshift.is_some() => Ok((pos, false)),
+                        Some(ref context) => match context.duration() {
+                            Some(duration) if pos > duration - SEEK_END_MARGIN_SECS => Ok(((duration - SEEK_END_MARGIN_SECS).max(0.0), true)),
+                            Some(_) => Ok((pos, false)),
+                            None => Err(Reply::from(Error::Unsupported(String::from("seeking in a stream without a duration")))),
+                        },
+                        None => Ok((pos, false)),
+                    };
+                    let (pos, clamped) = match target {
+                        Ok(target) => target,
+                        Err(reply) => {
+                            warn!("refusing to seek to {}", pos);
+                            tx.send(reply);
+                            async_seek.finish();
+                            continue;
+                        },
+                    };
+                    if clamped {
+                        debug!("seeking to {:.3}s instead, the video ends before", pos);
+                    }
                     if let Some(ref mut context) = context {
                         handle_channel_error!(packet_channel.send(PacketWrapper::Stop), tx);
+                        video_time = None;
                         match context.get_extra_data() {
                             Ok(extra_data) => {
                                 handle_channel_error!(packet_channel.send(PacketWrapper::ExtraData(extra_data)), tx);
                             },
                             Err(e) => {
-                                println!("libav_thread: warning: get_extra_data failed: {}", e.display());
+                                warn!("get_extra_data failed: {}", e.display());
                             }
                         };
-                        tx.send(result_to_ecode(context.seek(pos)));
+                        pacer.reset();
+                        history.clear();
+                        backward = None;
+                        report_first_packet = true;
+                        if let Some(ref mut timeshift) = timeshift {
+                            // the source is still read from the live edge, only what amcodec is
+                            // fed with moves
+                            timeshift.seek(pos);
+                            handle_channel_error!(packet_channel.send(PacketWrapper::Seeked(generation)), tx);
+                            tx.send(FfiErrorCode::None);
+                        } else {
+                            allow_next_frame = true;
+                            let r = if clamped {
+                                context.seek_backward(pos)
+                            } else {
+                                context.seek(pos)
+                            };
+                            if r.is_ok() {
+                                handle_channel_error!(packet_channel.send(PacketWrapper::Seeked(generation)), tx);
+                            }
+                            tx.respond_with("seeking", || r);
+                        }
                     } else {
                         // there is no point "Seeking" something when nothing is loaded in the
                         // first place ...
                         tx.send(FfiErrorCode::InvalidCommand);
                     }
+                    async_seek.finish();
+                },
+                Ok(Request { query: Message::SetVideoDelay(delay), reply: tx, .. }) => {
+                    // !(delay >= 0.0) also rejects NaN
+                    if !(delay >= 0.0) {
+                        tx.send(FfiErrorCode::InvalidCommand);
+                    } else {
+                        pacer.video_delay = delay;
+                        pacer.reset();
+                        tx.send(FfiErrorCode::None);
+                    }
+                },
+                Ok(Request { query: Message::SetMaxDecodeRate(packets_per_sec), reply: tx, .. }) => {
+                    rate_limiter.set_rate(packets_per_sec);
+                    tx.send(FfiErrorCode::None);
+                },
+                Ok(Request { query: Message::SetBackwardSpeed(speed), reply: tx, .. }) => {
+                    let context = match context {
+                        // !(speed >= 0.0) also rejects NaN
+                        Some(ref mut context) if speed >= 0.0 && speed.is_finite() => context,
+                        _ => {
+                            tx.send(FfiErrorCode::InvalidCommand);
+                            continue;
+                        }
+                    };
+                    if speed > 0.0 && timeshift.is_some() {
+                        tx.send(Error::Unsupported(String::from("backward playback of a timeshifted live stream")));
+                        continue;
+                    }
+                    if speed > 0.0 {
+                        if let Some(ref mut backward) = backward {
+                            backward.speed = speed;
+                            tx.send(FfiErrorCode::None);
+                            continue;
+                        }
+                        let current = match history.newest() {
+                            Some(current) => current,
+                            // nothing was demuxed yet, we don't know where we are
+                            None => {
+                                tx.send(FfiErrorCode::InvalidCommand);
+                                continue;
+                            }
+                        };
+                        // what amcodec still has is ahead of us, it must not be shown anymore
+                        handle_channel_error!(packet_channel.send(PacketWrapper::Stop), tx);
+                        if let Ok(extra_data) = context.get_extra_data() {
+                            handle_channel_error!(packet_channel.send(PacketWrapper::ExtraData(extra_data)), tx);
+                        }
+                        backward = Some(Backward {
+                            speed: speed,
+                            current: current,
+                            next_step: Some(Instant::now()),
+                        });
+                        tx.send(FfiErrorCode::None);
+                    } else if let Some(backward) = backward.take() {
+                        // the normal playback starts again from the keyframe shown, like a Seek
+                        handle_channel_error!(packet_channel.send(PacketWrapper::Stop), tx);
+                        if let Ok(extra_data) = context.get_extra_data() {
+                            handle_channel_error!(packet_channel.send(PacketWrapper::ExtraData(extra_data)), tx);
+                        }
+                        pacer.reset();
+                        history.clear();
+                        allow_next_frame = true;
+                        report_first_packet = true;
+                        tx.respond_with("seeking", || context.seek(backward.current));
+                    } else {
+                        tx.send(FfiErrorCode::None);
+                    }
+                },
+                Ok(Request { query: Message::GetSubtitleTrackCount(value_tx), reply: tx, .. }) => {
+                    match context {
+                        Some(ref context) => {
+                            value_tx.send(context.subtitle_streams.len() as u32);
+                            tx.send(FfiErrorCode::None);
+                        },
+                        None => tx.send(FfiErrorCode::InvalidCommand),
+                    }
+                },
+                Ok(Request { query: Message::GetCodecInfo(value_tx), reply: tx, .. }) => {
+                    match context {
+                        Some(ref context) => {
+                            value_tx.send(context.codec_info());
+                            tx.send(FfiErrorCode::None);
+                        },
+                        None => tx.send(FfiErrorCode::InvalidCommand),
+                    }
+                },
+                Ok(Request { query: Message::GetDuration(value_tx), reply: tx, .. }) => {
+                    match context {
+                        Some(ref context) => match context.duration() {
+                            Some(duration) => {
+                                value_tx.send(duration);
+                                tx.send(FfiErrorCode::None);
+                            },
+                            None => tx.send(Error::Unsupported(String::from("getting the duration of a stream without one"))),
+                        },
+                        None => tx.send(FfiErrorCode::InvalidCommand),
+                    }
+                },
+                Ok(Request { query: Message::SetSubtitleTrack(track), reply: tx, .. }) => {
+                    let track = match (&context, track) {
+                        (&Some(_), None) => None,
+                        (&Some(ref context), Some(track)) if track < context.subtitle_streams.len() => {
+                            Some(context.subtitle_streams[track])
+                        },
+                        _ => {
+                            tx.send(FfiErrorCode::InvalidCommand);
+                            continue;
+                        }
+                    };
+                    if let Some((index, SubtitleFormat::Bitmap)) = track {
+                        tx.send(Error::Unsupported(format!("subtitle stream {} is made of bitmaps", index)));
+                    } else {
+                        subtitle_track = track;
+                        tx.send(FfiErrorCode::None);
+                    }
+                },
+                Ok(Request { query: Message::ForceVideoFormat(codec), reply: tx, .. }) => {
+                    forced_codec = codec;
+                    tx.send(FfiErrorCode::None);
+                },
+                Ok(Request { query: Message::SetRtspTransport(transport), reply: tx, .. }) => {
+                    network.rtsp_transport = Some(transport);
+                    tx.send(FfiErrorCode::None);
+                },
+                Ok(Request { query: Message::SetNetworkBufferSize(size), reply: tx, .. }) => {
+                    network.buffer_size = size;
+                    tx.send(FfiErrorCode::None);
+                },
+                Ok(Request { query: Message::StartRecording(path), reply: tx, .. }) => {
+                    if source.as_ref().map_or(false, |source| recording::same_file(&path, source)) {
+                        warn!("refusing to record to `{}`, which is being played", path);
+                        tx.send(FfiErrorCode::InvalidCommand);
+                        continue;
+                    }
+                    if let Some(previous) = recording.take() {
+                        if let Err(e) = previous.finish() {
+                            error!("{}", e.display());
+                        }
+                    }
+                    let started = Recording::create(&path).and_then(|mut new_recording| {
+                        // nothing can be decoded before it, a video loaded later brings its own
+                        if let Some(ref context) = context {
+                            new_recording.write_extra_data(&context.get_extra_data()?)?;
+                        }
+                        Ok(new_recording)
+                    });
+                    match started {
+                        Ok(new_recording) => {
+                            recording = Some(new_recording);
+                            tx.send(FfiErrorCode::None);
+                        },
+                        Err(e) => {
+                            error!("failed to start recording: {}", e.display());
+                            tx.send(e);
+                        },
+                    };
+                },
+                Ok(Request { query: Message::StopRecording, reply: tx, .. }) => {
+                    match recording.take() {
+                        Some(recording) => tx.respond_with("finishing the recording", || recording.finish()),
+                        None => tx.send(FfiErrorCode::InvalidCommand),
+                    };
+                },
+                Ok(Request { query: Message::Pause, reply: tx, .. }) => {
+                    match (&context, &config.timeshift_path) {
+                        (&Some(ref context), &Some(ref path)) if timeshift.is_none() && backward.is_none() && context.is_live() => {
+                            let max_span = config.timeshift_max_duration.map(duration_to_secs);
+                            match Timeshift::create(path, config.timeshift_size, max_span) {
+                                Ok(created) => timeshift = Some(created),
+                                Err(e) => warn!("failed to start the timeshift, the live stream will stall: {}", e.display()),
+                            }
+                        },
+                        _ => {},
+                    }
+                    tx.send(FfiErrorCode::None);
+                },
+                Ok(Request { query: Message::SetTimeshift(path, max_duration), reply: tx, .. }) => {
+                    config.timeshift_path = path;
+                    config.timeshift_max_duration = max_duration;
+                    tx.send(FfiErrorCode::None);
+                },
+                Ok(Request { query: Message::SeekTimeshift(delta), reply: tx, .. }) => {
+                    if let (&mut Some(ref mut timeshift), &Some(ref context)) = (&mut timeshift, &context) {
+                        // same as a Seek in the buffer
+                        handle_channel_error!(packet_channel.send(PacketWrapper::Stop), tx);
+                        if let Ok(extra_data) = context.get_extra_data() {
+                            handle_channel_error!(packet_channel.send(PacketWrapper::ExtraData(extra_data)), tx);
+                        }
+                        pacer.reset();
+                        history.clear();
+                        backward = None;
+                        report_first_packet = true;
+                        timeshift.seek_by(delta);
+                        tx.send(FfiErrorCode::None);
+                    } else {
+                        // only a paused live stream has a buffer to move in
+                        tx.send(FfiErrorCode::InvalidCommand);
+                    }
+                },
+                Ok(Request { query: Message::Shutdown, reply: tx, .. }) => {
+                    allow_next_frame = false;
+                    backward = None;
+                    subtitle_track = None;
+                    context = None;
+                    preloaded = None;
+                    timeshift = None;
+                    tx.send(FfiErrorCode::None);
                 },
                 Err(TryRecvError::Disconnected) => {
                     // the other end of the channel has hung up
@@ -332,21 +1616,185 @@ pub fn main_thread(rx: Receiver<(Message, SuSender<FfiErrorCode>)>, packet_chann
                     // we received the fact that keep_running became false
                     //
                     // in both cases breaking the loop is the correct thing to do here
-                    println!("libav_thread: uh oh ...");
+                    error!("uh oh ...");
                     break;
                 },
                 // no message
                 _ => {}
             };
-            if allow_next_frame {
+            if let Some(ref mut preloaded) = preloaded {
+                if let (Some(code), false) = (preloaded.poll(), preloaded.speculative) {
+                    let _r = status_channel.send(PlayerEvent::PreloadFailed {
+                        url: preloaded.url.clone(),
+                        code: code,
+                    });
+                }
+            }
+            if let Ok(PacketWrapper::Underrun) = feedback_channel.try_recv() {
+                // stop holding packets back, the VPU needs them now
+                pacer.reset();
+                rate_limiter.refill();
+            }
+            if let (&mut Some(ref mut backward), &mut Some(ref mut context)) = (&mut backward, &mut context) {
+                if backward.next_step.map_or(false, |next_step| Instant::now() >= next_step) {
+                    demuxed = true;
+                    match backward.step(context, &mut history) {
+                        Ok(Some(packet)) => {
+                            let _r = position_channel.send(PositionReport::Shown(packet.inner.pts));
+                            trace!("sending keyframe pts={} size={} backward", packet.inner.pts, packet.inner.size);
+                            handle_channel_error!(packet_channel.send(PacketWrapper::Packet(packet.to_video(&pool))));
+                            stats::add(&stats.packets_demuxed, 1);
+                        },
+                        Ok(None) => {
+                            debug!("start of the stream reached, stopping there");
+                            backward.next_step = None;
+                        },
+                        Err(e) => {
+                            warn!("backward playback failed: {}", e.display());
+                            backward.next_step = Some(Instant::now() + secs_to_duration(BACKWARD_SEEK_STEP_SECS));
+                        },
+                    }
+                }
+            } else if let (&mut Some(ref mut timeshift), &mut Some(ref mut context)) = (&mut timeshift, &mut context) {
+                // the source is read as it comes, whatever amcodec does
+                if allow_next_frame {
+                    demuxed = true;
+                    match context.next_frame() {
+                        Ok(packet) => {
+                            if packet.inner.stream_index as usize == context.hevc_stream {
+                                recording::record_packet(&mut recording, packet.data(), packet.is_keyframe());
+                                if let Err(e) = timeshift.push(packet.to_spooled()) {
+                                    error!("timeshift failed: {}", e.display());
+                                    handle_channel_error!(packet_channel.send(PacketWrapper::Error(e)));
+                                    allow_next_frame = false;
+                                }
+                                stats.timeshift_span_ms.store((timeshift.span() * 1000.0) as usize, Ordering::Relaxed);
+                            } else if let Some((index, format)) = subtitle_track {
+                                if packet.inner.stream_index as usize == index {
+                                    if let Some(cue) = context.subtitle_cue(&packet, format) {
+                                        let _r = status_channel.send(cue);
+                                    }
+                                }
+                            }
+                        },
+                        Err(Error::Eof) => {
+                            allow_next_frame = false;
+                            timeshift_eof = true;
+                        },
+                        Err(e) => {
+                            handle_channel_error!(packet_channel.send(PacketWrapper::Error(e)));
+                            allow_next_frame = false;
+                        }
+                    };
+                }
+                if !queue_full(&stats, Some(config.packet_buffer_bound.unwrap_or(TIMESHIFT_QUEUE_BOUND))) {
+                    if timeshift.fell_behind() {
+                        warn!("the timeshift buffer is full, going on from its oldest keyframe");
+                        handle_channel_error!(packet_channel.send(PacketWrapper::Stop));
+                        if let Ok(extra_data) = context.get_extra_data() {
+                            handle_channel_error!(packet_channel.send(PacketWrapper::ExtraData(extra_data)));
+                        }
+                        pacer.reset();
+                        report_first_packet = true;
+                    }
+                    match timeshift.next() {
+                        Ok(Some(spooled)) => {
+                            demuxed = true;
+                            if report_first_packet && spooled.pts != AV_NOPTS_VALUE {
+                                let _r = position_channel.send(PositionReport::FirstPacket(spooled.pts));
+                                report_first_packet = false;
+                            }
+                            interrupted = pacer.wait_for(spooled.pts, context.time_base(), &abort_io, &rx);
+                            rate_limiter.take();
+                            trace!("sending timeshifted packet pts={} size={}", spooled.pts, spooled.data.len());
+                            let packet = VideoPacket {
+                                data: BufferPool::adopt(&pool, spooled.data),
+                                pts: spooled.pts,
+                                time_base: context.time_base(),
+                            };
+                            handle_channel_error!(packet_channel.send(PacketWrapper::Packet(packet)));
+                            stats::add(&stats.packets_demuxed, 1);
+                        },
+                        Ok(None) => {
+                            if timeshift_eof {
+                                handle_channel_error!(packet_channel.send(PacketWrapper::EOF));
+                                timeshift_eof = false;
+                            }
+                        },
+                        Err(e) => {
+                            error!("timeshift failed: {}", e.display());
+                            handle_channel_error!(packet_channel.send(PacketWrapper::Error(e)));
+                            allow_next_frame = false;
+                            timeshift_eof = false;
+                        },
+                    }
+                }
+            } else if allow_next_frame && !queue_full(&stats, config.packet_buffer_bound) {
                 if let Some(ref mut context) = context {
+                    demuxed = true;
                     match context.next_frame() {
                         Ok(packet) => {
+                            let index = packet.inner.stream_index as usize;
+                            let time = context.packet_time(&packet);
+                            // a program change libav tells us about right away, else the video
+                            // stream is only found lost after a while
+                            let switch_to = match (context.replacing_hevc_stream(), time, video_time) {
+                                (Some(new_stream), _, _) => {
+                                    warn!("the program of the video stream {} changed, switching to the stream {}", context.hevc_stream, new_stream);
+                                    Some(new_stream)
+                                },
+                                (None, Some(time), Some(last)) if index != context.hevc_stream && time - last > VIDEO_STREAM_LOST_SECS
+                                    && context.is_hevc_stream(index) => {
+                                    warn!("no packet of the video stream {} for {:.1}s, switching to the stream {}",
+                                          context.hevc_stream, time - last, index);
+                                    Some(index)
+                                },
+                                _ => None,
+                            };
+                            if let Some(new_stream) = switch_to {
+                                context.hevc_stream = new_stream;
+                                // the new stream may have other parameters, the decoder starts
+                                // over from its first keyframe
+                                handle_channel_error!(packet_channel.send(PacketWrapper::Stop));
+                                match context.get_extra_data() {
+                                    Ok(extra_data) => {
+                                        recording::record_extra_data(&mut recording, &extra_data);
+                                        handle_channel_error!(packet_channel.send(PacketWrapper::ExtraData(extra_data)));
+                                    },
+                                    // the parameter sets of transport streams are in band
+                                    Err(e) => debug!("no extra data for the stream {}: {}", new_stream, e.display()),
+                                }
+                                let _r = position_channel.send(PositionReport::Stream(context.time_base(), context.start_time(), context.get_sample_aspect_ratio()));
+                                let _r = status_channel.send(PlayerEvent::StreamChanged { stream_index: new_stream as u32 });
+                                video_time = time.or(video_time);
+                            } else if let Some(time) = time {
+                                if video_time.is_none() || index == context.hevc_stream {
+                                    video_time = Some(time);
+                                }
+                            }
                             if packet.inner.stream_index as usize == context.hevc_stream {
-                                handle_channel_error!(packet_channel.send(PacketWrapper::Packet(packet)));
+                                if report_first_packet && packet.inner.pts != AV_NOPTS_VALUE {
+                                    let _r = position_channel.send(PositionReport::FirstPacket(packet.inner.pts));
+                                    report_first_packet = false;
+                                }
+                                if packet.is_keyframe() && packet.inner.pts != AV_NOPTS_VALUE {
+                                    history.push(packet.inner.pts as f64 * packet.time_base);
+                                }
+                                recording::record_packet(&mut recording, packet.data(), packet.is_keyframe());
+                                interrupted = pacer.wait_for(packet.inner.pts, context.time_base(), &abort_io, &rx);
+                                rate_limiter.take();
+                                trace!("sending packet pts={} size={}", packet.inner.pts, packet.inner.size);
+                                handle_channel_error!(packet_channel.send(PacketWrapper::Packet(packet.to_video(&pool))));
+                                stats::add(&stats.packets_demuxed, 1);
+                            } else if let Some((index, format)) = subtitle_track {
+                                if packet.inner.stream_index as usize == index {
+                                    if let Some(cue) = context.subtitle_cue(&packet, format) {
+                                        let _r = status_channel.send(cue);
+                                    }
+                                }
                             }
                         },
-                        Err(Error(ErrorKind::EOF,_)) => {
+                        Err(Error::Eof) => {
                             handle_channel_error!(packet_channel.send(PacketWrapper::EOF));
                             allow_next_frame = false;
                         },
@@ -357,12 +1805,112 @@ pub fn main_thread(rx: Receiver<(Message, SuSender<FfiErrorCode>)>, packet_chann
                     };
                 };
             };
-            // a very small sleep time still allows us to not "actively" sleep and ease the CPU's
-            // load
-            thread::sleep(Duration::from_millis(5));
+            // a command is answered as soon as it arrives either way
+            wait = if demuxed {
+                None
+            } else if let Some(next_step) = backward.as_ref().and_then(|backward| backward.next_step) {
+                let now = Instant::now();
+                Some(if next_step > now { next_step - now } else { Duration::new(0, 0) })
+            } else if context.is_some() && (allow_next_frame || timeshift.is_some()) {
+                Some(Duration::from_millis(BACKPRESSURE_WAIT_MS))
+            } else {
+                Some(Duration::from_millis(IDLE_WAIT_MS))
+            };
+        }
+    }
+    debug!("shutting down ...");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 22 bytes of configuration, then a VPS array of one NAL unit and an SPS array of two
+    fn hvcc() -> Vec<u8> {
+        let mut data = vec![1; 21];
+        data.push(0xf3);
+        data.push(2);
+        data.extend_from_slice(&[0x20, 0, 1, 0, 3, 0x40, 0x01, 0x0c]);
+        data.extend_from_slice(&[0x21, 0, 2, 0, 2, 0x42, 0x01, 0, 1, 0x43]);
+        data
+    }
+
+    #[test]
+    fn hvcc_to_annexb_prefixes_every_nal_unit() {
+        assert_eq!(hvcc_to_annexb(&hvcc()).unwrap(), vec![0, 0, 0, 1, 0x40, 0x01, 0x0c, 0, 0, 0, 1, 0x42, 0x01, 0, 0, 0, 1, 0x43]);
+        // no array at all is not an error, there just is nothing to send
+        let mut data = hvcc();
+        data.truncate(23);
+        data[22] = 0;
+        assert_eq!(hvcc_to_annexb(&data).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn hvcc_truncated_at_every_offset() {
+        let data = hvcc();
+        for len in 0..data.len() {
+            assert!(hvcc_to_annexb(&data[..len]).is_err(), "hvcC truncated at {} bytes", len);
         }
     }
-    if cfg!(debug_assertions) {
-        println!("libav_thread: shutting down ...");
+
+    #[test]
+    fn hvcc_counts_and_lengths_past_the_end() {
+        let cases : &[(usize, &[u8])] = &[
+            // more arrays than there are
+            (22, &[3]),
+            (22, &[0xff]),
+            // more NAL units than there are in the VPS array
+            (24, &[0, 2]),
+            (24, &[0xff, 0xff]),
+            // NAL units longer than what is left
+            (26, &[0, 4]),
+            (26, &[0xff, 0xff]),
+            (34, &[0, 3]),
+            (37, &[0xff, 0xff]),
+        ];
+        for &(offset, bytes) in cases {
+            let mut data = hvcc();
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+            assert!(hvcc_to_annexb(&data).is_err(), "{:?} at {}", bytes, offset);
+        }
+    }
+
+    /// a Pacer whose first packet, at pts 0 in milliseconds, was sent right now
+    fn pacer(video_delay: f64) -> Pacer {
+        let mut pacer = Pacer::new();
+        pacer.video_delay = video_delay;
+        let (_tx, rx) = mpsc::channel::<()>();
+        assert!(pacer.wait_for(0, 0.001, &AtomicBool::new(false), &rx).is_none());
+        pacer
+    }
+
+    #[test]
+    fn pacer_starts_over_after_a_jump() {
+        let mut pacer = pacer(0.5);
+        let (_tx, rx) = mpsc::channel::<()>();
+        let started = Instant::now();
+        assert!(pacer.wait_for(60_000, 0.001, &AtomicBool::new(false), &rx).is_none());
+        assert!(started.elapsed() < Duration::from_secs(1));
+        // the packets after the jump are paced from it
+        assert_eq!(pacer.reference.map(|(_, pts)| pts), Some(60_000));
+    }
+
+    #[test]
+    fn pacer_wait_is_interrupted_by_a_command() {
+        let mut pacer = pacer(0.5);
+        let (tx, rx) = mpsc::channel();
+        tx.send(42).unwrap();
+        let started = Instant::now();
+        assert_eq!(pacer.wait_for(2_000, 0.001, &AtomicBool::new(false), &rx), Some(42));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn pacer_waits_for_packets_ahead() {
+        let mut pacer = pacer(0.1);
+        let (_tx, rx) = mpsc::channel::<()>();
+        let started = Instant::now();
+        assert!(pacer.wait_for(300, 0.001, &AtomicBool::new(false), &rx).is_none());
+        assert!(started.elapsed() >= Duration::from_millis(150));
     }
 }
@@ -0,0 +1,81 @@
+/*
+ * Everything the player prints goes through the macros of this module, as "{thread}: {message}".
+ * The thread is the one the message comes from (main_thread, libav_thread, amcodec_thread, ...),
+ * so there is no need to repeat it in every message: code shared by several threads (the window
+ * backends, Amcodec::new, ...) is attributed to whoever calls it.
+ *
+ * error, warn and info are always printed. debug is only printed in debug builds, and trace (one
+ * message per packet, and the like) only when built with the trace_logs feature: the arguments
+ * of a disabled trace! are not even evaluated.
+ */
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::thread;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn enabled(&self) -> bool {
+        match *self {
+            Level::Error | Level::Warn | Level::Info => true,
+            Level::Debug => cfg!(debug_assertions),
+            Level::Trace => cfg!(feature = "trace_logs"),
+        }
+    }
+}
+
+static ERROR_COUNT : AtomicUsize = ATOMIC_USIZE_INIT;
+static WARNING_COUNT : AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Number of errors and warnings logged since the library was loaded, by every player
+pub fn counts() -> (usize, usize) {
+    (ERROR_COUNT.load(Ordering::Relaxed), WARNING_COUNT.load(Ordering::Relaxed))
+}
+
+/// Use the macros instead
+pub fn log(level: Level, args: fmt::Arguments) {
+    match level {
+        Level::Error => { ERROR_COUNT.fetch_add(1, Ordering::Relaxed); },
+        Level::Warn => { WARNING_COUNT.fetch_add(1, Ordering::Relaxed); },
+        _ => {},
+    };
+    if !level.enabled() {
+        return;
+    }
+    let current = thread::current();
+    // threads of the API user may have no name
+    let thread_name = current.name().unwrap_or("api");
+    println!("{}: {}", thread_name, args);
+}
+
+macro_rules! error {
+    ($($arg:tt)+) => ($crate::log::log($crate::log::Level::Error, format_args!($($arg)+)))
+}
+
+macro_rules! warn {
+    ($($arg:tt)+) => ($crate::log::log($crate::log::Level::Warn, format_args!($($arg)+)))
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => ($crate::log::log($crate::log::Level::Info, format_args!($($arg)+)))
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => ($crate::log::log($crate::log::Level::Debug, format_args!($($arg)+)))
+}
+
+macro_rules! trace {
+    ($($arg:tt)+) => (
+        if cfg!(feature = "trace_logs") {
+            $crate::log::log($crate::log::Level::Trace, format_args!($($arg)+))
+        }
+    )
+}
@@ -20,96 +20,488 @@
  */
 
 use error::*;
-use super::x11helper::X11Helper;
+use super::window::{self, Window, WindowEvent, WindowBackend, DisplayPath, ShowHideMode};
 use super::libavhelper::{main_thread as libav_main_thread, Message as LibavMessage, PacketWrapper as LibavPacket};
-use super::amcodec::{self, main_loop as amcodec_main_loop, Message as AmcodecMessage, EndReason as VideoEndReason};
-use super::utils::SingleUseSender as SuSender;
+use super::amcodec::{self, main_loop as amcodec_main_loop, Message as AmcodecMessage};
+use super::utils::{single_use_channel, spawn_supervised, SingleUseSender as SuSender, SingleUseReceiver as SuReceiver, Request};
+use super::playlist::Playlist;
+use super::position::{PositionTracker, PositionReport, AsyncSeek};
+use super::eof_future::PlayerEofFuture;
+use super::events::{EventQueue, PlayerEvent};
+use super::stats::Stats;
+use super::audio;
+use super::config::PlayerConfig;
+use super::subtitles::{Cue, SidecarSubtitles};
+use super::settings::{VideoSettings, VideoAxis, Animation, Easing};
 
-use std::sync::{Arc, atomic};
-use std::{ptr, thread};
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, atomic};
+use std::thread;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// How long the API waits for a reply by default, see FfiPlayer::reply_timeout_ms
+pub const DEFAULT_REPLY_TIMEOUT_MS : u64 = 10000;
+/// How long each worker thread has to stop playing when the player is destroyed, see stop_workers
+const SHUTDOWN_ACK_TIMEOUT_MS : u64 = 1000;
 use libc::c_int;
 use std::thread::JoinHandle;
 use libavformat;
-use super::libavhelper::avformat_version;
+use super::libavhelper::{avformat_version, Abi};
 
 /// This is the struct that will get "forgotten" and sent back to the API every time the user needs
 /// do send a command. For all these calls the most important thing here is "sender", but the
 /// others are needed for "destroy" as well: we need to wait for all the threads to finish for us
 /// to finish, so we need to join every thread in "destroy".
+///
+/// Thread handles are Options so that they can be taken out of the struct when joining: dropping a
+/// FfiPlayer which has not been shut down shuts it down and joins every thread.
 pub struct FfiPlayer {
-    pub main_thread: JoinHandle<()>,
-    pub x11_event_loop_thread: JoinHandle<()>,
-    pub amcodec_thread: JoinHandle<()>,
-    pub libav_getter_thread: JoinHandle<()>,
-    pub video_status_queue: Receiver<VideoEndReason>,
-    pub sender: Sender<Message>,
+    pub main_thread: Option<JoinHandle<()>>,
+    /// None when running headless
+    pub window_event_loop_thread: Option<JoinHandle<()>>,
+    pub amcodec_thread: Option<JoinHandle<()>>,
+    pub libav_getter_thread: Option<JoinHandle<()>>,
+    /// filled by the main thread, see aml_video_player_next_event
+    pub events: Arc<EventQueue>,
+    /// filled by the worker threads, see aml_video_player_get_stats
+    pub stats: Arc<Stats>,
+    pub sender: Sender<Request<Message, Reply>>,
     pub keep_running: Arc<atomic::AtomicBool>,
+    /// chosen once and for all when the window is created
+    pub display_path: DisplayPath,
+    /// how long API calls wait for the answer of the main thread, in milliseconds, 0 means
+    /// forever. Atomic since it is changed through the API while other calls may be waiting.
+    pub reply_timeout_ms: atomic::AtomicU64,
+    /// ALSA device audio will be played on and its volume, kept across loads. Only the amcodec
+    /// thread changes the volume, since it has to go to the mixer as well.
+    pub audio: Arc<Mutex<audio::AudioOutput>>,
+    /// shared with the main and libav threads
+    pub async_seek: Arc<AsyncSeek>,
+    /// last error reported by a worker thread to an API call, see aml_video_player_last_error
+    pub last_error: Mutex<Option<ErrorReport>>,
+    /// FfiErrorCode of the last error, whether it was the answer to an API call or pushed as an
+    /// event by the main thread. 0 until something fails, see
+    /// aml_video_player_get_last_error_code
+    pub last_error_code: Arc<atomic::AtomicI32>,
+}
+
+impl Drop for FfiPlayer {
+    fn drop(&mut self) {
+        // already shut down, don't send anything to a disconnected channel
+        if self.main_thread.is_none() {
+            return;
+        }
+        let _r = self.shutdown();
+    }
 }
 
 impl FfiPlayer {
-    /// Join all 4 threads and return an error if one didn't return successfully
-    pub fn join(self) -> FfiResult {
+    /// Asks every thread to stop and waits for them
+    pub fn shutdown(&mut self) -> FfiResult {
+        // nobody waits for the reply, the threads are joined instead
+        let (tx, _rx) = single_use_channel::<Reply>();
+        self.send_message(Message::Shutdown, tx);
+        self.join()
+    }
+
+    /// Join all 4 threads (3 when headless) and return an error if one didn't return successfully
+    ///
+    /// Threads which were already joined are skipped.
+    pub fn join(&mut self) -> FfiResult {
         let mut error_code = Ok(());
-        if let Err(_) = self.main_thread.join() {
+        if let Some(Err(_)) = self.main_thread.take().map(|t| t.join()) {
             error_code = Err(FfiErrorCode::ShutdownError);
-            println!("Main Thread panicked");
+            error!("main_thread panicked");
         };
-        if let Err(_) = self.x11_event_loop_thread.join() {
+        if let Some(Err(_)) = self.window_event_loop_thread.take().map(|t| t.join()) {
             error_code = Err(FfiErrorCode::ShutdownError);
-            println!("X11 Event Thread panicked");
+            error!("the window thread panicked");
         };
-        if let Err(_) = self.amcodec_thread.join() {
+        if let Some(Err(_)) = self.amcodec_thread.take().map(|t| t.join()) {
             error_code = Err(FfiErrorCode::ShutdownError);
-            println!("Amcodec Thread panicked");
+            error!("amcodec_thread panicked");
         };
-        if let Err(_) = self.libav_getter_thread.join() {
+        if let Some(Err(_)) = self.libav_getter_thread.take().map(|t| t.join()) {
             error_code = Err(FfiErrorCode::ShutdownError);
-            println!("Libav Thread panicked");
+            error!("libav_thread panicked");
         };
         error_code
     }
 
-    pub fn send_message(&self, message: Message) -> bool {
-        match self.sender.send(message) {
+    /// The error code is sent to `reply` once the message is handled, see Message
+    pub fn send_message(&self, message: Message, reply: SuSender<Reply>) -> bool {
+        match self.sender.send(Request::new(message, reply)) {
             Ok(_) => true,
             Err(e) => {
-                println!("Receiving end of the channel disconnected: {}", e);
+                error!("receiving end of the channel disconnected: {}", e);
                 false
             }
         }
     }
 
+    /// None when the API calls wait forever, see reply_timeout_ms
+    pub fn reply_timeout(&self) -> Option<Duration> {
+        match self.reply_timeout_ms.load(atomic::Ordering::SeqCst) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    /// Same as wait_for_video_status, but doesn't block the calling thread
+    pub fn wait_async<'a>(&'a mut self) -> PlayerEofFuture<'a> {
+        PlayerEofFuture::new(&self.events)
+    }
+
     pub fn wait_for_video_status(&mut self) -> c_int {
-        match self.video_status_queue.recv() {
-            Ok(VideoEndReason::Error(s)) => {
-                println!("A fatal error happened when decoding a video packet: {}", s);
+        // the other events are left in the queue for aml_video_player_next_event
+        match self.events.wait_end() {
+            PlayerEvent::FatalError { code: FfiErrorCode::Disconnected, message } => {
+                warn!("video status channel disconnected: {}", message);
+                -1
+            },
+            PlayerEvent::FatalError { message, .. } => {
+                error!("a fatal error happened when playing the video: {}", message);
                 1
             },
-            Ok(VideoEndReason::EOF) => 0,
-            Err(e) => {
-                println!("Video status channel disconnected : {}", e);
-                -1
-            }
+            _ => 0,
         }
     }
 }
 
-/// all the messages possible which can be sent to the main_thread
+/// all the messages possible which can be sent to the main_thread, as the query of a
+/// Request<Message, Reply>: the error code goes to the reply of the request, and the queries
+/// which return something are given a second channel for their value
 /// notice that every single one of them has an equivalent in the API
 pub enum Message {
-    SetSize(SuSender<FfiErrorCode>, (u16, u16)),
-    SetPos(SuSender<FfiErrorCode>,(i16, i16)),
-    SetFullscreen(SuSender<FfiErrorCode>, bool),
-    Show(SuSender<FfiErrorCode>),
-    Hide(SuSender<FfiErrorCode>),
-    Play(SuSender<FfiErrorCode>),
-    Pause(SuSender<FfiErrorCode>),
-    Load(SuSender<FfiErrorCode>, String),
-    Seek(SuSender<FfiErrorCode>, f64),
+    SetSize((u32, u32)),
+    SetPos((i32, i32)),
+    /// SetPos and SetSize at once: (x, y, width, height)
+    SetRect((i32, i32, u32, u32)),
+    SetFullscreen(bool),
+    SetSticky(bool),
+    SetSkipTaskbar(bool),
+    GetScreenSize(SuSender<(u32, u32)>),
+    /// in seconds
+    GetPosition(SuSender<f64>),
+    Show,
+    Hide,
+    SetShowHideMode(ShowHideMode),
+    SetClickThrough(bool),
+    SetHideWhenObscured(bool),
+    /// false to keep SetPos and SetSize from putting the window out of the screen
+    SetAllowOffscreen(bool),
+    /// see aml_video_player_set_auto_fullscreen
+    SetAutoFullscreen(bool),
+    /// the most screens auto fullscreen still happens with
+    SetAutoFullscreenThreshold(u32),
+    /// (width, height) of the video, letterboxed in the window without resizing it. None to fill
+    /// the window again.
+    SetVideoAspect(Option<(u32, u32)>),
+    /// (num, den) of the pixels of every video, instead of the one of the stream. None goes back
+    /// to the stream's.
+    SetOverrideSar(Option<(u32, u32)>),
+    /// a SetRect where the video goes to the rectangle over the duration, see VideoAxis::Animated
+    AnimateToRect((i32, i32, u32, u32), Duration, Easing),
+    Play,
+    Pause,
+    Load(String),
+    Preload(String),
+    PlayPreloaded,
+    SetPlaylist(Playlist),
+    /// Seeks to `pos`, and drops the target of an async seek not forwarded yet
+    Seek(f64),
+    /// Forwards the target of aml_video_player_async_seek to libav, see AsyncSeek. Nothing is left
+    /// to forward if a Seek came first.
+    AsyncSeek,
+    /// cues of a SRT file, for the video currently loaded only
+    LoadSubtitles(Vec<Cue>),
+    /// (start, end) in seconds, None stops repeating
+    SetLoopRange(Option<(f64, f64)>),
+    GetLoopCount(SuSender<u32>),
+    /// Sent to the libav thread as-is, for everything the main thread doesn't have to know about.
+    /// The ones it does (see handled_by_main_thread) are refused.
+    Libav(LibavMessage),
+    /// Same as Libav, for the amcodec thread
+    Amcodec(AmcodecMessage),
     Shutdown
 }
 
+/// The messages of the worker threads which only the main thread may send, because it keeps
+/// track of what they do: they have a Message of their own instead.
+fn handled_by_main_thread(message: &Message) -> bool {
+    match *message {
+        Message::Libav(LibavMessage::Load(_)) | Message::Libav(LibavMessage::Preload(_))
+        | Message::Libav(LibavMessage::Seek(..)) | Message::Libav(LibavMessage::Pause)
+        | Message::Libav(LibavMessage::Shutdown)
+        | Message::Amcodec(AmcodecMessage::Play) | Message::Amcodec(AmcodecMessage::Pause)
+        | Message::Amcodec(AmcodecMessage::SetVideoVisible(_)) | Message::Amcodec(AmcodecMessage::Shutdown) => true,
+        _ => false,
+    }
+}
+
+/// A Load of the next entry of the playlist which the libav thread didn't answer yet: the main
+/// thread goes on meanwhile, opening a file can take a while.
+struct PlaylistLoad {
+    url: String,
+    reply: SuReceiver<Reply>,
+    /// how many entries were tried before this one, since the previous video ended
+    skipped: usize,
+}
+
+/// Asks the libav thread to load the next entry of the playlist, without waiting for it.
+///
+/// Entries which fail to load are skipped by calling this again, but we only try every entry
+/// once: if nothing in the playlist can be loaded, give up and return None.
+fn load_next_playlist_entry(playlist: &mut Playlist, libav_channel: &Sender<Request<LibavMessage, Reply>>, skipped: usize) -> Option<PlaylistLoad> {
+    if skipped >= playlist.entries.len() {
+        return None;
+    }
+    let url = match playlist.advance() {
+        Some(url) => url.to_owned(),
+        None => return None,
+    };
+    let (tx, rx) = single_use_channel::<Reply>();
+    match libav_channel.send(Request::new(LibavMessage::Load(url.clone()), tx)) {
+        Ok(()) => Some(PlaylistLoad { url: url, reply: rx, skipped: skipped }),
+        Err(_) => None,
+    }
+}
+
+/// Asks the libav thread to open the entry after the current one in the background, so that
+/// switching to it is instant. Returns the url being preloaded.
+fn preload_next_playlist_entry(playlist: &Playlist, libav_channel: &Sender<Request<LibavMessage, Reply>>) -> Option<String> {
+    let url = match playlist.next_url() {
+        Some(url) => url.to_owned(),
+        None => return None,
+    };
+    // the preload itself is done in the background, there is nothing to wait for
+    let (tx, _rx) = single_use_channel::<Reply>();
+    match libav_channel.send(Request::new(LibavMessage::Preload(url.clone()), tx)) {
+        Ok(()) => Some(url),
+        Err(_) => None,
+    }
+}
+
+/// A seek of the API user which libav didn't answer yet: the position and the subtitles only move
+/// to `pos` once libav accepted it, a refused seek leaves them where they were.
+struct SeekInFlight {
+    /// None once a wrap of the A-B repeat overtook it
+    pos: Option<f64>,
+    libav_reply: SuReceiver<Reply>,
+    reply: SuSender<Reply>,
+}
+
+/// Forwards the answers of libav to the API user, in the order the seeks were sent. This must be
+/// done before the position reports are handled: a FirstPacket following a seek would be undone
+/// otherwise.
+fn finish_seeks(in_flight: &mut VecDeque<SeekInFlight>, position: &mut PositionTracker, sidecar: &mut Option<SidecarSubtitles>) {
+    loop {
+        let reply = match in_flight.front() {
+            Some(seek) => match seek.libav_reply.try_recv() {
+                Ok(reply) => reply,
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => Reply::from(FfiErrorCode::Dead),
+            },
+            None => return,
+        };
+        let seek = in_flight.pop_front().unwrap();
+        match (seek.pos, reply.code) {
+            (Some(pos), FfiErrorCode::None) => {
+                position.seek(pos);
+                if let Some(ref mut sidecar) = *sidecar {
+                    sidecar.resync(pos);
+                }
+            },
+            (Some(pos), code) => debug!("the seek to {:.3}s was refused ({:?}), staying where we were", pos, code),
+            (None, _) => {},
+        }
+        seek.reply.send(reply);
+    }
+}
+
+/// Called before the API user loads a new video: the end of the previous one must not be taken
+/// for the end of the new one by wait_until_end, even if it is still on its way from amcodec.
+fn start_new_generation(status_rx: &Receiver<PlayerEvent>, events: &EventQueue) {
+    while let Ok(event) = status_rx.try_recv() {
+        events.push(event);
+    }
+    events.new_generation();
+}
+
+/// Stops the playback before the player is destroyed: libav stops sending packets first, then
+/// amcodec drops what it still has and clears the video layer. Otherwise the thread would exit in
+/// the middle of the playback, leaving a frozen frame on screen and a full buffer behind, and the
+/// next player could wait for seconds before opening the device.
+///
+/// A thread which doesn't answer in time (or which is dead already) is not waited for. libav may
+/// be blocked on the network, so abort_io is set first.
+fn stop_workers(libav_channel: &Sender<Request<LibavMessage, Reply>>,
+                amcodec_channel: &Sender<Request<AmcodecMessage, Reply>>,
+                abort_io: &atomic::AtomicBool) {
+    let timeout = Duration::from_millis(SHUTDOWN_ACK_TIMEOUT_MS);
+    abort_io.store(true, atomic::Ordering::SeqCst);
+    let (tx, rx) = single_use_channel::<Reply>();
+    if let Ok(()) = libav_channel.send(Request::new(LibavMessage::Shutdown, tx)) {
+        if let Err(_) = rx.recv_timeout(timeout) {
+            warn!("libav_thread didn't stop in time");
+        }
+    }
+    let (tx, rx) = single_use_channel::<Reply>();
+    if let Ok(()) = amcodec_channel.send(Request::new(AmcodecMessage::Shutdown, tx)) {
+        if let Err(_) = rx.recv_timeout(timeout) {
+            warn!("amcodec_thread didn't stop in time");
+        }
+    }
+}
+
+/// Returns the new position of a window (x, y, w, h), so that as much of it as possible is in
+/// the screen
+fn clamp_on_screen((x, y, w, h): (i32, i32, u32, u32), (screen_w, screen_h): (u32, u32)) -> (i32, i32) {
+    let clamp = |pos: i32, size: u32, screen_size: u32| -> i32 {
+        let max_pos = screen_size as i64 - size as i64;
+        let pos = pos as i64;
+        let pos = if pos > max_pos { max_pos } else { pos };
+        let pos = if pos < 0 { 0 } else { pos };
+        pos as i32
+    };
+    (clamp(x, w, screen_w), clamp(y, h, screen_h))
+}
+
+/// The resolution of the screen the video is shown on
+fn screen_size(window: &Option<Arc<Window>>) -> Result<(u32, u32)> {
+    match *window {
+        Some(ref window) => window.screen_size(),
+        None => amcodec::fb_screen_size(),
+    }
+}
+
+/// Number of screens, 1 when headless (there is only the framebuffer) or when the backend can't
+/// tell
+fn screen_count(window: &Option<Arc<Window>>) -> u32 {
+    match *window {
+        Some(ref window) => window.screen_count().unwrap_or_else(|e| {
+            debug!("assuming a single screen: {}", e.display());
+            1
+        }),
+        None => 1,
+    }
+}
+
+/// The largest rectangle of the aspect ratio (width, height) centered in (x, y, w, h), at least
+/// 1x1. Its position stops at i32::MAX, the rect comes from the API user.
+fn fit_aspect((x, y, w, h): (i32, i32, u32, u32), (aspect_w, aspect_h): (u32, u32)) -> (i32, i32, u32, u32) {
+    let (aspect_w, aspect_h) = (aspect_w as u64, aspect_h as u64);
+    let (video_w, video_h) = if w as u64 * aspect_h > h as u64 * aspect_w {
+        // wider than the video: bars on the sides
+        (h as u64 * aspect_w / aspect_h, h as u64)
+    } else {
+        // bars above and below
+        (w as u64, w as u64 * aspect_h / aspect_w)
+    };
+    // an extreme aspect ratio would leave nothing to show
+    let (video_w, video_h) = (::std::cmp::max(video_w, 1), ::std::cmp::max(video_h, 1));
+    let center = |pos: i32, size: u32, video_size: u64| -> i32 {
+        let pos = pos as i64 + (size as u64).saturating_sub(video_size) as i64 / 2;
+        if pos > i32::max_value() as i64 { i32::max_value() } else { pos as i32 }
+    };
+    (center(x, w, video_w), center(y, h, video_h), video_w as u32, video_h as u32)
+}
+
+/// The aspect an anamorphic video is letterboxed to: its size comes from amcodec once its first
+/// frame is shown (PositionReport::VideoSize), the shape of its pixels from libav when it is
+/// loaded (PositionReport::Stream) unless the API user overrides it. None until both are known.
+fn stream_aspect(video_size: Option<(u32, u32)>, stream_sar: Option<(u32, u32)>, sar_override: Option<(u32, u32)>) -> Option<(u32, u32)> {
+    match (video_size, sar_override.or(stream_sar)) {
+        (Some(size), Some(sar)) => anamorphic_aspect(size, sar),
+        _ => None,
+    }
+}
+
+/// The aspect ratio a video of (width, height) pixels of `sar` is meant to be shown at, None if
+/// its pixels are square: it then fills the window, as any other video
+fn anamorphic_aspect((width, height): (u32, u32), (sar_num, sar_den): (u32, u32)) -> Option<(u32, u32)> {
+    if sar_num == sar_den || sar_num == 0 || sar_den == 0 {
+        return None;
+    }
+    let (mut aspect_w, mut aspect_h) = (width as u64 * sar_num as u64, height as u64 * sar_den as u64);
+    // 1440x1080 of 4:3 is 16:9, and must fit in a u32 again
+    let (mut a, mut b) = (aspect_w, aspect_h);
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    aspect_w /= a;
+    aspect_h /= a;
+    while aspect_w > u32::max_value() as u64 || aspect_h > u32::max_value() as u64 {
+        aspect_w /= 2;
+        aspect_h /= 2;
+    }
+    Some((aspect_w.max(1) as u32, aspect_h.max(1) as u32))
+}
+
+/// Where the video is on the screen right now, None if it is unknown
+fn current_video_rect(axis: Option<VideoAxis>, window: &Option<Arc<Window>>) -> Option<(i32, i32, u32, u32)> {
+    match axis {
+        Some(VideoAxis::Rect(x, y, w, h)) => Some((x, y, w, h)),
+        // an animation going on is taken over from where it is
+        Some(VideoAxis::Animated(animation)) => Some(animation.at(Instant::now()).0),
+        Some(VideoAxis::Fullscreen) | None => screen_size(window).ok().map(|(w, h)| (0, 0, w, h)),
+    }
+}
+
+/// The video axis for the window at `rect`, or fullscreen if None. With an aspect ratio, the
+/// video is letterboxed in there instead of filling it: the one set by the API user, or else the
+/// one of an anamorphic video (see anamorphic_aspect).
+fn video_axis(rect: Option<(i32, i32, u32, u32)>, aspect: Option<(u32, u32)>, window: &Option<Arc<Window>>) -> VideoAxis {
+    let aspect = match aspect {
+        Some(aspect) => aspect,
+        None => return rect.map_or(VideoAxis::Fullscreen, |(x, y, w, h)| VideoAxis::Rect(x, y, w, h)),
+    };
+    let rect = match rect {
+        Some(rect) => rect,
+        None => match screen_size(window) {
+            Ok((screen_w, screen_h)) => (0, 0, screen_w, screen_h),
+            Err(e) => {
+                warn!("failed to get the screen size, the video fills it: {}", e.display());
+                return VideoAxis::Fullscreen;
+            },
+        },
+    };
+    let (x, y, w, h) = fit_aspect(rect, aspect);
+    VideoAxis::Rect(x, y, w, h)
+}
+
+/// video_axis with the aspect ratio set by the API user, or else the one of an anamorphic video
+fn compute_video_axis(rect: Option<(i32, i32, u32, u32)>, video_aspect: Option<(u32, u32)>, anamorphic: Option<(u32, u32)>, window: &Option<Arc<Window>>) -> VideoAxis {
+    video_axis(rect, video_aspect.or(anamorphic), window)
+}
+
+/// The rect video_axis is given for the window: None when it is fullscreen
+fn window_rect(fullscreen: bool, rect: (i32, i32, u32, u32)) -> Option<(i32, i32, u32, u32)> {
+    if fullscreen {
+        None
+    } else {
+        Some(rect)
+    }
+}
+
+/// Where a window (x, y, w, h) has to go so that it stays on the screen, unless `allow_offscreen`
+/// is set. The position is kept as is if the screen size can't be read.
+fn place_on_screen((x, y, w, h): (i32, i32, u32, u32), window: &Option<Arc<Window>>, allow_offscreen: bool) -> (i32, i32) {
+    if allow_offscreen {
+        return (x, y);
+    }
+    match screen_size(window) {
+        Ok(screen) => clamp_on_screen((x, y, w, h), screen),
+        Err(e) => {
+            warn!("failed to get the screen size, the window may be out of it: {}", e.display());
+            (x, y)
+        },
+    }
+}
+
 // when this is called, we are still in the thread of the user of the API
 // we will need to "detach" our core logic
 //
@@ -120,67 +512,130 @@ pub enum Message {
 // video hevc packets to the amcodec_thread
 // * amcodec_thread: receive messages from libav_thread and main_thread and process them (write
 // libavpacket in VPU, resize the VPU's output area, ...)
-// * x11_thread : handle the event loop
+// * window_thread : handle the event loop of the window (X11 or Wayland)
 // * main_thread: receive messages from the API and send messages to other threads accordingly
-pub fn player_start() -> Result<FfiPlayer> {
+/// The window backend is selected with AMPLAYER_WINDOW_BACKEND, see `WindowBackend::from_env`
+pub fn player_start(config: PlayerConfig) -> Result<FfiPlayer> {
+    start_with_backend(WindowBackend::from_env(), config)
+}
+
+/// Never creates a window, whatever AMPLAYER_WINDOW_BACKEND says: the video is still shown on
+/// the VPU's layer, for capture pipelines or devices without any display server
+pub fn player_start_offscreen() -> Result<FfiPlayer> {
+    start_with_backend(WindowBackend::Headless, PlayerConfig::default())
+}
+
+fn start_with_backend(backend: WindowBackend, config: PlayerConfig) -> Result<FfiPlayer> {
     let (version_major, version_minor) = avformat_version();
     // we are only checking the major version here, because breaking changes
     // only happen between major versions, hence even though the minor version changes,
     // we are still "safe" from unexpected behavior
-    if version_major != libavformat::LIBAVCODEC_VERSION_MAJOR as u16 {
-        println!("Linked avformat version ({}) differs from the one the header was built with ({}). \
-                This can lead to unexpected behavior and segfaults at times. \
-                Aborting", version_major, libavformat::LIBAVCODEC_VERSION_MAJOR);
-        bail!(ErrorKind::WrongLibavVersion);
-    } else {
-        println!("using libavformat version {}.{}", version_major, version_minor);
-    };
+    match Abi::detect() {
+        Ok(abi) => {
+            if version_major != libavformat::LIBAVFORMAT_VERSION_MAJOR as u16 {
+                info!("bindings were built for libavformat {}, using the layout of {} instead",
+                      libavformat::LIBAVFORMAT_VERSION_MAJOR, abi.major());
+            }
+            info!("using libavformat version {}.{}", version_major, version_minor);
+        },
+        Err(e) => {
+            error!("Linked avformat version ({}) is none of the supported ones (56 and 57). \
+                    This can lead to unexpected behavior and segfaults at times. \
+                    Aborting", version_major);
+            return Err(e);
+        }
+    }
 
-    // note that x11_thread doesn't receive messages like other threads: this is because the X11
-    // API is thread safe, and thus we can call multiple functions of the same window at once.
-    // channels allow us to have the guarentee that 1 message is processed at a time, but we don't
-    // really care in x11's case.
-    let x11_helper = Arc::new(X11Helper::new(ptr::null_mut())?);
-    if let Err(e) = x11_helper.set_borderless(true) {
-        println!("failed to set x11 window borderless: {}", e.display());
+    // note that window_thread doesn't receive messages like other threads: this is because both
+    // the X11 and Wayland APIs are thread safe, and thus we can call multiple functions of the
+    // same window at once. channels allow us to have the guarentee that 1 message is processed at
+    // a time, but we don't really care in the window's case.
+    //
+    // window is None when running headless: in this case there is no window_thread at all, and
+    // every window-related message only deals with the VPU's video layer
+    let window : Option<Arc<Window>> = window::create_window(backend, config.window_size)?;
+    if let Some(ref window) = window {
+        info!("using the {} window backend", window.backend_name());
+        if let Err(e) = window.set_borderless(true) {
+            warn!("failed to set {} window borderless: {}", window.backend_name(), e.display());
+        };
+    } else {
+        info!("running headless: no window will be created");
+    }
+    let display_path = match window {
+        Some(ref window) => window.display_path(),
+        None => DisplayPath::Headless,
     };
+    info!("display path: {:?}", display_path);
 
     // channel from the API to the main_thread
-    let (sender, receiver) = mpsc::channel::<Message>();
-    // channel from amcodec_thread to the API thread: send when an EOF is reached on the playback
-    // side
-    let (video_status_sender, video_status_rx) = mpsc::channel::<VideoEndReason>();
+    let (sender, receiver) = mpsc::channel::<Request<Message, Reply>>();
+    // events from the main_thread to the API thread, such as the end of the video
+    let events = Arc::new(EventQueue::new());
+    // events from amcodec_thread and libav_thread to the main_thread: the main thread decides
+    // whether the end of a video must be forwarded to the API thread or if the next entry of a
+    // playlist must be loaded
+    let (status_sender, status_rx) = mpsc::channel::<PlayerEvent>();
+    // both libav and amcodec tell the main thread what they know of the current position
+    let (position_sender, position_rx) = mpsc::channel::<PositionReport>();
 
     // shared boolean between every thread: when this becomes false every thread will stop as soon
     // as possible
     let keep_running = Arc::new(atomic::AtomicBool::new(true));
-    
-    let x11_thread = {
+    // set right before the shutdown, so that libav doesn't keep us waiting on the network
+    let abort_io = Arc::new(atomic::AtomicBool::new(false));
+    // see aml_video_player_get_stats
+    let stats = Arc::new(Stats::new());
+    // see aml_video_player_async_seek
+    let async_seek = Arc::new(AsyncSeek::new());
+    let last_error_code = Arc::new(atomic::AtomicI32::new(FfiErrorCode::None as i32));
+    // see FfiPlayer::audio
+    let audio = Arc::new(Mutex::new(audio::AudioOutput::new()));
+    // what the main thread changes too often to go through amcodec's channel, see settings.rs
+    let video_settings = Arc::new(VideoSettings::new());
+
+    // channel from the window_thread to the main_thread, for events such as resolution changes
+    let (window_event_sender, window_event_rx) = mpsc::channel::<WindowEvent>();
+
+    let window_thread = window.as_ref().map(|window| {
         // thread needs to "move" the caught variables in its closure, hence we need to clone these
         // so the clones can get moved, otherwise we get a compile error saying we already used
-        // x11_helper (moved in this thread)
-        let x11_helper = x11_helper.clone();
+        // window (moved in this thread)
+        let window = window.clone();
         let keep_running = keep_running.clone();
-        thread::spawn(move || {
-            x11_helper.event_loop(keep_running);
-        })
-    };
+        let window_event_sender = window_event_sender.clone();
+        thread::Builder::new().name(format!("{}_thread", window.backend_name())).spawn(move || {
+            window.event_loop(keep_running, window_event_sender);
+        }).expect("failed to spawn thread")
+    });
 
     // channel between libav_thread and amcodec_thread, which is meant for libav to send packets to
     // amcodec
     let (packet_sender, packet_receiver) = mpsc::channel::<LibavPacket>();
+    // and the other way around, for hints like "the VPU is running out of data"
+    let (feedback_sender, feedback_receiver) = mpsc::channel::<LibavPacket>();
    
     // channel beetween main_thread and libav_thread, where messages such as Load("url") are sent
-    let (libav_sender, libav_receiver) = mpsc::channel::<(LibavMessage, SuSender<FfiErrorCode>)>();
+    let (libav_sender, libav_receiver) = mpsc::channel::<Request<LibavMessage, Reply>>();
 
-    // channel between main_thread and amcodec_thread, where messages such as "SetSize(x,y,w,h)"
-    // are sent to amcodec_thread
-    let (amcodec_sender, amcodec_receiver) = mpsc::channel::<(AmcodecMessage, SuSender<FfiErrorCode>)>();
+    // channel between main_thread and amcodec_thread, where messages such as "Play" are sent to
+    // amcodec_thread
+    let (amcodec_sender, amcodec_receiver) = mpsc::channel::<Request<AmcodecMessage, Reply>>();
+
+    // channel from the worker threads (libav and amcodec) to the main_thread, telling which one
+    // died and why
+    let (worker_death_sender, worker_death_rx) = mpsc::channel::<(&'static str, String)>();
 
     let libav_thread = {
         let keep_running = keep_running.clone();
-        thread::spawn(move || {
-            libav_main_thread(libav_receiver, packet_sender, keep_running);
+        let position_sender = position_sender.clone();
+        let status_sender = status_sender.clone();
+        let abort_io = abort_io.clone();
+        let stats = stats.clone();
+        let config = config.clone();
+        let async_seek = async_seek.clone();
+        spawn_supervised("libav_thread", worker_death_sender.clone(), keep_running.clone(), move || {
+            libav_main_thread(libav_receiver, packet_sender, feedback_receiver, position_sender, status_sender, stats, config, async_seek, abort_io, keep_running);
         })
     };
 
@@ -189,122 +644,1079 @@ pub fn player_start() -> Result<FfiPlayer> {
         // _fb_wrapper is not used but is the thing that allow us to have a transparent framebuffer
         // as long as it lives we can set some alpha of the framebuffer to 0
         let _fb_wrapper = amcodec::FbWrapper::new()?;
+        // same thing for the display mode, which may be changed by SetVideoOutput
+        let _display_mode_wrapper = amcodec::DisplayModeWrapper::new();
         // we are doing this initialization here instead of in the thread because we can then
         // return an error directly if something went wrong (if this went wrong there is no point
         // in doing anything else)
-        let amcodec = amcodec::Amcodec::new(video_status_sender.clone())?;
+        let mut amcodec = amcodec::Amcodec::new()?;
+        amcodec.finishing_threshold = config.finishing_threshold;
+        amcodec.blackout_mode = config.blackout_mode;
         let version = amcodec.version()?;
-        println!("amcodec_thread: AMSTREAM version {}.{}", version.0, version.1);
-        thread::spawn(move || {
+        info!("AMSTREAM version {}.{}", version.0, version.1);
+        let stats = stats.clone();
+        let video_settings = video_settings.clone();
+        let audio = audio.clone();
+        spawn_supervised("amcodec_thread", worker_death_sender, keep_running.clone(), move || {
             // move fb_wrapper inside the thread so that it is only destroyed after the thread is
             // complete
             let _fb_wrapper = _fb_wrapper;
-            amcodec_main_loop(amcodec, amcodec_receiver, packet_receiver, video_status_sender, keep_running);
+            let _display_mode_wrapper = _display_mode_wrapper;
+            amcodec_main_loop(amcodec, amcodec_receiver, packet_receiver, feedback_sender, status_sender, position_sender, stats, video_settings, audio, keep_running);
         })
     };
 
     let main_thread = {
         // keep track of the current window's dimensions
-        let (mut window_x, mut window_y, mut window_w, mut window_h) = (0i16, 0i16, 1920u16, 1080u16);
+        let (mut window_x, mut window_y, mut window_w, mut window_h) = (0i32, 0i32, 1920u32, 1080u32);
+        let mut fullscreen = false;
+        // when enabled, the video layer is disabled while the window is entirely covered
+        let mut hide_when_obscured = false;
+        let mut obscured = false;
+        // when enabled, SetPos and SetSize may put the window out of the screen, for multi-head
+        // setups whose other screens the VPU doesn't know about
+        let mut allow_offscreen = false;
+        // when enabled, every video goes fullscreen once its first frame is shown, as long as
+        // there are at most auto_fullscreen_max_screens screens
+        let mut auto_fullscreen = false;
+        let mut auto_fullscreen_max_screens = 1u32;
+        // every seek sent to libav gets the next generation, SeekCompleted is only sent for the
+        // one in pending_seek
+        let mut seek_generation = 0u64;
+        // see SetVideoAspect, the window keeps window_w and window_h
+        let mut video_aspect : Option<(u32, u32)> = None;
+        // the size and sample aspect ratio of the video playing, as amcodec and libav report them,
+        // and the sample aspect ratio the API user forces instead. The video is letterboxed to
+        // `anamorphic` when there is no video_aspect, see stream_aspect.
+        let mut video_size : Option<(u32, u32)> = None;
+        let mut stream_sar : Option<(u32, u32)> = None;
+        let mut sar_override : Option<(u32, u32)> = None;
+        let mut anamorphic : Option<(u32, u32)> = None;
+        let mut pending_seek : Option<u64> = None;
+        let mut seeks_in_flight : VecDeque<SeekInFlight> = VecDeque::new();
         let keep_running = keep_running.clone();
-        thread::spawn(move || {
+        let events = events.clone();
+        let async_seek = async_seek.clone();
+        let last_error_code = last_error_code.clone();
+        thread::Builder::new().name(String::from("main_thread")).spawn(move || {
             let libav_channel = libav_sender;
             let amcodec_channel = amcodec_sender;
-            'mainloop: for message in receiver.iter() {
+            // the playlist currently being played, if any. A plain Load discards it.
+            let mut playlist : Option<Playlist> = None;
+            // the entry of the playlist being loaded after the previous one ended
+            let mut playlist_load : Option<PlaylistLoad> = None;
+            let mut position = PositionTracker::new();
+            // subtitles loaded from a file, for the video currently playing
+            let mut sidecar : Option<SidecarSubtitles> = None;
+            // the A-B repeat of the video currently playing, and how many times it wrapped
+            let mut loop_range : Option<(f64, f64)> = None;
+            let mut loop_count : u32 = 0;
+            // as last asked by the API user: a paused video must not wrap before it plays again
+            let mut paused = false;
+            // url the libav thread is currently preloading, if any
+            let mut preloaded_url : Option<String> = None;
+            // once a worker thread is dead, the player is broken for good: every command is
+            // answered with Dead, and only Shutdown still does something
+            let mut broken : Option<String> = None;
+            let mut broken_reported = false;
+            'mainloop: loop {
+                if let Ok((thread_name, reason)) = worker_death_rx.try_recv() {
+                    broken = broken.or(Some(format!("{} {}", thread_name, reason)));
+                }
+                if !broken_reported {
+                    if let Some(ref reason) = broken {
+                        error!("fatal: {}, the player must be destroyed", reason);
+                        last_error_code.store(FfiErrorCode::Dead as i32, atomic::Ordering::SeqCst);
+                        // wakes up anyone waiting for the end of the video with the reason
+                        events.push(PlayerEvent::FatalError {
+                            code: FfiErrorCode::Dead,
+                            message: reason.clone(),
+                        });
+                        broken_reported = true;
+                    }
+                }
+                finish_seeks(&mut seeks_in_flight, &mut position, &mut sidecar);
+                let load_reply = match playlist_load {
+                    Some(ref load) => match load.reply.try_recv() {
+                        Ok(reply) => Some(reply),
+                        Err(TryRecvError::Empty) => None,
+                        Err(TryRecvError::Disconnected) => Some(Reply::from(FfiErrorCode::Dead)),
+                    },
+                    None => None,
+                };
+                if let Some(reply) = load_reply {
+                    let load = playlist_load.take().unwrap();
+                    let failure = match reply {
+                        Reply { code: FfiErrorCode::None, .. } => None,
+                        Reply { report: Some(report), .. } => Some(report.to_string()),
+                        Reply { code, .. } => Some(format!("{:?}", code)),
+                    };
+                    let mut gave_up = false;
+                    if let Some(ref mut playlist) = playlist {
+                        match failure {
+                            None => {
+                                if let Some(url) = preload_next_playlist_entry(playlist, &libav_channel) {
+                                    preloaded_url = Some(url);
+                                }
+                            },
+                            Some(reason) => {
+                                warn!("playlist entry `{}` failed to load ({}), skipping it", load.url, reason);
+                                playlist_load = load_next_playlist_entry(playlist, &libav_channel, load.skipped + 1);
+                                gave_up = playlist_load.is_none();
+                            },
+                        }
+                    }
+                    // nothing in the playlist can be loaded
+                    if gave_up {
+                        playlist = None;
+                        events.push(PlayerEvent::EndOfStream);
+                    }
+                }
+                while let Ok(report) = position_rx.try_recv() {
+                    // libav answers a seek before it sends anything about it
+                    finish_seeks(&mut seeks_in_flight, &mut position, &mut sidecar);
+                    match report {
+                        PositionReport::SeekShown(generation) => {
+                            // the seeks overtaken by another one aren't reported
+                            if pending_seek == Some(generation) {
+                                pending_seek = None;
+                                if let Some(pos) = position.position() {
+                                    events.push(PlayerEvent::SeekCompleted { position_ms: (pos * 1000.0) as i64 });
+                                }
+                            }
+                        },
+                        PositionReport::VideoSize(width, height) => video_size = Some((width, height)),
+                        report => {
+                            if let PositionReport::Stream(_, _, sar) = report {
+                                stream_sar = Some(sar);
+                            }
+                            position.update(report);
+                        },
+                    }
+                }
+                let aspect = stream_aspect(video_size, stream_sar, sar_override);
+                if aspect != anamorphic {
+                    debug!("video of {:?} (sample aspect ratio {:?}) shown at {:?}", video_size, sar_override.or(stream_sar), aspect);
+                    anamorphic = aspect;
+                    video_settings.axis.set(compute_video_axis(window_rect(fullscreen, (window_x, window_y, window_w, window_h)), video_aspect, anamorphic, &window));
+                }
+                if let (&mut Some(ref mut sidecar), Some(pos)) = (&mut sidecar, position.position()) {
+                    for cue in sidecar.poll(pos) {
+                        events.push(PlayerEvent::Subtitle {
+                            start_ms: cue.start_ms,
+                            end_ms: cue.end_ms,
+                            text: cue.text,
+                        });
+                    }
+                }
+                if let (Some((start, end)), Some(pos)) = (loop_range, position.position()) {
+                    if !paused && pos >= end {
+                        loop_count += 1;
+                        debug!("reached the end of the loop at {:.3}s, going back to {:.3}s ({} times)", pos, start, loop_count);
+                        // the seek target is the position until the VPU reports again, so this
+                        // doesn't wrap twice
+                        position.seek(start);
+                        if let Some(ref mut sidecar) = sidecar {
+                            sidecar.resync(start);
+                        }
+                        // nobody waits for this one, and it overtakes any seek of the API user
+                        seek_generation += 1;
+                        pending_seek = None;
+                        for seek in seeks_in_flight.iter_mut() {
+                            seek.pos = None;
+                        }
+                        let (tx, _rx) = single_use_channel::<Reply>();
+                        if let Err(_) = libav_channel.send(Request::new(LibavMessage::Seek(start, seek_generation), tx)) {
+                            broken = broken.or(Some(String::from("libav_thread is not running anymore")));
+                            continue 'mainloop;
+                        };
+                        events.push(PlayerEvent::LoopWrapped { count: loop_count });
+                    }
+                }
+                match status_rx.try_recv() {
+                    Ok(PlayerEvent::EndOfStream) => {
+                        // the next entry is preloaded once this one is loaded
+                        playlist_load = match playlist {
+                            Some(ref mut playlist) => {
+                                position.reset();
+                                sidecar = None;
+                                loop_range = None;
+                                load_next_playlist_entry(playlist, &libav_channel, 0)
+                            },
+                            None => None,
+                        };
+                        if playlist_load.is_none() {
+                            playlist = None;
+                            events.push(PlayerEvent::EndOfStream);
+                        }
+                    },
+                    Ok(event) => {
+                        if event.is_end() {
+                            playlist = None;
+                            playlist_load = None;
+                        }
+                        if let PlayerEvent::FirstFrameDisplayed = event {
+                            if auto_fullscreen && !fullscreen {
+                                let count = screen_count(&window);
+                                if count <= auto_fullscreen_max_screens {
+                                    info!("{} screen(s) found, going fullscreen", count);
+                                    fullscreen = true;
+                                    video_settings.axis.set(compute_video_axis(None, video_aspect, anamorphic, &window));
+                                    if let Some(ref window) = window {
+                                        if let Err(e) = window.set_fullscreen(true) {
+                                            error!("failed to set {} window fullscreen: {}", window.backend_name(), e.display());
+                                        };
+                                    }
+                                }
+                            }
+                        }
+                        if let Some(code) = event.error_code() {
+                            last_error_code.store(code as i32, atomic::Ordering::SeqCst);
+                        }
+                        events.push(event);
+                    },
+                    Err(TryRecvError::Disconnected) | Err(TryRecvError::Empty) => {},
+                };
+                match window_event_rx.try_recv() {
+                    Ok(WindowEvent::ScreenResized(screen_w, screen_h)) => {
+                        if fullscreen {
+                            // the VPU's axis is still the one of the old resolution
+                            video_settings.axis.set(compute_video_axis(None, video_aspect, anamorphic, &window));
+                            if let Some(ref window) = window {
+                                if let Err(e) = window.set_fullscreen(true) {
+                                    error!("failed to set {} window fullscreen: {}", window.backend_name(), e.display());
+                                };
+                            }
+                        } else if !allow_offscreen {
+                            // bring the window back on screen if it's not anymore
+                            let (new_x, new_y) = clamp_on_screen((window_x, window_y, window_w, window_h), (screen_w, screen_h));
+                            if (new_x, new_y) != (window_x, window_y) {
+                                window_x = new_x;
+                                window_y = new_y;
+                                video_settings.axis.set(compute_video_axis(Some((window_x, window_y, window_w, window_h)), video_aspect, anamorphic, &window));
+                                if let Some(ref window) = window {
+                                    window.set_pos(window_x, window_y);
+                                }
+                            }
+                        }
+                    },
+                    Ok(WindowEvent::Obscured(now_obscured)) => {
+                        if hide_when_obscured && now_obscured != obscured {
+                            let (tx, _rx) = single_use_channel::<Reply>();
+                            let _r = amcodec_channel.send(Request::new(AmcodecMessage::SetVideoVisible(!now_obscured), tx));
+                        }
+                        obscured = now_obscured;
+                    },
+                    Ok(WindowEvent::CloseRequested) => {
+                        // up to the API user to decide what to do with it
+                        events.push(PlayerEvent::WindowCloseRequested);
+                    },
+                    Err(TryRecvError::Disconnected) | Err(TryRecvError::Empty) => {},
+                };
+                // the timeout allows us to check for amcodec_thread's notifications once in a while
+                let Request { id, query: message, reply: tx } = match receiver.recv_timeout(Duration::from_millis(10)) {
+                    Ok(request) => request,
+                    Err(RecvTimeoutError::Timeout) => continue 'mainloop,
+                    Err(RecvTimeoutError::Disconnected) => break 'mainloop,
+                };
+                if let Message::Shutdown = message {
+                    break 'mainloop;
+                }
+                if let Some(ref reason) = broken {
+                    debug!("request #{} refused, {}", id, reason);
+                    tx.send(FfiErrorCode::Dead);
+                    continue 'mainloop;
+                }
+                if handled_by_main_thread(&message) {
+                    error!("request #{} can't be forwarded as-is, it has a Message of its own", id);
+                    tx.send(FfiErrorCode::InvalidCommand);
+                    continue 'mainloop;
+                }
                 match message {
                     Message::Shutdown => {
                         break 'mainloop;
                     },
-                    Message::SetFullscreen(tx, b) => {
+                    Message::SetFullscreen(b) => {
+                        fullscreen = b;
                         if b == true {
-                            if let Err(_) = amcodec_channel.send((AmcodecMessage::Fullscreen, tx.clone())) {
-                                println!("main_thread: amcodec_channel disconnected, aborting");
-                                tx.send(FfiErrorCode::Disconnected);
-                                break 'mainloop;
-                            }
+                            video_settings.axis.set(compute_video_axis(None, video_aspect, anamorphic, &window));
                         } else {
-                            if let Err(_) = amcodec_channel.send((AmcodecMessage::Resize(window_x, window_y, window_w, window_h), tx.clone())) {
-                                println!("main_thread: amcodec_channel disconnected, aborting");
-                                tx.send(FfiErrorCode::Disconnected);
-                                break 'mainloop;
-                            }
+                            video_settings.axis.set(compute_video_axis(Some((window_x, window_y, window_w, window_h)), video_aspect, anamorphic, &window));
+                        }
+                        tx.send(FfiErrorCode::None);
+                        if let Some(ref window) = window {
+                            if let Err(e) = window.set_fullscreen(b) {
+                                error!("failed to set {} window fullscreen: {}", window.backend_name(), e.display());
+                            };
                         }
-                        if let Err(e) = x11_helper.set_fullscreen(b) {
-                            println!("main_thread: failed to set x11 window fullscreen: {}", e.display());
+                    },
+                    Message::SetSticky(sticky) => {
+                        // there are no virtual desktops without a window manager
+                        match window {
+                            Some(ref window) => tx.respond_with("making the window sticky", || window.set_sticky(sticky)),
+                            None => tx.send(FfiErrorCode::None),
+                        };
+                    },
+                    Message::SetSkipTaskbar(skip) => {
+                        // no window, no taskbar entry either
+                        match window {
+                            Some(ref window) => tx.respond_with("changing whether the window is in the taskbar", || window.set_skip_taskbar(skip)),
+                            None => tx.send(FfiErrorCode::None),
+                        };
+                    },
+                    Message::GetPosition(pos_tx) => {
+                        match position.position() {
+                            Some(secs) => {
+                                pos_tx.send(secs);
+                                tx.send(FfiErrorCode::None);
+                            },
+                            // nothing is loaded
+                            None => tx.send(FfiErrorCode::InvalidCommand),
+                        };
+                    },
+                    Message::GetScreenSize(size_tx) => {
+                        tx.respond_value_with(Some(size_tx), "getting the screen size", || screen_size(&window));
+                    },
+                    Message::Show => {
+                        if let Some(ref window) = window {
+                            window.show();
+                            tx.send(FfiErrorCode::None);
+                        } else if let Err(_) = amcodec_channel.send(Request::new(AmcodecMessage::SetVideoVisible(true), tx.clone())) {
+                            tx.send(FfiErrorCode::Dead);
+                            broken = broken.or(Some(String::from("amcodec_thread is not running anymore")));
+                            continue 'mainloop;
+                        }
+                    },
+                    Message::Hide => {
+                        if let Some(ref window) = window {
+                            window.hide();
+                            tx.send(FfiErrorCode::None);
+                        } else if let Err(_) = amcodec_channel.send(Request::new(AmcodecMessage::SetVideoVisible(false), tx.clone())) {
+                            tx.send(FfiErrorCode::Dead);
+                            broken = broken.or(Some(String::from("amcodec_thread is not running anymore")));
+                            continue 'mainloop;
+                        }
+                    },
+                    Message::SetShowHideMode(mode) => {
+                        // when headless, the video layer is simply enabled or disabled
+                        if let Some(ref window) = window {
+                            window.set_show_hide_mode(mode);
+                        }
+                        tx.send(FfiErrorCode::None);
+                    },
+                    Message::SetClickThrough(enabled) => {
+                        // without a window there's nothing to catch pointer events anyway
+                        match window {
+                            Some(ref window) => tx.respond_with("setting click-through", || window.set_click_through(enabled)),
+                            None => tx.send(FfiErrorCode::None),
                         };
                     },
-                    Message::Show(tx) => {
-                        x11_helper.show();
+                    Message::SetHideWhenObscured(enabled) => {
+                        // the video layer must be put back in the state the window is in
+                        if obscured && enabled != hide_when_obscured {
+                            if let Err(_) = amcodec_channel.send(Request::new(AmcodecMessage::SetVideoVisible(!enabled), tx.clone())) {
+                                tx.send(FfiErrorCode::Dead);
+                                broken = broken.or(Some(String::from("amcodec_thread is not running anymore")));
+                                continue 'mainloop;
+                            }
+                        } else {
+                            tx.send(FfiErrorCode::None);
+                        }
+                        hide_when_obscured = enabled;
+                    },
+                    Message::SetVideoAspect(aspect) => {
+                        video_aspect = aspect;
+                        video_settings.axis.set(compute_video_axis(window_rect(fullscreen, (window_x, window_y, window_w, window_h)), video_aspect, anamorphic, &window));
+                        tx.send(FfiErrorCode::None);
+                    },
+                    Message::SetOverrideSar(sar) => {
+                        sar_override = sar;
+                        anamorphic = stream_aspect(video_size, stream_sar, sar_override);
+                        video_settings.axis.set(compute_video_axis(window_rect(fullscreen, (window_x, window_y, window_w, window_h)), video_aspect, anamorphic, &window));
+                        tx.send(FfiErrorCode::None);
+                    },
+                    Message::SetAllowOffscreen(enabled) => {
+                        allow_offscreen = enabled;
                         tx.send(FfiErrorCode::None);
                     },
-                    Message::Hide(tx) => {
-                        x11_helper.hide();
+                    Message::SetAutoFullscreen(enabled) => {
+                        auto_fullscreen = enabled;
                         tx.send(FfiErrorCode::None);
                     },
-                    Message::SetPos(tx,(x, y)) => {
-                        // when setting a position we must set the position of the X11 window as
+                    Message::SetAutoFullscreenThreshold(max_screens) => {
+                        auto_fullscreen_max_screens = max_screens;
+                        tx.send(FfiErrorCode::None);
+                    },
+                    Message::SetPos((x, y)) => {
+                        // when setting a position we must set the position of the window as
                         // well as the position of the VPU's output video
+                        let (x, y) = place_on_screen((x, y, window_w, window_h), &window, allow_offscreen);
+                        window_x = x;
+                        window_y = y;
+                        video_settings.axis.set(compute_video_axis(Some((window_x, window_y, window_w, window_h)), video_aspect, anamorphic, &window));
+                        tx.send(FfiErrorCode::None);
+                        if let Some(ref window) = window {
+                            window.set_pos(x, y);
+                        }
+                    },
+                    Message::SetSize((w, h)) => {
+                        if w == 0 || h == 0 {
+                            warn!("refusing to resize the video to {}x{}", w, h);
+                            tx.send(FfiErrorCode::InvalidCommand);
+                            continue 'mainloop;
+                        }
+                        // a bigger window may not fit where it is anymore
+                        let (x, y) = place_on_screen((window_x, window_y, w, h), &window, allow_offscreen);
+                        let moved = (x, y) != (window_x, window_y);
                         window_x = x;
                         window_y = y;
-                        if let Err(_) = amcodec_channel.send((AmcodecMessage::Resize(window_x, window_y, window_w, window_h), tx.clone())) {
-                            println!("main_thread: amcodec_channel disconnected, aborting");
-                            tx.send(FfiErrorCode::Disconnected);
-                            break 'mainloop;
+                        window_w = w;
+                        window_h = h;
+                        video_settings.axis.set(compute_video_axis(Some((window_x, window_y, window_w, window_h)), video_aspect, anamorphic, &window));
+                        if let Some(ref window) = window {
+                            if moved {
+                                window.set_pos(x, y);
+                            }
+                            window.set_size(w, h);
                         }
-                        x11_helper.set_pos(x, y);
+                        tx.send(FfiErrorCode::None);
                     },
-                    Message::SetSize(tx,(w, h)) => {
+                    Message::SetRect((x, y, w, h)) => {
+                        // not clamped: this is how the edges of the video are pushed out of the
+                        // screen to compensate for overscan
+                        if w == 0 || h == 0 {
+                            warn!("refusing to set the video rectangle to {}x{}", w, h);
+                            tx.send(FfiErrorCode::InvalidCommand);
+                            continue 'mainloop;
+                        }
+                        window_x = x;
+                        window_y = y;
                         window_w = w;
                         window_h = h;
-                        if let Err(_) = amcodec_channel.send((AmcodecMessage::Resize(window_x, window_y, window_w, window_h), tx.clone())) {
-                            println!("main_thread: amcodec_channel disconnected, aborting");
-                            tx.send(FfiErrorCode::Disconnected);
-                            break 'mainloop;
+                        video_settings.axis.set(compute_video_axis(Some((window_x, window_y, window_w, window_h)), video_aspect, anamorphic, &window));
+                        tx.send(FfiErrorCode::None);
+                        if let Some(ref window) = window {
+                            window.set_pos(x, y);
+                            window.set_size(w, h);
+                        }
+                    },
+                    Message::AnimateToRect((x, y, w, h), duration, easing) => {
+                        if w == 0 || h == 0 {
+                            warn!("refusing to animate the video to {}x{}", w, h);
+                            tx.send(FfiErrorCode::InvalidCommand);
+                            continue 'mainloop;
                         }
-                        x11_helper.set_size(w, h);
+                        let from = current_video_rect(video_settings.axis.get(), &window);
+                        window_x = x;
+                        window_y = y;
+                        window_w = w;
+                        window_h = h;
+                        let to = compute_video_axis(Some((window_x, window_y, window_w, window_h)), video_aspect, anamorphic, &window);
+                        let axis = match (from, to) {
+                            (Some(from), VideoAxis::Rect(x, y, w, h)) if duration > Duration::new(0, 0) => {
+                                VideoAxis::Animated(Animation {
+                                    from: from,
+                                    to: (x, y, w, h),
+                                    start: Instant::now(),
+                                    duration: duration,
+                                    easing: easing,
+                                })
+                            },
+                            // nothing to animate from, or no time to: same as SetRect
+                            (_, to) => to,
+                        };
+                        video_settings.axis.set(axis);
                         tx.send(FfiErrorCode::None);
+                        // the window, which only has to be transparent where the video ends up,
+                        // goes there right away
+                        if let Some(ref window) = window {
+                            if fullscreen {
+                                if let Err(e) = window.set_fullscreen(false) {
+                                    error!("failed to take the {} window out of fullscreen: {}", window.backend_name(), e.display());
+                                };
+                            }
+                            window.set_pos(x, y);
+                            window.set_size(w, h);
+                        }
+                        fullscreen = false;
+                    },
+                    Message::Load(url) => {
+                        playlist = None;
+                        playlist_load = None;
+                        position.reset();
+                        sidecar = None;
+                        loop_range = None;
+                        start_new_generation(&status_rx, &events);
+                        // libav uses the preloaded context if this is the same url
+                        if preloaded_url.as_ref() == Some(&url) {
+                            preloaded_url = None;
+                        }
+                        if let Err(_) = libav_channel.send(Request::new(LibavMessage::Load(url), tx.clone())) {
+                            tx.send(FfiErrorCode::Dead);
+                            broken = broken.or(Some(String::from("libav_thread is not running anymore")));
+                        };
+                    },
+                    Message::Preload(url) => {
+                        preloaded_url = Some(url.clone());
+                        if let Err(_) = libav_channel.send(Request::new(LibavMessage::Preload(url), tx.clone())) {
+                            tx.send(FfiErrorCode::Dead);
+                            broken = broken.or(Some(String::from("libav_thread is not running anymore")));
+                        };
+                    },
+                    Message::PlayPreloaded => {
+                        let url = match preloaded_url.take() {
+                            Some(url) => url,
+                            None => {
+                                tx.send(FfiErrorCode::InvalidCommand);
+                                continue 'mainloop;
+                            }
+                        };
+                        playlist = None;
+                        playlist_load = None;
+                        position.reset();
+                        sidecar = None;
+                        loop_range = None;
+                        start_new_generation(&status_rx, &events);
+                        if let Err(_) = libav_channel.send(Request::new(LibavMessage::Load(url), tx.clone())) {
+                            tx.send(FfiErrorCode::Dead);
+                            broken = broken.or(Some(String::from("libav_thread is not running anymore")));
+                        };
                     },
-                    Message::Load(tx,url) => {
-                        if let Err(_) = libav_channel.send((LibavMessage::Load(url), tx.clone())) {
-                            tx.send(FfiErrorCode::LibAvDisconnected);
+                    Message::SetPlaylist(new_playlist) => {
+                        let url = match new_playlist.current_url() {
+                            Some(url) => url.to_owned(),
+                            None => {
+                                tx.send(FfiErrorCode::InvalidPlaylist);
+                                continue 'mainloop;
+                            }
+                        };
+                        playlist_load = None;
+                        position.reset();
+                        sidecar = None;
+                        loop_range = None;
+                        start_new_generation(&status_rx, &events);
+                        if let Err(_) = libav_channel.send(Request::new(LibavMessage::Load(url), tx.clone())) {
+                            tx.send(FfiErrorCode::Dead);
+                            broken = broken.or(Some(String::from("libav_thread is not running anymore")));
+                            continue 'mainloop;
+                        };
+                        if let Some(url) = preload_next_playlist_entry(&new_playlist, &libav_channel) {
+                            preloaded_url = Some(url);
+                        }
+                        playlist = Some(new_playlist);
+                    },
+                    Message::Seek(_) | Message::AsyncSeek => {
+                        let pos = match message {
+                            // the async seek requested before this one must not take it over
+                            Message::Seek(pos) => {
+                                async_seek.take();
+                                pos
+                            },
+                            _ => match async_seek.take() {
+                                Some(pos) => pos,
+                                None => {
+                                    // a Seek went first, its libav completes the async seek
+                                    tx.send(FfiErrorCode::None);
+                                    continue 'mainloop;
+                                },
+                            },
+                        };
+                        seek_generation += 1;
+                        pending_seek = Some(seek_generation);
+                        let (libav_tx, libav_rx) = single_use_channel::<Reply>();
+                        if let Err(_) = libav_channel.send(Request::new(LibavMessage::Seek(pos, seek_generation), libav_tx)) {
+                            tx.send(FfiErrorCode::Dead);
+                            broken = broken.or(Some(String::from("libav_thread is not running anymore")));
+                            continue 'mainloop;
                         };
+                        seeks_in_flight.push_back(SeekInFlight {
+                            pos: Some(pos),
+                            libav_reply: libav_rx,
+                            reply: tx,
+                        });
                     },
-                    Message::Seek(tx, pos) => {
-                        if let Err(_) = libav_channel.send((LibavMessage::Seek(pos), tx.clone())) {
-                            tx.send(FfiErrorCode::LibAvDisconnected);
+                    Message::LoadSubtitles(cues) => {
+                        match position.position() {
+                            Some(pos) => {
+                                let mut subtitles = SidecarSubtitles::new(cues);
+                                subtitles.resync(pos);
+                                sidecar = Some(subtitles);
+                                tx.send(FfiErrorCode::None);
+                            },
+                            // they would be cleared by the next Load anyway
+                            None => tx.send(FfiErrorCode::InvalidCommand),
+                        }
+                    },
+                    Message::SetLoopRange(range) => {
+                        // same as the subtitles, the range belongs to the video loaded
+                        if range.is_some() && position.position().is_none() {
+                            tx.send(FfiErrorCode::InvalidCommand);
+                            continue 'mainloop;
+                        }
+                        // cleared, the video simply plays on past the end of the range
+                        if range.is_some() {
+                            loop_count = 0;
+                        }
+                        loop_range = range;
+                        tx.send(FfiErrorCode::None);
+                    },
+                    Message::GetLoopCount(count_tx) => {
+                        count_tx.send(loop_count);
+                        tx.send(FfiErrorCode::None);
+                    },
+                    Message::Libav(query) => {
+                        if let Err(_) = libav_channel.send(Request::new(query, tx.clone())) {
+                            tx.send(FfiErrorCode::Dead);
+                            broken = broken.or(Some(String::from("libav_thread is not running anymore")));
                         };
                     },
-                    Message::Play(tx) => {
-                        if let Err(_) = amcodec_channel.send((AmcodecMessage::Play, tx.clone())) {
-                            println!("main_thread: amcodec_channel disconnected, aborting");
-                            tx.send(FfiErrorCode::Disconnected);
-                            break 'mainloop;
+                    Message::Amcodec(query) => {
+                        if let Err(_) = amcodec_channel.send(Request::new(query, tx.clone())) {
+                            tx.send(FfiErrorCode::Dead);
+                            broken = broken.or(Some(String::from("amcodec_thread is not running anymore")));
                         };
                     },
-                    Message::Pause(tx) => {
-                        if let Err(_) = amcodec_channel.send((AmcodecMessage::Pause, tx.clone())) {
-                            println!("main_thread: amcodec_channel disconnected, aborting");
-                            tx.send(FfiErrorCode::Disconnected);
-                            break 'mainloop;
+                    Message::Play => {
+                        paused = false;
+                        if let Err(_) = amcodec_channel.send(Request::new(AmcodecMessage::Play, tx.clone())) {
+                            tx.send(FfiErrorCode::Dead);
+                            broken = broken.or(Some(String::from("amcodec_thread is not running anymore")));
+                            continue 'mainloop;
+                        };
+                    },
+                    Message::Pause => {
+                        paused = true;
+                        // a live stream starts timeshifting, nobody waits for that
+                        let (libav_tx, _rx) = single_use_channel::<Reply>();
+                        if let Err(_) = libav_channel.send(Request::new(LibavMessage::Pause, libav_tx)) {
+                            tx.send(FfiErrorCode::Dead);
+                            broken = broken.or(Some(String::from("libav_thread is not running anymore")));
+                            continue 'mainloop;
+                        };
+                        if let Err(_) = amcodec_channel.send(Request::new(AmcodecMessage::Pause, tx.clone())) {
+                            tx.send(FfiErrorCode::Dead);
+                            broken = broken.or(Some(String::from("amcodec_thread is not running anymore")));
+                            continue 'mainloop;
                         };
                     }
                 };
             };
+            stop_workers(&libav_channel, &amcodec_channel, &abort_io);
             keep_running.store(false, atomic::Ordering::SeqCst);
-            if cfg!(debug_assertions) {
-                println!("Finishing main loop ...");
+            // the other threads only poll keep_running every few milliseconds, except the window
+            // which would otherwise wait for its next event
+            if let Some(ref window) = window {
+                window.wake_up();
             }
-        })
+            // a pending PlayerEofFuture or wait_until_end must see that the player is shut down
+            events.close();
+            debug!("shutting down ...");
+        }).expect("failed to spawn thread")
     };
 
     // once every thread is spawned, return FfiPlayer to the API caller
     Ok(FfiPlayer {
-        main_thread: main_thread,
-        x11_event_loop_thread: x11_thread,
-        amcodec_thread: amcodec_thread,
-        libav_getter_thread: libav_thread,
-        video_status_queue: video_status_rx,
+        main_thread: Some(main_thread),
+        window_event_loop_thread: window_thread,
+        amcodec_thread: Some(amcodec_thread),
+        libav_getter_thread: Some(libav_thread),
+        events: events,
+        stats: stats,
         sender: sender,
         keep_running: keep_running,
+        display_path: display_path,
+        reply_timeout_ms: atomic::AtomicU64::new(DEFAULT_REPLY_TIMEOUT_MS),
+        audio: audio,
+        async_seek: async_seek,
+        last_error: Mutex::new(None),
+        last_error_code: last_error_code,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a window on a 1920x1080 screen, see stubwindow.rs
+    fn stub_window() -> Option<Arc<Window>> {
+        window::create_window(WindowBackend::Stub, (1920, 1080)).unwrap()
+    }
+
+    #[test]
+    fn large_rects_survive_the_round_trip() {
+        let window = stub_window();
+        for &rect in &[(2000, 1500, 3840, 2160), (40000, 40000, 1920, 1080), (-20, -12, 1960, 1104)] {
+            let axis = compute_video_axis(window_rect(false, rect), None, None, &window);
+            assert_eq!(axis, VideoAxis::Rect(rect.0, rect.1, rect.2, rect.3));
+            assert_eq!(current_video_rect(Some(axis), &window), Some(rect));
+            // a rectangle of the aspect of the video is filled as is
+            let aspect = (rect.2, rect.3);
+            assert_eq!(compute_video_axis(Some(rect), Some(aspect), None, &window), axis);
+        }
+    }
+
+    #[test]
+    fn large_rects_are_letterboxed_without_overflowing() {
+        let window = stub_window();
+        assert_eq!(compute_video_axis(Some((2000, 1500, 3840, 2160)), Some((4, 3)), None, &window),
+                   VideoAxis::Rect(2480, 1500, 2880, 2160));
+        assert_eq!(compute_video_axis(Some((0, 0, u32::max_value(), 1000)), Some((1, 1)), None, &window),
+                   VideoAxis::Rect(2147483147, 0, 1000, 1000));
+        // centered past i32::MAX
+        let max = i32::max_value();
+        assert_eq!(compute_video_axis(Some((max - 100, max, 3840, u32::max_value())), Some((16, 9)), None, &window),
+                   VideoAxis::Rect(max - 100, max, 3840, 2160));
+        assert_eq!(compute_video_axis(Some((max - 100, 0, u32::max_value(), 1000)), Some((1, 1)), None, &window),
+                   VideoAxis::Rect(max, 0, 1000, 1000));
+        // too thin to be seen, but something is still given to the VPU
+        assert_eq!(compute_video_axis(Some((0, 0, 1920, 1080)), Some((u32::max_value(), 1)), None, &window),
+                   VideoAxis::Rect(0, 539, 1920, 1));
+    }
+
+    #[test]
+    fn fullscreen_axis_is_the_screen() {
+        let window = stub_window();
+        assert_eq!(compute_video_axis(window_rect(true, (2000, 1500, 3840, 2160)), None, None, &window), VideoAxis::Fullscreen);
+        assert_eq!(current_video_rect(Some(VideoAxis::Fullscreen), &window), Some((0, 0, 1920, 1080)));
+    }
+
+    #[test]
+    fn clamped_windows_stay_on_the_screen() {
+        let screen = (1920, 1080);
+        assert_eq!(clamp_on_screen((100, 200, 640, 480), screen), (100, 200));
+        // right on the edges
+        assert_eq!(clamp_on_screen((0, 0, 1920, 1080), screen), (0, 0));
+        assert_eq!(clamp_on_screen((1280, 600, 640, 480), screen), (1280, 600));
+        assert_eq!(clamp_on_screen((1281, 601, 640, 480), screen), (1280, 600));
+        assert_eq!(clamp_on_screen((-1, -1, 640, 480), screen), (0, 0));
+        assert_eq!(clamp_on_screen((i32::min_value(), i32::max_value(), 1, 1), screen), (0, 1079));
+        // larger than the screen: its top left corner is kept on it
+        assert_eq!(clamp_on_screen((500, 500, 3840, 2160), screen), (0, 0));
+        assert_eq!(clamp_on_screen((500, 500, u32::max_value(), u32::max_value()), screen), (0, 0));
+    }
+
+    #[test]
+    fn windows_are_placed_off_the_screen_only_when_allowed() {
+        let window = stub_window();
+        assert_eq!(place_on_screen((-100, 1000, 640, 480), &window, false), (0, 600));
+        assert_eq!(place_on_screen((-100, 1000, 640, 480), &window, true), (-100, 1000));
+        assert_eq!(place_on_screen((i32::max_value(), i32::min_value(), 640, 480), &window, true),
+                   (i32::max_value(), i32::min_value()));
+        assert_eq!(place_on_screen((1280, 600, 640, 480), &window, false), (1280, 600));
+    }
+
+    #[test]
+    fn fit_aspect_boundaries() {
+        // same aspect: no bars
+        assert_eq!(fit_aspect((10, 20, 1920, 1080), (16, 9)), (10, 20, 1920, 1080));
+        assert_eq!(fit_aspect((0, 0, 1920, 1080), (4, 3)), (240, 0, 1440, 1080));
+        assert_eq!(fit_aspect((0, 0, 1440, 1080), (16, 9)), (0, 135, 1440, 810));
+        assert_eq!(fit_aspect((0, 0, u32::max_value(), u32::max_value()), (1, 1)),
+                   (0, 0, u32::max_value(), u32::max_value()));
+        // never emptied, however small the rect or extreme the aspect
+        assert_eq!(fit_aspect((-5, -5, 1, 1), (16, 9)), (-5, -5, 1, 1));
+        assert_eq!(fit_aspect((0, 0, 1000, u32::max_value()), (u32::max_value(), 1)), (0, 2147483647, 1000, 1));
+        assert_eq!(fit_aspect((0, 0, 1, 1000), (1, u32::max_value())), (0, 0, 1, 1000));
+        assert_eq!(fit_aspect((0, 0, u32::max_value(), 1), (1, u32::max_value())), (2147483647, 0, 1, 1));
+    }
+
+    #[test]
+    fn anamorphic_aspects() {
+        assert_eq!(anamorphic_aspect((1440, 1080), (4, 3)), Some((16, 9)));
+        assert_eq!(anamorphic_aspect((720, 576), (64, 45)), Some((16, 9)));
+        assert_eq!(anamorphic_aspect((720, 480), (8, 9)), Some((4, 3)));
+        // square pixels, or an unknown shape
+        assert_eq!(anamorphic_aspect((1920, 1080), (1, 1)), None);
+        assert_eq!(anamorphic_aspect((1920, 1080), (7, 7)), None);
+        assert_eq!(anamorphic_aspect((1920, 1080), (0, 1)), None);
+        assert_eq!(anamorphic_aspect((1920, 1080), (1, 0)), None);
+        // the reduced aspect doesn't fit in a u32
+        let max = u32::max_value();
+        let (w, h) = anamorphic_aspect((max, 1), (max, 1)).unwrap();
+        assert!(w >= h && h >= 1, "{}:{}", w, h);
+        assert_eq!(anamorphic_aspect((max, max), (max - 1, max)), Some((max - 1, max)));
+    }
+
+    #[test]
+    fn stream_aspect_needs_the_size_and_the_shape() {
+        let size = Some((1440, 1080));
+        assert_eq!(stream_aspect(size, Some((4, 3)), None), Some((16, 9)));
+        assert_eq!(stream_aspect(None, Some((4, 3)), None), None);
+        assert_eq!(stream_aspect(size, None, None), None);
+        // the override of the API user wins over the stream
+        assert_eq!(stream_aspect(size, Some((4, 3)), Some((1, 1))), None);
+        assert_eq!(stream_aspect(size, None, Some((4, 3))), Some((16, 9)));
+        assert_eq!(stream_aspect(Some((720, 576)), Some((1, 1)), Some((64, 45))), Some((16, 9)));
+    }
+
+    /// Players on the dummy Amcodec, playing the fixture of the integration tests
+    #[cfg(not(target_arch = "aarch64"))]
+    mod dummy {
+        use super::*;
+        use stats;
+        use std::env;
+        use std::sync::atomic::Ordering;
+        use amcodec::DUMMY_IN_USE;
+
+        /// the fixture of the integration tests, see tests/fixtures/README.md
+        fn clip() -> String {
+            format!("{}/tests/fixtures/clip.mp4", env!("CARGO_MANIFEST_DIR"))
+        }
+
+        /// A player on the dummy Amcodec and the stub window
+        struct DummyPlayer {
+            player: FfiPlayer,
+        }
+
+        impl DummyPlayer {
+            /// With these environment variables set while it is created (AMPLAYER_DUMMY_FRAMES, ...)
+            fn with_env(vars: &[(&str, &str)]) -> DummyPlayer {
+                while DUMMY_IN_USE.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                for &(name, value) in vars {
+                    env::set_var(name, value);
+                }
+                let player = start_with_backend(WindowBackend::Stub, PlayerConfig::default());
+                for &(name, _) in vars {
+                    env::remove_var(name);
+                }
+                match player {
+                    Ok(player) => DummyPlayer { player: player },
+                    Err(e) => {
+                        DUMMY_IN_USE.store(false, Ordering::SeqCst);
+                        panic!("failed to start the player: {}", e.display());
+                    },
+                }
+            }
+
+            fn query(&self, message: Message) -> FfiErrorCode {
+                let (tx, rx) = single_use_channel::<Reply>();
+                assert!(self.player.send_message(message, tx));
+                rx.recv().map(|reply| reply.code).unwrap_or(FfiErrorCode::Dead)
+            }
+
+            fn load(&self, url: String) {
+                match self.query(Message::Load(url)) {
+                    FfiErrorCode::None => {},
+                    code => panic!("the load failed with {:?}", code),
+                }
+            }
+
+            fn play(&self) {
+                match self.query(Message::Play) {
+                    FfiErrorCode::None => {},
+                    code => panic!("play failed with {:?}", code),
+                }
+            }
+
+            /// Waits until amcodec is in `state` (see stats::STATE_IDLE, ...)
+            fn wait_for_state(&self, state: usize, timeout: Duration) {
+                let start = Instant::now();
+                while self.player.stats.state.load(atomic::Ordering::Relaxed) != state {
+                    assert!(start.elapsed() < timeout, "amcodec never went to the state {}", state);
+                    thread::sleep(Duration::from_millis(5));
+                }
+            }
+
+            /// Removes every event of the queue
+            fn drain_events(&self) -> Vec<PlayerEvent> {
+                let mut events = Vec::new();
+                while let Ok((event, _)) = self.player.events.next(Some(Duration::from_millis(0))) {
+                    events.push(event);
+                }
+                events
+            }
+        }
+
+        impl Drop for DummyPlayer {
+            fn drop(&mut self) {
+                let _r = self.player.shutdown();
+                DUMMY_IN_USE.store(false, Ordering::SeqCst);
+            }
+        }
+
+        #[test]
+        fn end_of_the_previous_video_doesnt_end_the_next_one() {
+            let dummy = DummyPlayer::with_env(&[(amcodec::DUMMY_FRAMES_ENV, "60")]);
+            dummy.load(clip());
+            dummy.play();
+            // A ends, and nobody waits for it
+            dummy.wait_for_state(stats::STATE_PLAYING, Duration::from_secs(5));
+            dummy.wait_for_state(stats::STATE_IDLE, Duration::from_secs(5));
+            // its end goes through the main thread first
+            thread::sleep(Duration::from_millis(100));
+            // B is loaded, and waited for right away
+            dummy.load(clip());
+            dummy.play();
+            let events = dummy.player.events.clone();
+            let (tx, rx) = mpsc::channel();
+            let waiter = thread::spawn(move || {
+                let _r = tx.send(events.wait_end());
+            });
+            // B lasts a second: wait_until_end used to return the end of A at once
+            let ended = rx.recv_timeout(Duration::from_millis(500));
+            assert!(ended.is_err(), "B ended right away with {:?}", ended);
+            match rx.recv_timeout(Duration::from_secs(5)) {
+                Ok(PlayerEvent::EndOfStream) => {},
+                other => panic!("B didn't end: {:?}", other),
+            }
+            waiter.join().unwrap();
+            // the end of A is still there for aml_video_player_next_event
+            let ends = dummy.drain_events().into_iter().filter(|event| event.is_end()).count();
+            assert_eq!(ends, 1);
+        }
+
+        /// (tag, generation) of every record of a dump, see amcodec::DUMMY_DUMP_ENV
+        fn dump_records(data: &[u8]) -> Vec<([u8; 4], u32)> {
+            let be_u32 = |bytes: &[u8]| bytes.iter().fold(0u32, |value, &byte| value << 8 | byte as u32);
+            let mut records = Vec::new();
+            let mut offset = 0;
+            while offset + 12 <= data.len() {
+                let mut tag = [0u8; 4];
+                tag.copy_from_slice(&data[offset..offset + 4]);
+                records.push((tag, be_u32(&data[offset + 4..offset + 8])));
+                offset += 12 + be_u32(&data[offset + 8..offset + 12]) as usize;
+            }
+            records
+        }
+
+        #[test]
+        fn fifty_clips_back_to_back() {
+            use std::fs::{self, File};
+            use std::io::Read;
+            const CLIPS : usize = 50;
+            let dump = env::temp_dir().join(format!("c2player-back-to-back-{}.dump", ::std::process::id()));
+            let _r = fs::remove_file(&dump);
+            let dummy = DummyPlayer::with_env(&[(amcodec::DUMMY_FRAMES_ENV, "40"), (amcodec::DUMMY_DUMP_ENV, dump.to_str().unwrap())]);
+            for i in 0..CLIPS {
+                dummy.load(clip());
+                dummy.play();
+                // the next one is loaded the moment this one ends, while amcodec may still be
+                // busy with its end
+                match dummy.player.events.wait_end() {
+                    PlayerEvent::EndOfStream => {},
+                    other => panic!("clip {} ended with {:?}", i, other),
+                }
+                let shown = dummy.drain_events().into_iter().any(|event| match event {
+                    PlayerEvent::FirstFrameDisplayed => true,
+                    _ => false,
+                });
+                assert!(shown, "nothing of clip {} was shown", i);
+            }
+            drop(dummy);
+            let mut data = Vec::new();
+            File::open(&dump).unwrap().read_to_end(&mut data).unwrap();
+            let _r = fs::remove_file(&dump);
+            // every clip got its extra data before its first packet, or the VPU would have shown
+            // nothing but black
+            let records = dump_records(&data);
+            let mut with_extra_data = 0;
+            for (generation, _) in records.iter().filter(|&&(ref tag, _)| tag == b"STOP").enumerate() {
+                let generation = generation as u32 + 1;
+                let first = records.iter().find(|&&(ref tag, record_generation)| {
+                    record_generation == generation && (tag == b"XDAT" || tag == b"PKT ")
+                });
+                match first {
+                    Some(&(ref tag, _)) if tag == b"XDAT" => with_extra_data += 1,
+                    Some(_) => panic!("a packet of the generation {} came before its extra data", generation),
+                    None => {},
+                }
+            }
+            assert_eq!(with_extra_data, CLIPS);
+        }
+
+        #[test]
+        fn stats_move_on_the_dummy() {
+            // the 50 packets of the fixture, and their size
+            const CLIP_PACKETS : u64 = 50;
+            const CLIP_BYTES : u64 = 3245;
+            let dummy = DummyPlayer::with_env(&[(amcodec::DUMMY_FRAMES_ENV, "40")]);
+            let stats = || dummy.player.stats.snapshot(dummy.player.events.dropped_total());
+            let before = stats();
+            assert_eq!((before.packets_demuxed, before.packets_written, before.bytes_written), (0, 0, 0));
+            assert_eq!(before.state, stats::STATE_IDLE as c_int);
+            for played in 1..3 {
+                dummy.load(clip());
+                dummy.play();
+                dummy.wait_for_state(stats::STATE_PLAYING, Duration::from_secs(5));
+                assert_eq!(stats().state, stats::STATE_PLAYING as c_int);
+                match dummy.player.events.wait_end() {
+                    PlayerEvent::EndOfStream => {},
+                    other => panic!("the clip ended with {:?}", other),
+                }
+                // the dummy ends on its own, libav may still be sending the last packets
+                let start = Instant::now();
+                while stats().packets_written < CLIP_PACKETS * played {
+                    assert!(start.elapsed() < Duration::from_secs(5), "{} packets written", stats().packets_written);
+                    thread::sleep(Duration::from_millis(10));
+                }
+                // not reset by the load
+                let after = stats();
+                assert_eq!(after.packets_demuxed, CLIP_PACKETS * played);
+                assert_eq!(after.packets_written, CLIP_PACKETS * played);
+                assert_eq!(after.bytes_written, CLIP_BYTES * played);
+                assert_eq!(after.queue_depth, 0);
+                assert_eq!(after.state, stats::STATE_IDLE as c_int);
+            }
+            assert!(stats().uptime_ms > before.uptime_ms);
+        }
+
+        #[test]
+        fn blocking_seek_wins_over_a_pending_async_one() {
+            let dummy = DummyPlayer::with_env(&[]);
+            dummy.load(clip());
+            let position = || {
+                let (pos_tx, pos_rx) = single_use_channel::<f64>();
+                match dummy.query(Message::GetPosition(pos_tx)) {
+                    FfiErrorCode::None => pos_rx.recv().unwrap(),
+                    code => panic!("no position: {:?}", code),
+                }
+            };
+            // requested, its AsyncSeek is still on its way to the main thread
+            assert!(dummy.player.async_seek.request(1.5));
+            match dummy.query(Message::Seek(0.5)) {
+                FfiErrorCode::None => {},
+                code => panic!("the seek failed with {:?}", code),
+            }
+            // the keyframe at 1s would be shown for 1.5s
+            let pos = position();
+            assert!(pos < 1.0, "at {}s after a seek to 0.5s", pos);
+            match dummy.query(Message::AsyncSeek) {
+                FfiErrorCode::None => {},
+                code => panic!("the async seek failed with {:?}", code),
+            }
+            let start = Instant::now();
+            while !dummy.player.async_seek.is_complete() {
+                assert!(start.elapsed() < Duration::from_secs(5), "the async seek never completed");
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert_eq!(position(), pos);
+            // nothing is pending anymore, the next one is sent
+            assert!(dummy.player.async_seek.request(1.5));
+        }
+
+        #[test]
+        fn empty_rects_are_rejected() {
+            let dummy = DummyPlayer::with_env(&[]);
+            let rejected = |message: Message| match dummy.query(message) {
+                FfiErrorCode::InvalidCommand => {},
+                code => panic!("an empty rectangle was answered with {:?}", code),
+            };
+            let accepted = |message: Message| match dummy.query(message) {
+                FfiErrorCode::None => {},
+                code => panic!("a 1x1 rectangle was answered with {:?}", code),
+            };
+            let animation = Duration::from_millis(10);
+            for &(w, h) in &[(0, 100), (100, 0), (0, 0)] {
+                rejected(Message::SetRect((10, 10, w, h)));
+                rejected(Message::AnimateToRect((10, 10, w, h), animation, Easing::Linear));
+                rejected(Message::SetSize((w, h)));
+            }
+            // the smallest rectangles there are
+            accepted(Message::SetRect((10, 10, 1, 1)));
+            accepted(Message::AnimateToRect((10, 10, 1, 1), animation, Easing::Linear));
+            accepted(Message::SetSize((1, 1)));
+        }
+    }
+}
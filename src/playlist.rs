@@ -0,0 +1,232 @@
+/*
+ * Playlists are handled by the main thread: every time the amcodec thread tells us that a video
+ * is done playing, the main thread asks the libav thread to load the next entry of the playlist
+ * instead of notifying the API user.
+ *
+ * Only the m3u format (and its UTF-8 variant m3u8) is supported for now.
+ */
+
+use error::*;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// Maximum number of entries we accept in a single playlist file. Anything bigger than that is
+/// most likely an adversarial file trying to exhaust our memory.
+pub const MAX_PLAYLIST_ENTRIES : usize = 10000;
+
+/// Longest line we accept, without its line break: a file without line breaks (a video given as
+/// a playlist by mistake) would otherwise be read in memory all at once
+pub const MAX_PLAYLIST_LINE_LEN : usize = 64 * 1024;
+
+// urls starting with one of these are not resolved relatively to the playlist's directory
+const NETWORK_PREFIXES : [&'static str; 3] = ["http://", "https://", "rtsp://"];
+
+// duration and title are stored but not exposed through the API yet
+#[allow(unused)]
+#[derive(Debug, Clone)]
+pub struct PlaylistEntry {
+    pub url: String,
+    /// duration in seconds, as given by #EXTINF (None if unknown or -1)
+    pub duration: Option<f64>,
+    pub title: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Playlist {
+    pub entries: Vec<PlaylistEntry>,
+    /// if true, the first entry is loaded again once the last one is finished
+    pub looping: bool,
+    current: usize,
+}
+
+impl Playlist {
+    pub fn new(entries: Vec<PlaylistEntry>, looping: bool) -> Playlist {
+        Playlist {
+            entries: entries,
+            looping: looping,
+            current: 0,
+        }
+    }
+
+    /// Reads and parses a .m3u or .m3u8 file
+    ///
+    /// .m3u8 files are always read as UTF-8, .m3u files are read as UTF-8 if they are valid UTF-8
+    /// and as Latin-1 otherwise.
+    pub fn from_m3u<P: AsRef<Path>>(path: P, looping: bool) -> Result<Playlist> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .map_err(|e| Error::InvalidPlaylist {
+                reason: format!("failed to open {}", path.display()),
+                source: Some(e),
+            })?;
+        let force_utf8 = match path.extension() {
+            Some(ext) => ext.to_string_lossy().to_lowercase() == "m3u8",
+            None => false,
+        };
+        // relative paths are relative to the playlist's directory, not to our working directory
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+
+        let mut reader = BufReader::new(file);
+        let mut entries : Vec<PlaylistEntry> = Vec::new();
+        // (duration, title) of the last #EXTINF line, applied to the next url
+        let mut pending_info : Option<(Option<f64>, Option<String>)> = None;
+        let mut raw_line : Vec<u8> = Vec::new();
+        loop {
+            raw_line.clear();
+            let read = reader.by_ref().take(MAX_PLAYLIST_LINE_LEN as u64 + 1).read_until(b'\n', &mut raw_line)
+                .map_err(|e| Error::InvalidPlaylist {
+                    reason: format!("failed to read {}", path.display()),
+                    source: Some(e),
+                })?;
+            if read == 0 {
+                break;
+            }
+            if read > MAX_PLAYLIST_LINE_LEN && raw_line.last() != Some(&b'\n') {
+                return Err(Error::InvalidPlaylist {
+                    reason: format!("a line of {} is longer than {} bytes", path.display(), MAX_PLAYLIST_LINE_LEN),
+                    source: None,
+                });
+            }
+            let line = decode_line(&raw_line, force_utf8);
+            // the UTF-8 BOM may be present at the very beginning of the file
+            let line = line.trim_left_matches('\u{feff}').trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with("#EXTINF:") {
+                pending_info = Some(parse_extinf(&line["#EXTINF:".len()..]));
+                continue;
+            }
+            if line.starts_with('#') {
+                continue;
+            }
+            if entries.len() >= MAX_PLAYLIST_ENTRIES {
+                return Err(Error::InvalidPlaylist {
+                    reason: format!("more than {} entries", MAX_PLAYLIST_ENTRIES),
+                    source: None,
+                });
+            }
+            let (duration, title) = pending_info.take().unwrap_or((None, None));
+            entries.push(PlaylistEntry {
+                url: resolve_url(line, base_dir),
+                duration: duration,
+                title: title,
+            });
+        }
+        if entries.is_empty() {
+            return Err(Error::InvalidPlaylist {
+                reason: format!("{} has no entries", path.display()),
+                source: None,
+            });
+        }
+        Ok(Playlist::new(entries, looping))
+    }
+
+    /// url of the entry that should be playing right now
+    pub fn current_url(&self) -> Option<&str> {
+        self.entries.get(self.current).map(|e| e.url.as_str())
+    }
+
+    /// url of the entry advance would go to, without going there
+    pub fn next_url(&self) -> Option<&str> {
+        if self.current + 1 < self.entries.len() {
+            self.entries.get(self.current + 1).map(|e| e.url.as_str())
+        } else if self.looping {
+            self.entries.first().map(|e| e.url.as_str())
+        } else {
+            None
+        }
+    }
+
+    /// Goes to the next entry and returns its url, or None if the playlist is over
+    pub fn advance(&mut self) -> Option<&str> {
+        if self.current + 1 < self.entries.len() {
+            self.current += 1;
+        } else if self.looping && !self.entries.is_empty() {
+            self.current = 0;
+        } else {
+            return None;
+        }
+        self.current_url()
+    }
+}
+
+fn decode_line(raw: &[u8], force_utf8: bool) -> String {
+    match ::std::str::from_utf8(raw) {
+        Ok(s) => s.to_owned(),
+        Err(_) if force_utf8 => String::from_utf8_lossy(raw).into_owned(),
+        // Latin-1 maps every byte to the unicode code point of the same value
+        Err(_) => raw.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// parses "123.4,Some title" (what comes after "#EXTINF:")
+fn parse_extinf(info: &str) -> (Option<f64>, Option<String>) {
+    let mut split = info.splitn(2, ',');
+    // the duration may be followed by attributes (tvg-id="..." ...), only keep the number
+    let duration = split.next()
+        .and_then(|d| d.split_whitespace().next())
+        .and_then(|d| d.parse::<f64>().ok())
+        .and_then(|d| if d < 0.0 { None } else { Some(d) });
+    let title = split.next()
+        .map(|t| t.trim())
+        .and_then(|t| if t.is_empty() { None } else { Some(t.to_owned()) });
+    (duration, title)
+}
+
+fn resolve_url(line: &str, base_dir: &Path) -> String {
+    if NETWORK_PREFIXES.iter().any(|prefix| line.starts_with(prefix)) {
+        return line.to_owned();
+    }
+    let path = Path::new(line);
+    if path.is_absolute() {
+        line.to_owned()
+    } else {
+        base_dir.join(path).to_string_lossy().into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    /// A playlist of its own in the temporary directory, removed by the caller
+    fn write_playlist(name: &str, content: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!("c2player-{}-{}.m3u8", name, ::std::process::id()));
+        File::create(&path).unwrap().write_all(content).unwrap();
+        path
+    }
+
+    #[test]
+    fn lines_up_to_the_limit_are_read() {
+        let url = format!("http://example.com/{}", "a".repeat(MAX_PLAYLIST_LINE_LEN - 19));
+        assert_eq!(url.len(), MAX_PLAYLIST_LINE_LEN);
+        let path = write_playlist("longest-line", format!("#EXTM3U\n{}\nhttp://example.com/b\n", url).as_bytes());
+        let playlist = Playlist::from_m3u(&path, false);
+        let _r = fs::remove_file(&path);
+        let playlist = playlist.unwrap();
+        assert_eq!(playlist.entries.len(), 2);
+        assert_eq!(playlist.entries[0].url, url);
+    }
+
+    #[test]
+    fn longer_lines_are_refused() {
+        // a binary file without a single line break
+        let path = write_playlist("too-long", &vec![b'x'; 4 * MAX_PLAYLIST_LINE_LEN]);
+        let playlist = Playlist::from_m3u(&path, false);
+        let _r = fs::remove_file(&path);
+        match playlist {
+            Err(Error::InvalidPlaylist { source: None, .. }) => {},
+            other => panic!("a line of {} bytes gave {:?}", 4 * MAX_PLAYLIST_LINE_LEN, other),
+        }
+        let path = write_playlist("one-byte-too-long", &vec![b'x'; MAX_PLAYLIST_LINE_LEN + 1]);
+        let playlist = Playlist::from_m3u(&path, false);
+        let _r = fs::remove_file(&path);
+        assert!(playlist.is_err());
+    }
+}
@@ -0,0 +1,91 @@
+/*
+ * The payload of every video packet is copied out of libav by the libav thread, so that the
+ * AVPacket is unref'd right away, and written to the VPU by amcodec from that copy. The buffers
+ * go back to a pool once amcodec is done with them (or once they are dropped with the rest of the
+ * queue by a Stop), instead of a Vec being allocated for every packet.
+ */
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+/// Buffers larger than this aren't kept: a few huge keyframes must not keep that much memory
+/// around for good
+const MAX_POOLED_BUFFER_LEN : usize = 2 * 1024 * 1024;
+
+pub struct BufferPool {
+    free: Mutex<Vec<Vec<u8>>>,
+    /// buffers kept at most, the others are freed when given back
+    capacity: usize,
+}
+
+impl BufferPool {
+    pub fn new(capacity: usize) -> BufferPool {
+        BufferPool {
+            free: Mutex::new(Vec::with_capacity(capacity)),
+            capacity: capacity,
+        }
+    }
+
+    /// A copy of `data`, in a buffer of the pool if one is left
+    pub fn copy(pool: &Arc<BufferPool>, data: &[u8]) -> PooledBuffer {
+        let mut buffer = pool.free.lock().ok().and_then(|mut free| free.pop()).unwrap_or_default();
+        buffer.clear();
+        buffer.extend_from_slice(data);
+        PooledBuffer {
+            buffer: buffer,
+            pool: pool.clone(),
+        }
+    }
+
+    /// `buffer` goes to the pool once dropped, as if it came from it
+    pub fn adopt(pool: &Arc<BufferPool>, buffer: Vec<u8>) -> PooledBuffer {
+        PooledBuffer {
+            buffer: buffer,
+            pool: pool.clone(),
+        }
+    }
+
+    fn give_back(&self, buffer: Vec<u8>) {
+        if buffer.capacity() > MAX_POOLED_BUFFER_LEN {
+            return;
+        }
+        if let Ok(mut free) = self.free.lock() {
+            if free.len() < self.capacity {
+                free.push(buffer);
+            }
+        }
+    }
+}
+
+/// Goes back to its pool when dropped
+pub struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let buffer = ::std::mem::replace(&mut self.buffer, Vec::new());
+        self.pool.give_back(buffer);
+    }
+}
+
+impl ::std::fmt::Debug for PooledBuffer {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "PooledBuffer({} bytes)", self.buffer.len())
+    }
+}
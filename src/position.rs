@@ -0,0 +1,150 @@
+/*
+ * The current position of the video is known by nobody in particular: the libav thread knows the
+ * timestamps of what it demuxes, but this is often seconds ahead of what is on screen, and the VPU
+ * only knows how far it went since it has been fed, not where this is in the file.
+ *
+ * So both threads report what they know to the main thread, which puts it together in a
+ * PositionTracker and can answer position queries without asking anyone.
+ */
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Sent by the libav and amcodec threads to the main thread
+#[derive(Debug)]
+pub enum PositionReport {
+    /// Sent by libav when a video is loaded: the time_base of the HEVC stream (in seconds), its
+    /// start_time (in time_base units, 0 if unknown) and the shape of its pixels, see
+    /// Context::get_sample_aspect_ratio
+    Stream(f64, i64, (u32, u32)),
+    /// Sent by libav with the pts of the first packet sent after a Load or a Seek, since this is
+    /// where the VPU starts from
+    FirstPacket(i64),
+    /// Sent by amcodec every once in a while: the vpts, in seconds. No pts are given to the
+    /// VPU, so it counts from the first packet it has been fed.
+    Presented(f64),
+    /// Sent by libav during the backward playback, with the pts of each keyframe sent: the vpts
+    /// mean nothing then, since the VPU only sees keyframes going back
+    Shown(i64),
+    /// Sent by amcodec once the first frame fed after a Seek is shown, with the generation of the
+    /// seek. This one is for the main thread itself, the position comes with Presented.
+    SeekShown(u64),
+    /// Sent by amcodec once the first frame of a video is shown, with its (width, height) as the
+    /// VPU decodes it. Also for the main thread itself, see anamorphic_aspect.
+    VideoSize(u32, u32),
+}
+
+pub struct PositionTracker {
+    /// (time_base, start_time) of the stream currently loaded
+    stream: Option<(f64, i64)>,
+    /// position of the first packet given to the VPU, in seconds from the start of the stream
+    origin: Option<f64>,
+    position: Option<f64>,
+}
+
+impl PositionTracker {
+    pub fn new() -> PositionTracker {
+        PositionTracker {
+            stream: None,
+            origin: None,
+            position: None,
+        }
+    }
+
+    /// Forgets everything, nothing is loaded anymore
+    pub fn reset(&mut self) {
+        self.stream = None;
+        self.origin = None;
+        self.position = None;
+    }
+
+    /// The target is returned until the VPU tells us otherwise, since the position before the
+    /// seek is certainly not the right answer
+    pub fn seek(&mut self, target: f64) {
+        if self.stream.is_some() {
+            self.origin = None;
+            self.position = Some(target);
+        }
+    }
+
+    pub fn update(&mut self, report: PositionReport) {
+        match report {
+            PositionReport::Stream(time_base, start_time, _) => {
+                self.stream = Some((time_base, start_time));
+                self.origin = None;
+                self.position = Some(0.0);
+            },
+            PositionReport::FirstPacket(pts) => {
+                if let Some((time_base, start_time)) = self.stream {
+                    let origin = (pts - start_time) as f64 * time_base;
+                    self.origin = Some(origin.max(0.0));
+                }
+            },
+            PositionReport::Presented(vpts_secs) => {
+                // until the first packet is known, this is what remains of the previous stream
+                if let Some(origin) = self.origin {
+                    self.position = Some(origin + vpts_secs);
+                }
+            },
+            PositionReport::Shown(pts) => {
+                if let Some((time_base, start_time)) = self.stream {
+                    // until the next FirstPacket
+                    self.origin = None;
+                    self.position = Some(((pts - start_time) as f64 * time_base).max(0.0));
+                }
+            },
+            PositionReport::SeekShown(_) | PositionReport::VideoSize(..) => {},
+        }
+    }
+
+    /// Position in seconds from the start of the stream, None if nothing is loaded
+    pub fn position(&self) -> Option<f64> {
+        self.position
+    }
+}
+
+/// The seeks of aml_video_player_async_seek, which nobody waits for. Only one of them is sent to
+/// the main thread at a time: the ones requested meanwhile only replace its target, so that a
+/// user dragging a slider doesn't queue dozens of seeks. A blocking seek drops the target not
+/// forwarded yet instead.
+pub struct AsyncSeek {
+    /// Some from the request until the main thread forwards it to libav
+    target: Mutex<Option<f64>>,
+    /// set by libav once the seek was done (or failed) and no other one is pending
+    complete: AtomicBool,
+}
+
+impl AsyncSeek {
+    pub fn new() -> AsyncSeek {
+        AsyncSeek {
+            target: Mutex::new(None),
+            complete: AtomicBool::new(true),
+        }
+    }
+
+    /// Returns false if a seek is already on its way, which will go to `target` instead
+    pub fn request(&self, target: f64) -> bool {
+        let mut pending = self.target.lock().unwrap_or_else(|e| e.into_inner());
+        let was_pending = pending.is_some();
+        *pending = Some(target);
+        self.complete.store(false, Ordering::SeqCst);
+        !was_pending
+    }
+
+    /// The most recent target, for the seek being forwarded
+    pub fn take(&self) -> Option<f64> {
+        self.target.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+
+    /// Called by libav after every seek
+    pub fn finish(&self) {
+        let pending = self.target.lock().unwrap_or_else(|e| e.into_inner());
+        if pending.is_none() {
+            self.complete.store(true, Ordering::SeqCst);
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.complete.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,133 @@
+/*
+ * Every picture adjustment of the display at once, so that a setup can be saved to a file and
+ * applied again on the next start. The file has one "key=value" per line, and only holds what
+ * was set through the API: the others are left to the driver when it is loaded back.
+ *
+ * noise_reduction=40
+ * contrast=medium
+ * saturation=10
+ * hue=0
+ * sharpness=60
+ */
+
+use amcodec::{ContrastMode, MAX_NOISE_REDUCTION, MAX_SHARPNESS, MIN_SATURATION_HUE, MAX_SATURATION_HUE};
+use error::*;
+use std::fs::File;
+use std::io::{Read, Write};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoQualitySettings {
+    /// 0..MAX_NOISE_REDUCTION
+    pub noise_reduction: Option<u8>,
+    pub contrast_mode: Option<ContrastMode>,
+    /// both in MIN_SATURATION_HUE..MAX_SATURATION_HUE, and always written together
+    pub saturation_hue: Option<(i32, i32)>,
+    /// 0..MAX_SHARPNESS
+    pub sharpness: Option<u8>,
+}
+
+impl VideoQualitySettings {
+    pub fn to_file_content(&self) -> String {
+        let mut lines = Vec::new();
+        if let Some(level) = self.noise_reduction {
+            lines.push(format!("noise_reduction={}", level));
+        }
+        if let Some(mode) = self.contrast_mode {
+            let contrast = match mode {
+                ContrastMode::Off => String::from("off"),
+                ContrastMode::Low => String::from("low"),
+                ContrastMode::Medium => String::from("medium"),
+                ContrastMode::High => String::from("high"),
+                ContrastMode::Custom(value) => value.to_string(),
+            };
+            lines.push(format!("contrast={}", contrast));
+        }
+        if let Some((saturation, hue)) = self.saturation_hue {
+            lines.push(format!("saturation={}", saturation));
+            lines.push(format!("hue={}", hue));
+        }
+        if let Some(level) = self.sharpness {
+            lines.push(format!("sharpness={}", level));
+        }
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    /// Empty lines and the ones starting with # are ignored. Unknown keys and out of range values
+    /// are refused, rather than applying half of a file.
+    pub fn parse(content: &str) -> Result<VideoQualitySettings> {
+        let mut settings = VideoQualitySettings {
+            noise_reduction: None,
+            contrast_mode: None,
+            saturation_hue: None,
+            sharpness: None,
+        };
+        let (mut saturation, mut hue) = (None, None);
+        for (n, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || Error::InvalidConfig(format!("line {}: invalid setting `{}`", n + 1, line));
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts.next().ok_or_else(&invalid)?.trim();
+            match key {
+                "noise_reduction" => {
+                    let level : u8 = value.parse().map_err(|_| invalid())?;
+                    if level > MAX_NOISE_REDUCTION {
+                        return Err(invalid());
+                    }
+                    settings.noise_reduction = Some(level);
+                },
+                "contrast" => {
+                    settings.contrast_mode = Some(match value {
+                        "off" => ContrastMode::Off,
+                        "low" => ContrastMode::Low,
+                        "medium" => ContrastMode::Medium,
+                        "high" => ContrastMode::High,
+                        custom => ContrastMode::Custom(custom.parse().map_err(|_| invalid())?),
+                    });
+                },
+                "saturation" | "hue" => {
+                    let value : i32 = value.parse().map_err(|_| invalid())?;
+                    if value < MIN_SATURATION_HUE || value > MAX_SATURATION_HUE {
+                        return Err(invalid());
+                    }
+                    if key == "saturation" {
+                        saturation = Some(value);
+                    } else {
+                        hue = Some(value);
+                    }
+                },
+                "sharpness" => {
+                    let level : u8 = value.parse().map_err(|_| invalid())?;
+                    if level > MAX_SHARPNESS {
+                        return Err(invalid());
+                    }
+                    settings.sharpness = Some(level);
+                },
+                _ => return Err(invalid()),
+            }
+        }
+        // the driver only takes both at once, the missing one is left at its default
+        if saturation.is_some() || hue.is_some() {
+            settings.saturation_hue = Some((saturation.unwrap_or(0), hue.unwrap_or(0)));
+        }
+        Ok(settings)
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        File::create(path)
+            .and_then(|mut f| f.write_all(self.to_file_content().as_bytes()))
+            .map_err(|e| Error::Io { what: format!("failed to write {}", path), source: e })
+    }
+
+    pub fn load(path: &str) -> Result<VideoQualitySettings> {
+        let mut content = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .map_err(|e| Error::Io { what: format!("failed to read {}", path), source: e })?;
+        Self::parse(&content)
+    }
+}
@@ -0,0 +1,115 @@
+/*
+ * Recording: the video stream is written to a file as it is demuxed, whatever amcodec does with
+ * it, as a raw elementary stream any HEVC decoder can read (ffmpeg -f hevc, ...). Only the video
+ * stream is kept, there is no container and thus no timestamps.
+ *
+ * The extra data (VPS, SPS, PPS) comes first, and the packets only from the next keyframe on,
+ * since the ones before it can't be decoded without what came before the recording.
+ */
+
+use error::*;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+const START_CODE : [u8; 4] = [0, 0, 0, 1];
+
+pub struct Recording {
+    pub path: String,
+    file: BufWriter<File>,
+    /// the packets are skipped until the first keyframe
+    wait_keyframe: bool,
+}
+
+impl Recording {
+    /// The file is truncated if it exists
+    pub fn create(path: &str) -> Result<Recording> {
+        let file = File::create(path)
+            .map_err(|e| Error::Io { what: format!("failed to create {}", path), source: e })?;
+        debug!("recording to {}", path);
+        Ok(Recording {
+            path: path.to_owned(),
+            file: BufWriter::new(file),
+            wait_keyframe: true,
+        })
+    }
+
+    /// The extra data of every video, already in Annex B (see Context::get_extra_data). The next
+    /// packets are skipped until a keyframe.
+    pub fn write_extra_data(&mut self, extra_data: &[u8]) -> Result<()> {
+        self.wait_keyframe = true;
+        self.write(extra_data)
+    }
+
+    /// The NAL units of the packet are length-prefixed, as in MP4 and matroska: the lengths are
+    /// replaced by start codes. Packets already in Annex B (MPEG-TS) are written as they are.
+    pub fn write_packet(&mut self, data: &[u8], keyframe: bool) -> Result<()> {
+        if self.wait_keyframe {
+            if !keyframe {
+                return Ok(());
+            }
+            self.wait_keyframe = false;
+        }
+        if data.starts_with(&START_CODE) || data.starts_with(&START_CODE[1..]) {
+            return self.write(data);
+        }
+        let mut offset = 0;
+        while offset + 4 <= data.len() {
+            let nal_len = ((data[offset] as usize) << 24) | ((data[offset + 1] as usize) << 16)
+                | ((data[offset + 2] as usize) << 8) | data[offset + 3] as usize;
+            let end = (offset + 4 + nal_len).min(data.len());
+            self.write(&START_CODE)?;
+            self.write(&data[offset + 4..end])?;
+            offset = end;
+        }
+        Ok(())
+    }
+
+    /// Writes what is still buffered and closes the file
+    pub fn finish(mut self) -> Result<()> {
+        let path = &self.path;
+        self.file.flush()
+            .map_err(|e| Error::Io { what: format!("failed to write to {}", path), source: e })?;
+        debug!("recording to {} finished", path);
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let path = &self.path;
+        self.file.write_all(data)
+            .map_err(|e| Error::Io { what: format!("failed to write to {}", path), source: e })
+    }
+}
+
+/// True if the recording `path` is `url`: a file must not be played while it is recorded to
+pub fn same_file(path: &str, url: &str) -> bool {
+    match (fs::canonicalize(path), fs::canonicalize(url)) {
+        (Ok(path), Ok(url)) => path == url,
+        // urls, or a file which doesn't exist yet
+        _ => path == url,
+    }
+}
+
+/// Same as record_packet, for the extra data of a new video
+pub fn record_extra_data(recording: &mut Option<Recording>, extra_data: &[u8]) {
+    let failed = match *recording {
+        Some(ref mut recording) => recording.write_extra_data(extra_data).err(),
+        None => None,
+    };
+    if let Some(e) = failed {
+        error!("recording stopped: {}", e.display());
+        *recording = None;
+    }
+}
+
+/// Writes a packet to the recording if there is one. The recording is stopped if it fails, the
+/// video keeps playing.
+pub fn record_packet(recording: &mut Option<Recording>, data: &[u8], keyframe: bool) {
+    let failed = match *recording {
+        Some(ref mut recording) => recording.write_packet(data, keyframe).err(),
+        None => None,
+    };
+    if let Some(e) = failed {
+        error!("recording stopped: {}", e.display());
+        *recording = None;
+    }
+}
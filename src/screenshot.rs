@@ -0,0 +1,277 @@
+/*
+ * Captures what is on /dev/fb0 and compares it with a reference image, so that a test bench can
+ * check what is actually on screen. The framebuffer is the layer drawn above the VPU's: the video
+ * itself is only seen through the transparent pixels, it is not part of the capture.
+ *
+ * References must be binary PPM files (P6, 8 bits per channel): there is no PNG decoder in our
+ * dependencies, `convert reference.png reference.ppm` does the job.
+ *
+ * The video layer can be captured on its own with amvideocap, and written as a PNG file. The
+ * encoder below doesn't compress anything: these are only meant for test benches, and this keeps
+ * the output byte for byte reproducible.
+ */
+
+use error::*;
+use std::fs::File;
+use std::io::{Read, Write};
+
+/// 24 bits RGB, line by line without any padding
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub rgb: Vec<u8>,
+}
+
+const PNG_MAGIC : &'static [u8] = b"\x89PNG";
+
+/// Mean absolute error returned when the two images don't even have the same size
+pub const MAX_DISTANCE : f64 = 255.0;
+
+#[cfg(target_arch = "aarch64")]
+pub fn capture_framebuffer() -> Result<Image> {
+    use std::io::{Seek, SeekFrom};
+    use std::os::unix::io::AsRawFd;
+    use super::amcodec_sys::{FbBitfield, fb_get_vscreen_info};
+
+    let mut fb0 = File::open("/dev/fb0").map_err(|e| Error::open(e, "/dev/fb0"))?;
+    let screeninfo = fb_get_vscreen_info(fb0.as_raw_fd())?;
+    let bytes_per_pixel = (screeninfo.bits_per_pixel / 8) as usize;
+    if bytes_per_pixel != 3 && bytes_per_pixel != 4 {
+        return Err(Error::Unsupported(format!("{} bits per pixel framebuffer", screeninfo.bits_per_pixel)));
+    }
+    // FBIOGET_FSCREENINFO isn't bound, the lines are assumed to have no padding
+    let line_length = screeninfo.xres_virtual as usize * bytes_per_pixel;
+    let start = screeninfo.yoffset as usize * line_length + screeninfo.xoffset as usize * bytes_per_pixel;
+    let read_error = |e| Error::Io { what: String::from("failed to read /dev/fb0"), source: e };
+    fb0.seek(SeekFrom::Start(start as u64)).map_err(&read_error)?;
+    let (width, height) = (screeninfo.xres as usize, screeninfo.yres as usize);
+    let mut line = vec![0u8; line_length];
+    let mut rgb = Vec::with_capacity(width * height * 3);
+    let channel = |pixel: u32, field: &FbBitfield| -> u8 {
+        if field.length == 0 || field.length > 16 {
+            return 0;
+        }
+        let value = (pixel >> field.offset) & ((1u32 << field.length) - 1);
+        // scaled to 8 bits, for the 565 and 10 bits formats
+        (value * 255 / ((1u32 << field.length) - 1)) as u8
+    };
+    for _ in 0..height {
+        fb0.read_exact(&mut line).map_err(&read_error)?;
+        for x in 0..width {
+            let bytes = &line[x * bytes_per_pixel..(x + 1) * bytes_per_pixel];
+            let pixel = bytes.iter().rev().fold(0u32, |pixel, &byte| (pixel << 8) | byte as u32);
+            rgb.push(channel(pixel, &screeninfo.red));
+            rgb.push(channel(pixel, &screeninfo.green));
+            rgb.push(channel(pixel, &screeninfo.blue));
+        }
+    }
+    Ok(Image {
+        width: width as u32,
+        height: height as u32,
+        rgb: rgb,
+    })
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+pub fn capture_framebuffer() -> Result<Image> {
+    Err(Error::Unsupported(String::from("there is no Amlogic framebuffer on this architecture")))
+}
+
+/// The frame shown by the VPU, at its own resolution
+#[cfg(target_arch = "aarch64")]
+pub fn capture_video() -> Result<Image> {
+    use std::fs::OpenOptions;
+    use std::os::unix::io::AsRawFd;
+    use super::amcodec_sys::{GE2D_FORMAT_S24_RGB, amvideocap_get_srcframe_size, amvideocap_set_wantframe};
+
+    const AMVIDEOCAP_PATH : &'static str = "/dev/amvideocap0";
+
+    let mut device = OpenOptions::new().read(true).open(AMVIDEOCAP_PATH).map_err(|e| Error::open(e, AMVIDEOCAP_PATH))?;
+    let fd = device.as_raw_fd();
+    let (width, height) = amvideocap_get_srcframe_size(fd)?;
+    if width <= 0 || height <= 0 {
+        return Err(Error::NothingDisplayed);
+    }
+    amvideocap_set_wantframe(fd, GE2D_FORMAT_S24_RGB, width, height)?;
+    let mut rgb = vec![0u8; width as usize * height as usize * 3];
+    match device.read(&mut rgb) {
+        Ok(len) if len == rgb.len() => {},
+        // a short read happens when the video stopped in the meantime
+        Ok(_) => return Err(Error::NothingDisplayed),
+        Err(ref e) if e.raw_os_error() == Some(::libc::EAGAIN) || e.raw_os_error() == Some(::libc::ENODATA) => {
+            return Err(Error::NothingDisplayed);
+        },
+        Err(e) => return Err(Error::Io { what: format!("failed to read {}", AMVIDEOCAP_PATH), source: e }),
+    }
+    Ok(Image {
+        width: width as u32,
+        height: height as u32,
+        rgb: rgb,
+    })
+}
+
+/// Color bars with a gradient below, always the same, so that test benches can run their PNG
+/// pipeline anywhere
+#[cfg(not(target_arch = "aarch64"))]
+pub fn capture_video() -> Result<Image> {
+    const WIDTH : usize = 320;
+    const HEIGHT : usize = 180;
+    const BARS : [[u8; 3]; 8] = [
+        [255, 255, 255], [255, 255, 0], [0, 255, 255], [0, 255, 0],
+        [255, 0, 255], [255, 0, 0], [0, 0, 255], [0, 0, 0],
+    ];
+    let mut rgb = Vec::with_capacity(WIDTH * HEIGHT * 3);
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            if y < HEIGHT * 2 / 3 {
+                rgb.extend_from_slice(&BARS[x * BARS.len() / WIDTH]);
+            } else {
+                let level = (x * 255 / (WIDTH - 1)) as u8;
+                rgb.extend_from_slice(&[level, level, level]);
+            }
+        }
+    }
+    Ok(Image {
+        width: WIDTH as u32,
+        height: HEIGHT as u32,
+        rgb: rgb,
+    })
+}
+
+pub fn save_png(image: &Image, path: &str) -> Result<()> {
+    File::create(path)
+        .and_then(|mut f| f.write_all(&encode_png(image)))
+        .map_err(|e| Error::WriteFailed { path: path.to_owned(), source: e })
+}
+
+/// 8 bits RGB, no filter, and zlib's stored blocks instead of compressed ones
+pub fn encode_png(image: &Image) -> Vec<u8> {
+    // every line starts with its filter type, 0 being none
+    let line_len = image.width as usize * 3;
+    let mut raw = Vec::with_capacity((line_len + 1) * image.height as usize);
+    for line in image.rgb.chunks(line_len.max(1)).take(image.height as usize) {
+        raw.push(0);
+        raw.extend_from_slice(line);
+    }
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        zlib.push(if blocks.peek().is_none() { 1 } else { 0 });
+        zlib.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&be32(adler32(&raw)));
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&be32(image.width));
+    ihdr.extend_from_slice(&be32(image.height));
+    // bit depth, color type (RGB), compression, filter, interlace
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    push_chunk(&mut png, b"IHDR", &ihdr);
+    push_chunk(&mut png, b"IDAT", &zlib);
+    push_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn push_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&be32(data.len() as u32));
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&be32(crc));
+}
+
+fn be32(value: u32) -> [u8; 4] {
+    [(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+pub fn load_reference(path: &str) -> Result<Image> {
+    let mut content = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut content))
+        .map_err(|e| Error::Io { what: format!("failed to read {}", path), source: e })?;
+    if content.starts_with(PNG_MAGIC) {
+        // not Unsupported: aml_video_player_screenshot_compare returns distances as positive codes
+        return Err(Error::Other(format!("{} is a PNG file, only PPM references can be read", path)));
+    }
+    parse_ppm(&content).ok_or_else(|| Error::Other(format!("{} is not a binary PPM file (P6, 8 bits)", path)))
+}
+
+/// Only P6 with a maxval of 255 is supported
+fn parse_ppm(content: &[u8]) -> Option<Image> {
+    // the header is 4 fields separated by whitespace, with comments from # to the end of line,
+    // followed by a single whitespace before the pixels
+    let mut fields : Vec<String> = Vec::with_capacity(4);
+    let mut pos = 0;
+    while fields.len() < 4 {
+        match content.get(pos) {
+            Some(&b'#') => {
+                while pos < content.len() && content[pos] != b'\n' {
+                    pos += 1;
+                }
+            },
+            Some(c) if c.is_ascii_whitespace() => pos += 1,
+            Some(_) => {
+                let start = pos;
+                while pos < content.len() && !content[pos].is_ascii_whitespace() {
+                    pos += 1;
+                }
+                fields.push(String::from_utf8_lossy(&content[start..pos]).into_owned());
+            },
+            None => return None,
+        }
+    }
+    let pixels = &content[(pos + 1).min(content.len())..];
+    let width : u32 = fields[1].parse().ok()?;
+    let height : u32 = fields[2].parse().ok()?;
+    if fields[0] != "P6" || fields[3] != "255" {
+        return None;
+    }
+    let len = width as usize * height as usize * 3;
+    if pixels.len() < len {
+        return None;
+    }
+    Some(Image {
+        width: width,
+        height: height,
+        rgb: pixels[..len].to_vec(),
+    })
+}
+
+/// Mean absolute difference between the channels of both images, from 0 (identical) to 255
+pub fn mean_absolute_error(a: &Image, b: &Image) -> f64 {
+    if a.width != b.width || a.height != b.height || a.rgb.is_empty() {
+        return MAX_DISTANCE;
+    }
+    let total : u64 = a.rgb.iter().zip(b.rgb.iter())
+        .map(|(&x, &y)| (x as i32 - y as i32).abs() as u64)
+        .sum();
+    total as f64 / a.rgb.len() as f64
+}
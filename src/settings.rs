@@ -0,0 +1,179 @@
+/*
+ * Settings the main thread changes and amcodec applies, without going through amcodec's channel:
+ * the main thread replaces the value and bumps its version, and amcodec applies the latest value
+ * at its next iteration if the version moved. A setting changed many times in a row (a window
+ * being dragged, or resized by an animation) is then applied at most once per iteration, instead
+ * of piling up messages which amcodec would apply one after the other, late.
+ *
+ * The API user doesn't wait for amcodec either: the errors are only logged. This is why only the
+ * video axis (which the aspect ratio of the video is part of) goes through here: the display mode
+ * and the color settings are set once in a while, and their errors are returned to the API user.
+ */
+
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use utils::duration_to_secs;
+
+pub struct Versioned<T> {
+    value: RwLock<T>,
+    /// bumped after every write of value
+    version: AtomicUsize,
+}
+
+impl<T: Clone> Versioned<T> {
+    /// The initial value is never reported by changed: it is what the driver already has
+    pub fn new(value: T) -> Versioned<T> {
+        Versioned {
+            value: RwLock::new(value),
+            version: AtomicUsize::new(0),
+        }
+    }
+
+    /// The value is applied again even if it didn't change: the screen may have changed under it
+    pub fn set(&self, value: T) {
+        // a thread which panicked while holding the lock can't have left a half-written value
+        *self.value.write().unwrap_or_else(|e| e.into_inner()) = value;
+        self.version.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// The value, if it was set since the version `seen`, which is then updated
+    pub fn changed(&self, seen: &mut usize) -> Option<T> {
+        let version = self.version.load(Ordering::SeqCst);
+        if version == *seen {
+            return None;
+        }
+        *seen = version;
+        // whatever was written since the version was read is newer still
+        Some(self.value.read().unwrap_or_else(|e| e.into_inner()).clone())
+    }
+
+    /// The latest value, whether it was seen or not
+    pub fn get(&self) -> Option<T> {
+        Some(self.value.read().unwrap_or_else(|e| e.into_inner()).clone())
+    }
+}
+
+/// How an animation goes from its start to its end
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Easing {
+    Linear,
+    /// slow at the start
+    EaseIn,
+    /// slow at the end
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    pub fn from_int(easing: u32) -> Option<Easing> {
+        match easing {
+            0 => Some(Easing::Linear),
+            1 => Some(Easing::EaseIn),
+            2 => Some(Easing::EaseOut),
+            3 => Some(Easing::EaseInOut),
+            _ => None,
+        }
+    }
+
+    /// How far the animation went (between 0 and 1) when `t` of its duration passed, as cubic
+    /// curves
+    pub fn apply(&self, t: f64) -> f64 {
+        let t = t.max(0.0).min(1.0);
+        match *self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => if t < 0.5 {
+                4.0 * t * t * t
+            } else {
+                1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+            },
+        }
+    }
+}
+
+/// The video going from a rectangle to another, see VideoAxis::Animated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Animation {
+    /// x, y, width, height
+    pub from: (i32, i32, u32, u32),
+    pub to: (i32, i32, u32, u32),
+    pub start: Instant,
+    pub duration: Duration,
+    pub easing: Easing,
+}
+
+impl Animation {
+    /// Where the video is at `now`, and whether the animation is over. The position is computed
+    /// in floating point and only rounded at the end, the VPU only takes whole pixels.
+    pub fn at(&self, now: Instant) -> ((i32, i32, u32, u32), bool) {
+        let elapsed = if now > self.start { now - self.start } else { Duration::new(0, 0) };
+        if elapsed >= self.duration {
+            return (self.to, true);
+        }
+        let progress = self.easing.apply(duration_to_secs(elapsed) / duration_to_secs(self.duration));
+        let lerp = |from: f64, to: f64| (from + (to - from) * progress).round();
+        let (from, to) = (self.from, self.to);
+        let rect = (
+            lerp(from.0 as f64, to.0 as f64) as i32,
+            lerp(from.1 as f64, to.1 as f64) as i32,
+            lerp(from.2 as f64, to.2 as f64) as u32,
+            lerp(from.3 as f64, to.3 as f64) as u32,
+        );
+        (rect, false)
+    }
+}
+
+/// Where the VPU shows the video
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VideoAxis {
+    /// the whole screen, whatever its resolution is when this is applied
+    Fullscreen,
+    /// x, y, width, height
+    Rect(i32, i32, u32, u32),
+    /// amcodec moves the video a bit at every iteration, until it reaches Animation::to
+    Animated(Animation),
+}
+
+/// Written by the main thread, read by amcodec
+pub struct VideoSettings {
+    pub axis: Versioned<VideoAxis>,
+}
+
+impl VideoSettings {
+    pub fn new() -> VideoSettings {
+        VideoSettings {
+            axis: Versioned::new(VideoAxis::Fullscreen),
+        }
+    }
+}
+
+/// What amcodec saw last of each setting
+#[derive(Debug, Default)]
+pub struct SeenVersions {
+    pub axis: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn set_after_a_panic_is_still_seen() {
+        let axis = Arc::new(Versioned::new(VideoAxis::Fullscreen));
+        let mut seen = 0;
+        let poisoner = axis.clone();
+        let _r = thread::spawn(move || {
+            let _guard = poisoner.value.write().unwrap();
+            panic!("poisoning the lock");
+        }).join();
+        assert!(axis.value.is_poisoned());
+        axis.set(VideoAxis::Rect(0, 0, 640, 480));
+        assert_eq!(axis.changed(&mut seen), Some(VideoAxis::Rect(0, 0, 640, 480)));
+        assert_eq!(axis.changed(&mut seen), None);
+        assert_eq!(axis.get(), Some(VideoAxis::Rect(0, 0, 640, 480)));
+    }
+}
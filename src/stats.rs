@@ -0,0 +1,181 @@
+/*
+ * Counters maintained by the worker threads for the whole life of a player (they are not reset
+ * when a video is loaded), read by aml_video_player_get_stats without going through the main
+ * thread. They are only ever incremented with relaxed atomics, so that the packet path never
+ * waits for a lock: a snapshot isn't guaranteed to be consistent between two counters.
+ */
+
+use libc::c_int;
+use log;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// AMPLAYER_STATE_* in aml_player.h
+pub const STATE_IDLE : usize = 0;
+pub const STATE_PLAYING : usize = 1;
+pub const STATE_PAUSED : usize = 2;
+/// everything was sent to the VPU, which is still showing the last frames
+pub const STATE_FINISHING : usize = 3;
+
+pub struct Stats {
+    created: Instant,
+    /// log::counts when the player was created
+    log_counts: (usize, usize),
+    /// packets of the video stream sent by libav to amcodec
+    pub packets_demuxed: AtomicUsize,
+    /// packets taken out of the packet channel by amcodec, written or not
+    pub packets_dequeued: AtomicUsize,
+    /// packets fully written to the device
+    pub packets_written: AtomicUsize,
+    pub bytes_written: AtomicUsize,
+    pub device_reopens: AtomicUsize,
+    /// writes to the device which were interrupted and tried again
+    pub write_retries: AtomicUsize,
+    /// times the VPU's buffer went below the underflow threshold
+    pub stalls: AtomicUsize,
+    /// one of the STATE_* constants
+    pub state: AtomicUsize,
+    /// video in the timeshift buffer of a paused live stream, see timeshift.rs
+    pub timeshift_span_ms: AtomicUsize,
+    /// microseconds the libav thread spent waiting for a command, because amcodec had enough
+    /// packets or because there was nothing to demux
+    pub libav_idle_us: AtomicUsize,
+    /// microseconds the amcodec thread spent sleeping between two iterations
+    pub amcodec_idle_us: AtomicUsize,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            created: Instant::now(),
+            log_counts: log::counts(),
+            packets_demuxed: AtomicUsize::new(0),
+            packets_dequeued: AtomicUsize::new(0),
+            packets_written: AtomicUsize::new(0),
+            bytes_written: AtomicUsize::new(0),
+            device_reopens: AtomicUsize::new(0),
+            write_retries: AtomicUsize::new(0),
+            stalls: AtomicUsize::new(0),
+            state: AtomicUsize::new(STATE_IDLE),
+            timeshift_span_ms: AtomicUsize::new(0),
+            libav_idle_us: AtomicUsize::new(0),
+            amcodec_idle_us: AtomicUsize::new(0),
+        }
+    }
+
+    /// events_dropped is kept by the EventQueue itself
+    pub fn snapshot(&self, events_dropped: u64) -> AmlPlayerStats {
+        let load = |counter: &AtomicUsize| counter.load(Ordering::Relaxed) as u64;
+        let uptime = self.created.elapsed();
+        let (errors, warnings) = log::counts();
+        AmlPlayerStats {
+            packets_demuxed: load(&self.packets_demuxed),
+            packets_written: load(&self.packets_written),
+            bytes_written: load(&self.bytes_written),
+            // both are read separately, a packet may have been sent in between
+            queue_depth: load(&self.packets_demuxed).saturating_sub(load(&self.packets_dequeued)),
+            device_reopens: load(&self.device_reopens),
+            write_retries: load(&self.write_retries),
+            stalls: load(&self.stalls),
+            events_dropped: events_dropped,
+            uptime_ms: uptime.as_secs() * 1000 + (uptime.subsec_nanos() / 1_000_000) as u64,
+            state: load(&self.state) as c_int,
+            timeshift_span_ms: load(&self.timeshift_span_ms),
+            libav_idle_ms: load(&self.libav_idle_us) / 1000,
+            amcodec_idle_ms: load(&self.amcodec_idle_us) / 1000,
+            errors: errors.saturating_sub(self.log_counts.0) as u64,
+            warnings: warnings.saturating_sub(self.log_counts.1) as u64,
+        }
+    }
+}
+
+pub fn add(counter: &AtomicUsize, n: usize) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+/// Adds the microseconds since `since` to counter
+pub fn add_elapsed(counter: &AtomicUsize, since: Instant) {
+    let elapsed = since.elapsed();
+    add(counter, elapsed.as_secs() as usize * 1_000_000 + (elapsed.subsec_nanos() / 1000) as usize);
+}
+
+/// thread::sleep, counted in `counter` (see add_elapsed)
+pub fn sleep(counter: &AtomicUsize, duration: Duration) {
+    let started = Instant::now();
+    thread::sleep(duration);
+    add_elapsed(counter, started);
+}
+
+/// C version of Stats
+#[repr(C)]
+pub struct AmlPlayerStats {
+    pub packets_demuxed: u64,
+    pub packets_written: u64,
+    pub bytes_written: u64,
+    /// packets waiting in the channel between libav and amcodec
+    pub queue_depth: u64,
+    pub device_reopens: u64,
+    pub write_retries: u64,
+    pub stalls: u64,
+    /// events lost because the event queue was full
+    pub events_dropped: u64,
+    /// since the player was created
+    pub uptime_ms: u64,
+    /// see Stats::state
+    pub state: c_int,
+    /// 0 unless a live stream is timeshifting
+    pub timeshift_span_ms: u64,
+    /// see Stats::libav_idle_us, the rest of the uptime the thread was working
+    pub libav_idle_ms: u64,
+    pub amcodec_idle_ms: u64,
+    /// logged since the player was created, by any player: the log can't tell them apart
+    pub errors: u64,
+    pub warnings: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_of_new_stats() {
+        let stats = Stats::new();
+        let snapshot = stats.snapshot(3);
+        assert_eq!((snapshot.packets_demuxed, snapshot.packets_written, snapshot.bytes_written), (0, 0, 0));
+        assert_eq!(snapshot.queue_depth, 0);
+        assert_eq!(snapshot.events_dropped, 3);
+        assert_eq!(snapshot.state, STATE_IDLE as c_int);
+    }
+
+    #[test]
+    fn snapshot_of_moving_counters() {
+        let stats = Stats::new();
+        add(&stats.packets_demuxed, 10);
+        add(&stats.packets_dequeued, 7);
+        add(&stats.libav_idle_us, 2_500);
+        stats.state.store(STATE_PLAYING, Ordering::Relaxed);
+        let snapshot = stats.snapshot(0);
+        assert_eq!(snapshot.queue_depth, 3);
+        assert_eq!(snapshot.libav_idle_ms, 2);
+        assert_eq!(snapshot.state, STATE_PLAYING as c_int);
+        // dequeued read after a packet demuxed meanwhile went through
+        add(&stats.packets_dequeued, 5);
+        assert_eq!(stats.snapshot(0).queue_depth, 0);
+        // the other tests log as well
+        error!("an error for snapshot_of_moving_counters");
+        warn!("a warning for snapshot_of_moving_counters");
+        warn!("another warning for snapshot_of_moving_counters");
+        let logged = stats.snapshot(0);
+        assert!(logged.errors >= snapshot.errors + 1, "{} errors", logged.errors);
+        assert!(logged.warnings >= snapshot.warnings + 2, "{} warnings", logged.warnings);
+    }
+
+    #[test]
+    fn sleep_is_counted() {
+        let stats = Stats::new();
+        sleep(&stats.amcodec_idle_us, Duration::from_millis(20));
+        assert!(stats.snapshot(0).amcodec_idle_ms >= 20);
+        assert!(stats.snapshot(0).uptime_ms >= 20);
+    }
+}
@@ -0,0 +1,137 @@
+/*
+ * A window which only exists in memory, the windowing equivalent of the dummy Amcodec: no display
+ * is opened, every call is logged and the geometry is only remembered. This lets the windowed
+ * paths of the main thread (and thus the whole FFI) run on machines without any display server,
+ * such as a CI runner, where headless would take the other branch of every window-related message.
+ *
+ * Selected with AMPLAYER_WINDOW_BACKEND=stub, or by default when built with the stub_window
+ * feature.
+ */
+
+use error::*;
+use super::window::{Window, WindowEvent, DisplayPath, ShowHideMode};
+
+use std::sync::{Arc, Mutex, Condvar, atomic};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+/// The screen the stub pretends to be on
+const STUB_SCREEN_SIZE : (u32, u32) = (1920, 1080);
+
+#[derive(Debug)]
+struct StubState {
+    x: i32,
+    y: i32,
+    w: u32,
+    h: u32,
+    visible: bool,
+    fullscreen: bool,
+}
+
+pub struct StubWindow {
+    state: Mutex<StubState>,
+    /// notified by wake_up, so that the event loop stops right away
+    wake_up: Condvar,
+}
+
+impl StubWindow {
+    pub fn new((w, h): (u16, u16)) -> StubWindow {
+        debug!("(stub) window of {}x{} created", w, h);
+        StubWindow {
+            state: Mutex::new(StubState {
+                x: 0,
+                y: 0,
+                w: w as u32,
+                h: h as u32,
+                visible: true,
+                fullscreen: false,
+            }),
+            wake_up: Condvar::new(),
+        }
+    }
+}
+
+impl Window for StubWindow {
+    fn backend_name(&self) -> &'static str {
+        "stub"
+    }
+
+    /// Nothing covers the video layer, as when headless
+    fn display_path(&self) -> DisplayPath {
+        DisplayPath::Headless
+    }
+
+    fn set_borderless(&self, borderless: bool) -> Result<()> {
+        debug!("(stub) window borderless: {}", borderless);
+        Ok(())
+    }
+
+    fn set_fullscreen(&self, fullscreen: bool) -> Result<()> {
+        debug!("(stub) window fullscreen: {}", fullscreen);
+        self.state.lock().unwrap().fullscreen = fullscreen;
+        Ok(())
+    }
+
+    fn set_sticky(&self, sticky: bool) -> Result<()> {
+        debug!("(stub) window sticky: {}", sticky);
+        Ok(())
+    }
+
+    fn set_skip_taskbar(&self, skip: bool) -> Result<()> {
+        debug!("(stub) window out of the taskbar: {}", skip);
+        Ok(())
+    }
+
+    fn screen_size(&self) -> Result<(u32, u32)> {
+        Ok(STUB_SCREEN_SIZE)
+    }
+
+    fn screen_count(&self) -> Result<u32> {
+        Ok(1)
+    }
+
+    fn show(&self) {
+        debug!("(stub) window shown");
+        self.state.lock().unwrap().visible = true;
+    }
+
+    fn hide(&self) {
+        debug!("(stub) window hidden");
+        self.state.lock().unwrap().visible = false;
+    }
+
+    fn set_pos(&self, x: i32, y: i32) {
+        let mut state = self.state.lock().unwrap();
+        state.x = x;
+        state.y = y;
+        debug!("(stub) window moved: {:?}", *state);
+    }
+
+    fn set_size(&self, w: u32, h: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.w = w;
+        state.h = h;
+        debug!("(stub) window resized: {:?}", *state);
+    }
+
+    fn set_click_through(&self, enabled: bool) -> Result<()> {
+        debug!("(stub) window click-through: {}", enabled);
+        Ok(())
+    }
+
+    fn set_show_hide_mode(&self, mode: ShowHideMode) {
+        debug!("(stub) window show/hide mode: {:?}", mode);
+    }
+
+    /// No event ever happens, this only waits for the shutdown
+    fn event_loop(&self, keep_running: Arc<atomic::AtomicBool>, _events: Sender<WindowEvent>) {
+        let mut state = self.state.lock().unwrap();
+        while keep_running.load(atomic::Ordering::SeqCst) {
+            state = self.wake_up.wait_timeout(state, Duration::from_millis(50)).unwrap().0;
+        }
+    }
+
+    fn wake_up(&self) {
+        self.wake_up.notify_all();
+    }
+}
@@ -0,0 +1,383 @@
+/*
+ * Subtitles are only demuxed and turned into text: showing them is up to the API user, who gets
+ * every cue as a PlayerEvent::Subtitle. Cues are sent as soon as they are demuxed, which is
+ * ahead of what the VPU shows: their start time has to be compared with the position of the
+ * video.
+ *
+ * Only text formats are supported, bitmap ones (DVD, PGS, DVB) would need libavcodec.
+ *
+ * Subtitles can also come from a SRT file next to the video. These are sent by the main thread
+ * when the position of the video reaches them instead, see SidecarSubtitles.
+ */
+
+use error::*;
+use std::fs::File;
+use std::io::Read;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubtitleFormat {
+    /// MP4's tx3g: the length of the text on 16 bits (big endian), the text, then style boxes
+    MovText,
+    /// SRT, WebVTT and raw text: the packet is the text itself
+    Plain,
+    /// the fields of a Dialogue line, the text being the last one
+    Ass,
+    Bitmap,
+}
+
+/// Fields before the text in matroska's ASS packets: ReadOrder, Layer, Style, Name, MarginL,
+/// MarginR, MarginV, Effect
+const ASS_FIELDS_BEFORE_TEXT : usize = 8;
+
+/// None if the packet holds no text (or is a bitmap)
+pub fn decode_text(format: SubtitleFormat, data: &[u8]) -> Option<String> {
+    let text = match format {
+        SubtitleFormat::MovText => {
+            if data.len() < 2 {
+                return None;
+            }
+            let len = ((data[0] as usize) << 8) | data[1] as usize;
+            String::from_utf8_lossy(&data[2..(2 + len).min(data.len())]).into_owned()
+        },
+        SubtitleFormat::Plain => String::from_utf8_lossy(data).into_owned(),
+        SubtitleFormat::Ass => ass_text(&String::from_utf8_lossy(data)),
+        SubtitleFormat::Bitmap => return None,
+    };
+    let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    if text.is_empty() { None } else { Some(text.to_owned()) }
+}
+
+/// Removes the fields before the text and the override tags ({\i1} and the like)
+fn ass_text(line: &str) -> String {
+    // the ASS demuxer gives whole lines, with Start and End as well
+    let (line, fields) = if line.starts_with("Dialogue:") {
+        (&line["Dialogue:".len()..], ASS_FIELDS_BEFORE_TEXT + 1)
+    } else {
+        (line, ASS_FIELDS_BEFORE_TEXT)
+    };
+    let raw = line.splitn(fields + 1, ',').nth(fields).unwrap_or("");
+    let mut text = String::with_capacity(raw.len());
+    let mut in_tag = false;
+    for c in raw.chars() {
+        match c {
+            '{' => in_tag = true,
+            '}' if in_tag => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {},
+        }
+    }
+    text.replace("\\N", "\n").replace("\\n", "\n").replace("\\h", " ")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub start_ms: i64,
+    pub end_ms: i64,
+    pub text: String,
+}
+
+/// Parses "HH:MM:SS,mmm". The hours can be omitted and the milliseconds separated by a dot, as
+/// some tools write them.
+fn parse_srt_timestamp(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let (hms, ms) = match s.find(|c| c == ',' || c == '.') {
+        Some(i) => (&s[..i], &s[i + 1..]),
+        None => (s, "0"),
+    };
+    let mut secs : i64 = 0;
+    let fields : Vec<&str> = hms.split(':').collect();
+    if fields.len() < 2 || fields.len() > 3 {
+        return None;
+    }
+    for field in fields {
+        secs = secs * 60 + field.trim().parse::<i64>().ok()?;
+    }
+    // "5" is 500ms, "05" 50ms
+    let ms_digits : String = ms.chars().take_while(|c| c.is_ascii_digit()).take(3).collect();
+    if ms_digits.is_empty() {
+        return None;
+    }
+    let ms = ms_digits.parse::<i64>().ok()? * 10i64.pow(3 - ms_digits.len() as u32);
+    Some(secs * 1000 + ms)
+}
+
+/// "start --> end", with anything after end (positions) ignored
+fn parse_srt_timing(line: &str) -> Option<(i64, i64)> {
+    let mut parts = line.splitn(2, "-->");
+    let start = parse_srt_timestamp(parts.next()?)?;
+    let end = parse_srt_timestamp(parts.next()?.trim().split_whitespace().next()?)?;
+    Some((start, end))
+}
+
+/// Sorted by start time, overlapping cues are kept as they are. The indices are not trusted: a
+/// cue is anything that starts with a timing line, whatever comes before it. Cues which can't be
+/// made sense of are skipped, with a warning.
+pub fn parse_srt(content: &str) -> Vec<Cue> {
+    // old Mac files end their lines with \r only, which lines() doesn't split
+    let content = content.trim_start_matches('\u{feff}').replace("\r\n", "\n").replace('\r', "\n");
+    let mut cues : Vec<Cue> = Vec::new();
+    let mut current : Option<(i64, i64, Vec<&str>)> = None;
+    for (n, line) in content.lines().enumerate() {
+        if line.contains("-->") {
+            // the index of this cue, when no blank line ended the previous one
+            if let Some((_, _, ref mut text)) = current {
+                if text.last().map_or(false, |last| is_srt_index(last)) {
+                    text.pop();
+                }
+            }
+            match parse_srt_timing(line) {
+                Some((start, end)) => {
+                    if let Some((start, end, text)) = current.take() {
+                        push_cue(&mut cues, start, end, &text);
+                    }
+                    current = Some((start, end, Vec::new()));
+                },
+                None => {
+                    warn!("line {}: invalid SRT timing `{}`, ignoring this cue", n + 1, line);
+                    if let Some((start, end, text)) = current.take() {
+                        push_cue(&mut cues, start, end, &text);
+                    }
+                },
+            }
+            continue;
+        }
+        if let Some((_, _, ref mut text)) = current {
+            text.push(if line.trim().is_empty() { "" } else { line });
+        }
+    }
+    if let Some((start, end, text)) = current.take() {
+        push_cue(&mut cues, start, end, &text);
+    }
+    // stable: the cues starting at the same time stay in the file's order
+    cues.sort_by_key(|cue| cue.start_ms);
+    cues
+}
+
+fn is_srt_index(line: &str) -> bool {
+    let line = line.trim();
+    !line.is_empty() && line.chars().all(|c| c.is_ascii_digit())
+}
+
+fn push_cue(cues: &mut Vec<Cue>, start_ms: i64, end_ms: i64, lines: &[&str]) {
+    // the text can't hold an empty line: what follows is the index of the next cue, whatever
+    // it looks like
+    let text = lines.iter().skip_while(|line| line.is_empty()).take_while(|line| !line.is_empty()).cloned().collect::<Vec<_>>().join("\n");
+    let text = text.trim();
+    if text.is_empty() || end_ms < start_ms {
+        debug!("skipping empty or reversed cue at {}ms", start_ms);
+        return;
+    }
+    cues.push(Cue {
+        start_ms: start_ms,
+        end_ms: end_ms,
+        text: text.to_owned(),
+    });
+}
+
+/// Not UTF-8 files (latin-1 is common) get their invalid bytes replaced
+pub fn load_srt(path: &str) -> Result<Vec<Cue>> {
+    let mut content = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut content))
+        .map_err(|e| Error::Io { what: format!("failed to read {}", path), source: e })?;
+    let cues = parse_srt(&String::from_utf8_lossy(&content));
+    if cues.is_empty() {
+        return Err(Error::InvalidConfig(format!("{} has no SRT cue", path)));
+    }
+    Ok(cues)
+}
+
+/// The position reported can go back a little without anybody seeking, when the VPU corrects it
+const BACKWARD_TOLERANCE_MS : i64 = 500;
+
+/// The cues of a SRT file, handed out as the position of the video reaches them
+pub struct SidecarSubtitles {
+    cues: Vec<Cue>,
+    /// the cues before this one have been sent, or skipped by a seek
+    next: usize,
+    /// after a seek, the cues which ended before this position are skipped
+    from_ms: i64,
+    last_position_ms: i64,
+}
+
+impl SidecarSubtitles {
+    pub fn new(cues: Vec<Cue>) -> SidecarSubtitles {
+        SidecarSubtitles {
+            cues: cues,
+            next: 0,
+            from_ms: 0,
+            last_position_ms: 0,
+        }
+    }
+
+    /// The cue being shown at `position_secs` is sent again, the ones before are not
+    pub fn resync(&mut self, position_secs: f64) {
+        self.next = 0;
+        self.from_ms = (position_secs * 1000.0) as i64;
+        self.last_position_ms = self.from_ms;
+    }
+
+    /// The cues to show now, since the previous call
+    pub fn poll(&mut self, position_secs: f64) -> Vec<Cue> {
+        let position_ms = (position_secs * 1000.0) as i64;
+        if position_ms + BACKWARD_TOLERANCE_MS < self.last_position_ms {
+            // going back without a seek: the backward playback
+            self.resync(position_secs);
+        }
+        self.last_position_ms = position_ms;
+        let mut due = Vec::new();
+        while self.next < self.cues.len() && self.cues[self.next].start_ms <= position_ms {
+            if self.cues[self.next].end_ms > self.from_ms {
+                due.push(self.cues[self.next].clone());
+            }
+            self.next += 1;
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cue(start_ms: i64, end_ms: i64, text: &str) -> Cue {
+        Cue {
+            start_ms: start_ms,
+            end_ms: end_ms,
+            text: text.to_owned(),
+        }
+    }
+
+    const TWO_CUES : &'static str = "1\n00:00:01,000 --> 00:00:02,500\nHello\n\n2\n00:00:03,000 --> 00:00:04,000\nWorld\nagain\n";
+
+    #[test]
+    fn plain_file() {
+        assert_eq!(parse_srt(TWO_CUES), vec![cue(1000, 2500, "Hello"), cue(3000, 4000, "World\nagain")]);
+    }
+
+    #[test]
+    fn bom() {
+        assert_eq!(parse_srt(&format!("\u{feff}{}", TWO_CUES)), parse_srt(TWO_CUES));
+    }
+
+    #[test]
+    fn crlf_and_bare_cr() {
+        assert_eq!(parse_srt(&TWO_CUES.replace('\n', "\r\n")), parse_srt(TWO_CUES));
+        assert_eq!(parse_srt(&TWO_CUES.replace('\n', "\r")), parse_srt(TWO_CUES));
+    }
+
+    #[test]
+    fn bad_indices() {
+        let expected = vec![cue(1000, 2000, "a"), cue(3000, 4000, "b"), cue(5000, 6000, "c")];
+        // not numbers, repeated, missing
+        let content = "one\n00:00:01,000 --> 00:00:02,000\na\n\n1\n00:00:03,000 --> 00:00:04,000\nb\n\n00:00:05,000 --> 00:00:06,000\nc\n";
+        assert_eq!(parse_srt(content), expected);
+        // no blank line before the index of the next cue
+        let content = "1\n00:00:01,000 --> 00:00:02,000\na\n2\n00:00:03,000 --> 00:00:04,000\nb\n3\n00:00:05,000 --> 00:00:06,000\nc\n";
+        assert_eq!(parse_srt(content), expected);
+    }
+
+    #[test]
+    fn overlapping_cues_are_kept_and_sorted() {
+        let content = "1\n00:00:05,000 --> 00:00:08,000\nlate\n\n2\n00:00:01,000 --> 00:00:06,000\nearly\n\n3\n00:00:01,000 --> 00:00:02,000\nsame start\n";
+        assert_eq!(parse_srt(content), vec![cue(1000, 6000, "early"), cue(1000, 2000, "same start"), cue(5000, 8000, "late")]);
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_srt_timestamp("01:02:03,456"), Some(3723456));
+        // dot milliseconds
+        assert_eq!(parse_srt_timestamp("00:00:01.250"), Some(1250));
+        // missing hours
+        assert_eq!(parse_srt_timestamp("02:03,400"), Some(123400));
+        // short milliseconds are the first digits
+        assert_eq!(parse_srt_timestamp("00:00:01,5"), Some(1500));
+        assert_eq!(parse_srt_timestamp("00:00:01,05"), Some(1050));
+        // no milliseconds at all
+        assert_eq!(parse_srt_timestamp("00:00:07"), Some(7000));
+        assert_eq!(parse_srt_timestamp("7"), None);
+        assert_eq!(parse_srt_timestamp("00:xx:01,000"), None);
+        assert_eq!(parse_srt_timestamp("00:00:01,"), None);
+    }
+
+    #[test]
+    fn timing_with_positions() {
+        assert_eq!(parse_srt_timing("00:00:01.000 --> 00:00:02.000 X1:100 X2:600"), Some((1000, 2000)));
+        assert_eq!(parse_srt_timing("00:01,000-->00:02,000"), Some((1000, 2000)));
+    }
+
+    #[test]
+    fn blank_lines_inside_cues() {
+        // right after the timing line, they are skipped
+        let content = "1\n00:00:01,000 --> 00:00:02,000\n\n\nHello\n\n2\n00:00:03,000 --> 00:00:04,000\nWorld\n";
+        assert_eq!(parse_srt(content), vec![cue(1000, 2000, "Hello"), cue(3000, 4000, "World")]);
+        // after the text, they end it
+        let content = "1\n00:00:01,000 --> 00:00:02,000\nHello\n\nstray line\n\n2\n00:00:03,000 --> 00:00:04,000\nWorld\n";
+        assert_eq!(parse_srt(content), vec![cue(1000, 2000, "Hello"), cue(3000, 4000, "World")]);
+        // whitespace-only lines are blank too
+        let content = "1\n00:00:01,000 --> 00:00:02,000\nHello\n  \t\nstray line\n";
+        assert_eq!(parse_srt(content), vec![cue(1000, 2000, "Hello")]);
+    }
+
+    #[test]
+    fn invalid_cues_are_skipped() {
+        let content = "1\n00:00:0x,000 --> 00:00:02,000\nbad timing\n\n2\n00:00:04,000 --> 00:00:03,000\nreversed\n\n3\n00:00:05,000 --> 00:00:06,000\n\n\n4\n00:00:07,000 --> 00:00:08,000\nkept\n";
+        assert_eq!(parse_srt(content), vec![cue(7000, 8000, "kept")]);
+        assert_eq!(parse_srt(""), vec![]);
+        assert_eq!(parse_srt("no cue at all\n"), vec![]);
+    }
+
+    fn texts(cues: Vec<Cue>) -> Vec<String> {
+        cues.into_iter().map(|cue| cue.text).collect()
+    }
+
+    fn sidecar() -> SidecarSubtitles {
+        SidecarSubtitles::new(vec![cue(1000, 3000, "a"), cue(2000, 6000, "b"), cue(7000, 8000, "c"), cue(9000, 10000, "d")])
+    }
+
+    #[test]
+    fn poll_sends_each_cue_once() {
+        let mut subtitles = sidecar();
+        assert!(subtitles.poll(0.5).is_empty());
+        assert_eq!(texts(subtitles.poll(2.0)), vec!["a", "b"]);
+        assert!(subtitles.poll(2.5).is_empty());
+        assert_eq!(texts(subtitles.poll(7.0)), vec!["c"]);
+        assert_eq!(texts(subtitles.poll(20.0)), vec!["d"]);
+        assert!(subtitles.poll(21.0).is_empty());
+    }
+
+    #[test]
+    fn seek_forward_resends_the_cue_being_shown() {
+        let mut subtitles = sidecar();
+        assert_eq!(texts(subtitles.poll(1.0)), vec!["a"]);
+        subtitles.resync(5.0);
+        // "a" ended before 5s, "b" is still on screen
+        assert_eq!(texts(subtitles.poll(5.0)), vec!["b"]);
+        assert_eq!(texts(subtitles.poll(9.5)), vec!["c", "d"]);
+    }
+
+    #[test]
+    fn seek_backward_sends_the_cues_again() {
+        let mut subtitles = sidecar();
+        assert_eq!(texts(subtitles.poll(7.5)), vec!["a", "b", "c"]);
+        subtitles.resync(1.5);
+        assert_eq!(texts(subtitles.poll(1.5)), vec!["a"]);
+        assert_eq!(texts(subtitles.poll(2.0)), vec!["b"]);
+    }
+
+    #[test]
+    fn position_going_back_a_little_resends_nothing() {
+        let mut subtitles = sidecar();
+        assert_eq!(texts(subtitles.poll(2.0)), vec!["a", "b"]);
+        assert!(subtitles.poll(1.7).is_empty());
+        assert!(subtitles.poll(2.1).is_empty());
+    }
+
+    #[test]
+    fn position_going_back_without_a_seek_resyncs() {
+        let mut subtitles = sidecar();
+        assert_eq!(texts(subtitles.poll(9.0)), vec!["a", "b", "c", "d"]);
+        // the backward playback
+        assert_eq!(texts(subtitles.poll(2.5)), vec!["a", "b"]);
+    }
+}
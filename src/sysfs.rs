@@ -0,0 +1,23 @@
+/*
+ * Amlogic drivers expose most of their settings (display mode, picture quality, ...) as sysfs
+ * nodes, which are simple files that can be read and written.
+ */
+
+use error::*;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+
+/// Reads a sysfs node, without the trailing newline
+pub fn read(path: &str) -> Result<String> {
+    let mut content = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut content))
+        .map_err(|e| Error::Sysfs { path: path.to_owned(), source: e })?;
+    Ok(content.trim_right().to_owned())
+}
+
+pub fn write(path: &str, value: &str) -> Result<()> {
+    OpenOptions::new().write(true).open(path)
+        .and_then(|mut f| f.write_all(value.as_bytes()))
+        .map_err(|e| Error::Sysfs { path: path.to_owned(), source: e })
+}
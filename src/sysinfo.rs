@@ -0,0 +1,99 @@
+/*
+ * What board we are running on, for API users deploying on several Amlogic SoCs. None of this is
+ * needed to play anything, so every value falls back to "unknown" when the nodes are missing
+ * (non-Amlogic board, older kernel, ...). The linked libavformat is reported as well, since the
+ * same .so runs with several of its major versions.
+ */
+
+use libavhelper::{avformat_version, Abi};
+use sysfs;
+
+/// Tried in order, the first one which exists is used
+const SOC_PATHS : [&'static str; 2] = [
+    "/proc/device-tree/amlogic-dt-id",
+    "/sys/class/amhdmitx/amhdmitx0/board_type",
+];
+
+const VPU_VERSION_PATHS : [&'static str; 2] = [
+    "/proc/amvideo",
+    "/sys/module/amvideo/version",
+];
+
+/// amlogic-dt-id starts with the codename of the SoC family (gxl_p212_2g, sm1_ac214_4g, ...),
+/// which nobody knows by heart
+const SOC_FAMILIES : [(&'static str, &'static str); 7] = [
+    ("gxbb", "S905"),
+    ("gxl", "S905X"),
+    ("gxm", "S912"),
+    ("g12a", "S905X2"),
+    ("g12b", "S922X"),
+    ("sm1", "S905X3"),
+    ("sc2", "S905X4"),
+];
+
+const UNKNOWN : &'static str = "unknown";
+const UNSUPPORTED : &'static str = "unsupported";
+
+pub struct SysInfo {
+    pub soc: String,
+    pub vpu_version: String,
+    /// of the linked libavformat, as "57.71.100"
+    pub avformat_version: String,
+    /// major version whose struct layouts are used with it (see libavhelper::Abi), "unsupported"
+    /// if the player can't be created
+    pub avformat_abi: String,
+}
+
+impl SysInfo {
+    pub fn read() -> SysInfo {
+        let soc = read_first(&SOC_PATHS).map(|soc| {
+            let family = soc.split(|c| c == '_' || c == '-').next().unwrap_or("").to_lowercase();
+            match SOC_FAMILIES.iter().find(|&&(codename, _)| codename == family) {
+                Some(&(_, model)) => model.to_owned(),
+                None => soc,
+            }
+        });
+        let (major, minor_micro) = avformat_version();
+        SysInfo {
+            soc: soc.unwrap_or_else(|| UNKNOWN.to_owned()),
+            vpu_version: read_first(&VPU_VERSION_PATHS).unwrap_or_else(|| UNKNOWN.to_owned()),
+            avformat_version: format!("{}.{}.{}", major, minor_micro >> 8, minor_micro & 0xFF),
+            avformat_abi: match Abi::detect() {
+                Ok(abi) => abi.major().to_string(),
+                Err(_) => UNSUPPORTED.to_owned(),
+            },
+        }
+    }
+
+    /// {"soc":"S905X3","vpu_ver":"v2.1","avformat_ver":"57.71.100","avformat_abi":"57"}
+    pub fn to_json(&self) -> String {
+        format!("{{\"soc\":\"{}\",\"vpu_ver\":\"{}\",\"avformat_ver\":\"{}\",\"avformat_abi\":\"{}\"}}",
+                json_escape(&self.soc), json_escape(&self.vpu_version),
+                json_escape(&self.avformat_version), json_escape(&self.avformat_abi))
+    }
+}
+
+/// First line of the first node of `paths` which exists and isn't empty. Device tree properties
+/// are null-terminated, so this is trimmed as well.
+fn read_first(paths: &[&str]) -> Option<String> {
+    paths.iter()
+        .filter_map(|path| sysfs::read(path).ok())
+        .filter_map(|content| {
+            let line = content.lines().next().unwrap_or("").trim_matches(|c: char| c == '\0' || c.is_whitespace());
+            if line.is_empty() { None } else { Some(line.to_owned()) }
+        })
+        .next()
+}
+
+fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
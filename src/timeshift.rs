@@ -0,0 +1,199 @@
+/*
+ * "Pause live TV": a live stream can't wait for us, so once it is paused it keeps being demuxed
+ * into a ring buffer on disk instead of piling up in memory (or being dropped by the network).
+ * From then on, the libav thread reads the source as it comes and feeds amcodec from the buffer,
+ * as fast as amcodec takes the packets: the video goes on from where it was paused, as far behind
+ * the live edge as it was paused for.
+ *
+ * Only the packets of the video stream are spooled, their data in the file and everything else in
+ * memory. When the file is full, the oldest packets are overwritten.
+ */
+
+use error::*;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// A video packet as it was demuxed, see libavhelper::Packet
+#[derive(Debug)]
+pub struct SpooledPacket {
+    pub pts: i64,
+    pub dts: i64,
+    pub flags: i32,
+    pub keyframe: bool,
+    /// pts in seconds, None if the packet has none
+    pub time: Option<f64>,
+    pub data: Vec<u8>,
+}
+
+/// Where a packet is in the file, and what isn't stored there
+struct Record {
+    offset: u64,
+    len: usize,
+    pts: i64,
+    dts: i64,
+    flags: i32,
+    keyframe: bool,
+    /// the one of the previous packet if this one has no pts
+    time: f64,
+}
+
+pub struct Timeshift {
+    path: String,
+    file: File,
+    capacity: u64,
+    /// where the next packet is written
+    head: u64,
+    records: VecDeque<Record>,
+    /// number of records[0] since the start, the ones before it were overwritten
+    first_seq: u64,
+    /// number of the next record sent to amcodec
+    cursor: u64,
+    /// after a seek or an overrun, the records up to the next keyframe are skipped
+    wait_keyframe: bool,
+    /// seconds of video kept at most, whatever room is left in the file
+    max_span: Option<f64>,
+}
+
+impl Timeshift {
+    /// The file is truncated, and removed once this is dropped. The oldest packets are dropped
+    /// once the file is full, or once the buffer is longer than max_span seconds.
+    pub fn create(path: &str, capacity: u64, max_span: Option<f64>) -> Result<Timeshift> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)
+            .map_err(|e| Error::Io { what: format!("failed to create {}", path), source: e })?;
+        debug!("timeshifting to {} ({} bytes at most)", path, capacity);
+        Ok(Timeshift {
+            path: path.to_owned(),
+            file: file,
+            capacity: capacity,
+            head: 0,
+            records: VecDeque::new(),
+            first_seq: 0,
+            cursor: 0,
+            wait_keyframe: false,
+            max_span: max_span,
+        })
+    }
+
+    pub fn push(&mut self, packet: SpooledPacket) -> Result<()> {
+        let len = packet.data.len() as u64;
+        if len > self.capacity {
+            return Err(Error::Other(format!("a packet of {} bytes doesn't fit in the timeshift buffer", len)));
+        }
+        if self.head + len > self.capacity {
+            // what the previous lap left after head is the oldest, and too short for this packet
+            while self.records.front().map_or(false, |record| record.offset >= self.head) {
+                self.records.pop_front();
+                self.first_seq += 1;
+            }
+            self.head = 0;
+        }
+        let (start, end) = (self.head, self.head + len);
+        while self.records.front().map_or(false, |record| record.offset < end && start < record.offset + record.len as u64) {
+            self.records.pop_front();
+            self.first_seq += 1;
+        }
+        let path = &self.path;
+        let file = &mut self.file;
+        file.seek(SeekFrom::Start(start))
+            .and_then(|_| file.write_all(&packet.data))
+            .map_err(|e| Error::Io { what: format!("failed to write to {}", path), source: e })?;
+        let time = packet.time
+            .or_else(|| self.records.back().map(|record| record.time))
+            .unwrap_or(0.0);
+        self.records.push_back(Record {
+            offset: start,
+            len: packet.data.len(),
+            pts: packet.pts,
+            dts: packet.dts,
+            flags: packet.flags,
+            keyframe: packet.keyframe,
+            time: time,
+        });
+        self.head = end;
+        if let Some(max_span) = self.max_span {
+            while self.span() > max_span {
+                self.records.pop_front();
+                self.first_seq += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// True if the packets which were about to be sent got overwritten. The next one sent is then
+    /// the oldest keyframe left, which amcodec must start over from.
+    pub fn fell_behind(&mut self) -> bool {
+        if self.cursor >= self.first_seq {
+            return false;
+        }
+        self.cursor = self.first_seq;
+        self.wait_keyframe = true;
+        true
+    }
+
+    /// The next packet to send to amcodec, None if it caught up with the source
+    pub fn next(&mut self) -> Result<Option<SpooledPacket>> {
+        loop {
+            let index = self.cursor.saturating_sub(self.first_seq) as usize;
+            let record = match self.records.get(index) {
+                Some(record) => record,
+                None => return Ok(None),
+            };
+            self.cursor += 1;
+            if self.wait_keyframe && !record.keyframe {
+                continue;
+            }
+            self.wait_keyframe = false;
+            let mut data = vec![0; record.len];
+            let path = &self.path;
+            let file = &mut self.file;
+            file.seek(SeekFrom::Start(record.offset))
+                .and_then(|_| file.read_exact(&mut data))
+                .map_err(|e| Error::Io { what: format!("failed to read {}", path), source: e })?;
+            return Ok(Some(SpooledPacket {
+                pts: record.pts,
+                dts: record.dts,
+                flags: record.flags,
+                keyframe: record.keyframe,
+                time: Some(record.time),
+                data: data,
+            }));
+        }
+    }
+
+    /// Goes to the last keyframe at or before `time` (in seconds): the oldest one if `time` is
+    /// before the buffer, the newest one, at the live edge, if it is after
+    pub fn seek(&mut self, time: f64) {
+        let index = self.records.iter().rposition(|record| record.keyframe && record.time <= time)
+            .or_else(|| self.records.iter().position(|record| record.keyframe))
+            .unwrap_or(self.records.len());
+        self.cursor = self.first_seq + index as u64;
+        self.wait_keyframe = true;
+    }
+
+    /// Goes `delta` seconds away from the last packet sent (backward if negative), see seek
+    pub fn seek_by(&mut self, delta: f64) {
+        let index = (self.cursor.saturating_sub(self.first_seq) as usize).saturating_sub(1);
+        let current = match self.records.get(index).or_else(|| self.records.back()) {
+            Some(record) => record.time,
+            None => return,
+        };
+        self.seek(current + delta);
+    }
+
+    /// Seconds of video in the buffer
+    pub fn span(&self) -> f64 {
+        match (self.records.front(), self.records.back()) {
+            (Some(oldest), Some(newest)) => (newest.time - oldest.time).max(0.0),
+            _ => 0.0,
+        }
+    }
+}
+
+impl Drop for Timeshift {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("failed to remove the timeshift buffer {}: {}", self.path, e);
+        }
+    }
+}
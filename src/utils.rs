@@ -3,21 +3,37 @@
 // It has a very limited use, and we could have done without it with retrospective, but I don't
 // think it's that bad either.
 
-use std::sync::mpsc::{self, SyncSender, Receiver};
+use error::{self, Reply, FfiErrorCode};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::fmt;
+use std::panic;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::sync::mpsc::{self, Sender, SyncSender, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Only used to tell requests apart in the logs
+static NEXT_REQUEST_ID : AtomicUsize = ATOMIC_USIZE_INIT;
 
 pub fn single_use_channel<T>() -> (SingleUseSender<T>, SingleUseReceiver<T>) {
     let (tx, rx) = mpsc::sync_channel(1);
+    let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
     let tx = SingleUseSender {
         inner: tx,
+        id: id,
     };
     let rx = SingleUseReceiver {
         inner: rx,
+        id: id,
     };
     (tx, rx)
 }
 
 pub struct SingleUseReceiver<T> {
     inner: Receiver<T>,
+    id: usize,
 }
 
 #[derive(Clone)]
@@ -30,16 +46,410 @@ pub struct SingleUseReceiver<T> {
 /// of Clone, it is not an issue
 pub struct SingleUseSender<T> {
     inner: SyncSender<T>,
+    id: usize,
 }
 
 impl<T> SingleUseReceiver<T> {
     pub fn recv(self) -> Result<T, mpsc::RecvError> {
         self.inner.recv()
     }
+
+    /// Since this consumes the receiver, a reply arriving after the timeout has nowhere to go: the
+    /// sender's `send` silently fails, and the reply can never be mistaken for the one of another
+    /// call.
+    pub fn recv_timeout(self, timeout: Duration) -> Result<T, mpsc::RecvTimeoutError> {
+        self.inner.recv_timeout(timeout)
+    }
+
+    /// Never blocks. Unlike the other methods, the receiver is kept when nothing was received yet,
+    /// so that it can be tried again later.
+    pub fn try_recv(&self) -> Result<T, mpsc::TryRecvError> {
+        self.inner.try_recv()
+    }
+
+    /// The same as the one of its sender, so that a reply which never came can be matched with
+    /// what the worker thread logged about it
+    pub fn id(&self) -> usize {
+        self.id
+    }
+}
+
+impl<T> fmt::Debug for SingleUseSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SingleUseSender(#{})", self.id)
+    }
 }
 
 impl<T> SingleUseSender<T> {
-    pub fn send(self, value: T) {
-        let _r = self.inner.send(value);
+    /// anything which converts to T is accepted, so that a bare FfiErrorCode or an Error can be
+    /// sent as a Reply
+    ///
+    /// Never blocks: once a clone has sent its value, the channel's only slot is full until the
+    /// receiver takes it, and what the other clones send is dropped instead of waiting for room.
+    pub fn send<V: Into<T>>(self, value: V) {
+        let _r = self.inner.try_send(value.into());
+    }
+
+    /// Every channel gets its own, shared by the clones of the sender
+    pub fn id(&self) -> usize {
+        self.id
+    }
+}
+
+impl SingleUseSender<Reply> {
+    /// Replies with the result of `f`, logging the error if there is one. `what` completes
+    /// "error when ...", as in "setting the gamma".
+    pub fn respond_with<F: FnOnce() -> error::Result<()>>(self, what: &str, f: F) {
+        self.respond_value_with(None, what, f)
+    }
+
+    /// Same as respond_with for the queries which return something: the value is sent to
+    /// `value_tx` before the reply, so that it's already there when the caller gets the reply.
+    pub fn respond_value_with<V, F: FnOnce() -> error::Result<V>>(self, value_tx: Option<SingleUseSender<V>>, what: &str, f: F) {
+        match f() {
+            Ok(value) => {
+                if let Some(value_tx) = value_tx {
+                    value_tx.send(value);
+                }
+                self.send(FfiErrorCode::None);
+            },
+            Err(e) => {
+                error!("request #{}: error when {}: {}", self.id, what, e.display());
+                self.send(e);
+            }
+        }
+    }
+}
+
+/// A query sent to another thread, along with the channel its reply has to be sent to
+///
+/// `id` is the one of the reply channel: a request forwarded from one thread to another keeps it,
+/// so every log line about it can be told apart from the others.
+pub struct Request<Q, R> {
+    pub id: usize,
+    pub query: Q,
+    pub reply: SingleUseSender<R>,
+}
+
+impl<Q, R> Request<Q, R> {
+    pub fn new(query: Q, reply: SingleUseSender<R>) -> Request<Q, R> {
+        Request {
+            id: reply.id(),
+            query: query,
+            reply: reply,
+        }
+    }
+}
+
+/// Converts a Duration to a number of seconds
+pub fn duration_to_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9
+}
+
+/// Converts a positive number of seconds to a Duration. Negative values give an empty Duration.
+pub fn secs_to_duration(secs: f64) -> Duration {
+    if secs <= 0.0 {
+        return Duration::new(0, 0);
+    }
+    Duration::new(secs.trunc() as u64, (secs.fract() * 1e9) as u32)
+}
+
+/// A token bucket: `take` allows at most `rate` calls per second on average, with bursts of at
+/// most `burst` calls. A rate of 0 means unlimited.
+pub struct TokenBucket {
+    rate: u32,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u32, burst: u32) -> TokenBucket {
+        let burst = if burst == 0 { 1.0 } else { burst as f64 };
+        TokenBucket {
+            rate: rate,
+            burst: burst,
+            tokens: burst,
+            last_refill: Instant::now(),
+        }
+    }
+
+    pub fn set_rate(&mut self, rate: u32) {
+        self.rate = rate;
+        self.refill();
+    }
+
+    /// Fills the bucket entirely, allowing a burst right away
+    pub fn refill(&mut self) {
+        self.tokens = self.burst;
+        self.last_refill = Instant::now();
+    }
+
+    fn add_tokens(&mut self) {
+        let now = Instant::now();
+        let elapsed = duration_to_secs(now.duration_since(self.last_refill));
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.burst);
+    }
+
+    /// Takes a token, sleeping until one is available if needed
+    pub fn take(&mut self) {
+        if self.rate == 0 {
+            return;
+        }
+        self.add_tokens();
+        if self.tokens < 1.0 {
+            thread::sleep(secs_to_duration((1.0 - self.tokens) / self.rate as f64));
+            self.add_tokens();
+        }
+        self.tokens = (self.tokens - 1.0).max(0.0);
+    }
+}
+
+/// Timestamps (in seconds) of the last keyframes seen, the oldest ones being forgotten once they
+/// are more than `span` seconds behind the newest one
+pub struct KeyframeHistory {
+    span: f64,
+    keyframes: VecDeque<f64>,
+}
+
+impl KeyframeHistory {
+    pub fn new(span: f64) -> KeyframeHistory {
+        KeyframeHistory {
+            span: span,
+            keyframes: VecDeque::new(),
+        }
+    }
+
+    /// Timestamps must be pushed in increasing order, the ones that aren't are ignored
+    pub fn push(&mut self, time: f64) {
+        if let Some(&newest) = self.keyframes.back() {
+            if time <= newest {
+                return;
+            }
+        }
+        self.keyframes.push_back(time);
+        while let Some(&oldest) = self.keyframes.front() {
+            if time - oldest <= self.span {
+                break;
+            }
+            self.keyframes.pop_front();
+        }
+    }
+
+    pub fn newest(&self) -> Option<f64> {
+        self.keyframes.back().cloned()
+    }
+
+    /// Forgets every keyframe at or after `time`, and returns the one right before it, which is
+    /// kept
+    pub fn previous(&mut self, time: f64) -> Option<f64> {
+        while let Some(&newest) = self.keyframes.back() {
+            if newest < time {
+                return Some(newest);
+            }
+            self.keyframes.pop_back();
+        }
+        None
+    }
+
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+    }
+}
+
+/// Spawns a worker thread which tells `deaths` (name, reason) if it panics, or if it stops while
+/// keep_running is still true. The panic is then resumed, so that joining the thread still
+/// returns an error.
+pub fn spawn_supervised<F>(name: &'static str, deaths: Sender<(&'static str, String)>, keep_running: Arc<AtomicBool>, f: F) -> JoinHandle<()>
+    where F: FnOnce() + Send + 'static {
+    thread::Builder::new().name(name.to_owned()).spawn(move || {
+        match panic::catch_unwind(panic::AssertUnwindSafe(f)) {
+            Ok(()) => {
+                if keep_running.load(Ordering::SeqCst) {
+                    let _r = deaths.send((name, String::from("stopped unexpectedly")));
+                }
+            },
+            Err(payload) => {
+                let _r = deaths.send((name, format!("panicked: {}", panic_message(&*payload))));
+                panic::resume_unwind(payload);
+            }
+        }
+    }).expect("failed to spawn thread")
+}
+
+fn panic_message(payload: &(Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        String::from(*s)
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        String::from("unknown panic payload")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::RecvError;
+
+    #[test]
+    fn second_send_is_dropped() {
+        let (tx, rx) = single_use_channel::<u32>();
+        let tx2 = tx.clone();
+        tx.send(1u32);
+        // the slot is full: this must neither block nor replace the first value
+        tx2.send(2u32);
+        assert_eq!(rx.recv(), Ok(1));
+    }
+
+    #[test]
+    fn sender_dropped_without_sending() {
+        let (tx, rx) = single_use_channel::<u32>();
+        drop(tx);
+        assert_eq!(rx.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn only_the_first_clone_is_received() {
+        let (tx, rx) = single_use_channel::<usize>();
+        let senders : Vec<_> = (0..8usize).map(|i| {
+            let tx = tx.clone();
+            thread::spawn(move || tx.send(i))
+        }).collect();
+        drop(tx);
+        for sender in senders {
+            sender.join().unwrap();
+        }
+        let first = rx.inner.recv().unwrap();
+        assert!(first < 8);
+        // every sender is gone, and nothing else was queued behind the first value
+        assert_eq!(rx.inner.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn send_after_the_receiver_is_dropped() {
+        let (tx, rx) = single_use_channel::<u32>();
+        drop(rx);
+        thread::spawn(move || tx.send(1u32)).join().unwrap();
+    }
+
+    #[test]
+    fn cross_thread() {
+        let (tx, rx) = single_use_channel::<String>();
+        let receiver = thread::spawn(move || rx.recv());
+        thread::spawn(move || tx.send(String::from("reply"))).join().unwrap();
+        assert_eq!(receiver.join().unwrap(), Ok(String::from("reply")));
+    }
+
+    #[test]
+    fn reply_before_the_timeout() {
+        let (tx, rx) = single_use_channel::<u32>();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            tx.send(1u32);
+        });
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(1));
+    }
+
+    #[test]
+    fn late_reply_after_the_timeout() {
+        let (tx, rx) = single_use_channel::<u32>();
+        let (late_tx, late_rx) = mpsc::channel();
+        let sender = thread::spawn(move || {
+            // only replies once the caller gave up
+            late_rx.recv().unwrap();
+            tx.send(1u32);
+        });
+        assert_eq!(rx.recv_timeout(Duration::from_millis(10)), Err(mpsc::RecvTimeoutError::Timeout));
+        late_tx.send(()).unwrap();
+        // the reply has nowhere to go, and the sender doesn't block on it
+        sender.join().unwrap();
+        // nor does it reach the next call
+        let (tx, rx) = single_use_channel::<u32>();
+        tx.send(2u32);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(2));
+    }
+
+    #[test]
+    fn sender_dropped_before_the_timeout() {
+        let (tx, rx) = single_use_channel::<u32>();
+        thread::spawn(move || drop(tx));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Err(mpsc::RecvTimeoutError::Disconnected));
+    }
+
+    #[test]
+    fn try_recv_keeps_the_receiver() {
+        let (tx, rx) = single_use_channel::<u32>();
+        assert_eq!(rx.try_recv(), Err(mpsc::TryRecvError::Empty));
+        tx.send(1u32);
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Err(mpsc::TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn clones_share_the_id() {
+        let (tx, _rx) = single_use_channel::<u32>();
+        let (other, _other_rx) = single_use_channel::<u32>();
+        assert_eq!(tx.clone().id(), tx.id());
+        assert!(other.id() != tx.id());
+    }
+
+    #[test]
+    fn requests_keep_the_id_of_their_reply() {
+        let (tx, rx) = single_use_channel::<Reply>();
+        let request = Request::new("query", tx.clone());
+        assert_eq!(request.id, tx.id());
+        assert_eq!(rx.id(), tx.id());
+        // forwarded to another thread, the request is still the same one
+        let forwarded = Request::new(request.query, request.reply.clone());
+        assert_eq!(forwarded.id, request.id);
+    }
+
+    #[test]
+    fn respond_with_ok() {
+        let (tx, rx) = single_use_channel::<Reply>();
+        tx.respond_with("doing nothing", || Ok(()));
+        let reply = rx.recv().unwrap();
+        assert_eq!(reply.code as i32, FfiErrorCode::None as i32);
+        assert!(reply.report.is_none());
+    }
+
+    #[test]
+    fn respond_with_error() {
+        let (tx, rx) = single_use_channel::<Reply>();
+        tx.respond_with("failing", || Err(error::Error::Other(String::from("nope"))));
+        let reply = rx.recv().unwrap();
+        assert_eq!(reply.code as i32, FfiErrorCode::Unknown as i32);
+        let report = reply.report.expect("the error should be reported");
+        assert_eq!(report.code as i32, FfiErrorCode::Unknown as i32);
+        assert!(report.message.contains("nope"));
+    }
+
+    #[test]
+    fn respond_value_with_sends_the_value_first() {
+        let (tx, rx) = single_use_channel::<Reply>();
+        let (value_tx, value_rx) = single_use_channel::<u32>();
+        tx.respond_value_with(Some(value_tx), "counting", || Ok(7));
+        assert_eq!(rx.recv().unwrap().code as i32, FfiErrorCode::None as i32);
+        // already there when the reply is, without waiting
+        assert_eq!(value_rx.try_recv(), Ok(7));
+    }
+
+    #[test]
+    fn respond_value_with_error_sends_no_value() {
+        let (tx, rx) = single_use_channel::<Reply>();
+        let (value_tx, value_rx) = single_use_channel::<u32>();
+        tx.respond_value_with(Some(value_tx), "counting", || Err(error::Error::Other(String::from("nope"))));
+        assert_eq!(rx.recv().unwrap().code as i32, FfiErrorCode::Unknown as i32);
+        // the sender was dropped along with the closure's result
+        assert_eq!(value_rx.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn respond_value_with_nobody_waiting_for_the_value() {
+        let (tx, rx) = single_use_channel::<Reply>();
+        tx.respond_value_with(None, "counting", || Ok(7));
+        assert_eq!(rx.recv().unwrap().code as i32, FfiErrorCode::None as i32);
     }
 }
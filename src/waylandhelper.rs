@@ -0,0 +1,500 @@
+/*
+ * Wayland equivalent of x11helper.rs
+ *
+ * libwayland-client is loaded at runtime (like x11-dl does for Xlib), so that the library can still
+ * be used on systems where it isn't installed.
+ *
+ * The idea is exactly the same as with X11: we create a surface whose buffer is ARGB8888 and
+ * entirely filled with 0, making it transparent on the framebuffer level, so that the VPU's layer
+ * can be seen through it.
+ *
+ * We are using the wl_shell protocol because it is part of the core protocol (its interfaces are
+ * exported by libwayland-client itself, we don't need any generated code), and it is enough for
+ * what we are doing. Note that Wayland clients cannot choose where their surface is placed:
+ * set_pos only moves the video, not the surface.
+ */
+
+use error::*;
+use super::window::{Window, WindowEvent, DisplayPath};
+use super::amcodec::fb_screen_size;
+
+use libc::{self, c_char, c_int, c_void};
+use std::ffi::{CStr, CString};
+use std::sync::{Arc, Mutex, atomic};
+use std::sync::mpsc::Sender;
+use std::{env, mem, ptr};
+
+// opaque type: every wayland object (including the display) is a proxy on the client side
+enum WlProxy {}
+
+#[repr(C)]
+struct WlInterface {
+    name: *const c_char,
+    version: c_int,
+    // methods and events are never read by us
+    method_count: c_int,
+    methods: *const c_void,
+    event_count: c_int,
+    events: *const c_void,
+}
+
+// request opcodes, see wayland.xml
+const WL_DISPLAY_GET_REGISTRY : u32 = 1;
+const WL_REGISTRY_BIND : u32 = 0;
+const WL_COMPOSITOR_CREATE_SURFACE : u32 = 0;
+const WL_SHM_CREATE_POOL : u32 = 0;
+const WL_SHM_POOL_CREATE_BUFFER : u32 = 0;
+const WL_SHM_POOL_DESTROY : u32 = 1;
+const WL_BUFFER_DESTROY : u32 = 0;
+const WL_SHELL_GET_SHELL_SURFACE : u32 = 0;
+const WL_SHELL_SURFACE_PONG : u32 = 0;
+const WL_SHELL_SURFACE_SET_TOPLEVEL : u32 = 3;
+const WL_SHELL_SURFACE_SET_FULLSCREEN : u32 = 5;
+const WL_SURFACE_DESTROY : u32 = 0;
+const WL_SURFACE_ATTACH : u32 = 1;
+const WL_SURFACE_DAMAGE : u32 = 2;
+const WL_SURFACE_COMMIT : u32 = 6;
+
+const WL_SHM_FORMAT_ARGB8888 : u32 = 0;
+
+/// Function pointers and interfaces retrieved from libwayland-client.so
+struct WaylandLib {
+    handle: *mut c_void,
+    display_connect: unsafe extern "C" fn(*const c_char) -> *mut WlProxy,
+    display_disconnect: unsafe extern "C" fn(*mut WlProxy),
+    display_roundtrip: unsafe extern "C" fn(*mut WlProxy) -> c_int,
+    display_dispatch: unsafe extern "C" fn(*mut WlProxy) -> c_int,
+    display_dispatch_pending: unsafe extern "C" fn(*mut WlProxy) -> c_int,
+    display_flush: unsafe extern "C" fn(*mut WlProxy) -> c_int,
+    display_get_fd: unsafe extern "C" fn(*mut WlProxy) -> c_int,
+    proxy_marshal: unsafe extern "C" fn(*mut WlProxy, u32, ...),
+    proxy_marshal_constructor: unsafe extern "C" fn(*mut WlProxy, u32, *const WlInterface, ...) -> *mut WlProxy,
+    proxy_marshal_constructor_versioned: unsafe extern "C" fn(*mut WlProxy, u32, *const WlInterface, u32, ...) -> *mut WlProxy,
+    proxy_add_listener: unsafe extern "C" fn(*mut WlProxy, *const c_void, *mut c_void) -> c_int,
+    proxy_destroy: unsafe extern "C" fn(*mut WlProxy),
+    registry_interface: *const WlInterface,
+    compositor_interface: *const WlInterface,
+    surface_interface: *const WlInterface,
+    shm_interface: *const WlInterface,
+    shm_pool_interface: *const WlInterface,
+    buffer_interface: *const WlInterface,
+    shell_interface: *const WlInterface,
+    shell_surface_interface: *const WlInterface,
+}
+
+// retrieves a symbol from the library and transmutes it to whatever type is expected
+macro_rules! wl_symbol {
+    ($handle: expr, $name: expr) => {{
+        let name = CString::new($name).unwrap();
+        let symbol = libc::dlsym($handle, name.as_ptr());
+        if symbol.is_null() {
+            libc::dlclose($handle);
+            return Err(Error::Wayland(format!("symbol {} not found in libwayland-client", $name)));
+        }
+        mem::transmute(symbol)
+    }};
+}
+
+impl WaylandLib {
+    fn open() -> Result<WaylandLib> {
+        unsafe {
+            let lib_name = CString::new("libwayland-client.so.0").unwrap();
+            let handle = libc::dlopen(lib_name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+            if handle.is_null() {
+                return Err(Error::Wayland(String::from("failed to load libwayland-client.so.0")));
+            }
+            Ok(WaylandLib {
+                handle: handle,
+                display_connect: wl_symbol!(handle, "wl_display_connect"),
+                display_disconnect: wl_symbol!(handle, "wl_display_disconnect"),
+                display_roundtrip: wl_symbol!(handle, "wl_display_roundtrip"),
+                display_dispatch: wl_symbol!(handle, "wl_display_dispatch"),
+                display_dispatch_pending: wl_symbol!(handle, "wl_display_dispatch_pending"),
+                display_flush: wl_symbol!(handle, "wl_display_flush"),
+                display_get_fd: wl_symbol!(handle, "wl_display_get_fd"),
+                proxy_marshal: wl_symbol!(handle, "wl_proxy_marshal"),
+                proxy_marshal_constructor: wl_symbol!(handle, "wl_proxy_marshal_constructor"),
+                proxy_marshal_constructor_versioned: wl_symbol!(handle, "wl_proxy_marshal_constructor_versioned"),
+                proxy_add_listener: wl_symbol!(handle, "wl_proxy_add_listener"),
+                proxy_destroy: wl_symbol!(handle, "wl_proxy_destroy"),
+                registry_interface: wl_symbol!(handle, "wl_registry_interface"),
+                compositor_interface: wl_symbol!(handle, "wl_compositor_interface"),
+                surface_interface: wl_symbol!(handle, "wl_surface_interface"),
+                shm_interface: wl_symbol!(handle, "wl_shm_interface"),
+                shm_pool_interface: wl_symbol!(handle, "wl_shm_pool_interface"),
+                buffer_interface: wl_symbol!(handle, "wl_buffer_interface"),
+                shell_interface: wl_symbol!(handle, "wl_shell_interface"),
+                shell_surface_interface: wl_symbol!(handle, "wl_shell_surface_interface"),
+            })
+        }
+    }
+}
+
+impl Drop for WaylandLib {
+    fn drop(&mut self) {
+        unsafe {
+            libc::dlclose(self.handle);
+        }
+    }
+}
+
+/// Globals announced by the compositor that we care about. This is filled by the registry
+/// listener during the initial roundtrip.
+struct Globals {
+    lib: *const WaylandLib,
+    registry: *mut WlProxy,
+    compositor: *mut WlProxy,
+    shm: *mut WlProxy,
+    shell: *mut WlProxy,
+}
+
+/// State shared with the shell surface listener
+struct ShellSurfaceState {
+    lib: *const WaylandLib,
+    /// size the compositor wants us to have (when fullscreen for instance)
+    configured_size: Mutex<Option<(i32, i32)>>,
+}
+
+// a listener in libwayland is simply an array of function pointers, one per event
+#[repr(C)]
+struct RegistryListener {
+    global: unsafe extern "C" fn(*mut c_void, *mut WlProxy, u32, *const c_char, u32),
+    global_remove: unsafe extern "C" fn(*mut c_void, *mut WlProxy, u32),
+}
+
+#[repr(C)]
+struct ShellSurfaceListener {
+    ping: unsafe extern "C" fn(*mut c_void, *mut WlProxy, u32),
+    configure: unsafe extern "C" fn(*mut c_void, *mut WlProxy, u32, i32, i32),
+    popup_done: unsafe extern "C" fn(*mut c_void, *mut WlProxy),
+}
+
+static REGISTRY_LISTENER : RegistryListener = RegistryListener {
+    global: registry_global,
+    global_remove: registry_global_remove,
+};
+
+static SHELL_SURFACE_LISTENER : ShellSurfaceListener = ShellSurfaceListener {
+    ping: shell_surface_ping,
+    configure: shell_surface_configure,
+    popup_done: shell_surface_popup_done,
+};
+
+unsafe extern "C" fn registry_global(data: *mut c_void, registry: *mut WlProxy, name: u32, interface: *const c_char, _version: u32) {
+    let globals = &mut *(data as *mut Globals);
+    let lib = &*globals.lib;
+    let interface = CStr::from_ptr(interface).to_bytes();
+    let (target, wl_interface) = match interface {
+        b"wl_compositor" => (&mut globals.compositor, lib.compositor_interface),
+        b"wl_shm" => (&mut globals.shm, lib.shm_interface),
+        b"wl_shell" => (&mut globals.shell, lib.shell_interface),
+        _ => return,
+    };
+    // version 1 of every interface is enough for us
+    *target = (lib.proxy_marshal_constructor_versioned)(registry, WL_REGISTRY_BIND, wl_interface, 1,
+                                                          name, (*wl_interface).name, 1u32, ptr::null_mut::<WlProxy>());
+}
+
+unsafe extern "C" fn registry_global_remove(_data: *mut c_void, _registry: *mut WlProxy, _name: u32) {
+}
+
+unsafe extern "C" fn shell_surface_ping(data: *mut c_void, shell_surface: *mut WlProxy, serial: u32) {
+    // the compositor considers us unresponsive if we don't answer
+    let state = &*(data as *const ShellSurfaceState);
+    ((*state.lib).proxy_marshal)(shell_surface, WL_SHELL_SURFACE_PONG, serial);
+}
+
+unsafe extern "C" fn shell_surface_configure(data: *mut c_void, _shell_surface: *mut WlProxy, _edges: u32, width: i32, height: i32) {
+    let state = &*(data as *const ShellSurfaceState);
+    if width > 0 && height > 0 {
+        if let Ok(mut configured_size) = state.configured_size.lock() {
+            *configured_size = Some((width, height));
+        }
+    }
+}
+
+unsafe extern "C" fn shell_surface_popup_done(_data: *mut c_void, _shell_surface: *mut WlProxy) {
+}
+
+/// A wl_buffer filled with transparent pixels
+struct ShmBuffer {
+    buffer: *mut WlProxy,
+    width: i32,
+    height: i32,
+}
+
+struct Display(pub *mut WlProxy);
+
+// same as in x11helper: libwayland-client is thread safe
+unsafe impl Send for Display {}
+unsafe impl Sync for Display {}
+
+pub struct WaylandHelper {
+    display: Display,
+    globals: Box<Globals>,
+    surface: *mut WlProxy,
+    shell_surface: *mut WlProxy,
+    shell_surface_state: Box<ShellSurfaceState>,
+    buffer: Mutex<Option<ShmBuffer>>,
+    /// size requested via set_size, the configured size may differ when fullscreen
+    requested_size: Mutex<(i32, i32)>,
+    fullscreen: atomic::AtomicBool,
+    visible: atomic::AtomicBool,
+    // fields are dropped in declaration order, and the lib must be dropped last
+    lib: Box<WaylandLib>,
+}
+
+unsafe impl Send for WaylandHelper {}
+unsafe impl Sync for WaylandHelper {}
+
+impl Drop for WaylandHelper {
+    fn drop(&mut self) {
+        unsafe {
+            if let Ok(mut buffer) = self.buffer.lock() {
+                if let Some(buffer) = buffer.take() {
+                    (self.lib.proxy_marshal)(buffer.buffer, WL_BUFFER_DESTROY);
+                    (self.lib.proxy_destroy)(buffer.buffer);
+                }
+            }
+            (self.lib.proxy_destroy)(self.shell_surface);
+            (self.lib.proxy_marshal)(self.surface, WL_SURFACE_DESTROY);
+            (self.lib.proxy_destroy)(self.surface);
+            for proxy in &[self.globals.shell, self.globals.shm, self.globals.compositor, self.globals.registry] {
+                (self.lib.proxy_destroy)(*proxy);
+            }
+            (self.lib.display_disconnect)(self.display.0);
+        }
+    }
+}
+
+impl WaylandHelper {
+    /// (width, height) is the initial size of the surface
+    pub fn new((width, height): (u16, u16)) -> Result<WaylandHelper> {
+        let lib = Box::new(WaylandLib::open()?);
+        unsafe {
+            let display = (lib.display_connect)(ptr::null());
+            if display.is_null() {
+                return Err(Error::Wayland(String::from("wl_display_connect failed")));
+            }
+            let mut globals = Box::new(Globals {
+                lib: &*lib as *const WaylandLib,
+                registry: ptr::null_mut(),
+                compositor: ptr::null_mut(),
+                shm: ptr::null_mut(),
+                shell: ptr::null_mut(),
+            });
+            globals.registry = (lib.proxy_marshal_constructor)(display, WL_DISPLAY_GET_REGISTRY, lib.registry_interface, ptr::null_mut::<WlProxy>());
+            (lib.proxy_add_listener)(globals.registry,
+                                     &REGISTRY_LISTENER as *const _ as *const c_void,
+                                     &mut *globals as *mut Globals as *mut c_void);
+            // the registry announces every global during this first roundtrip
+            (lib.display_roundtrip)(display);
+            if globals.compositor.is_null() || globals.shm.is_null() || globals.shell.is_null() {
+                (lib.display_disconnect)(display);
+                return Err(Error::Wayland(String::from("compositor doesn't support wl_compositor, wl_shm or wl_shell")));
+            }
+
+            let surface = (lib.proxy_marshal_constructor)(globals.compositor, WL_COMPOSITOR_CREATE_SURFACE, lib.surface_interface, ptr::null_mut::<WlProxy>());
+            let shell_surface = (lib.proxy_marshal_constructor)(globals.shell, WL_SHELL_GET_SHELL_SURFACE, lib.shell_surface_interface,
+                                                                 ptr::null_mut::<WlProxy>(), surface);
+            let mut shell_surface_state = Box::new(ShellSurfaceState {
+                lib: &*lib as *const WaylandLib,
+                configured_size: Mutex::new(None),
+            });
+            (lib.proxy_add_listener)(shell_surface,
+                                     &SHELL_SURFACE_LISTENER as *const _ as *const c_void,
+                                     &mut *shell_surface_state as *mut ShellSurfaceState as *mut c_void);
+            (lib.proxy_marshal)(shell_surface, WL_SHELL_SURFACE_SET_TOPLEVEL);
+
+            let helper = WaylandHelper {
+                display: Display(display),
+                globals: globals,
+                surface: surface,
+                shell_surface: shell_surface,
+                shell_surface_state: shell_surface_state,
+                buffer: Mutex::new(None),
+                requested_size: Mutex::new((width as i32, height as i32)),
+                fullscreen: atomic::AtomicBool::new(false),
+                visible: atomic::AtomicBool::new(true),
+                lib: lib,
+            };
+            helper.resize_buffer(width as i32, height as i32)?;
+            Ok(helper)
+        }
+    }
+
+    /// Creates an ARGB8888 buffer filled with 0 (hence transparent) of the given size
+    fn create_buffer(&self, width: i32, height: i32) -> Result<ShmBuffer> {
+        let stride = width * 4;
+        let size = stride * height;
+        // wl_shm needs a file descriptor, we create an unlinked file in XDG_RUNTIME_DIR (which is
+        // usually a tmpfs) for this purpose
+        let runtime_dir = env::var("XDG_RUNTIME_DIR").unwrap_or(String::from("/tmp"));
+        let template = CString::new(format!("{}/c2player-shm-XXXXXX", runtime_dir)).unwrap();
+        let mut template = template.into_bytes_with_nul();
+        unsafe {
+            let fd = libc::mkstemp(template.as_mut_ptr() as *mut c_char);
+            if fd < 0 {
+                return Err(Error::Wayland(String::from("failed to create shm file")));
+            }
+            libc::unlink(template.as_ptr() as *const c_char);
+            // ftruncate fills the file with 0: every pixel is transparent black
+            if libc::ftruncate(fd, size as libc::off_t) < 0 {
+                libc::close(fd);
+                return Err(Error::Wayland(String::from("failed to resize shm file")));
+            }
+            let pool = (self.lib.proxy_marshal_constructor)(self.globals.shm, WL_SHM_CREATE_POOL, self.lib.shm_pool_interface,
+                                                            ptr::null_mut::<WlProxy>(), fd, size);
+            let buffer = (self.lib.proxy_marshal_constructor)(pool, WL_SHM_POOL_CREATE_BUFFER, self.lib.buffer_interface,
+                                                              ptr::null_mut::<WlProxy>(), 0i32, width, height, stride, WL_SHM_FORMAT_ARGB8888);
+            // the buffer keeps the memory alive, we don't need the pool nor the fd anymore
+            (self.lib.proxy_marshal)(pool, WL_SHM_POOL_DESTROY);
+            (self.lib.proxy_destroy)(pool);
+            libc::close(fd);
+            Ok(ShmBuffer {
+                buffer: buffer,
+                width: width,
+                height: height,
+            })
+        }
+    }
+
+    /// replaces the current buffer by a buffer of the given size, and attaches it if the surface
+    /// is visible
+    fn resize_buffer(&self, width: i32, height: i32) -> Result<()> {
+        let mut current = self.buffer.lock().unwrap();
+        if let Some(ref buffer) = *current {
+            if buffer.width == width && buffer.height == height {
+                return Ok(());
+            }
+        }
+        let new_buffer = self.create_buffer(width, height)?;
+        if self.visible.load(atomic::Ordering::SeqCst) {
+            self.attach(new_buffer.buffer, width, height);
+        }
+        if let Some(old_buffer) = current.take() {
+            unsafe {
+                (self.lib.proxy_marshal)(old_buffer.buffer, WL_BUFFER_DESTROY);
+                (self.lib.proxy_destroy)(old_buffer.buffer);
+            }
+        }
+        *current = Some(new_buffer);
+        Ok(())
+    }
+
+    /// attaching a null buffer unmaps the surface
+    fn attach(&self, buffer: *mut WlProxy, width: i32, height: i32) {
+        unsafe {
+            (self.lib.proxy_marshal)(self.surface, WL_SURFACE_ATTACH, buffer, 0i32, 0i32);
+            (self.lib.proxy_marshal)(self.surface, WL_SURFACE_DAMAGE, 0i32, 0i32, width, height);
+            (self.lib.proxy_marshal)(self.surface, WL_SURFACE_COMMIT);
+            (self.lib.display_flush)(self.display.0);
+        }
+    }
+}
+
+impl Window for WaylandHelper {
+    fn backend_name(&self) -> &'static str {
+        "wayland"
+    }
+
+    // every Wayland compositor is, well, a compositor
+    fn display_path(&self) -> DisplayPath {
+        DisplayPath::Compositor
+    }
+
+    // wl_shell surfaces never have decorations drawn by the compositor
+    fn set_borderless(&self, _borderless: bool) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_fullscreen(&self, fullscreen: bool) -> Result<()> {
+        self.fullscreen.store(fullscreen, atomic::Ordering::SeqCst);
+        unsafe {
+            if fullscreen {
+                // method 0 is "default", framerate 0 is "don't care", and a null output lets the
+                // compositor choose
+                (self.lib.proxy_marshal)(self.shell_surface, WL_SHELL_SURFACE_SET_FULLSCREEN,
+                                         0u32, 0u32, ptr::null_mut::<WlProxy>());
+            } else {
+                (self.lib.proxy_marshal)(self.shell_surface, WL_SHELL_SURFACE_SET_TOPLEVEL);
+            }
+            (self.lib.display_flush)(self.display.0);
+        }
+        if !fullscreen {
+            let (w, h) = *self.requested_size.lock().unwrap();
+            self.resize_buffer(w, h)?;
+        }
+        Ok(())
+    }
+
+    // the event loop is also where we apply the size the compositor asked for
+    fn event_loop(&self, keep_running: Arc<atomic::AtomicBool>, events: Sender<WindowEvent>) {
+        let fd = unsafe { (self.lib.display_get_fd)(self.display.0) };
+        loop {
+            unsafe {
+                (self.lib.display_dispatch_pending)(self.display.0);
+                (self.lib.display_flush)(self.display.0);
+                // wait 50ms at most for new events
+                let mut pollfd = libc::pollfd {
+                    fd: fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                if libc::poll(&mut pollfd, 1, 50) > 0 {
+                    if (self.lib.display_dispatch)(self.display.0) < 0 {
+                        error!("connection to the compositor lost");
+                        break;
+                    }
+                }
+            }
+            let configured_size = self.shell_surface_state.configured_size.lock().unwrap().take();
+            if let Some((w, h)) = configured_size {
+                if self.fullscreen.load(atomic::Ordering::SeqCst) {
+                    if let Err(e) = self.resize_buffer(w, h) {
+                        error!("failed to resize the surface: {}", e.display());
+                    }
+                    // a fullscreen surface is as big as the output, so this is the new resolution
+                    if w > 0 && h > 0 {
+                        let _r = events.send(WindowEvent::ScreenResized(w as u32, h as u32));
+                    }
+                }
+            }
+            if !keep_running.load(atomic::Ordering::SeqCst) {
+                break;
+            };
+        }
+        debug!("shutting down ...");
+    }
+
+    fn show(&self) {
+        self.visible.store(true, atomic::Ordering::SeqCst);
+        let buffer = self.buffer.lock().unwrap();
+        if let Some(ref buffer) = *buffer {
+            self.attach(buffer.buffer, buffer.width, buffer.height);
+        }
+    }
+
+    fn hide(&self) {
+        self.visible.store(false, atomic::Ordering::SeqCst);
+        self.attach(ptr::null_mut(), 0, 0);
+    }
+
+    // we don't bind wl_output, and the compositor is displaying on the framebuffer anyway
+    fn screen_size(&self) -> Result<(u32, u32)> {
+        fb_screen_size()
+    }
+
+    // Wayland clients are not allowed to position their own surfaces, the compositor decides.
+    fn set_pos(&self, _x: i32, _y: i32) {
+    }
+
+    fn set_size(&self, w: u32, h: u32) {
+        *self.requested_size.lock().unwrap() = (w as i32, h as i32);
+        if !self.fullscreen.load(atomic::Ordering::SeqCst) {
+            if let Err(e) = self.resize_buffer(w as i32, h as i32) {
+                error!("failed to resize the wayland surface: {}", e.display());
+            }
+        }
+    }
+}
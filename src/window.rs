@@ -0,0 +1,168 @@
+/*
+ * The windowing system is only needed to "punch a hole" in the desktop: a transparent surface is
+ * placed where the video should be, and the VPU's layer is shown through it.
+ *
+ * Both X11 and Wayland can do that, so the main thread only talks to a `Window`, and never needs
+ * to know which backend is actually used. amcodec and libav don't know about the window at all.
+ */
+
+use error::*;
+use std::env;
+use std::ptr;
+use std::sync::{Arc, atomic};
+use std::sync::mpsc::Sender;
+use super::x11helper::X11Helper;
+use super::waylandhelper::WaylandHelper;
+use super::stubwindow::StubWindow;
+
+/// Environment variable which allows to force a backend instead of the automatic selection.
+///
+/// Accepted values are "x11", "wayland", "headless" and "stub".
+pub const WINDOW_BACKEND_ENV : &'static str = "AMPLAYER_WINDOW_BACKEND";
+
+/// How the video actually reaches the screen. Exposed to the API user through
+/// aml_video_player_get_display_path, since some of these may show nothing at all.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisplayPath {
+    /// The window's pixels are written as-is in the framebuffer, and since they are all
+    /// transparent the VPU's layer below is visible. This is the path everything was designed for.
+    FramebufferAlpha = 0,
+    /// A compositor draws the windows: our transparent pixels are blended with whatever is below
+    /// our window before reaching the framebuffer, so the video may be hidden by the desktop.
+    Compositor = 1,
+    /// No window at all, the video layer is shown directly
+    Headless = 2,
+}
+
+/// How `Window::show` and `Window::hide` behave
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShowHideMode {
+    /// hide lowers the window below every other one, but it stays mapped: it still catches input
+    /// and keeps its place in the window manager's stacking order. This is the default.
+    RaiseLower = 0,
+    /// hide unmaps the window entirely, show maps it back and applies its state again
+    MapUnmap = 1,
+}
+
+impl ShowHideMode {
+    pub fn from_int(mode: i32) -> Option<ShowHideMode> {
+        match mode {
+            0 => Some(ShowHideMode::RaiseLower),
+            1 => Some(ShowHideMode::MapUnmap),
+            _ => None,
+        }
+    }
+}
+
+/// Sent by the event loop to the main thread, for things the window can't handle by itself
+#[derive(Debug)]
+pub enum WindowEvent {
+    /// The resolution of the screen changed (width, height)
+    ScreenResized(u32, u32),
+    /// true when the window became entirely covered by other windows, false when it is (at least
+    /// partly) visible again
+    Obscured(bool),
+    /// The user asked the window manager to close our window
+    CloseRequested,
+}
+
+/// Every method must be callable from any thread: the event loop runs in its own thread while the
+/// main thread sets the geometry.
+pub trait Window: Send + Sync {
+    /// name of the backend, for logging purposes
+    fn backend_name(&self) -> &'static str;
+    fn display_path(&self) -> DisplayPath;
+    fn set_borderless(&self, borderless: bool) -> Result<()>;
+    fn set_fullscreen(&self, fullscreen: bool) -> Result<()>;
+    /// Shows the window on every virtual desktop
+    fn set_sticky(&self, _sticky: bool) -> Result<()> {
+        Err(Error::Unsupported(format!("sticky windows are not supported by the {} backend", self.backend_name())))
+    }
+    /// Keeps the window out of the taskbar and the pager
+    fn set_skip_taskbar(&self, _skip: bool) -> Result<()> {
+        Err(Error::Unsupported(format!("hiding from the taskbar is not supported by the {} backend", self.backend_name())))
+    }
+    /// Size of the screen the window is on. Must be queried every time, since the resolution can
+    /// change while playing.
+    fn screen_size(&self) -> Result<(u32, u32)>;
+    /// Number of displays connected and in use, see aml_video_player_set_auto_fullscreen
+    fn screen_count(&self) -> Result<u32> {
+        Err(Error::Unsupported(format!("counting the screens is not supported by the {} backend", self.backend_name())))
+    }
+    fn show(&self);
+    fn hide(&self);
+    fn set_pos(&self, x: i32, y: i32);
+    fn set_size(&self, w: u32, h: u32);
+    /// Lets pointer events go through the window, to whatever is below it
+    fn set_click_through(&self, _enabled: bool) -> Result<()> {
+        Err(Error::Unsupported(format!("click-through is not supported by the {} backend", self.backend_name())))
+    }
+    /// Backends which only have one way of hiding a window may ignore this
+    fn set_show_hide_mode(&self, _mode: ShowHideMode) {
+    }
+    /// must run until keep_running becomes false
+    fn event_loop(&self, keep_running: Arc<atomic::AtomicBool>, events: Sender<WindowEvent>);
+    /// Called once keep_running is false, so that event_loop notices right away instead of at its
+    /// next timeout. Backends which don't wait for long can ignore this.
+    fn wake_up(&self) {
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowBackend {
+    /// try X11 first, then Wayland, and finally go headless
+    Auto,
+    X11,
+    Wayland,
+    /// No window at all: the framebuffer is already transparent thanks to FbWrapper, so the video
+    /// can be seen without any windowing system. Show and Hide are done on the video layer itself.
+    Headless,
+    /// A window which only exists in memory, see stubwindow.rs
+    Stub,
+}
+
+impl WindowBackend {
+    /// Reads the backend from AMPLAYER_WINDOW_BACKEND, defaults to Auto
+    pub fn from_env() -> WindowBackend {
+        match env::var(WINDOW_BACKEND_ENV).map(|s| s.to_lowercase()) {
+            Ok(ref s) if s == "x11" => WindowBackend::X11,
+            Ok(ref s) if s == "wayland" => WindowBackend::Wayland,
+            Ok(ref s) if s == "headless" => WindowBackend::Headless,
+            Ok(ref s) if s == "stub" => WindowBackend::Stub,
+            Ok(ref s) if s == "auto" || s.is_empty() => WindowBackend::Auto,
+            Ok(s) => {
+                warn!("unknown value `{}` for {}, selecting the window backend automatically", s, WINDOW_BACKEND_ENV);
+                WindowBackend::Auto
+            },
+            Err(_) => WindowBackend::Auto,
+        }
+    }
+}
+
+/// Returns None when running headless. size is the initial (width, height) of the window.
+pub fn create_window(backend: WindowBackend, size: (u16, u16)) -> Result<Option<Arc<Window>>> {
+    match backend {
+        WindowBackend::X11 => Ok(Some(Arc::new(X11Helper::new(ptr::null_mut(), size)?))),
+        WindowBackend::Wayland => Ok(Some(Arc::new(WaylandHelper::new(size)?))),
+        WindowBackend::Headless => Ok(None),
+        WindowBackend::Stub => Ok(Some(Arc::new(StubWindow::new(size)))),
+        // builds meant to run without a display server never try a real one
+        #[cfg(feature = "stub_window")]
+        WindowBackend::Auto => Ok(Some(Arc::new(StubWindow::new(size)))),
+        #[cfg(not(feature = "stub_window"))]
+        WindowBackend::Auto => {
+            let x11_error = match X11Helper::new(ptr::null_mut(), size) {
+                Ok(x11_helper) => return Ok(Some(Arc::new(x11_helper))),
+                Err(e) => e,
+            };
+            warn!("failed to initialize X11 ({}), trying Wayland instead", x11_error.display());
+            match WaylandHelper::new(size) {
+                Ok(wayland_helper) => Ok(Some(Arc::new(wayland_helper))),
+                Err(e) => {
+                    warn!("failed to initialize Wayland ({}), running headless", e.display());
+                    Ok(None)
+                }
+            }
+        }
+    }
+}
@@ -4,12 +4,16 @@
  */
 
 use error::*;
+use super::window::{Window, WindowEvent, DisplayPath, ShowHideMode};
+use super::xfixes::{XFixesLib, SHAPE_INPUT};
 
-use x11_dl::xlib;
-use libc::{c_int, c_long, c_ulong, c_uint, c_char, c_uchar};
+use x11_dl::{xlib, xrandr};
+use libc::{self, c_int, c_long, c_ulong, c_uint, c_char, c_uchar, c_void};
 use std::ffi::CString;
 use std::{mem, ptr};
-use std::sync::{Arc, atomic};
+use std::mem::MaybeUninit;
+use std::sync::{Arc, Mutex, atomic};
+use std::sync::mpsc::Sender;
 
 struct Display(pub *mut xlib::Display);
 
@@ -18,6 +22,25 @@ struct Display(pub *mut xlib::Display);
 unsafe impl Send for Display {}
 unsafe impl Sync for Display {}
 
+/// What must be applied again when the window is mapped back in ShowHideMode::MapUnmap, since
+/// window managers tend to forget about it when a window is unmapped
+struct WindowState {
+    show_hide_mode: ShowHideMode,
+    /// true when hidden in ShowHideMode::MapUnmap
+    unmapped: bool,
+    fullscreen: bool,
+    sticky: bool,
+    /// both _NET_WM_STATE_SKIP_TASKBAR and _NET_WM_STATE_SKIP_PAGER
+    skip_taskbar: bool,
+    /// pointer events go through the window. Forwarding input from the window (if it ever
+    /// happens) must be refused while this is set, since the window doesn't get any input.
+    click_through: bool,
+    x: i32,
+    y: i32,
+    w: u32,
+    h: u32,
+}
+
 pub struct X11Helper {
     display: Display,
     // Xlib is a very large struct, so allocate it on the heap with Box
@@ -25,23 +48,42 @@ pub struct X11Helper {
     xlib: Box<xlib::Xlib>,
     window: c_ulong,
     root_window: c_ulong,
+    display_path: DisplayPath,
+    state: Mutex<WindowState>,
+    /// loaded the first time it is needed, since most users will never need it
+    xfixes: Mutex<Option<XFixesLib>>,
+    /// XRandR and its event base, None if the extension is not available: resolution changes
+    /// won't be noticed in this case
+    xrandr: Option<(Box<xrandr::Xrandr>, c_int)>,
+    /// (read end, write end): wake_up writes in it to interrupt the event loop's poll. -1 if the
+    /// pipe couldn't be created, the event loop then only notices keep_running on its timeout.
+    wakeup_pipe: [c_int; 2],
 }
 
+/// How long the event loop waits for X11 events, unless woken up by wake_up
+const EVENT_LOOP_TIMEOUT_MS : c_int = 100;
+
 impl Drop for X11Helper {
     fn drop(&mut self) {
         unsafe {
             (self.xlib.XCloseDisplay)(self.display.0);
+            for &fd in self.wakeup_pipe.iter() {
+                if fd >= 0 {
+                    libc::close(fd);
+                }
+            }
         }
     }
 }
 
 impl X11Helper {
-    pub fn new(display_name: *const c_char) -> Result<X11Helper> {
+    /// (width, height) is the initial size of the window
+    pub fn new(display_name: *const c_char, (width, height): (u16, u16)) -> Result<X11Helper> {
         let xlib = Box::new(xlib::Xlib::open()?);
 
         let display = unsafe {(xlib.XOpenDisplay)(display_name)};
         if display.is_null() {
-            bail!(ErrorKind::X11Other(String::from("XOpenDisplay failed")));
+            return Err(Error::X11(String::from("XOpenDisplay failed")));
         };
 
         let screen = unsafe { (xlib.XDefaultScreen)(display) };
@@ -56,20 +98,194 @@ impl X11Helper {
         visual_info_template.screen = unsafe {(xlib.XDefaultScreen)(display)};
         let window = unsafe {
             (xlib.XCreateWindow)(display, root,
-                                 0, 0, 800, 600,
+                                 0, 0, width as c_uint, height as c_uint,
                                  0, 0,
                                  xlib::InputOutput as c_uint, ptr::null_mut(),
                                  xlib::CWBackPixel | xlib::CWEventMask, &mut attributes)
         };
-        Ok(X11Helper {
+        let xrandr = match xrandr::Xrandr::open() {
+            Ok(xrandr) => {
+                let (mut event_base, mut error_base) = (0, 0);
+                if unsafe {(xrandr.XRRQueryExtension)(display, &mut event_base, &mut error_base)} != 0 {
+                    Some((Box::new(xrandr), event_base))
+                } else {
+                    warn!("XRandR extension not available, screen resolution changes will be ignored");
+                    None
+                }
+            },
+            Err(e) => {
+                warn!("failed to load libXrandr ({}), screen resolution changes will be ignored", e);
+                None
+            }
+        };
+        let mut wakeup_pipe : [c_int; 2] = [-1, -1];
+        if unsafe { libc::pipe(wakeup_pipe.as_mut_ptr()) } < 0 {
+            warn!("failed to create the X11 wakeup pipe, destroying the player may be slower");
+            wakeup_pipe = [-1, -1];
+        }
+        let mut x11_helper = X11Helper {
             display: Display(display),
             xlib: xlib,
             window: window,
             root_window: root,
-        })
+            display_path: DisplayPath::FramebufferAlpha,
+            state: Mutex::new(WindowState {
+                show_hide_mode: ShowHideMode::RaiseLower,
+                unmapped: false,
+                fullscreen: false,
+                sticky: false,
+                skip_taskbar: false,
+                click_through: false,
+                x: 0,
+                y: 0,
+                w: 800,
+                h: 600,
+            }),
+            xfixes: Mutex::new(None),
+            xrandr: xrandr,
+            wakeup_pipe: wakeup_pipe,
+        };
+        if x11_helper.compositor_running(screen) {
+            // With a compositor our window is not written as-is in the framebuffer anymore: it is
+            // blended with what's below it, so the hole we're punching may well be filled with
+            // the desktop. There is not much we can do about it, but at least make sure the
+            // compositor doesn't fade the window any further and tell why nothing may be seen.
+            warn!("a compositing window manager is running, the video may be hidden by the \
+                     desktop. Disable the compositor or use AMPLAYER_WINDOW_BACKEND=headless if \
+                     nothing is shown");
+            x11_helper.set_opacity(0xffffffff);
+            x11_helper.display_path = DisplayPath::Compositor;
+        }
+        Ok(x11_helper)
+    }
+
+    /// A compositing manager owns the _NET_WM_CM_S<screen> selection, as stated by the EWMH spec
+    fn compositor_running(&self, screen: c_int) -> bool {
+        let cm_selection_str = CString::new(format!("_NET_WM_CM_S{}", screen)).unwrap();
+        unsafe {
+            let cm_selection = (self.xlib.XInternAtom)(self.display.0, cm_selection_str.as_ptr(), xlib::False);
+            cm_selection != 0 && (self.xlib.XGetSelectionOwner)(self.display.0, cm_selection) != 0
+        }
+    }
+
+    fn configure(&self, mask: u16, x: i32, y: i32, w: u32, h: u32) {
+        let mut window_changes : xlib::XWindowChanges = unsafe {mem::zeroed()};
+        window_changes.x = x as c_int;
+        window_changes.y = y as c_int;
+        window_changes.width = w as c_int;
+        window_changes.height = h as c_int;
+        unsafe {
+            (self.xlib.XConfigureWindow)(self.display.0, self.window, mask as c_uint, &mut window_changes as *mut _);
+        }
+    }
+
+    fn intern_atom(&self, name: &str) -> xlib::Atom {
+        let name_str = CString::new(name).unwrap();
+        unsafe {(self.xlib.XInternAtom)(self.display.0, name_str.as_ptr(), 0)}
+    }
+
+    /// Asks the window manager to add or remove one of the _NET_WM_STATE_* properties of the
+    /// window, like _NET_WM_STATE_FULLSCREEN
+    fn send_wm_state(&self, enable: bool, state_name: &str) -> Result<()> {
+        self.send_wm_states(enable, state_name, None)
+    }
+
+    /// Same as send_wm_state, but the _NET_WM_STATE message has room for two properties: both
+    /// are changed at once
+    fn send_wm_states(&self, enable: bool, state_name: &str, second_state_name: Option<&str>) -> Result<()> {
+        let wm_state = self.intern_atom("_NET_WM_STATE");
+        let state_atom = self.intern_atom(state_name);
+        let second_state_atom = match second_state_name {
+            Some(name) => self.intern_atom(name),
+            None => 0,
+        };
+        let mut xclient_message_event : xlib::XClientMessageEvent = unsafe { mem::zeroed() };
+        xclient_message_event.type_ = xlib::ClientMessage;
+        xclient_message_event.window = self.window;
+        xclient_message_event.message_type = wm_state;
+        xclient_message_event.format = 32;
+        xclient_message_event.data = xlib::ClientMessageData::new();
+        {
+            let l : &mut [c_long] = xclient_message_event.data.as_longs_mut();
+            l[0] = if enable { 1 } else { 0 };
+            l[1] = state_atom as c_long;
+            l[2] = second_state_atom as c_long;
+        }
+        let r = unsafe {
+            (self.xlib.XSendEvent)(
+                self.display.0,
+                self.root_window, 
+                0,
+                xlib::SubstructureRedirectMask | xlib::SubstructureNotifyMask,
+                &mut xclient_message_event as *mut _ as *mut xlib::XEvent)
+        };
+        if r != 0 {
+            return Err(Error::X11Status(r as u8));
+        }
+        Ok(())
+    }
+
+    /// Writes the _NET_WM_STATE property of the window directly. This must only be used before
+    /// the window is mapped, after that the window manager owns this property and we must go
+    /// through send_wm_state.
+    fn set_initial_wm_state(&self) {
+        let state = self.state.lock().unwrap();
+        let mut atoms : Vec<c_ulong> = Vec::new();
+        if state.fullscreen {
+            atoms.push(self.intern_atom("_NET_WM_STATE_FULLSCREEN") as c_ulong);
+        }
+        if state.sticky {
+            atoms.push(self.intern_atom("_NET_WM_STATE_STICKY") as c_ulong);
+        }
+        if state.skip_taskbar {
+            atoms.push(self.intern_atom("_NET_WM_STATE_SKIP_TASKBAR") as c_ulong);
+            atoms.push(self.intern_atom("_NET_WM_STATE_SKIP_PAGER") as c_ulong);
+        }
+        if atoms.is_empty() {
+            return;
+        }
+        let wm_state = self.intern_atom("_NET_WM_STATE");
+        unsafe {
+            (self.xlib.XChangeProperty)(self.display.0,
+                                        self.window,
+                                        wm_state,
+                                        xlib::XA_ATOM,
+                                        32,
+                                        xlib::PropModeReplace,
+                                        atoms.as_ptr() as *const u8 as *mut u8,
+                                        atoms.len() as c_int);
+        }
+    }
+
+    /// 0xffffffff is fully opaque, 0 is fully transparent
+    fn set_opacity(&self, opacity: u32) {
+        let opacity_str = CString::new("_NET_WM_WINDOW_OPACITY").unwrap();
+        let opacity_atom = unsafe {(self.xlib.XInternAtom)(self.display.0, opacity_str.as_ptr(), xlib::False)};
+        // properties of format 32 must be given as an array of longs, whatever their real size is
+        let value : c_ulong = opacity as c_ulong;
+        unsafe {
+            (self.xlib.XChangeProperty)(self.display.0,
+                                        self.window,
+                                        opacity_atom,
+                                        xlib::XA_CARDINAL,
+                                        32,
+                                        xlib::PropModeReplace,
+                                        &value as *const c_ulong as *const u8 as *mut u8,
+                                        1);
+        }
     }
+}
 
-    pub fn set_borderless(&self, borderless: bool) -> Result<()> {
+impl Window for X11Helper {
+    fn backend_name(&self) -> &'static str {
+        "x11"
+    }
+
+    fn display_path(&self) -> DisplayPath {
+        self.display_path
+    }
+
+    fn set_borderless(&self, borderless: bool) -> Result<()> {
         // according to http://stackoverflow.com/a/1909708/3731958
         // this method to hide borders with x11 is deprecated, but it still works 
         // so whatever
@@ -84,7 +300,7 @@ impl X11Helper {
         let motif_wm_hints_str = CString::new("_MOTIF_WM_HINTS").unwrap();
         let wm_hints : xlib::Atom = unsafe { (self.xlib.XInternAtom)(self.display.0, motif_wm_hints_str.as_ptr(), 1) };
         if wm_hints == 0 {
-            bail!(ErrorKind::X11Other(String::from("XInternAtom returned None")));
+            return Err(Error::X11(String::from("XInternAtom returned None")));
         }
         let mwh_hints = MwmHints {
             flags: (1 << 1), // "decorations" set
@@ -108,44 +324,67 @@ impl X11Helper {
         if r == 0 {
             Ok(())
         } else {
-            Err(Error::from_kind(ErrorKind::X11Internal(r as u8)))
+            Err(Error::X11Status(r as u8))
         }
     }
 
-    pub fn set_fullscreen(&self, fullscreen: bool) -> Result<()> {
-        let wm_state_str = CString::new("_NET_WM_STATE").unwrap();
-        let wm_state_fullscreen_str = CString::new("_NET_WM_STATE_FULLSCREEN").unwrap();
-        let wm_state = unsafe {(self.xlib.XInternAtom)(self.display.0, wm_state_str.as_ptr(), 0)};
-        let fullscreen_atom = unsafe {(self.xlib.XInternAtom)(self.display.0, wm_state_fullscreen_str.as_ptr(), 0)};
-        let mut xclient_message_event : xlib::XClientMessageEvent = unsafe { mem::zeroed() };
-        xclient_message_event.type_ = xlib::ClientMessage;
-        xclient_message_event.window = self.window;
-        xclient_message_event.message_type = wm_state;
-        xclient_message_event.format = 32;
-        xclient_message_event.data = xlib::ClientMessageData::new();
-        {
-            let mut l : &mut [c_long] = xclient_message_event.data.as_longs_mut();
-            l[0] = if fullscreen { 1 } else { 0 };
-            l[1] = fullscreen_atom as c_long;
+    fn set_fullscreen(&self, fullscreen: bool) -> Result<()> {
+        self.state.lock().unwrap().fullscreen = fullscreen;
+        self.send_wm_state(fullscreen, "_NET_WM_STATE_FULLSCREEN")
+    }
+
+    // the root window always has the size of the screen, and unlike XDisplayWidth this is not
+    // cached by Xlib, so a resolution change made with XRandR is seen right away
+    fn screen_size(&self) -> Result<(u32, u32)> {
+        let mut attributes : xlib::XWindowAttributes = unsafe {mem::zeroed()};
+        let r = unsafe {(self.xlib.XGetWindowAttributes)(self.display.0, self.root_window, &mut attributes)};
+        if r == 0 {
+            return Err(Error::X11(String::from("XGetWindowAttributes failed on the root window")));
         }
-        let r = unsafe {
-            (self.xlib.XSendEvent)(
-                self.display.0,
-                self.root_window, 
-                0,
-                xlib::SubstructureRedirectMask | xlib::SubstructureNotifyMask,
-                &mut xclient_message_event as *mut _ as *mut xlib::XEvent)
+        Ok((attributes.width as u32, attributes.height as u32))
+    }
+
+    /// The outputs of XRandR which are connected and have a CRTC, i.e. which show something
+    fn screen_count(&self) -> Result<u32> {
+        let xrandr = match self.xrandr {
+            Some((ref xrandr, _)) => xrandr,
+            None => return Err(Error::Unsupported(String::from("the screens can't be counted without XRandR"))),
         };
-        if r != 0 {
-            bail!(ErrorKind::X11Internal(r as u8))
+        unsafe {
+            let resources = (xrandr.XRRGetScreenResourcesCurrent)(self.display.0, self.root_window);
+            if resources.is_null() {
+                return Err(Error::X11(String::from("XRRGetScreenResourcesCurrent failed")));
+            }
+            let mut count = 0;
+            for i in 0..(*resources).noutput as isize {
+                let output = (xrandr.XRRGetOutputInfo)(self.display.0, resources, *(*resources).outputs.offset(i));
+                if output.is_null() {
+                    continue;
+                }
+                if (*output).connection == xrandr::RR_Connected as u16 && (*output).crtc != 0 {
+                    count += 1;
+                }
+                (xrandr.XRRFreeOutputInfo)(output);
+            }
+            (xrandr.XRRFreeScreenResources)(resources);
+            Ok(count)
         }
-        Ok(())
+    }
+
+    fn set_sticky(&self, sticky: bool) -> Result<()> {
+        self.state.lock().unwrap().sticky = sticky;
+        self.send_wm_state(sticky, "_NET_WM_STATE_STICKY")
+    }
+
+    fn set_skip_taskbar(&self, skip: bool) -> Result<()> {
+        self.state.lock().unwrap().skip_taskbar = skip;
+        self.send_wm_states(skip, "_NET_WM_STATE_SKIP_TASKBAR", Some("_NET_WM_STATE_SKIP_PAGER"))
     }
 
     // this is the X11 event loop.
     // We are not doing anything special in there, but we still need to run this (otherwise X11
     // doesn't do anything)
-    pub fn event_loop(&self, keep_running: Arc<atomic::AtomicBool>) {
+    fn event_loop(&self, keep_running: Arc<atomic::AtomicBool>, events: Sender<WindowEvent>) {
         // Hook close requests.
         let wm_delete_window_str = CString::new("WM_DELETE_WINDOW").unwrap();
         let wm_delete_window = unsafe {(self.xlib.XInternAtom)(self.display.0, wm_delete_window_str.as_ptr(), xlib::False)};
@@ -154,60 +393,177 @@ impl X11Helper {
 
         unsafe {
             (self.xlib.XSetWMProtocols)(self.display.0, self.window, protocols.as_mut_ptr(), protocols.len() as c_int);
-
-            (self.xlib.XMapWindow)(self.display.0, self.window);
         }
+        // whatever has been set before the window is mapped must be there from the start
+        self.set_initial_wm_state();
+        unsafe {
+            (self.xlib.XMapWindow)(self.display.0, self.window);
 
-        // since this will be modified by XNextEvent, we dont care if its
-        // initialized or not
-        let mut event: xlib::XEvent = unsafe {mem::uninitialized()};
+            // to know when our window is covered by another one
+            (self.xlib.XSelectInput)(self.display.0, self.window, xlib::VisibilityChangeMask);
 
-        loop {
-            use std::{thread, time};
+            if let Some((ref xrandr, _)) = self.xrandr {
+                (xrandr.XRRSelectInput)(self.display.0, self.root_window, xrandr::RRScreenChangeNotifyMask);
+            }
+        }
 
+        let connection_fd = unsafe {(self.xlib.XConnectionNumber)(self.display.0)};
+        loop {
             let n_events = unsafe {(self.xlib.XPending)(self.display.0)};
             for _ in 0..n_events {
-                unsafe {
-                    (self.xlib.XNextEvent)(self.display.0, &mut event);
+                // XNextEvent fills the whole event, whatever its type
+                let mut event = unsafe {
+                    let mut event = MaybeUninit::<xlib::XEvent>::uninit();
+                    (self.xlib.XNextEvent)(self.display.0, event.as_mut_ptr());
+                    event.assume_init()
+                };
+                if event.get_type() == xlib::ClientMessage {
+                    let client_message : xlib::XClientMessageEvent = From::from(event);
+                    if client_message.data.get_long(0) as xlib::Atom == wm_delete_window {
+                        let _r = events.send(WindowEvent::CloseRequested);
+                    }
+                }
+                if event.get_type() == xlib::VisibilityNotify {
+                    let visibility_event : xlib::XVisibilityEvent = From::from(event);
+                    let obscured = visibility_event.state == xlib::VisibilityFullyObscured;
+                    let _r = events.send(WindowEvent::Obscured(obscured));
+                }
+                if let Some((ref xrandr, event_base)) = self.xrandr {
+                    if event.get_type() == event_base + xrandr::RRScreenChangeNotify {
+                        // lets Xlib know about the new resolution
+                        unsafe {
+                            (xrandr.XRRUpdateConfiguration)(&mut event);
+                        }
+                        match self.screen_size() {
+                            Ok((w, h)) => {
+                                info!("screen resolution changed to {}x{}", w, h);
+                                let _r = events.send(WindowEvent::ScreenResized(w, h));
+                            },
+                            Err(e) => error!("failed to get the new screen size: {}", e.display()),
+                        };
+                    }
                 }
             };
             if !keep_running.load(atomic::Ordering::SeqCst) {
                 break;
             };
-            thread::sleep(time::Duration::from_millis(50));
+            // XPending flushed our requests, so we can sleep until the server or wake_up has
+            // something for us. poll ignores negative fds, should the pipe be missing.
+            let mut pollfds = [
+                libc::pollfd { fd: connection_fd, events: libc::POLLIN, revents: 0 },
+                libc::pollfd { fd: self.wakeup_pipe[0], events: libc::POLLIN, revents: 0 },
+            ];
+            unsafe {
+                if libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, EVENT_LOOP_TIMEOUT_MS) > 0
+                    && pollfds[1].revents & libc::POLLIN != 0 {
+                    let mut buf = [0u8; 16];
+                    libc::read(self.wakeup_pipe[0], buf.as_mut_ptr() as *mut c_void, buf.len());
+                }
+            }
         }
-        println!("x11_thread: shutting down ...");
+        debug!("shutting down ...");
     }
 
-    pub fn show(&self) {
-        unsafe {
-            (self.xlib.XRaiseWindow)(self.display.0, self.window);
+    fn wake_up(&self) {
+        if self.wakeup_pipe[1] >= 0 {
+            let byte = 1u8;
+            unsafe {
+                libc::write(self.wakeup_pipe[1], &byte as *const u8 as *const c_void, 1);
+            }
         }
     }
 
-    pub fn hide(&self) {
+    fn show(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.unmapped {
+            state.unmapped = false;
+            unsafe {
+                (self.xlib.XMapWindow)(self.display.0, self.window);
+            }
+            self.configure(xlib::CWX | xlib::CWY | xlib::CWWidth | xlib::CWHeight, state.x, state.y, state.w, state.h);
+            if state.fullscreen {
+                if let Err(e) = self.send_wm_state(true, "_NET_WM_STATE_FULLSCREEN") {
+                    error!("failed to set the X11 window fullscreen again: {}", e.display());
+                };
+            }
+            if state.sticky {
+                if let Err(e) = self.send_wm_state(true, "_NET_WM_STATE_STICKY") {
+                    error!("failed to set the X11 window sticky again: {}", e.display());
+                };
+            }
+            if state.skip_taskbar {
+                if let Err(e) = self.send_wm_states(true, "_NET_WM_STATE_SKIP_TASKBAR", Some("_NET_WM_STATE_SKIP_PAGER")) {
+                    error!("failed to hide the X11 window from the taskbar again: {}", e.display());
+                };
+            }
+        }
         unsafe {
-            (self.xlib.XLowerWindow)(self.display.0, self.window);
+            (self.xlib.XRaiseWindow)(self.display.0, self.window);
         }
     }
 
-    pub fn set_pos(&self, x: i16, y: i16) {
-        let mut window_changes : xlib::XWindowChanges = unsafe {mem::uninitialized()};
-        window_changes.x = x as c_int;
-        window_changes.y = y as c_int;
-        let mask = xlib::CWX | xlib::CWY; // x and y
+    fn hide(&self) {
+        let mut state = self.state.lock().unwrap();
+        match state.show_hide_mode {
+            ShowHideMode::RaiseLower => unsafe {
+                (self.xlib.XLowerWindow)(self.display.0, self.window);
+            },
+            ShowHideMode::MapUnmap => {
+                state.unmapped = true;
+                unsafe {
+                    (self.xlib.XUnmapWindow)(self.display.0, self.window);
+                }
+            },
+        };
+    }
+
+    fn set_pos(&self, x: i32, y: i32) {
+        let mut state = self.state.lock().unwrap();
+        state.x = x;
+        state.y = y;
+        self.configure(xlib::CWX | xlib::CWY, x, y, state.w, state.h);
+    }
+
+    fn set_size(&self, w: u32, h: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.w = w;
+        state.h = h;
+        self.configure(xlib::CWWidth | xlib::CWHeight, state.x, state.y, w, h);
+    }
+
+    fn set_click_through(&self, enabled: bool) -> Result<()> {
+        let mut xfixes = self.xfixes.lock().unwrap();
+        if xfixes.is_none() {
+            *xfixes = Some(XFixesLib::open(self.display.0)?);
+        }
+        let xfixes = xfixes.as_ref().unwrap();
         unsafe {
-            (self.xlib.XConfigureWindow)(self.display.0, self.window, mask as c_uint, &mut window_changes as *mut _);
+            if enabled {
+                // an empty input region: the window doesn't receive anything, every pointer event
+                // goes to whatever is below
+                let region = (xfixes.create_region)(self.display.0, ptr::null_mut(), 0);
+                (xfixes.set_window_shape_region)(self.display.0, self.window, SHAPE_INPUT, 0, 0, region);
+                (xfixes.destroy_region)(self.display.0, region);
+            } else {
+                // None (and not an empty region) sets the input region back to the whole window
+                (xfixes.set_window_shape_region)(self.display.0, self.window, SHAPE_INPUT, 0, 0, 0);
+            }
+            (self.xlib.XFlush)(self.display.0);
         }
+        self.state.lock().unwrap().click_through = enabled;
+        Ok(())
     }
 
-    pub fn set_size(&self, w: u16, h: u16) {
-        let mut window_changes : xlib::XWindowChanges = unsafe {mem::uninitialized()};
-        window_changes.width = w as c_int;
-        window_changes.height = h as c_int;
-        let mask = xlib::CWWidth | xlib::CWHeight; // w and h
-        unsafe {
-            (self.xlib.XConfigureWindow)(self.display.0, self.window, mask as c_uint, &mut window_changes as *mut _);
+    fn set_show_hide_mode(&self, mode: ShowHideMode) {
+        let mut state = self.state.lock().unwrap();
+        if state.unmapped && mode == ShowHideMode::RaiseLower {
+            // raise/lower can't map the window back, so keep it hidden the raise/lower way
+            state.unmapped = false;
+            unsafe {
+                (self.xlib.XMapWindow)(self.display.0, self.window);
+                (self.xlib.XLowerWindow)(self.display.0, self.window);
+            }
         }
+        state.show_hide_mode = mode;
     }
 }
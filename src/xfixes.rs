@@ -0,0 +1,75 @@
+/*
+ * x11-dl doesn't expose any function of libXfixes, so the few we need are loaded at runtime here,
+ * the same way x11-dl does for Xlib.
+ *
+ * XFixes is only used to change the input shape of the window (see X11Helper::set_click_through).
+ */
+
+use error::*;
+
+use x11_dl::xlib;
+use libc::{self, c_int, c_void};
+use std::ffi::CString;
+use std::mem;
+
+pub type XserverRegion = xlib::XID;
+
+/// from X11/extensions/shapeconst.h
+pub const SHAPE_INPUT : c_int = 2;
+
+pub struct XFixesLib {
+    handle: *mut c_void,
+    pub query_extension: unsafe extern "C" fn(*mut xlib::Display, *mut c_int, *mut c_int) -> xlib::Bool,
+    pub create_region: unsafe extern "C" fn(*mut xlib::Display, *mut xlib::XRectangle, c_int) -> XserverRegion,
+    pub destroy_region: unsafe extern "C" fn(*mut xlib::Display, XserverRegion),
+    pub set_window_shape_region: unsafe extern "C" fn(*mut xlib::Display, xlib::Window, c_int, c_int, c_int, XserverRegion),
+}
+
+// the handle is only used to dlclose the library
+unsafe impl Send for XFixesLib {}
+unsafe impl Sync for XFixesLib {}
+
+macro_rules! xfixes_symbol {
+    ($handle: expr, $name: expr) => {{
+        let name = CString::new($name).unwrap();
+        let symbol = libc::dlsym($handle, name.as_ptr());
+        if symbol.is_null() {
+            libc::dlclose($handle);
+            return Err(Error::Unsupported(format!("symbol {} not found in libXfixes", $name)));
+        }
+        mem::transmute(symbol)
+    }};
+}
+
+impl XFixesLib {
+    /// Fails if libXfixes is not installed, or if the X server doesn't support the extension
+    pub fn open(display: *mut xlib::Display) -> Result<XFixesLib> {
+        let lib = unsafe {
+            let lib_name = CString::new("libXfixes.so.3").unwrap();
+            let handle = libc::dlopen(lib_name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+            if handle.is_null() {
+                return Err(Error::Unsupported(String::from("failed to load libXfixes.so.3")));
+            }
+            XFixesLib {
+                handle: handle,
+                query_extension: xfixes_symbol!(handle, "XFixesQueryExtension"),
+                create_region: xfixes_symbol!(handle, "XFixesCreateRegion"),
+                destroy_region: xfixes_symbol!(handle, "XFixesDestroyRegion"),
+                set_window_shape_region: xfixes_symbol!(handle, "XFixesSetWindowShapeRegion"),
+            }
+        };
+        let (mut event_base, mut error_base) = (0, 0);
+        if unsafe {(lib.query_extension)(display, &mut event_base, &mut error_base)} == 0 {
+            return Err(Error::Unsupported(String::from("the X server doesn't support the XFixes extension")));
+        }
+        Ok(lib)
+    }
+}
+
+impl Drop for XFixesLib {
+    fn drop(&mut self) {
+        unsafe {
+            libc::dlclose(self.handle);
+        }
+    }
+}
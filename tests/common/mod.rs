@@ -0,0 +1,141 @@
+/*
+ * What the integration tests share: a player on the dummy Amcodec (every target but aarch64) with
+ * the stub window, and the fixtures of tests/fixtures.
+ *
+ * Only one player exists at a time, as on the real VPU: the tests of a file run in parallel, and
+ * the environment variables read by the player are process-wide.
+ */
+
+#![allow(dead_code)]
+
+use c2player::*;
+
+use std::env;
+use std::ffi::CString;
+use std::mem;
+use std::ptr;
+use libc::{c_int, c_void};
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const AMPLAYER_ERROR_NONE : c_int = 0;
+pub const AMPLAYER_ERROR_INVALID_COMMAND : c_int = 1;
+
+pub const AMPLAYER_EVENT_END_OF_STREAM : c_int = 0;
+pub const AMPLAYER_EVENT_FATAL_ERROR : c_int = 1;
+pub const AMPLAYER_EVENT_FIRST_FRAME_DISPLAYED : c_int = 4;
+pub const AMPLAYER_EVENT_SEEK_COMPLETED : c_int = 9;
+
+pub const AMPLAYER_CODEC_HEVC : u32 = 1;
+
+/// 2 seconds at 25 fps, a keyframe every second, see tests/fixtures/README.md
+pub const CLIP : &'static str = "clip.mp4";
+pub const CLIP_DURATION : f32 = 2.0;
+
+/// The dummy shows 1000 frames whatever it is given, at about 60 fps
+pub const END_TIMEOUT_SECS : u64 = 40;
+
+/// true while a Player exists
+static PLAYER_ALIVE : AtomicBool = ATOMIC_BOOL_INIT;
+
+pub fn fixture(name: &str) -> CString {
+    CString::new(format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+}
+
+pub struct Player {
+    pub ptr: *mut c_void,
+}
+
+impl Player {
+    pub fn new() -> Player {
+        Player::with_env(&[])
+    }
+
+    /// With these environment variables set while it is created (AMPLAYER_DUMMY_DUMP, ...)
+    pub fn with_env(vars: &[(&str, &str)]) -> Player {
+        while PLAYER_ALIVE.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        env::set_var("AMPLAYER_WINDOW_BACKEND", "stub");
+        for &(name, value) in vars {
+            env::set_var(name, value);
+        }
+        let ptr = aml_video_player_create();
+        for &(name, _) in vars {
+            env::remove_var(name);
+        }
+        if ptr.is_null() {
+            PLAYER_ALIVE.store(false, Ordering::SeqCst);
+            panic!("aml_video_player_create failed with {}", aml_video_player_create_error());
+        }
+        Player {
+            ptr: ptr,
+        }
+    }
+
+    pub fn load(&self, name: &str) -> c_int {
+        let path = fixture(name);
+        aml_video_player_load(self.ptr, path.as_ptr())
+    }
+
+    pub fn position(&self) -> Option<f32> {
+        let mut pos = 0.0;
+        match aml_video_player_get_position(self.ptr, &mut pos) {
+            AMPLAYER_ERROR_NONE => Some(pos),
+            _ => None,
+        }
+    }
+
+    /// Waits until the position is past `pos`, returns false if it isn't within `timeout`
+    pub fn wait_for_position(&self, pos: f32, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            if self.position().map_or(false, |current| current > pos) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+
+    /// Skips every other event until one of `kind` comes, None if none did within `timeout`
+    pub fn wait_for_event(&self, kind: c_int, timeout: Duration) -> Option<AmlEvent> {
+        let start = Instant::now();
+        loop {
+            let left = match timeout.checked_sub(start.elapsed()) {
+                Some(left) => left,
+                None => return None,
+            };
+            let left_ms = left.as_secs() * 1000 + (left.subsec_nanos() / 1000000) as u64;
+            let mut event : AmlEvent = unsafe { mem::zeroed() };
+            if aml_video_player_next_event(self.ptr, &mut event, left_ms as c_int) != AMPLAYER_ERROR_NONE {
+                return None;
+            }
+            if event.kind == kind {
+                return Some(event);
+            }
+            assert!(event.kind != AMPLAYER_EVENT_FATAL_ERROR, "fatal error while waiting for event {}", kind);
+        }
+    }
+}
+
+impl Player {
+    /// Destroys the player now, returns how long aml_video_player_destroy took
+    pub fn destroy(mut self) -> Duration {
+        let start = Instant::now();
+        assert_eq!(aml_video_player_destroy(self.ptr), AMPLAYER_ERROR_NONE);
+        let took = start.elapsed();
+        self.ptr = ptr::null_mut();
+        took
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        if !self.ptr.is_null() {
+            aml_video_player_destroy(self.ptr);
+        }
+        PLAYER_ALIVE.store(false, Ordering::SeqCst);
+    }
+}
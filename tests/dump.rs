@@ -0,0 +1,168 @@
+//! What the dummy Amcodec would have written to /dev/amstream_hevc, see AMPLAYER_DUMMY_DUMP.
+//!
+//! tests/fixtures/clip.dump is the dump of clip.mp4 played from the start: a STOP, its extra data
+//! in Annex B, its 50 packets with their NAL lengths rewritten to start codes, then an EOF. It
+//! was written from the hvcC and the samples of the MP4 file, not by the player itself.
+
+#![cfg(not(target_arch = "aarch64"))]
+
+extern crate c2player;
+extern crate libc;
+
+mod common;
+
+use c2player::*;
+use common::*;
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const GOLDEN : &'static str = "clip.dump";
+
+#[derive(Debug, PartialEq)]
+struct Record {
+    tag: [u8; 4],
+    generation: u32,
+    payload: Vec<u8>,
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |value, &byte| value << 8 | byte as u32)
+}
+
+/// The records of a dump, without the last one if it is still being written
+fn parse(data: &[u8]) -> Vec<Record> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while data.len() - offset >= 12 {
+        let len = be_u32(&data[offset + 8..offset + 12]) as usize;
+        if data.len() - offset - 12 < len {
+            break;
+        }
+        let mut tag = [0u8; 4];
+        tag.copy_from_slice(&data[offset..offset + 4]);
+        records.push(Record {
+            tag: tag,
+            generation: be_u32(&data[offset + 4..offset + 8]),
+            payload: data[offset + 12..offset + 12 + len].to_vec(),
+        });
+        offset += 12 + len;
+    }
+    records
+}
+
+fn read(path: &PathBuf) -> Vec<u8> {
+    let mut data = Vec::new();
+    if let Ok(mut file) = File::open(path) {
+        file.read_to_end(&mut data).unwrap();
+    }
+    data
+}
+
+fn golden() -> Vec<Record> {
+    parse(&read(&PathBuf::from(fixture(GOLDEN).into_string().unwrap())))
+}
+
+/// A player dumping to a file of its own, removed first since the dump is appended to
+fn dumping_player(name: &str) -> (Player, PathBuf) {
+    let path = env::temp_dir().join(format!("c2player-{}-{}.dump", name, ::std::process::id()));
+    let _r = fs::remove_file(&path);
+    let player = Player::with_env(&[("AMPLAYER_DUMMY_DUMP", path.to_str().unwrap())]);
+    (player, path)
+}
+
+/// Waits until the dump has `count` EOF records, and returns all of them
+fn wait_for_eofs(path: &PathBuf, count: usize) -> Vec<Record> {
+    let start = Instant::now();
+    loop {
+        let records = parse(&read(path));
+        if records.iter().filter(|r| &r.tag == b"EOF ").count() >= count {
+            return records;
+        }
+        assert!(start.elapsed() < Duration::from_secs(10), "only {} records were dumped", records.len());
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// The records of the golden file after its STOP and XDAT: the packets and the EOF
+fn golden_packets() -> Vec<Record> {
+    golden().into_iter().skip(2).collect()
+}
+
+/// The payloads of `records`, with the generation of the golden file
+fn regenerated(records: &[Record], generation: u32) -> Vec<Record> {
+    records.iter().map(|r| Record {
+        tag: r.tag,
+        generation: generation,
+        payload: r.payload.clone(),
+    }).collect()
+}
+
+#[test]
+fn golden_file_is_well_formed() {
+    let golden = golden();
+    assert_eq!(golden.len(), 53);
+    assert_eq!(&golden[0].tag, b"STOP");
+    // the VPU can't decode anything before the parameter sets
+    assert_eq!(&golden[1].tag, b"XDAT");
+    assert_eq!(&golden[52].tag, b"EOF ");
+    for record in &golden[1..52] {
+        assert!(record.payload.starts_with(&[0, 0, 0, 1]), "{:?} without a start code", record.tag);
+    }
+}
+
+#[test]
+fn dump_of_the_fixture_matches_the_golden_file() {
+    let (player, path) = dumping_player("golden");
+    assert_eq!(player.load(CLIP), AMPLAYER_ERROR_NONE);
+    assert_eq!(aml_video_player_play(player.ptr), AMPLAYER_ERROR_NONE);
+    wait_for_eofs(&path, 1);
+    drop(player);
+    let data = read(&path);
+    let _r = fs::remove_file(&path);
+    let golden = read(&PathBuf::from(fixture(GOLDEN).into_string().unwrap()));
+    assert!(data.len() >= golden.len(), "{} bytes dumped, {} expected", data.len(), golden.len());
+    assert!(data[..golden.len()] == golden[..], "the dump differs from {}", GOLDEN);
+    // the player may stop once more while it is destroyed, there is nothing to write then
+    for record in parse(&data[golden.len()..]) {
+        assert_eq!(&record.tag, b"STOP");
+    }
+}
+
+#[test]
+fn no_stale_packets_after_a_load() {
+    let (player, path) = dumping_player("reload");
+    assert_eq!(player.load(CLIP), AMPLAYER_ERROR_NONE);
+    assert_eq!(aml_video_player_play(player.ptr), AMPLAYER_ERROR_NONE);
+    assert!(player.wait_for_position(0.2, Duration::from_secs(5)));
+    assert_eq!(player.load(CLIP), AMPLAYER_ERROR_NONE);
+    let records = wait_for_eofs(&path, 2);
+    drop(player);
+    let _r = fs::remove_file(&path);
+    let last_stop = records.iter().rposition(|r| &r.tag == b"STOP" && r.generation == 2).unwrap();
+    let reloaded : Vec<Record> = records.into_iter().skip(last_stop).take(53).collect();
+    // nothing of the first video between the Stop and the extra data of the second one, which
+    // starts over from its first packet
+    assert_eq!(reloaded, regenerated(&golden(), 2));
+}
+
+#[test]
+fn no_stale_packets_after_a_seek() {
+    let (player, path) = dumping_player("seek");
+    assert_eq!(player.load(CLIP), AMPLAYER_ERROR_NONE);
+    wait_for_eofs(&path, 1);
+    assert_eq!(aml_video_player_seek(player.ptr, 1.0), AMPLAYER_ERROR_NONE);
+    let records = wait_for_eofs(&path, 2);
+    drop(player);
+    let _r = fs::remove_file(&path);
+    let stop = records.iter().position(|r| &r.tag == b"STOP" && r.generation == 2).unwrap();
+    let seeked = &records[stop..];
+    assert_eq!(&seeked[1].tag, b"XDAT");
+    assert_eq!(seeked[1].payload, golden()[1].payload);
+    // from the keyframe at 1s, the 26th packet, to the end
+    let expected = regenerated(&golden_packets()[25..], 2);
+    assert_eq!(&seeked[2..2 + expected.len()], &expected[..]);
+}
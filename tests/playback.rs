@@ -0,0 +1,47 @@
+//! A whole playback of the fixture on the dummy Amcodec: load, play, seek, end
+
+#![cfg(not(target_arch = "aarch64"))]
+
+extern crate c2player;
+extern crate libc;
+
+mod common;
+
+use c2player::*;
+use common::*;
+use std::time::Duration;
+
+#[test]
+fn plays_the_fixture_to_the_end() {
+    let player = Player::new();
+    assert_eq!(player.load(CLIP), AMPLAYER_ERROR_NONE);
+
+    let mut duration = 0.0;
+    assert_eq!(aml_video_player_get_duration(player.ptr, &mut duration), AMPLAYER_ERROR_NONE);
+    assert!((duration - CLIP_DURATION).abs() < 0.1, "duration of {}s", duration);
+    let mut info = AmlCodecInfo::default();
+    assert_eq!(aml_video_player_get_codec_info(player.ptr, &mut info), AMPLAYER_ERROR_NONE);
+    assert_eq!(info.codec_id, AMPLAYER_CODEC_HEVC);
+    // Main, 8 bits, 4:2:0
+    assert_eq!((info.profile, info.bit_depth, info.chroma_format), (1, 8, 1));
+
+    assert_eq!(aml_video_player_play(player.ptr), AMPLAYER_ERROR_NONE);
+    assert!(player.wait_for_event(AMPLAYER_EVENT_FIRST_FRAME_DISPLAYED, Duration::from_secs(5)).is_some());
+    let start = player.position().unwrap();
+    assert!(player.wait_for_position(start + 0.2, Duration::from_secs(5)), "stuck at {}s", start);
+
+    assert_eq!(aml_video_player_seek(player.ptr, 1.0), AMPLAYER_ERROR_NONE);
+    let seeked = player.wait_for_event(AMPLAYER_EVENT_SEEK_COMPLETED, Duration::from_secs(5)).unwrap();
+    // from the keyframe at 1s, plus what the VPU showed until it reported it
+    assert!(seeked.start_ms >= 1000 && seeked.start_ms < 1600, "seek completed at {}ms", seeked.start_ms);
+
+    assert!(player.wait_for_event(AMPLAYER_EVENT_END_OF_STREAM, Duration::from_secs(END_TIMEOUT_SECS)).is_some());
+}
+
+#[test]
+fn nothing_loaded() {
+    let player = Player::new();
+    let mut duration = 0.0;
+    assert_eq!(aml_video_player_get_duration(player.ptr, &mut duration), AMPLAYER_ERROR_INVALID_COMMAND);
+    assert_eq!(player.position(), None);
+}
@@ -0,0 +1,79 @@
+//! Seeks on the dummy Amcodec, and the position they leave behind
+
+#![cfg(not(target_arch = "aarch64"))]
+
+extern crate c2player;
+extern crate libc;
+
+mod common;
+
+use c2player::*;
+use common::*;
+use std::f32;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn wait_for_async_seek(player: &Player) {
+    let start = Instant::now();
+    while aml_video_player_is_seek_complete(player.ptr) == 0 {
+        assert!(start.elapsed() < Duration::from_secs(5), "the async seek never completed");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn negative_positions_are_refused_up_front() {
+    let player = Player::new();
+    assert_eq!(aml_video_player_seek(player.ptr, -1.0), AMPLAYER_ERROR_INVALID_COMMAND);
+    assert_eq!(aml_video_player_seek(player.ptr, f32::NAN), AMPLAYER_ERROR_INVALID_COMMAND);
+    // with nothing loaded, libav refuses any other one
+    assert_eq!(aml_video_player_seek(player.ptr, 1.0), AMPLAYER_ERROR_INVALID_COMMAND);
+    assert_eq!(player.position(), None);
+}
+
+#[test]
+fn position_is_the_target_once_the_seek_returns() {
+    let player = Player::new();
+    assert_eq!(player.load(CLIP), AMPLAYER_ERROR_NONE);
+    assert_eq!(aml_video_player_seek(player.ptr, 1.5), AMPLAYER_ERROR_NONE);
+    // nothing is played, the VPU can only report the keyframe before the target
+    let pos = player.position().unwrap();
+    assert!(pos >= 1.0 && pos <= 1.5, "at {}s after the seek", pos);
+}
+
+#[test]
+fn refused_seek_leaves_the_position_alone() {
+    let player = Player::new();
+    assert_eq!(player.load(CLIP), AMPLAYER_ERROR_NONE);
+    assert_eq!(aml_video_player_play(player.ptr), AMPLAYER_ERROR_NONE);
+    assert!(player.wait_for_position(0.3, Duration::from_secs(5)));
+    // async seeks are only checked by libav
+    assert_eq!(aml_video_player_async_seek(player.ptr, -5.0), AMPLAYER_ERROR_NONE);
+    wait_for_async_seek(&player);
+    let pos = player.position().unwrap();
+    assert!(pos >= 0.3, "at {}s after a refused seek", pos);
+    assert!(player.wait_for_position(pos + 0.2, Duration::from_secs(5)), "stuck at {}s", pos);
+}
+
+#[test]
+fn seek_past_the_end_is_clamped() {
+    let player = Player::new();
+    assert_eq!(player.load(CLIP), AMPLAYER_ERROR_NONE);
+    assert_eq!(aml_video_player_play(player.ptr), AMPLAYER_ERROR_NONE);
+    assert_eq!(aml_video_player_seek(player.ptr, 60.0), AMPLAYER_ERROR_NONE);
+    let seeked = player.wait_for_event(AMPLAYER_EVENT_SEEK_COMPLETED, Duration::from_secs(5)).unwrap();
+    assert!(seeked.start_ms < (CLIP_DURATION * 1000.0) as i64 + 500, "seek completed at {}ms", seeked.start_ms);
+}
+
+#[test]
+fn only_the_last_async_seek_completes() {
+    let player = Player::new();
+    assert_eq!(player.load(CLIP), AMPLAYER_ERROR_NONE);
+    assert_eq!(aml_video_player_play(player.ptr), AMPLAYER_ERROR_NONE);
+    for &pos in &[0.2, 0.5, 1.2] {
+        assert_eq!(aml_video_player_async_seek(player.ptr, pos), AMPLAYER_ERROR_NONE);
+    }
+    wait_for_async_seek(&player);
+    let seeked = player.wait_for_event(AMPLAYER_EVENT_SEEK_COMPLETED, Duration::from_secs(5)).unwrap();
+    assert!(seeked.start_ms >= 1000, "seek completed at {}ms", seeked.start_ms);
+}
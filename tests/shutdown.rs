@@ -0,0 +1,107 @@
+//! How long aml_video_player_destroy takes on the dummy Amcodec: under 100ms when idle, and under
+//! 500ms while libav is blocked reading from the network
+
+#![cfg(not(target_arch = "aarch64"))]
+
+extern crate c2player;
+extern crate libc;
+
+mod common;
+
+use c2player::*;
+use common::*;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+const IDLE_DESTROY_MS : u64 = 100;
+const BUSY_DESTROY_MS : u64 = 500;
+
+/// Serves the fixture over HTTP, its headers and first packets at once and the rest a few bytes at
+/// a time: libav is then blocked on the socket most of the time. Returns the url of the video.
+fn trickling_server() -> CString {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/{}", listener.local_addr().unwrap(), CLIP);
+    let mut clip = Vec::new();
+    File::open(fixture(CLIP).into_string().unwrap()).unwrap().read_to_end(&mut clip).unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => return,
+            };
+            let clip = clip.clone();
+            thread::spawn(move || {
+                let mut request = [0u8; 4096];
+                let _r = stream.read(&mut request);
+                let header = format!("HTTP/1.1 200 OK\r\nContent-Type: video/mp4\r\nContent-Length: {}\r\n\r\n", clip.len());
+                // the ftyp and moov boxes, and the first keyframe
+                let (head, tail) = clip.split_at(1500);
+                if stream.write_all(header.as_bytes()).and_then(|_| stream.write_all(head)).is_err() {
+                    return;
+                }
+                // until the player hangs up
+                for chunk in tail.chunks(16) {
+                    thread::sleep(Duration::from_millis(100));
+                    if stream.write_all(chunk).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    CString::new(url).unwrap()
+}
+
+fn assert_faster_than(took: Duration, max_ms: u64) {
+    let took_ms = took.as_secs() * 1000 + (took.subsec_nanos() / 1000000) as u64;
+    assert!(took_ms < max_ms, "aml_video_player_destroy took {}ms", took_ms);
+}
+
+#[test]
+fn destroy_right_after_create() {
+    let player = Player::new();
+    assert_faster_than(player.destroy(), IDLE_DESTROY_MS);
+}
+
+#[test]
+fn destroy_once_the_video_ended() {
+    let player = Player::new();
+    assert_eq!(player.load(CLIP), AMPLAYER_ERROR_NONE);
+    assert_eq!(aml_video_player_play(player.ptr), AMPLAYER_ERROR_NONE);
+    assert!(player.wait_for_event(AMPLAYER_EVENT_END_OF_STREAM, Duration::from_secs(END_TIMEOUT_SECS)).is_some());
+    assert_faster_than(player.destroy(), IDLE_DESTROY_MS);
+}
+
+#[test]
+fn destroy_while_playing() {
+    let player = Player::new();
+    assert_eq!(player.load(CLIP), AMPLAYER_ERROR_NONE);
+    assert_eq!(aml_video_player_play(player.ptr), AMPLAYER_ERROR_NONE);
+    assert!(player.wait_for_position(0.2, Duration::from_secs(5)));
+    assert_faster_than(player.destroy(), BUSY_DESTROY_MS);
+}
+
+#[test]
+fn destroy_while_libav_waits_for_the_network() {
+    let url = trickling_server();
+    let player = Player::new();
+    assert_eq!(aml_video_player_load(player.ptr, url.as_ptr()), AMPLAYER_ERROR_NONE);
+    assert_eq!(aml_video_player_play(player.ptr), AMPLAYER_ERROR_NONE);
+    // the first packets are played by now, libav is waiting for the next ones
+    thread::sleep(Duration::from_millis(300));
+    assert_faster_than(player.destroy(), BUSY_DESTROY_MS);
+}
+
+#[test]
+fn destroy_while_a_preload_waits_for_the_network() {
+    let url = trickling_server();
+    let player = Player::new();
+    assert_eq!(player.load(CLIP), AMPLAYER_ERROR_NONE);
+    assert_eq!(aml_video_player_preload(player.ptr, url.as_ptr()), AMPLAYER_ERROR_NONE);
+    thread::sleep(Duration::from_millis(300));
+    assert_faster_than(player.destroy(), BUSY_DESTROY_MS);
+}